@@ -0,0 +1,40 @@
+//! Assembling any source, however malformed, should give back an error rather than panic.
+
+#![no_main]
+
+use goonstation_asm::{AssemblerOptions, OutputFormat, Program};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the options, the rest is the source
+    let Some((&flags, source)) = data.split_first() else {
+        return;
+    };
+
+    let options = AssemblerOptions::new()
+        .strict(flags & 0b01 != 0)
+        .case_sensitive(flags & 0b10 != 0);
+
+    let program = match core::str::from_utf8(source) {
+        Ok(source) => Program::from_assembly_with(source, options),
+        Err(_) => Program::from_bytes(source),
+    };
+
+    let _ = program.to_opcodes();
+    let _ = program.to_opcodes_with_recovery();
+    let _ = program.validate();
+    let _ = program.ast().to_string();
+    let _ = program.symbols();
+    let _ = program.source_map();
+    let _ = program.stats();
+    let _ = program.xref();
+    let _ = program.docs();
+    let _ = program.to_string();
+    for format in OutputFormat::ALL {
+        let _ = program.emit(format);
+    }
+
+    if let Ok(program) = Program::from_container(data) {
+        let _ = program.to_opcodes();
+    }
+});
@@ -0,0 +1,23 @@
+//! Disassembling any input should either fail cleanly or give a program that assembles, and
+//! disassembling opcodes should always give back exactly the same opcodes.
+
+#![no_main]
+
+use goonstation_asm::{InputFormat, Program};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &[u8]| {
+    if let Ok(program) = Program::disassemble(input) {
+        assert!(program.to_opcodes().is_ok());
+    }
+
+    if InputFormat::detect(input) == InputFormat::Opcodes {
+        let opcodes: String = input
+            .iter()
+            .filter(|byte| !byte.is_ascii_whitespace())
+            .map(|byte| char::from(byte.to_ascii_uppercase()))
+            .collect();
+        let program = Program::disassemble(input).unwrap();
+        assert_eq!(program.to_opcodes().unwrap(), opcodes);
+    }
+});
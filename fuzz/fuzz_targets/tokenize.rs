@@ -0,0 +1,12 @@
+//! Tokenizing any source should always run to the end without panicking.
+
+#![no_main]
+
+use goonstation_asm::tokenize;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|source: &str| {
+    for (_, span) in tokenize(source) {
+        let _ = &source[span];
+    }
+});
@@ -0,0 +1,38 @@
+//! Assembling programs at compile time, so a program that doesn't assemble is a compile error
+//! rather than something found out in-game. This is a separate crate because proc-macros have to
+//! be, and depends on `goonstation-asm` to do the assembling.
+
+use goonstation_asm::Program;
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, LitStr};
+
+/// Assembles a string literal into its opcodes, as a `&'static str` like
+/// [`Program::to_opcodes`] gives. Errors are reported at the literal.
+#[proc_macro]
+pub fn mc14500_asm(input: TokenStream) -> TokenStream {
+    let source = parse_macro_input!(input as LitStr);
+
+    match Program::from_assembly(&source.value()).into_opcodes() {
+        Ok(opcodes) => quote!(#opcodes).into(),
+        Err(error) => compile_error(&source, error),
+    }
+}
+
+/// Like [`mc14500_asm!`], but packs the opcodes two to a byte as a `&'static [u8]`, the same way
+/// [`Program::to_bytes`] does.
+#[proc_macro]
+pub fn mc14500_asm_bytes(input: TokenStream) -> TokenStream {
+    let source = parse_macro_input!(input as LitStr);
+
+    match Program::from_assembly(&source.value()).to_bytes() {
+        Ok(bytes) => quote!(&[#(#bytes),*]).into(),
+        Err(error) => compile_error(&source, error),
+    }
+}
+
+fn compile_error(source: &LitStr, error: impl std::fmt::Display) -> TokenStream {
+    syn::Error::new(source.span(), error)
+        .to_compile_error()
+        .into()
+}
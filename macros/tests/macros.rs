@@ -0,0 +1,10 @@
+use goonstation_asm_macros::{mc14500_asm, mc14500_asm_bytes};
+
+#[test]
+fn handles_compile_time_assembly() {
+    const OPCODES: &str = mc14500_asm!("OEN 0\nloop: LD 7\nSTO 0\nJMP loop");
+    assert_eq!(OPCODES, "B01780C2");
+
+    const BYTES: &[u8] = mc14500_asm_bytes!("OEN 0\nSTO 0\nSKZ");
+    assert_eq!(BYTES, [0xB0, 0x80, 0xE0]);
+}
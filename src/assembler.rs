@@ -0,0 +1,1751 @@
+use alloc::borrow::Cow;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::rc::Rc;
+use alloc::sync::Arc;
+use core::ops::Range;
+
+use logos::Span;
+
+use crate::lexer::{self, Spanned, Token};
+use crate::prelude::*;
+use crate::symbols::{self, SymbolKind};
+use crate::{
+    AssemblerError, AssemblerOptions, Assertion, Doc, DocTarget, EmitOptions, FilePath, Location,
+    Metadata, Warning,
+};
+
+pub use directive::{Directive, DirectiveContext};
+
+use conditional::Conditional;
+use directive::Builtin;
+use expression::Expression;
+use source::{FileMap, SourceFile};
+
+mod conditional;
+mod directive;
+mod expression;
+mod repeat;
+mod source;
+
+/// Macros are free to invoke other macros, but nothing reasonable nests this deep. Past this point
+/// a macro is almost certainly (indirectly) invoking itself.
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+/// Addresses that read back whatever was last stored to them, as opposed to inputs and outputs.
+pub(crate) const SCRATCH_RAM: Range<usize> = 0x8..0x10;
+
+/// The pin that reads the complement of the result register rather than an input, which is what
+/// makes `OEN 0` at the start of a program turn the outputs on.
+pub(crate) const NOT_RR: u8 = 0;
+
+/// What each nibble is written out as in the opcodes.
+const HEX_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+/// Records which macro invocation produced a token, so errors can point back at the call site.
+struct Expansion {
+    name: String,
+    origin: Origin,
+    depth: usize,
+}
+
+/// Where a token came from: which part of which source, and which macro expansion (if any)
+/// produced it.
+#[derive(Clone)]
+struct Origin {
+    span: Span,
+    file: Rc<SourceFile>,
+    expansion: Option<Rc<Expansion>>,
+    /// Which file or macro expansion the token belongs to, for telling local labels apart
+    scope: usize,
+}
+
+/// A token waiting to be processed.
+#[derive(Clone)]
+struct Queued {
+    token: Token,
+    origin: Origin,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Queued>,
+}
+
+/// A single nibble of output, which may still be waiting on a symbol to be resolved.
+enum Slot {
+    Nibble(u8),
+    /// `origin` is where the expression starts
+    Expression {
+        expression: Expression,
+        origin: Origin,
+    },
+}
+
+/// Something a name can refer to: either a position in the program or a fixed value.
+enum Symbol {
+    Label(usize),
+    Constant(usize),
+}
+
+/// A symbol along with the name and place it was defined with, for diagnostics.
+struct Definition {
+    name: String,
+    symbol: Symbol,
+    origin: Origin,
+}
+
+/// The result of assembling a program that didn't have any errors.
+pub(crate) struct Assembled {
+    pub opcodes: String,
+    pub warnings: Vec<Warning>,
+    pub docs: Vec<Doc>,
+    pub metadata: Metadata,
+    pub assertions: Vec<Assertion>,
+    /// The addresses [`Assembled::finish`] added after the program
+    pub added: Range<usize>,
+    /// Where each nibble came from, only turned into locations if they're asked for
+    origins: Vec<Origin>,
+    symbols: BTreeMap<String, Definition>,
+    /// The end of the root source, which is where anything added after the program comes from
+    end: Origin,
+}
+
+impl Assembled {
+    /// Adds the `JMP 0` and padding `options` asks for after the program, as long as they fit
+    /// within `max_length`.
+    pub(crate) fn finish(
+        &mut self,
+        options: &EmitOptions,
+        max_length: usize,
+    ) -> Result<(), AssemblerError> {
+        let start = self.opcodes.len();
+        if options.terminate {
+            self.opcodes.push_str("C0");
+        }
+
+        if self.opcodes.len() > max_length {
+            self.opcodes.truncate(start);
+            return Err(self.end.locate(AssemblerError::ExceededMaxLength {
+                location: Location::UNKNOWN,
+            }));
+        }
+
+        if options.pad {
+            let missing = max_length - self.opcodes.len();
+            self.opcodes.extend(core::iter::repeat('0').take(missing));
+        }
+
+        self.origins.resize(self.opcodes.len(), self.end.clone());
+        self.added = start..self.opcodes.len();
+        Ok(())
+    }
+
+    pub(crate) fn source_map(&self) -> Vec<Location> {
+        self.origins.iter().map(Origin::location).collect()
+    }
+
+    /// Every macro expansion that produced any nibbles, nested ones included, as the macro's
+    /// name, where it was invoked and the addresses it produced. Sorted by address, with an
+    /// expansion coming before the ones nested in it.
+    pub(crate) fn expansions(&self) -> Vec<(String, Location, Range<usize>)> {
+        let mut expansions: Vec<(Rc<Expansion>, Range<usize>)> = Vec::new();
+        for (address, origin) in self.origins.iter().enumerate() {
+            let mut expansion = origin.expansion.as_ref();
+            while let Some(current) = expansion {
+                match expansions
+                    .iter_mut()
+                    .find(|(seen, _)| Rc::ptr_eq(seen, current))
+                {
+                    Some((_, range)) => range.end = address + 1,
+                    None => expansions.push((current.clone(), address..address + 1)),
+                }
+
+                expansion = current.origin.expansion.as_ref();
+            }
+        }
+
+        expansions.sort_by_key(|(expansion, range)| (range.start, expansion.depth));
+        expansions
+            .into_iter()
+            .map(|(expansion, range)| (expansion.name.clone(), expansion.origin.location(), range))
+            .collect()
+    }
+
+    /// Which line of the root source each nibble came from. A nibble from a macro or an included
+    /// file is traced back to the line that invoked or included it.
+    pub(crate) fn root_lines(&self) -> Vec<usize> {
+        self.origins
+            .iter()
+            .map(|origin| origin.root().location().line)
+            .collect()
+    }
+
+    /// Every symbol, sorted by name and then by where it was defined.
+    pub(crate) fn symbols(&self) -> Vec<symbols::Symbol> {
+        let mut symbols: Vec<_> = self
+            .symbols
+            .values()
+            .map(|definition| symbols::Symbol {
+                name: definition.name.clone(),
+                kind: match definition.symbol {
+                    Symbol::Label(address) => SymbolKind::Label { address },
+                    Symbol::Constant(value) => SymbolKind::Constant { value },
+                },
+                location: definition.origin.location(),
+            })
+            .collect();
+
+        symbols.sort_by(|a, b| {
+            (&a.name, a.location.line, a.location.column).cmp(&(
+                &b.name,
+                b.location.line,
+                b.location.column,
+            ))
+        });
+        symbols
+    }
+}
+
+/// Assembles a program, stopping at the first error unless `recover` is set. When recovering, the
+/// rest of any line with an error on it is skipped and assembly carries on from the next line, so
+/// every error in the program can be reported at once (in the order they were found).
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip(source, path, options))
+)]
+pub(crate) fn assemble(
+    source: &str,
+    path: Option<&FilePath>,
+    options: &AssemblerOptions,
+    recover: bool,
+) -> Result<Assembled, Vec<AssemblerError>> {
+    let mut files = FileMap::default();
+    let file = files
+        .add_root(source, path.cloned())
+        .preprocess(options)
+        .map_err(|error| vec![error])?;
+
+    let tokens = lexer::tokenize(&file.text, options);
+    let file = files.insert(file);
+    let end = Origin {
+        span: file.text.len()..file.text.len(),
+        file: file.clone(),
+        expansion: None,
+        scope: 0,
+    };
+    let mut assembler = Assembler::new(&tokens, file, files, options.clone(), recover);
+    assembler.run();
+
+    if !assembler.errors.is_empty() && !recover {
+        return Err(assembler.errors);
+    }
+
+    assembler.resolve(end)
+}
+
+struct Assembler {
+    options: AssemblerOptions,
+    files: FileMap,
+    /// Stored in reverse, so the next token can be popped off the end and macro expansions can be
+    /// pushed back on in front of everything else.
+    pending: Vec<Queued>,
+    slots: Vec<Slot>,
+    /// The token each slot was emitted for
+    origins: Vec<Origin>,
+    /// Keyed by [`symbol_key`]
+    symbols: BTreeMap<String, Definition>,
+    macros: BTreeMap<String, Macro>,
+    conditionals: Vec<Conditional>,
+    /// The statement that pushed the program over the length limit, if any
+    overflow: Option<Origin>,
+    /// Whether the last token taken off the queue was a newline
+    at_line_start: bool,
+    recover: bool,
+    errors: Vec<AssemblerError>,
+    /// How many scopes have been handed out for local labels so far
+    scopes: usize,
+    /// The next scratch RAM address for `.var` to hand out
+    next_variable: usize,
+    docs: Vec<Doc>,
+    metadata: Metadata,
+    assertions: Vec<Assertion>,
+    /// `;;;` lines read since the last statement, waiting for something to document
+    pending_doc: Option<(String, Origin)>,
+}
+
+impl Assembler {
+    fn new(
+        tokens: &[Spanned],
+        file: Rc<SourceFile>,
+        files: FileMap,
+        options: AssemblerOptions,
+        recover: bool,
+    ) -> Self {
+        let mut assembler = Self {
+            options,
+            files,
+            pending: Vec::new(),
+            slots: Vec::new(),
+            origins: Vec::new(),
+            symbols: BTreeMap::new(),
+            macros: BTreeMap::new(),
+            conditionals: Vec::new(),
+            overflow: None,
+            at_line_start: true,
+            recover,
+            errors: Vec::new(),
+            scopes: 0,
+            next_variable: SCRATCH_RAM.start,
+            docs: Vec::new(),
+            metadata: Metadata::default(),
+            assertions: Vec::new(),
+            pending_doc: None,
+        };
+
+        assembler.queue(tokens, file);
+        assembler
+    }
+
+    /// Queues up freshly lexed tokens to be processed before anything else that's pending.
+    fn queue(&mut self, tokens: &[Spanned], file: Rc<SourceFile>) {
+        let scope = self.new_scope();
+        self.pending
+            .extend(tokens.iter().rev().map(|spanned| Queued {
+                token: spanned.token.clone(),
+                origin: Origin {
+                    span: spanned.span.clone(),
+                    file: file.clone(),
+                    expansion: None,
+                    scope,
+                },
+            }));
+    }
+
+    /// Every file that's read in and every macro expansion gets its own set of local labels.
+    fn new_scope(&mut self) -> usize {
+        self.scopes += 1;
+        self.scopes
+    }
+
+    /// First pass: expand macros, lay out every nibble and note where each symbol points. Operands
+    /// can refer to labels further down the program, so they're left as placeholders for now.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "parse", level = "debug", skip_all)
+    )]
+    fn run(&mut self) {
+        while let Some(queued) = self.next() {
+            let origin = queued.origin.clone();
+            if let Err(error) = self.statement(queued) {
+                self.errors.push(origin.contextualize(error));
+                if !self.recover {
+                    return;
+                }
+
+                self.skip_line();
+            }
+        }
+
+        if let Some(conditional) = self.conditionals.last() {
+            let error = AssemblerError::UnterminatedConditional {
+                location: Location::UNKNOWN,
+            };
+
+            self.errors.push(conditional.origin.contextualize(error));
+        }
+
+        if let Some(origin) = &self.overflow {
+            let error = AssemblerError::ExceededMaxLength {
+                location: Location::UNKNOWN,
+            };
+
+            self.errors.push(origin.contextualize(error));
+        }
+    }
+
+    /// Second pass: now that every symbol has a value, resolve the placeholders.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "emit",
+            level = "debug",
+            skip_all,
+            fields(nibbles = self.slots.len())
+        )
+    )]
+    fn resolve(mut self, end: Origin) -> Result<Assembled, Vec<AssemblerError>> {
+        let mut output = String::with_capacity(self.slots.len());
+        for slot in &self.slots {
+            let nibble = match slot {
+                Slot::Nibble(nibble) => *nibble,
+                Slot::Expression { expression, origin } => match self.resolve_operand(expression) {
+                    Ok(nibble) => nibble,
+                    Err(error) => {
+                        self.errors.push(origin.contextualize(error));
+                        if !self.recover {
+                            break;
+                        }
+
+                        continue;
+                    }
+                },
+            };
+
+            // Every slot holds a single nibble, so this can't run off the end
+            output.push(char::from(HEX_DIGITS[usize::from(nibble & 0xF)]));
+        }
+
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+
+        let warnings = self.unused_labels();
+        Ok(Assembled {
+            added: output.len()..output.len(),
+            opcodes: output,
+            warnings,
+            docs: self.docs,
+            metadata: self.metadata,
+            assertions: self.assertions,
+            origins: self.origins,
+            symbols: self.symbols,
+            end,
+        })
+    }
+
+    /// Warns about every label that no operand refers to, in the order they appear.
+    fn unused_labels(&self) -> Vec<Warning> {
+        let mut used = BTreeSet::new();
+        for slot in &self.slots {
+            if let Slot::Expression { expression, .. } = slot {
+                expression.symbols(&mut |name, origin| {
+                    used.insert(symbol_key(name, origin.scope).into_owned());
+                });
+            }
+        }
+
+        let mut unused: Vec<_> = self
+            .symbols
+            .iter()
+            .filter_map(|(key, definition)| match definition.symbol {
+                Symbol::Label(address) if !used.contains(key) => Some((address, definition)),
+                _ => None,
+            })
+            .collect();
+        unused.sort_by(|(a, a_definition), (b, b_definition)| {
+            a.cmp(b)
+                .then_with(|| a_definition.name.cmp(&b_definition.name))
+        });
+
+        unused
+            .into_iter()
+            .map(|(_, definition)| Warning::UnusedLabel {
+                name: definition.name.clone(),
+                location: definition.origin.location(),
+            })
+            .collect()
+    }
+
+    fn resolve_operand(&self, expression: &Expression) -> Result<u8, AssemblerError> {
+        let value = expression.evaluate(&|name, origin| match self
+            .symbols
+            .get(&*symbol_key(name, origin.scope))
+        {
+            Some(Definition {
+                symbol: Symbol::Label(value) | Symbol::Constant(value),
+                ..
+            }) => Ok(*value as i64),
+            None => Err(AssemblerError::UndefinedSymbol {
+                name: name.to_string(),
+                location: Location::UNKNOWN,
+            }),
+        })?;
+
+        u8::try_from(value)
+            .ok()
+            .filter(|value| *value <= 0xF)
+            .ok_or_else(|| match expression {
+                // Report the symbol by name when it's the whole operand
+                Expression::Symbol { name, .. } => AssemblerError::SymbolOutOfRange {
+                    name: name.clone(),
+                    value: value as usize,
+                    location: Location::UNKNOWN,
+                },
+                _ => AssemblerError::ExpressionOutOfRange {
+                    value,
+                    location: Location::UNKNOWN,
+                },
+            })
+    }
+
+    fn next(&mut self) -> Option<Queued> {
+        let queued = self.pending.pop()?;
+        #[cfg(feature = "tracing")]
+        tracing::trace!(token = ?queued.token);
+
+        self.at_line_start = queued.token == Token::Newline;
+        Some(queued)
+    }
+
+    /// Adds a nibble to the output, remembering where the length limit was first passed.
+    fn emit(&mut self, slot: Slot, origin: &Origin) {
+        self.slots.push(slot);
+        self.origins.push(origin.clone());
+
+        if self.slots.len() > self.options.max_length && self.overflow.is_none() {
+            self.overflow = Some(origin.clone());
+        }
+    }
+
+    /// Attaches any `;;;` lines just before this statement to it.
+    fn document(&mut self, target: DocTarget) {
+        if let Some((text, origin)) = self.pending_doc.take() {
+            self.docs.push(Doc {
+                target,
+                text,
+                location: origin.location(),
+            });
+        }
+    }
+
+    /// Throws away whatever's left of the current line after an error.
+    fn skip_line(&mut self) {
+        while !self.at_line_start && self.next().is_some() {}
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.pending.last().map(|queued| &queued.token)
+    }
+
+    fn next_if_eq(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn statement(&mut self, queued: Queued) -> Result<(), AssemblerError> {
+        match queued.token {
+            Token::Newline => Ok(()),
+            Token::Comment(Some(text)) => {
+                match &mut self.pending_doc {
+                    Some((pending, _)) => {
+                        pending.push('\n');
+                        pending.push_str(&text);
+                    }
+                    None => self.pending_doc = Some((text, queued.origin)),
+                }
+
+                Ok(())
+            }
+            Token::Identifier(name) => {
+                if self.next_if_eq(&Token::Colon) {
+                    let address = self.slots.len();
+                    self.document(DocTarget::Label {
+                        name: name.clone(),
+                        address,
+                    });
+                    return self.define_symbol(name, Symbol::Label(address), queued.origin);
+                }
+
+                if self.macros.contains_key(&name) {
+                    return self.expand_macro(name, queued.origin);
+                }
+
+                if lexer::is_unseparated_instruction(&name, &self.options) {
+                    return Err(AssemblerError::MissingSeparator {
+                        text: name,
+                        location: Location::UNKNOWN,
+                    });
+                }
+
+                Err(AssemblerError::UnexpectedIdentifier {
+                    name,
+                    location: Location::UNKNOWN,
+                })
+            }
+            Token::Colon => Err(AssemblerError::ExpectedLabelName {
+                location: Location::UNKNOWN,
+            }),
+            Token::Unknown(text) => Err(unexpected(text)),
+            Token::MissingSeparator(text) => Err(AssemblerError::MissingSeparator {
+                text,
+                location: Location::UNKNOWN,
+            }),
+            // `tokenize` only keeps block comments that are missing their end
+            Token::BlockComment(_) => Err(AssemblerError::UnterminatedComment {
+                location: Location::UNKNOWN,
+            }),
+            Token::Directive(name) if self.next_if_eq(&Token::Colon) => {
+                let address = self.slots.len();
+                let name = format!(".{name}");
+                self.document(DocTarget::Label {
+                    name: name.clone(),
+                    address,
+                });
+                self.define_symbol(name, Symbol::Label(address), queued.origin)
+            }
+            Token::Directive(directive) => self.directive(directive, queued.origin),
+            // Part of an operand or argument list, with no instruction to go with it
+            Token::Operand(_)
+            | Token::Number(_)
+            | Token::String(_)
+            | Token::LibraryPath(_)
+            | Token::Comma
+            | Token::Plus
+            | Token::Minus
+            | Token::Star
+            | Token::Slash
+            | Token::Percent
+            | Token::Ampersand
+            | Token::Pipe
+            | Token::Caret
+            | Token::Tilde
+            | Token::ShiftLeft
+            | Token::ShiftRight
+            | Token::LeftParen
+            | Token::RightParen => Err(AssemblerError::UnexpectedToken {
+                text: queued.origin.text().to_string(),
+                location: Location::UNKNOWN,
+            }),
+            token => {
+                self.document(DocTarget::Instruction {
+                    address: self.slots.len(),
+                });
+
+                // Push the token representation to the output
+                if let Some(token_repr) = get_token_representation(&token) {
+                    self.emit(Slot::Nibble(token_repr), &queued.origin);
+                }
+
+                if does_token_require_operand(&token) {
+                    return self.operand(&token);
+                }
+
+                // Catch `NOP 3` and the like, rather than assembling the operand as an instruction
+                if let Some(Token::Operand(_) | Token::Number(_)) = self.peek() {
+                    let operand = self.next().unwrap();
+                    return Err(operand.origin.locate(AssemblerError::UnexpectedOperand {
+                        mnemonic: token.mnemonic().unwrap_or_default().to_string(),
+                        location: Location::UNKNOWN,
+                    }));
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    fn operand(&mut self, instruction: &Token) -> Result<(), AssemblerError> {
+        // A leading sign on a jump means it's relative rather than negative
+        if *instruction == Token::Jump {
+            if let Some(sign @ (Token::Plus | Token::Minus)) = self.peek() {
+                let origin = self.operand_origin()?;
+                let backwards = *sign == Token::Minus;
+                self.next();
+
+                let target = self
+                    .relative_target(backwards)
+                    .map_err(|error| origin.locate(error))?;
+
+                self.emit(Slot::Nibble(target), &origin);
+                return Ok(());
+            }
+        }
+
+        self.expression_operand()
+    }
+
+    /// Where the operand that's up next starts.
+    fn operand_origin(&self) -> Result<Origin, AssemblerError> {
+        // Leave the end of the line where it is, so that recovering from the error doesn't skip
+        // the line after it too
+        match self.pending.last() {
+            Some(queued) if queued.token != Token::Newline => Ok(queued.origin.clone()),
+            _ => Err(AssemblerError::ExpectedOperand {
+                location: Location::UNKNOWN,
+            }),
+        }
+    }
+
+    /// Reads an operand and emits it: right away if it's a number, or once every symbol has a
+    /// value if it's anything else.
+    fn expression_operand(&mut self) -> Result<(), AssemblerError> {
+        let origin = self.operand_origin()?;
+        let expression = self.expression(&|| AssemblerError::ExpectedOperand {
+            location: Location::UNKNOWN,
+        })?;
+
+        let slot = match expression {
+            Expression::Number(value) => {
+                let operand = u8::try_from(value)
+                    .ok()
+                    .filter(|operand| *operand <= 0xF)
+                    .ok_or_else(|| {
+                        origin.locate(AssemblerError::OperandOutOfRange {
+                            value: value as usize,
+                            location: Location::UNKNOWN,
+                        })
+                    })?;
+
+                Slot::Nibble(operand)
+            }
+            expression => Slot::Expression {
+                expression,
+                origin: origin.clone(),
+            },
+        };
+
+        self.emit(slot, &origin);
+        Ok(())
+    }
+
+    /// Reads the rest of a relative jump like `JMP +3` or `JMP -2`, which is counted from the
+    /// address of the `JMP` itself, and works out the address it lands on.
+    fn relative_target(&mut self, backwards: bool) -> Result<u8, AssemblerError> {
+        let offset = match self.peek() {
+            Some(Token::Operand(offset)) => usize::from(*offset),
+            Some(Token::Number(offset)) => *offset,
+            _ => {
+                return Err(AssemblerError::ExpectedOperand {
+                    location: Location::UNKNOWN,
+                })
+            }
+        };
+        self.next();
+
+        // The `JMP` has already been laid out
+        let address = self.slots.len() - 1;
+        let target = if backwards {
+            address.checked_sub(offset)
+        } else {
+            address.checked_add(offset)
+        };
+
+        target
+            .and_then(|target| u8::try_from(target).ok())
+            .filter(|target| *target <= 0xF)
+            .ok_or(AssemblerError::JumpOutOfRange {
+                offset: if backwards {
+                    -(offset as isize)
+                } else {
+                    offset as isize
+                },
+                address,
+                location: Location::UNKNOWN,
+            })
+    }
+
+    fn directive(&mut self, name: String, origin: Origin) -> Result<(), AssemblerError> {
+        // Built-in directives can't be replaced, since the likes of `.if` and `.endm` are also
+        // looked for by name while skipping over code
+        let directive: Arc<dyn Directive + Send + Sync> = match Builtin::from_name(&name) {
+            Some(builtin) => Arc::new(builtin),
+            None => match self.options.directives.get(&name) {
+                Some(directive) => directive.clone(),
+                None => {
+                    return Err(AssemblerError::UnknownDirective {
+                        name,
+                        location: Location::UNKNOWN,
+                    })
+                }
+            },
+        };
+
+        if let Some(allowed) = &self.options.allowed_directives {
+            if !allowed.contains(directive.name()) {
+                return Err(AssemblerError::DirectiveNotAllowed {
+                    name,
+                    location: Location::UNKNOWN,
+                });
+            }
+        }
+
+        // Only macro definitions can be documented
+        if Builtin::from_name(&name) != Some(Builtin::Macro) {
+            self.pending_doc = None;
+        }
+
+        directive.run(&mut DirectiveContext::new(self, &name, &origin))
+    }
+
+    /// Handles `.assert ...`, which emits nothing and is only checked by
+    /// [`crate::Program::check_assertions`]. Conditions use the likes of `==` and `in[1]`, which
+    /// the lexer doesn't know about, so the rest of the line is read as it's written instead,
+    /// with any macro parameters in it swapped for the tokens they stand for.
+    fn assertion(&mut self, origin: &Origin) -> Result<(), AssemblerError> {
+        let text = &origin.file.text;
+        let mut end = text.len();
+        // The span of each macro parameter, and the spelling of each token it stands for
+        let mut parameters: Vec<(Span, Vec<String>)> = Vec::new();
+        while let Some(queued) = self.pending.last() {
+            if !Rc::ptr_eq(&queued.origin.file, &origin.file) {
+                break;
+            }
+
+            if queued.token == Token::Newline {
+                end = queued.origin.span.start;
+                break;
+            }
+
+            let Some(queued) = self.next() else { break };
+            if self.is_parameter(&queued.origin) {
+                let spelling = queued.token.spelling();
+                match parameters.last_mut() {
+                    Some((span, tokens)) if *span == queued.origin.span => tokens.push(spelling),
+                    _ => parameters.push((queued.origin.span, vec![spelling])),
+                }
+            }
+        }
+
+        let line = &text[origin.span.end..end];
+        if let Some(comment) = [line.find([';', '#']), line.find("//")]
+            .into_iter()
+            .flatten()
+            .min()
+        {
+            end = origin.span.end + comment;
+        }
+
+        let mut assertion = String::new();
+        let mut position = origin.span.end;
+        for (span, tokens) in parameters.iter().filter(|(span, _)| span.end <= end) {
+            assertion.push_str(&text[position..span.start]);
+            assertion.push_str(&tokens.join(" "));
+            position = span.end;
+        }
+        assertion.push_str(&text[position..end]);
+
+        let assertion = Assertion::parse(&assertion, origin.location()).map_err(|message| {
+            AssemblerError::InvalidAssertion {
+                message,
+                location: Location::UNKNOWN,
+            }
+        })?;
+
+        self.assertions.push(assertion);
+        Ok(())
+    }
+
+    /// Whether the token from `origin` stood in for a parameter of the macro it was expanded
+    /// from, so isn't what's written there any more.
+    fn is_parameter(&self, origin: &Origin) -> bool {
+        origin
+            .expansion
+            .as_ref()
+            .and_then(|expansion| self.macros.get(&expansion.name))
+            .is_some_and(|definition| definition.params.iter().any(|param| param == origin.text()))
+    }
+
+    /// Reads a value that has to be known right away, like the value of a constant. `context`
+    /// describes what the value is for if it turns out to be missing.
+    fn value(&mut self, context: &str) -> Result<usize, AssemblerError> {
+        let expected_value = || AssemblerError::ExpectedValue {
+            context: context.to_string(),
+            location: Location::UNKNOWN,
+        };
+
+        let expression = self.expression(&expected_value)?;
+        let value = expression.evaluate(&|name, _| match self.symbols.get(name) {
+            Some(Definition {
+                symbol: Symbol::Constant(value),
+                ..
+            }) => Ok(*value as i64),
+            _ => Err(AssemblerError::UndefinedSymbol {
+                name: name.to_string(),
+                location: Location::UNKNOWN,
+            }),
+        })?;
+
+        usize::try_from(value).map_err(|_| AssemblerError::NegativeValue {
+            context: context.to_string(),
+            value,
+            location: Location::UNKNOWN,
+        })
+    }
+
+    /// Reads the file named by an `.include` directive and queues its tokens up next. Paths are
+    /// relative to the file doing the including, or the working directory if there isn't one,
+    /// while `<std/NAME>` is one of the standard library files built into the assembler.
+    fn include(&mut self, origin: Origin) -> Result<(), AssemblerError> {
+        let file = match self.next().map(|queued| queued.token) {
+            Some(Token::String(path)) => self.files.open(&path, origin.clone())?,
+            Some(Token::LibraryPath(name)) => self.files.open_library(&name, origin.clone())?,
+            _ => {
+                return Err(AssemblerError::ExpectedIncludePath {
+                    location: Location::UNKNOWN,
+                })
+            }
+        };
+
+        // Errors in the included file don't have a token for `contextualize` to trace back from
+        let file = file
+            .preprocess(&self.options)
+            .map_err(|error| AssemblerError::InInclude {
+                location: origin.location(),
+                source: Box::new(error),
+            })?;
+
+        let tokens = lexer::tokenize(&file.text, &self.options);
+        let file = self.files.insert(file);
+        self.queue(&tokens, file);
+        Ok(())
+    }
+
+    /// `origin` is where the name is written, for pointing at both definitions if it's defined
+    /// twice.
+    fn define_symbol(
+        &mut self,
+        name: String,
+        symbol: Symbol,
+        origin: Origin,
+    ) -> Result<(), AssemblerError> {
+        let key = symbol_key(&name, origin.scope).into_owned();
+        if let Some(previous) = self.symbols.get(&key) {
+            return Err(origin.locate(AssemblerError::DuplicateSymbol {
+                name,
+                previous: Box::new(previous.origin.location()),
+                location: Location::UNKNOWN,
+            }));
+        }
+
+        self.symbols.insert(
+            key,
+            Definition {
+                name,
+                symbol,
+                origin,
+            },
+        );
+        Ok(())
+    }
+
+    /// Reads a `.macro NAME PARAM, PARAM...` header and everything up to the matching `.endm`.
+    /// The body is stored as-is and only checked once the macro is expanded.
+    fn define_macro(&mut self) -> Result<(), AssemblerError> {
+        let name = match self.next().map(|queued| queued.token) {
+            Some(Token::Identifier(name)) => name,
+            _ => {
+                return Err(AssemblerError::ExpectedMacroName {
+                    location: Location::UNKNOWN,
+                })
+            }
+        };
+
+        self.document(DocTarget::Macro { name: name.clone() });
+
+        let expected_parameter = |name| AssemblerError::ExpectedParameterName {
+            name,
+            location: Location::UNKNOWN,
+        };
+
+        let mut params = Vec::new();
+        loop {
+            match self.next().map(|queued| queued.token) {
+                Some(Token::Identifier(param)) => params.push(param),
+                Some(Token::Newline) | None if params.is_empty() => break,
+                _ => return Err(expected_parameter(name)),
+            }
+
+            match self.next().map(|queued| queued.token) {
+                Some(Token::Comma) => {}
+                Some(Token::Newline) | None => break,
+                _ => return Err(expected_parameter(name)),
+            }
+        }
+
+        let mut body = Vec::new();
+        loop {
+            let queued = self
+                .next()
+                .ok_or_else(|| AssemblerError::UnterminatedMacro {
+                    name: name.clone(),
+                    location: Location::UNKNOWN,
+                })?;
+
+            let directive = match &queued.token {
+                Token::Directive(directive) => Builtin::from_name(directive),
+                _ => None,
+            };
+
+            match directive {
+                Some(Builtin::EndMacro) => break,
+                Some(Builtin::Macro) => {
+                    return Err(queued.origin.locate(AssemblerError::NestedMacro {
+                        name,
+                        location: Location::UNKNOWN,
+                    }))
+                }
+                _ => body.push(queued),
+            }
+        }
+
+        if self.macros.contains_key(&name) {
+            return Err(AssemblerError::DuplicateMacro {
+                name,
+                location: Location::UNKNOWN,
+            });
+        }
+
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    /// Substitutes the arguments following a macro invocation into its body and queues the result
+    /// up to be processed next.
+    fn expand_macro(&mut self, name: String, origin: Origin) -> Result<(), AssemblerError> {
+        let depth = origin.expansion.as_ref().map_or(0, |parent| parent.depth) + 1;
+
+        if depth > MAX_EXPANSION_DEPTH {
+            return Err(AssemblerError::MacroRecursionLimit {
+                name,
+                location: Location::UNKNOWN,
+            });
+        }
+
+        // Arguments are comma-separated runs of tokens, running until the end of the line
+        let mut args = vec![Vec::new()];
+        while !matches!(self.peek(), Some(Token::Newline) | None) {
+            if let Some(Queued { token, .. }) = self.next() {
+                match token {
+                    Token::Comma => args.push(Vec::new()),
+                    token => args.last_mut().unwrap().push(token),
+                }
+            }
+        }
+
+        if args.len() == 1 && args[0].is_empty() {
+            args.clear();
+        }
+
+        let scope = self.new_scope();
+        let definition = &self.macros[&name];
+        if args.len() != definition.params.len() {
+            return Err(AssemblerError::MacroArgumentCount {
+                name,
+                expected: definition.params.len(),
+                found: args.len(),
+                location: Location::UNKNOWN,
+            });
+        }
+
+        if args.iter().any(Vec::is_empty) {
+            return Err(AssemblerError::ExpectedMacroArgument {
+                name,
+                location: Location::UNKNOWN,
+            });
+        }
+
+        let expansion = Rc::new(Expansion {
+            name,
+            origin,
+            depth,
+        });
+
+        let mut expanded = Vec::new();
+        for queued in &definition.body {
+            let param = match &queued.token {
+                Token::Identifier(identifier) => definition
+                    .params
+                    .iter()
+                    .position(|param| param == identifier),
+                _ => None,
+            };
+
+            let tokens = match param {
+                Some(index) => args[index].as_slice(),
+                None => core::slice::from_ref(&queued.token),
+            };
+
+            expanded.extend(tokens.iter().map(|token| Queued {
+                token: token.clone(),
+                origin: Origin {
+                    expansion: Some(expansion.clone()),
+                    scope,
+                    ..queued.origin.clone()
+                },
+            }));
+        }
+
+        self.pending.extend(expanded.into_iter().rev());
+        Ok(())
+    }
+}
+
+impl Origin {
+    /// The source text the token was lexed from.
+    fn text(&self) -> &str {
+        &self.file.text[self.span.clone()]
+    }
+
+    fn location(&self) -> Location {
+        self.file.location(&self.span)
+    }
+
+    /// Where in the root source this token ended up coming from, through every macro expansion
+    /// and include.
+    fn root(&self) -> &Origin {
+        let mut current = self;
+        while let Some(next) = current
+            .expansion
+            .as_ref()
+            .map(|expansion| &expansion.origin)
+            .or(current.file.included_from.as_ref())
+        {
+            current = next;
+        }
+
+        current
+    }
+
+    /// Gives the error this token's location, unless it already has a more specific one.
+    fn locate(&self, error: AssemblerError) -> AssemblerError {
+        error.locate(|| self.location())
+    }
+
+    /// Locates the error, then wraps it in the chain of macro expansions and includes that led to
+    /// it, innermost first. A token from a macro is traced back through where the macro was
+    /// invoked rather than where it was defined.
+    fn contextualize(&self, error: AssemblerError) -> AssemblerError {
+        let mut error = self.locate(error);
+
+        let mut current = self;
+        loop {
+            let (next, wrapped) = if let Some(expansion) = &current.expansion {
+                let wrapped = AssemblerError::InMacro {
+                    name: expansion.name.clone(),
+                    location: expansion.origin.location(),
+                    source: Box::new(error),
+                };
+
+                (&expansion.origin, wrapped)
+            } else if let Some(include) = &current.file.included_from {
+                let wrapped = AssemblerError::InInclude {
+                    location: include.location(),
+                    source: Box::new(error),
+                };
+
+                (include, wrapped)
+            } else {
+                return error;
+            };
+
+            current = next;
+            error = wrapped;
+        }
+    }
+}
+
+/// The error for text that isn't part of the language, singling out characters that look out of
+/// place in a program to make them easier to spot.
+fn unexpected(text: String) -> AssemblerError {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(character), None) if !character.is_ascii_graphic() => {
+            AssemblerError::InvalidCharacter {
+                character,
+                location: Location::UNKNOWN,
+            }
+        }
+        _ => AssemblerError::UnexpectedToken {
+            text,
+            location: Location::UNKNOWN,
+        },
+    }
+}
+
+/// The name a symbol is stored under. Local labels start with a dot, and are only visible to the
+/// file or macro expansion they're defined in, so a macro can use `.loop` without two expansions
+/// clashing. They have their scope tacked on the end, after a character that can't appear in a
+/// name.
+fn symbol_key(name: &str, scope: usize) -> Cow<'_, str> {
+    if name.starts_with('.') {
+        Cow::Owned(format!("{name}@{scope}"))
+    } else {
+        Cow::Borrowed(name)
+    }
+}
+
+fn get_token_representation(token: &Token) -> Option<u8> {
+    match token {
+        Token::NoOp => Some(0x0),
+        Token::Load => Some(0x1),
+        Token::LoadComplement => Some(0x2),
+        Token::And => Some(0x3),
+        Token::AndComplement => Some(0x4),
+        Token::Or => Some(0x5),
+        Token::OrComplement => Some(0x6),
+        Token::ExclusiveNor => Some(0x7),
+        Token::Store => Some(0x8),
+        Token::StoreComplement => Some(0x9),
+        Token::InputEnable => Some(0xA),
+        Token::OutputEnable => Some(0xB),
+        Token::Jump => Some(0xC),
+        Token::Return => Some(0xD),
+        Token::SkipIfZero => Some(0xE),
+        Token::Operand(_)
+        | Token::Number(_)
+        | Token::Directive(_)
+        | Token::String(_)
+        | Token::Identifier(_)
+        | Token::Colon
+        | Token::Comma
+        | Token::Plus
+        | Token::Minus
+        | Token::Star
+        | Token::Slash
+        | Token::Percent
+        | Token::Ampersand
+        | Token::Pipe
+        | Token::Caret
+        | Token::Tilde
+        | Token::ShiftLeft
+        | Token::ShiftRight
+        | Token::LeftParen
+        | Token::RightParen
+        | Token::Newline
+        | Token::Unknown(_)
+        | Token::MissingSeparator(_)
+        | Token::BlockComment(_)
+        | Token::LibraryPath(_)
+        | Token::Comment(_)
+        | Token::Error => None,
+    }
+}
+
+fn does_token_require_operand(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Load
+            | Token::LoadComplement
+            | Token::And
+            | Token::AndComplement
+            | Token::Or
+            | Token::OrComplement
+            | Token::ExclusiveNor
+            | Token::Store
+            | Token::StoreComplement
+            | Token::InputEnable
+            | Token::OutputEnable
+            | Token::Jump
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "std")]
+    use std::fs;
+    #[cfg(feature = "std")]
+    use std::path::PathBuf;
+
+    use crate::{AssemblerError, FilePath, Location, Program};
+
+    fn location(
+        file: Option<FilePath>,
+        line: usize,
+        column: usize,
+        offset: usize,
+        snippet: &str,
+        length: usize,
+    ) -> Location {
+        Location {
+            file,
+            line,
+            column,
+            offset,
+            snippet: String::from(snippet),
+            length,
+        }
+    }
+
+    /// Writes each `(name, contents)` pair into a fresh scratch directory and returns its path.
+    #[cfg(feature = "std")]
+    fn scratch_dir(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("goonstation-asm-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        for (name, contents) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        dir
+    }
+
+    #[test]
+    fn handles_macros() {
+        let program = Program::from_assembly(
+            ".macro pulse pin\nSTO pin\nSTOC pin\n.endm\nOEN 0\npulse 3\npulse 4",
+        );
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B083938494")));
+    }
+
+    #[test]
+    fn handles_macros_with_several_params() {
+        let program = Program::from_assembly(
+            ".macro copy from, to\nLD from\nSTO to\n.endm\n.macro fan_out from\ncopy from, 8\ncopy from, 9\n.endm\nfan_out 1",
+        );
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("11881189")));
+    }
+
+    #[test]
+    fn handles_macro_argument_count() {
+        let program =
+            Program::from_assembly(".macro copy from, to\nLD from\nSTO to\n.endm\ncopy 1");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::MacroArgumentCount {
+                expected: 2,
+                found: 1,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn handles_errors_inside_macros() {
+        let program = Program::from_assembly(
+            ".macro broken\nSTO\n.endm\n.macro outer\nbroken\n.endm\nNOP\nouter",
+        );
+        let bin = program.into_opcodes();
+        assert_eq!(
+            bin,
+            Err(AssemblerError::InMacro {
+                name: String::from("outer"),
+                location: location(None, 8, 1, 54, "outer", 5),
+                source: Box::new(AssemblerError::InMacro {
+                    name: String::from("broken"),
+                    location: location(None, 5, 1, 37, "broken", 6),
+                    source: Box::new(AssemblerError::ExpectedOperand {
+                        location: location(None, 2, 1, 14, "STO", 3),
+                    }),
+                }),
+            })
+        );
+        assert_eq!(
+            bin.unwrap_err().to_string(),
+            "2:1: Expected operand\n  expanded from macro `broken` at 5:1\n  expanded from macro `outer` at 8:1"
+        );
+    }
+
+    #[test]
+    fn handles_recursive_macros() {
+        let program = Program::from_assembly(".macro forever\nforever\n.endm\nforever");
+        let bin = program.into_opcodes();
+        assert!(matches!(bin, Err(AssemblerError::InMacro { .. })));
+    }
+
+    #[test]
+    fn handles_unterminated_macros() {
+        let program = Program::from_assembly(".macro pulse pin\nSTO pin");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::UnterminatedMacro { name, .. }) if name == "pulse"
+        ));
+    }
+
+    #[test]
+    fn handles_macros_before_length_check() {
+        let body = "NOP ".repeat(64);
+        let program = Program::from_assembly(&format!(".macro pad\n{body}\n.endm\npad\npad\npad"));
+        let bin = program.into_opcodes();
+
+        // The error points at the instruction that didn't fit, inside the third expansion
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::InMacro { location, source, .. })
+                if location.line == 6
+                    && matches!(*source, AssemblerError::ExceededMaxLength { .. })
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn handles_includes() {
+        let dir = scratch_dir(
+            "includes",
+            &[
+                ("main.s", ".include \"lib/latch.s\"\nOEN 0\nlatch 3"),
+                (
+                    "lib/latch.s",
+                    ".include \"pins.s\"\n.macro latch pin\nLD pin\nSTO OUT\n.endm",
+                ),
+                ("lib/pins.s", ".equ OUT 8"),
+            ],
+        );
+
+        let program = Program::from_file(dir.join("main.s")).unwrap();
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B01388")));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn handles_errors_inside_includes() {
+        let dir = scratch_dir(
+            "include-errors",
+            &[
+                ("main.s", "NOP\n.include \"bad.s\""),
+                ("bad.s", "OEN 0\n\nSTO"),
+            ],
+        );
+
+        let program = Program::from_file(dir.join("main.s")).unwrap();
+        let bin = program.into_opcodes();
+        assert_eq!(
+            bin,
+            Err(AssemblerError::InInclude {
+                location: location(
+                    Some(dir.join("main.s").into()),
+                    2,
+                    1,
+                    4,
+                    ".include \"bad.s\"",
+                    8
+                ),
+                source: Box::new(AssemblerError::ExpectedOperand {
+                    location: location(Some(dir.join("bad.s").into()), 3, 1, 7, "STO", 3),
+                }),
+            })
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn handles_errors_in_macros_from_includes() {
+        let dir = scratch_dir(
+            "include-provenance",
+            &[
+                ("main.s", "OEN 0\n.include \"outer.s\""),
+                ("outer.s", ".include \"lib.s\"\nNOP\nstore"),
+                ("lib.s", ".macro store\nSTO\n.endm"),
+                ("defines.s", "#pragma once"),
+            ],
+        );
+
+        let program = Program::from_file(dir.join("main.s")).unwrap();
+        let error = program.into_opcodes().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "{lib}:2:1: Expected operand\n  expanded from macro `store` at {outer}:3:1\n  included from {main}:2:1",
+                lib = dir.join("lib.s").display(),
+                outer = dir.join("outer.s").display(),
+                main = dir.join("main.s").display(),
+            )
+        );
+
+        let program =
+            Program::from_assembly(&format!(".include \"{}\"", dir.join("defines.s").display()));
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::InInclude { source, .. })
+                if matches!(*source, AssemblerError::UnknownPreprocessorDirective { .. })
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn handles_missing_includes() {
+        let dir = scratch_dir("missing-include", &[("main.s", ".include \"nope.s\"")]);
+
+        let program = Program::from_file(dir.join("main.s")).unwrap();
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::IncludeFailed { location, .. }) if location.line == 1
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn handles_circular_includes() {
+        let dir = scratch_dir(
+            "circular-include",
+            &[
+                ("a.s", ".include \"b.s\""),
+                ("b.s", "NOP\n.include \"a.s\""),
+            ],
+        );
+
+        let program = Program::from_file(dir.join("a.s")).unwrap();
+        let bin = program.into_opcodes();
+        assert_eq!(
+            bin,
+            Err(AssemblerError::InInclude {
+                location: location(Some(dir.join("a.s").into()), 1, 1, 0, ".include \"b.s\"", 8),
+                source: Box::new(AssemblerError::CircularInclude {
+                    path: dir.join("a.s").into(),
+                    location: location(
+                        Some(dir.join("b.s").into()),
+                        2,
+                        1,
+                        4,
+                        ".include \"a.s\"",
+                        8
+                    ),
+                }),
+            })
+        );
+    }
+
+    #[cfg(not(feature = "std"))]
+    #[test]
+    fn handles_includes_without_std() {
+        let program = Program::from_assembly(".include \"other.s\"");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::IncludeFailed { path, .. }) if path == "other.s"
+        ));
+    }
+
+    #[test]
+    fn handles_standard_library() {
+        let program = Program::from_assembly(
+            ".include <std/latch>\n.include <std/edge>\n.include <std/toggle>\n.include <std/debounce>\n.var STATE, PREV\nlatch 1, 2, STATE\ntoggle 3, PREV, 4",
+        );
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("18514288134974941389")));
+
+        let program = Program::from_assembly(".include <std/nope>");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::UnknownLibraryFile { name, .. }) if name == "std/nope"
+        ));
+    }
+
+    #[test]
+    fn handles_org() {
+        let program = Program::from_assembly("JMP target\n.org 6\ntarget: LD 1\n.org 0xA\nSTO 2");
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("C60000110082")));
+    }
+
+    #[test]
+    fn handles_org_behind_current_address() {
+        let program = Program::from_assembly("LD 1\nSTO 2\n.org 3");
+        let bin = program.into_opcodes();
+        assert_eq!(
+            bin,
+            Err(AssemblerError::OrgBehind {
+                address: 3,
+                current: 4,
+                location: location(None, 3, 1, 11, ".org 3", 4),
+            })
+        );
+
+        let program = Program::from_assembly(".org 0x81");
+        let bin = program.into_opcodes();
+        assert!(matches!(bin, Err(AssemblerError::ExceededMaxLength { .. })));
+    }
+
+    #[test]
+    fn handles_raw_nibbles() {
+        let program = Program::from_assembly("OEN 0\n.raw \"F0 1\"\n.nibble \"c\"\nSTO 0");
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B0F01C80")));
+
+        let program = Program::from_assembly("OEN 0\n.raw \"F0G\"");
+        let bin = program.into_opcodes();
+        assert_eq!(
+            bin,
+            Err(AssemblerError::InvalidNibble {
+                character: 'G',
+                location: location(None, 2, 9, 14, ".raw \"F0G\"", 1),
+            })
+        );
+
+        let program = Program::from_assembly(".raw F0");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::ExpectedRawNibbles { .. })
+        ));
+    }
+
+    #[test]
+    fn handles_local_labels_in_macros() {
+        let program = Program::from_assembly(
+            ".macro wait\n.loop: SKZ\nJMP .loop\n.endm\nwait\nwait\n.loop: JMP .loop",
+        );
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("EC0EC3C6")));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn handles_local_labels_in_includes() {
+        let dir = scratch_dir(
+            "local-labels",
+            &[
+                ("main.s", "JMP .done\n.include \"lib.s\"\n.done: NOP"),
+                ("lib.s", ".done: SKZ\nJMP .done"),
+            ],
+        );
+
+        let program = Program::from_file(dir.join("main.s")).unwrap();
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("C5EC20")));
+    }
+
+    #[test]
+    fn handles_local_labels_out_of_scope() {
+        let program = Program::from_assembly(".loop: NOP\n.macro back\nJMP .loop\n.endm\nback");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::InMacro { source, .. })
+                if matches!(&*source, AssemblerError::UndefinedSymbol { name, .. } if name == ".loop")
+        ));
+
+        let program = Program::from_assembly(".loop: NOP\n.loop: NOP");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::DuplicateSymbol { name, .. }) if name == ".loop"
+        ));
+    }
+
+    #[test]
+    fn handles_relative_jumps() {
+        let program = Program::from_assembly("OEN 0\nSKZ\nJMP +4\nSTO 1\nLD 2\nJMP -0x8");
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B0EC78112C1")));
+    }
+
+    #[test]
+    fn handles_out_of_range_relative_jumps() {
+        let program = Program::from_assembly("OEN 0\nJMP -3");
+        let bin = program.into_opcodes();
+        assert_eq!(
+            bin,
+            Err(AssemblerError::JumpOutOfRange {
+                offset: -3,
+                address: 2,
+                location: location(None, 2, 5, 10, "JMP -3", 1),
+            })
+        );
+
+        // Only jumps are relative; anything else just gets a negative operand
+        let program = Program::from_assembly("STO -1");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::ExpressionOutOfRange { value: -1, .. })
+        ));
+    }
+
+    #[test]
+    fn handles_variables() {
+        let program = Program::from_assembly(
+            ".var last_state\n.var held, count\nLD 1\nSTO last_state\nSTO held\nLD count",
+        );
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("1188891A")));
+    }
+
+    #[test]
+    fn handles_exhausted_scratch_ram() {
+        let program = Program::from_assembly(".var a0, a1, a2, a3, a4, a5, a6, a7\n.var extra");
+        let bin = program.into_opcodes();
+        assert_eq!(
+            bin,
+            Err(AssemblerError::ScratchRamExhausted {
+                name: String::from("extra"),
+                location: location(None, 2, 6, 41, ".var extra", 5),
+            })
+        );
+    }
+
+    #[test]
+    fn handles_metadata() {
+        let program = Program::from_assembly(
+            ".name \"Blinker\"\n.author \"Nick\"\n.description \"Blinks a light\"\nLD 1",
+        );
+        let metadata = program.metadata().unwrap();
+        assert_eq!(metadata.name.as_deref(), Some("Blinker"));
+        assert_eq!(metadata.author.as_deref(), Some("Nick"));
+        assert_eq!(metadata.version, None);
+        assert_eq!(metadata.description.as_deref(), Some("Blinks a light"));
+        assert_eq!(program.into_opcodes(), Ok(String::from("11")));
+
+        let program = Program::from_assembly(".version \"1\"\n.version \"2\"");
+        assert_eq!(
+            program.into_opcodes(),
+            Err(AssemblerError::DuplicateMetadata {
+                directive: String::from("version"),
+                location: location(None, 2, 1, 13, ".version \"2\"", 8),
+            })
+        );
+
+        let program = Program::from_assembly(".name Blinker");
+        assert!(matches!(
+            program.into_opcodes(),
+            Err(AssemblerError::ExpectedString { .. })
+        ));
+    }
+
+    #[cfg(all(feature = "tracing", feature = "std"))]
+    #[test]
+    fn handles_tracing() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Mutex;
+
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata};
+
+        /// Remembers the name of every span and counts events, which is all the test needs.
+        #[derive(Default)]
+        struct Recorder {
+            spans: Mutex<Vec<&'static str>>,
+            events: AtomicU64,
+        }
+
+        impl tracing::Subscriber for Recorder {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut spans = self.spans.lock().unwrap();
+                spans.push(span.metadata().name());
+                Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+            fn event(&self, _event: &Event<'_>) {
+                self.events.fetch_add(1, Ordering::Relaxed);
+            }
+
+            fn enter(&self, _span: &Id) {}
+
+            fn exit(&self, _span: &Id) {}
+        }
+
+        let recorder = std::sync::Arc::new(Recorder::default());
+        tracing::subscriber::with_default(recorder.clone(), || {
+            Program::from_assembly("LD 1\nSTO 2").to_opcodes().unwrap();
+        });
+
+        assert_eq!(
+            *recorder.spans.lock().unwrap(),
+            ["assemble", "lex", "parse", "emit"]
+        );
+        // One for lexing, and one for each of the five tokens
+        assert_eq!(recorder.events.load(Ordering::Relaxed), 6);
+    }
+}
@@ -0,0 +1,180 @@
+//! `.if`/`.ifdef`/`.ifndef`, `.else` and `.endif`. Conditions are evaluated as soon as they're
+//! reached, so they can only see symbols defined earlier in the program.
+
+use super::{Assembler, Builtin, Origin};
+use crate::lexer::Token;
+use crate::prelude::*;
+use crate::{AssemblerError, Location};
+
+/// An `.if` block that's been entered but not yet closed.
+pub(super) struct Conditional {
+    seen_else: bool,
+    /// Where the block was opened, for reporting it if it's never closed
+    pub(super) origin: Origin,
+}
+
+impl Assembler {
+    pub(super) fn conditional(
+        &mut self,
+        directive: Builtin,
+        origin: Origin,
+    ) -> Result<(), AssemblerError> {
+        let condition = match directive {
+            Builtin::If => self.value(".if")? != 0,
+            Builtin::IfDefined | Builtin::IfNotDefined => {
+                let name = match self.next().map(|queued| queued.token) {
+                    Some(Token::Identifier(name)) => name,
+                    _ => {
+                        return Err(AssemblerError::ExpectedSymbolName {
+                            directive: directive.name().to_string(),
+                            location: Location::UNKNOWN,
+                        })
+                    }
+                };
+
+                self.symbols.contains_key(&name) == (directive == Builtin::IfDefined)
+            }
+            Builtin::Else => {
+                let conditional = self
+                    .conditionals
+                    .last_mut()
+                    .ok_or_else(|| unmatched(directive))?;
+
+                if conditional.seen_else {
+                    return Err(AssemblerError::DuplicateElse {
+                        location: Location::UNKNOWN,
+                    });
+                }
+
+                // Reaching an `.else` without skipping means the `.if` branch was taken
+                conditional.seen_else = true;
+                return self.skip_branch();
+            }
+            _ => {
+                // Anything else is an `.endif`
+                return self
+                    .conditionals
+                    .pop()
+                    .map(|_| ())
+                    .ok_or_else(|| unmatched(directive));
+            }
+        };
+
+        self.conditionals.push(Conditional {
+            seen_else: false,
+            origin,
+        });
+        if condition {
+            Ok(())
+        } else {
+            self.skip_branch()
+        }
+    }
+
+    /// Throws away tokens up to the `.else` or `.endif` that ends the innermost open conditional,
+    /// stepping over any conditionals nested inside it.
+    fn skip_branch(&mut self) -> Result<(), AssemblerError> {
+        let mut depth = 0;
+        while let Some(queued) = self.next() {
+            let directive = match &queued.token {
+                Token::Directive(name) => Builtin::from_name(name),
+                _ => None,
+            };
+
+            match directive {
+                Some(Builtin::If | Builtin::IfDefined | Builtin::IfNotDefined) => depth += 1,
+                Some(Builtin::EndIf) if depth > 0 => depth -= 1,
+                Some(Builtin::EndIf) => {
+                    self.conditionals.pop();
+                    return Ok(());
+                }
+                Some(Builtin::Else) if depth == 0 => {
+                    // `skip_branch` is only ever called with a conditional open
+                    let conditional = self.conditionals.last_mut().unwrap();
+                    if conditional.seen_else {
+                        return Err(queued.origin.locate(AssemblerError::DuplicateElse {
+                            location: Location::UNKNOWN,
+                        }));
+                    }
+
+                    conditional.seen_else = true;
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+
+        Err(AssemblerError::UnterminatedConditional {
+            location: Location::UNKNOWN,
+        })
+    }
+}
+
+fn unmatched(directive: Builtin) -> AssemblerError {
+    AssemblerError::UnmatchedDirective {
+        directive: directive.name().to_string(),
+        location: Location::UNKNOWN,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AssemblerError, Program};
+
+    #[test]
+    fn handles_if() {
+        let program = Program::from_assembly(
+            ".equ TWO_DOORS 1\nOEN 0\n.if TWO_DOORS\nSTO 3\nSTO 4\n.else\nSTO 3\n.endif",
+        );
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B08384")));
+    }
+
+    #[test]
+    fn handles_ifdef_else() {
+        let program =
+            Program::from_assembly("OEN 0\n.ifdef TWO_DOORS\nSTO 3\nSTO 4\n.else\nSTO 3\n.endif");
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B083")));
+    }
+
+    #[test]
+    fn handles_nested_conditionals() {
+        let program = Program::from_assembly(
+            ".equ MODE 1\n.ifndef MODE\n.if 1\nSTO 1\n.else\nSTO 2\n.endif\n.else\n.ifdef MODE\nSTO 3\n.endif\n.endif",
+        );
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("83")));
+    }
+
+    #[test]
+    fn handles_unterminated_conditionals() {
+        let program = Program::from_assembly(".if 0\nSTO 1");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::UnterminatedConditional { .. })
+        ));
+
+        let program = Program::from_assembly(".if 1\nSTO 1");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::UnterminatedConditional { .. })
+        ));
+    }
+
+    #[test]
+    fn handles_unmatched_conditionals() {
+        let program = Program::from_assembly("STO 1\n.endif");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::UnmatchedDirective { directive, .. }) if directive == "endif"
+        ));
+
+        let program = Program::from_assembly(".if 1\n.else\n.else\n.endif");
+        let bin = program.into_opcodes();
+        assert!(matches!(bin, Err(AssemblerError::DuplicateElse { .. })));
+    }
+}
@@ -0,0 +1,493 @@
+//! Directives, both built-in and those registered through
+//! [`AssemblerOptions::directive`](crate::AssemblerOptions::directive). Every directive runs as
+//! soon as it's reached, reading its arguments and emitting whatever it needs to through a
+//! [`DirectiveContext`].
+
+use core::fmt;
+
+use super::{Assembler, Origin, Queued, Slot, Symbol, SCRATCH_RAM};
+use crate::lexer::Token;
+use crate::prelude::*;
+use crate::{AssemblerError, Location};
+
+/// A directive like `.equ` or `.org`, invoked by writing its name after a `.`.
+///
+/// Directives are run while the program is being laid out, so they can define constants and emit
+/// nibbles, but can only see constants defined above them. An operand that needs a label further
+/// down can still be emitted with [`DirectiveContext::emit_expression`], which leaves working it
+/// out until the end.
+pub trait Directive {
+    /// The name the directive is invoked by, without the leading `.`. This is also the name
+    /// [`AssemblerOptions::allowed_directives`](crate::AssemblerOptions::allowed_directives)
+    /// checks for.
+    fn name(&self) -> &str;
+
+    /// Reads the directive's arguments from `context` and does whatever it does. Anything left on
+    /// the line afterwards is treated as the next statement.
+    fn run(&self, context: &mut DirectiveContext) -> Result<(), AssemblerError>;
+}
+
+/// What a [`Directive`] can see and do while it runs. Errors from any of these methods point at
+/// whatever was being read, or the directive itself otherwise.
+pub struct DirectiveContext<'a> {
+    assembler: &'a mut Assembler,
+    /// The name the directive was invoked by, which can be an alias
+    name: &'a str,
+    origin: &'a Origin,
+}
+
+/// The directives that are part of the language.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Builtin {
+    Equ,
+    Define,
+    Include,
+    Macro,
+    EndMacro,
+    If,
+    IfDefined,
+    IfNotDefined,
+    Else,
+    EndIf,
+    Repeat,
+    EndRepeat,
+    Org,
+    Raw,
+    Var,
+    Name,
+    Author,
+    Version,
+    Description,
+    Assert,
+}
+
+impl<'a> DirectiveContext<'a> {
+    pub(super) fn new(assembler: &'a mut Assembler, name: &'a str, origin: &'a Origin) -> Self {
+        Self {
+            assembler,
+            name,
+            origin,
+        }
+    }
+
+    /// The address the next nibble will be emitted at.
+    pub fn address(&self) -> usize {
+        self.assembler.slots.len()
+    }
+
+    /// Adds a nibble to the program, which has to be between `0x0` and `0xF`.
+    pub fn emit(&mut self, nibble: u8) -> Result<(), AssemblerError> {
+        if nibble > 0xF {
+            return Err(AssemblerError::OperandOutOfRange {
+                value: nibble.into(),
+                location: Location::UNKNOWN,
+            });
+        }
+
+        self.assembler.emit(Slot::Nibble(nibble), self.origin);
+        Ok(())
+    }
+
+    /// Reads a value that has to be known right away, which can be an expression using constants
+    /// defined earlier.
+    pub fn value(&mut self) -> Result<usize, AssemblerError> {
+        self.assembler.value(&format!(".{}", self.name))
+    }
+
+    /// Reads an operand, the same as an instruction's, and adds it to the program. Unlike
+    /// [`DirectiveContext::value`], it can use labels defined further down, since it's only
+    /// worked out once the whole program has been laid out. It has to come to between `0x0` and
+    /// `0xF`.
+    pub fn emit_expression(&mut self) -> Result<(), AssemblerError> {
+        self.assembler.expression_operand()
+    }
+
+    /// Reads a name, like the name of a constant to define.
+    pub fn symbol_name(&mut self) -> Result<String, AssemblerError> {
+        self.located_symbol_name().map(|(name, _)| name)
+    }
+
+    /// Like [`DirectiveContext::symbol_name`], along with where the name is written.
+    fn located_symbol_name(&mut self) -> Result<(String, Origin), AssemblerError> {
+        match self.assembler.next() {
+            Some(Queued {
+                token: Token::Identifier(name),
+                origin,
+            }) => Ok((name, origin)),
+            _ => Err(AssemblerError::ExpectedSymbolName {
+                directive: self.name.to_string(),
+                location: Location::UNKNOWN,
+            }),
+        }
+    }
+
+    /// Reads a quoted string.
+    pub fn string(&mut self) -> Result<String, AssemblerError> {
+        match self.assembler.next().map(|queued| queued.token) {
+            Some(Token::String(string)) => Ok(string),
+            _ => Err(AssemblerError::ExpectedString {
+                directive: self.name.to_string(),
+                location: Location::UNKNOWN,
+            }),
+        }
+    }
+
+    /// Skips over a comma if there's one next, for reading lists of arguments.
+    pub fn next_if_comma(&mut self) -> bool {
+        self.assembler.next_if_eq(&Token::Comma)
+    }
+
+    /// Whether there's nothing left on the line to read.
+    pub fn at_line_end(&self) -> bool {
+        matches!(self.assembler.peek(), Some(Token::Newline) | None)
+    }
+
+    /// Looks up a constant defined earlier in the program.
+    pub fn constant(&self, name: &str) -> Option<usize> {
+        match self.assembler.symbols.get(name)?.symbol {
+            Symbol::Constant(value) => Some(value),
+            Symbol::Label(_) => None,
+        }
+    }
+
+    /// Defines a constant, as if with `.equ`.
+    pub fn define_constant(
+        &mut self,
+        name: impl Into<String>,
+        value: usize,
+    ) -> Result<(), AssemblerError> {
+        self.assembler
+            .define_symbol(name.into(), Symbol::Constant(value), self.origin.clone())
+    }
+
+    /// An error for anything else that's wrong with how the directive was used.
+    pub fn error(&self, message: impl Into<String>) -> AssemblerError {
+        AssemblerError::DirectiveFailed {
+            name: self.name.to_string(),
+            message: message.into().into_boxed_str(),
+            location: Location::UNKNOWN,
+        }
+    }
+}
+
+/// The built-in directives that don't need anything from the assembler beyond what a custom one
+/// could do.
+impl DirectiveContext<'_> {
+    /// Handles `.equ NAME VALUE`, and `.define`, which is the same.
+    fn equ(&mut self) -> Result<(), AssemblerError> {
+        let (name, origin) = self.located_symbol_name()?;
+        let value = self.assembler.value(&name)?;
+        self.assembler
+            .define_symbol(name, Symbol::Constant(value), origin)
+    }
+
+    /// Handles `.var NAME, NAME...`, giving each name the next free scratch RAM address. Only
+    /// addresses handed out by `.var` are tracked, so mixing it with hardcoded scratch addresses
+    /// is best avoided.
+    fn var(&mut self) -> Result<(), AssemblerError> {
+        loop {
+            let (name, origin) = self.located_symbol_name()?;
+            let address = self.assembler.next_variable;
+            if !SCRATCH_RAM.contains(&address) {
+                let error = AssemblerError::ScratchRamExhausted {
+                    name,
+                    location: Location::UNKNOWN,
+                };
+
+                return Err(origin.locate(error));
+            }
+
+            self.assembler.next_variable += 1;
+            self.assembler
+                .define_symbol(name, Symbol::Constant(address), origin)?;
+
+            if !self.next_if_comma() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Handles `.name "..."` and the other directives that describe the program, each of which
+    /// can only be given once.
+    fn metadata(&mut self, directive: Builtin) -> Result<(), AssemblerError> {
+        let value = self.string()?;
+        let metadata = &mut self.assembler.metadata;
+        let field = match directive {
+            Builtin::Name => &mut metadata.name,
+            Builtin::Author => &mut metadata.author,
+            Builtin::Version => &mut metadata.version,
+            _ => &mut metadata.description,
+        };
+
+        if field.is_some() {
+            return Err(AssemblerError::DuplicateMetadata {
+                directive: self.name.to_string(),
+                location: Location::UNKNOWN,
+            });
+        }
+
+        *field = Some(value);
+        Ok(())
+    }
+
+    /// Handles `.org ADDRESS`, padding the program with `NOP`s up to the address, so that
+    /// whatever follows always starts there regardless of what comes before it.
+    fn org(&mut self) -> Result<(), AssemblerError> {
+        let address = self.value()?;
+        let current = self.address();
+        if address < current {
+            return Err(AssemblerError::OrgBehind {
+                address,
+                current,
+                location: Location::UNKNOWN,
+            });
+        }
+
+        if address > self.assembler.options.max_length {
+            return Err(AssemblerError::ExceededMaxLength {
+                location: Location::UNKNOWN,
+            });
+        }
+
+        for _ in current..address {
+            self.emit(0x0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Handles `.raw "..."`, emitting the hex digits of the string as-is, for opcodes that can't
+    /// be written as instructions. Spaces are ignored so long runs of nibbles can be grouped.
+    fn raw(&mut self) -> Result<(), AssemblerError> {
+        let Some(Queued {
+            token: Token::String(nibbles),
+            origin,
+        }) = self.assembler.next()
+        else {
+            return Err(AssemblerError::ExpectedRawNibbles {
+                location: Location::UNKNOWN,
+            });
+        };
+
+        for (index, character) in nibbles.char_indices() {
+            if character == ' ' {
+                continue;
+            }
+
+            // Point at the offending digit rather than the whole string, skipping the opening quote
+            let start = origin.span.start + 1 + index;
+            let digit = Origin {
+                span: start..start + character.len_utf8(),
+                ..origin.clone()
+            };
+
+            let nibble = character.to_digit(16).ok_or_else(|| {
+                digit.locate(AssemblerError::InvalidNibble {
+                    character,
+                    location: Location::UNKNOWN,
+                })
+            })?;
+
+            self.assembler.emit(Slot::Nibble(nibble as u8), &digit);
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for DirectiveContext<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DirectiveContext")
+            .field("name", &self.name)
+            .field("address", &self.address())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Builtin {
+    pub(super) fn from_name(name: &str) -> Option<Self> {
+        let directive = match name {
+            "equ" => Self::Equ,
+            "define" => Self::Define,
+            "include" => Self::Include,
+            "macro" => Self::Macro,
+            "endm" => Self::EndMacro,
+            "if" => Self::If,
+            "ifdef" => Self::IfDefined,
+            "ifndef" => Self::IfNotDefined,
+            "else" => Self::Else,
+            "endif" => Self::EndIf,
+            "rept" => Self::Repeat,
+            "endr" => Self::EndRepeat,
+            "org" => Self::Org,
+            "raw" | "nibble" => Self::Raw,
+            "var" => Self::Var,
+            "name" => Self::Name,
+            "author" => Self::Author,
+            "version" => Self::Version,
+            "description" => Self::Description,
+            "assert" => Self::Assert,
+            _ => return None,
+        };
+
+        Some(directive)
+    }
+
+    pub(super) fn name(self) -> &'static str {
+        match self {
+            Self::Equ => "equ",
+            Self::Define => "define",
+            Self::Include => "include",
+            Self::Macro => "macro",
+            Self::EndMacro => "endm",
+            Self::If => "if",
+            Self::IfDefined => "ifdef",
+            Self::IfNotDefined => "ifndef",
+            Self::Else => "else",
+            Self::EndIf => "endif",
+            Self::Repeat => "rept",
+            Self::EndRepeat => "endr",
+            Self::Org => "org",
+            Self::Raw => "raw",
+            Self::Var => "var",
+            Self::Name => "name",
+            Self::Author => "author",
+            Self::Version => "version",
+            Self::Description => "description",
+            Self::Assert => "assert",
+        }
+    }
+}
+
+impl Directive for Builtin {
+    fn name(&self) -> &str {
+        Builtin::name(*self)
+    }
+
+    fn run(&self, context: &mut DirectiveContext) -> Result<(), AssemblerError> {
+        let origin = context.origin;
+        match self {
+            Self::Equ | Self::Define => context.equ(),
+            Self::Var => context.var(),
+            Self::Name | Self::Author | Self::Version | Self::Description => {
+                context.metadata(*self)
+            }
+            Self::Org => context.org(),
+            Self::Raw => context.raw(),
+            Self::Include => context.assembler.include(origin.clone()),
+            Self::Macro => context.assembler.define_macro(),
+            Self::EndMacro | Self::EndRepeat => Err(AssemblerError::UnmatchedDirective {
+                directive: context.name.to_string(),
+                location: Location::UNKNOWN,
+            }),
+            Self::Repeat => context.assembler.repeat(),
+            Self::Assert => context.assembler.assertion(origin),
+            Self::If | Self::IfDefined | Self::IfNotDefined | Self::Else | Self::EndIf => {
+                context.assembler.conditional(*self, origin.clone())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Directive, DirectiveContext};
+    use crate::{AssemblerError, AssemblerOptions, Program};
+
+    /// `.pins NAME, NAME...`, numbering inputs from 1 like the in-game component does
+    struct Pins;
+
+    impl Directive for Pins {
+        fn name(&self) -> &str {
+            "pins"
+        }
+
+        fn run(&self, context: &mut DirectiveContext) -> Result<(), AssemblerError> {
+            let mut pin = 1;
+            loop {
+                let name = context.symbol_name()?;
+                if pin > 7 {
+                    return Err(context.error(format!("no pin left for `{name}`")));
+                }
+
+                context.define_constant(name, pin)?;
+                pin += 1;
+
+                if !context.next_if_comma() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// `.halt`, which jumps to itself forever
+    struct Halt;
+
+    impl Directive for Halt {
+        fn name(&self) -> &str {
+            "halt"
+        }
+
+        fn run(&self, context: &mut DirectiveContext) -> Result<(), AssemblerError> {
+            let address = context.address();
+            context.emit(0xC)?;
+            context.emit(address as u8)
+        }
+    }
+
+    /// `.goto TARGET`, another way of writing `JMP TARGET`
+    struct Goto;
+
+    impl Directive for Goto {
+        fn name(&self) -> &str {
+            "goto"
+        }
+
+        fn run(&self, context: &mut DirectiveContext) -> Result<(), AssemblerError> {
+            context.emit(0xC)?;
+            context.emit_expression()
+        }
+    }
+
+    #[test]
+    fn handles_custom_directives() {
+        let options = AssemblerOptions::new().directive(Pins).directive(Halt);
+        let program =
+            Program::from_assembly_with(".pins DOOR, BUTTON\nLD BUTTON\nSTO DOOR\n.halt", options);
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("1281C4")));
+
+        // Operands are worked out at the end, so they can use labels that come later
+        let options = AssemblerOptions::new().directive(Goto);
+        let program = Program::from_assembly_with(".goto end + 1\nNOP\nend: NOP\nRTN", options);
+        assert_eq!(program.into_opcodes(), Ok(String::from("C400D")));
+    }
+
+    #[test]
+    fn handles_custom_directive_errors() {
+        let options = AssemblerOptions::new().directive(Pins);
+        let program = Program::from_assembly_with(".pins P1, P2, P3, P4, P5, P6, P7, P8", options);
+        let bin = program.into_opcodes();
+        assert_eq!(
+            bin.unwrap_err().to_string(),
+            "1:1: `.pins`: no pin left for `P8`"
+        );
+
+        let options = AssemblerOptions::new().directive(Goto);
+        let program = Program::from_assembly_with(".goto far\n.org 16\nfar: RTN", options);
+        assert!(matches!(
+            program.into_opcodes(),
+            Err(AssemblerError::SymbolOutOfRange { name, value: 16, .. }) if name == "far"
+        ));
+
+        let options = AssemblerOptions::new()
+            .directive(Halt)
+            .allowed_directives(["equ"]);
+        let program = Program::from_assembly_with(".halt", options);
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::DirectiveNotAllowed { name, .. }) if name == "halt"
+        ));
+    }
+}
@@ -0,0 +1,338 @@
+//! Arithmetic in operands and directive values, like `STO BASE+1` or `.rept (PINS|1)`.
+//! Expressions are parsed as soon as they're reached, but operands are only evaluated once every
+//! symbol has a value, so they can refer to labels further down the program.
+
+use super::{unexpected, Assembler, Origin};
+use crate::lexer::Token;
+use crate::prelude::*;
+use crate::{AssemblerError, Location};
+
+/// A parsed expression. Values are signed and much wider than a nibble, so intermediate results
+/// like `BASE-1` don't need to fit in an operand; only the final value does.
+pub(super) enum Expression {
+    Number(i64),
+    Symbol {
+        name: String,
+        origin: Origin,
+    },
+    Negate(Box<Expression>),
+    Not(Box<Expression>),
+    Binary {
+        operator: Operator,
+        left: Box<Expression>,
+        right: Box<Expression>,
+        /// Where the operator is, for reporting a division by zero or an overflow
+        origin: Origin,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    And,
+    Or,
+    Xor,
+    ShiftLeft,
+    ShiftRight,
+}
+
+/// How many numbers, symbols, signs and parentheses an expression can be made of. Parsing and
+/// evaluating both recurse, so without a limit a long enough expression would overflow the stack.
+const MAX_TERMS: usize = 256;
+
+impl Assembler {
+    /// Parses an expression, calling `missing` for the error to give if there isn't one.
+    pub(super) fn expression(
+        &mut self,
+        missing: &impl Fn() -> AssemblerError,
+    ) -> Result<Expression, AssemblerError> {
+        self.binary(0, &mut 0, missing)
+    }
+
+    /// Precedence climbing: reads operators that bind at least as tightly as `min_precedence`,
+    /// leaving looser ones for the caller.
+    fn binary(
+        &mut self,
+        min_precedence: u8,
+        terms: &mut usize,
+        missing: &impl Fn() -> AssemblerError,
+    ) -> Result<Expression, AssemblerError> {
+        let mut left = self.unary(terms, missing)?;
+
+        while let Some(operator) = self.peek().and_then(Operator::from_token) {
+            let precedence = operator.precedence();
+            if precedence < min_precedence {
+                break;
+            }
+
+            let origin = self.next().unwrap().origin;
+            let right = self.binary(precedence + 1, terms, missing)?;
+            left = Expression::Binary {
+                operator,
+                left: Box::new(left),
+                right: Box::new(right),
+                origin,
+            };
+        }
+
+        Ok(left)
+    }
+
+    fn unary(
+        &mut self,
+        terms: &mut usize,
+        missing: &impl Fn() -> AssemblerError,
+    ) -> Result<Expression, AssemblerError> {
+        // Leave the end of the line for the statement after this one
+        let queued = match self.peek() {
+            Some(Token::Newline) | None => return Err(missing()),
+            Some(_) => self.next().unwrap(),
+        };
+
+        *terms += 1;
+        if *terms > MAX_TERMS {
+            return Err(queued.origin.locate(AssemblerError::ExpressionTooComplex {
+                location: Location::UNKNOWN,
+            }));
+        }
+
+        let expression = match queued.token {
+            Token::Operand(value) => Expression::Number(value.into()),
+            Token::Number(value) => Expression::Number(i64::try_from(value).map_err(|_| {
+                queued.origin.locate(AssemblerError::ArithmeticOverflow {
+                    location: Location::UNKNOWN,
+                })
+            })?),
+            Token::Identifier(name) => Expression::Symbol {
+                name,
+                origin: queued.origin,
+            },
+            Token::Directive(name) => Expression::Symbol {
+                name: format!(".{name}"),
+                origin: queued.origin,
+            },
+            Token::Plus => self.unary(terms, missing)?,
+            Token::Minus => Expression::Negate(Box::new(self.unary(terms, missing)?)),
+            Token::Tilde => Expression::Not(Box::new(self.unary(terms, missing)?)),
+            Token::LeftParen => {
+                let inner = self.binary(0, terms, missing)?;
+                if !self.next_if_eq(&Token::RightParen) {
+                    return Err(queued.origin.locate(AssemblerError::UnclosedParenthesis {
+                        location: Location::UNKNOWN,
+                    }));
+                }
+
+                inner
+            }
+            Token::Unknown(text) => return Err(queued.origin.locate(unexpected(text))),
+            Token::MissingSeparator(text) => {
+                return Err(queued.origin.locate(AssemblerError::MissingSeparator {
+                    text,
+                    location: Location::UNKNOWN,
+                }))
+            }
+            _ => return Err(queued.origin.locate(missing())),
+        };
+
+        Ok(expression)
+    }
+}
+
+impl Expression {
+    /// Works out the value of the expression, using `lookup` for the value of each symbol.
+    pub(super) fn evaluate(
+        &self,
+        lookup: &impl Fn(&str, &Origin) -> Result<i64, AssemblerError>,
+    ) -> Result<i64, AssemblerError> {
+        let overflow = || AssemblerError::ArithmeticOverflow {
+            location: Location::UNKNOWN,
+        };
+
+        match self {
+            Self::Number(value) => Ok(*value),
+            Self::Symbol { name, origin } => {
+                lookup(name, origin).map_err(|error| origin.locate(error))
+            }
+            Self::Negate(inner) => inner.evaluate(lookup)?.checked_neg().ok_or_else(overflow),
+            Self::Not(inner) => Ok(!inner.evaluate(lookup)?),
+            Self::Binary {
+                operator,
+                left,
+                right,
+                origin,
+            } => {
+                let left = left.evaluate(lookup)?;
+                let right = right.evaluate(lookup)?;
+
+                if matches!(operator, Operator::Divide | Operator::Remainder) && right == 0 {
+                    return Err(origin.locate(AssemblerError::DivisionByZero {
+                        location: Location::UNKNOWN,
+                    }));
+                }
+
+                operator
+                    .apply(left, right)
+                    .ok_or_else(|| origin.locate(overflow()))
+            }
+        }
+    }
+
+    /// Calls `f` with every symbol the expression refers to.
+    pub(super) fn symbols(&self, f: &mut impl FnMut(&str, &Origin)) {
+        match self {
+            Self::Number(_) => {}
+            Self::Symbol { name, origin } => f(name, origin),
+            Self::Negate(inner) | Self::Not(inner) => inner.symbols(f),
+            Self::Binary { left, right, .. } => {
+                left.symbols(f);
+                right.symbols(f);
+            }
+        }
+    }
+}
+
+impl Operator {
+    fn from_token(token: &Token) -> Option<Self> {
+        let operator = match token {
+            Token::Plus => Self::Add,
+            Token::Minus => Self::Subtract,
+            Token::Star => Self::Multiply,
+            Token::Slash => Self::Divide,
+            Token::Percent => Self::Remainder,
+            Token::Ampersand => Self::And,
+            Token::Pipe => Self::Or,
+            Token::Caret => Self::Xor,
+            Token::ShiftLeft => Self::ShiftLeft,
+            Token::ShiftRight => Self::ShiftRight,
+            _ => return None,
+        };
+
+        Some(operator)
+    }
+
+    /// Same order as C, from `|` binding loosest up to `*`, `/` and `%` binding tightest.
+    fn precedence(self) -> u8 {
+        match self {
+            Self::Or => 1,
+            Self::Xor => 2,
+            Self::And => 3,
+            Self::ShiftLeft | Self::ShiftRight => 4,
+            Self::Add | Self::Subtract => 5,
+            Self::Multiply | Self::Divide | Self::Remainder => 6,
+        }
+    }
+
+    /// `None` if the result doesn't fit.
+    fn apply(self, left: i64, right: i64) -> Option<i64> {
+        match self {
+            Self::Add => left.checked_add(right),
+            Self::Subtract => left.checked_sub(right),
+            Self::Multiply => left.checked_mul(right),
+            Self::Divide => left.checked_div(right),
+            Self::Remainder => left.checked_rem(right),
+            Self::And => Some(left & right),
+            Self::Or => Some(left | right),
+            Self::Xor => Some(left ^ right),
+            Self::ShiftLeft => left.checked_shl(u32::try_from(right).ok()?),
+            Self::ShiftRight => left.checked_shr(u32::try_from(right).ok()?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AssemblerError, Program};
+
+    #[test]
+    fn handles_expressions() {
+        let program = Program::from_assembly(
+            ".equ BASE 2\n.equ PINS 4\nOEN 0\nSTO BASE+1\nLD (PINS|1)\nSTO 2*BASE+PINS>>1\nSTOC ~BASE&0xF",
+        );
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B08315849D")));
+    }
+
+    #[test]
+    fn handles_expressions_with_labels() {
+        let program = Program::from_assembly("JMP end-1\nNOP\nSKZ\nend: NOP");
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("C30E0")));
+    }
+
+    #[test]
+    fn handles_expressions_in_directives() {
+        let program =
+            Program::from_assembly(".equ PINS 2\n.equ LAST PINS-1\n.rept PINS*2\nSTO LAST\n.endr");
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("81818181")));
+    }
+
+    #[test]
+    fn handles_out_of_range_expressions() {
+        let program = Program::from_assembly(".equ BASE 0xF\nSTO BASE+1");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::ExpressionOutOfRange { value: 16, .. })
+        ));
+
+        let program = Program::from_assembly("STO 1-2");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::ExpressionOutOfRange { value: -1, .. })
+        ));
+
+        let program = Program::from_assembly(".equ NEGATIVE 1-2");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::NegativeValue { value: -1, .. })
+        ));
+    }
+
+    #[test]
+    fn handles_malformed_expressions() {
+        let program = Program::from_assembly("STO 4/(2-2)");
+        let bin = program.into_opcodes();
+        assert!(
+            matches!(bin, Err(AssemblerError::DivisionByZero { location }) if location.column == 6)
+        );
+
+        let program = Program::from_assembly("STO (1+2\nNOP");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::UnclosedParenthesis { .. })
+        ));
+
+        let program = Program::from_assembly(&format!("STO {}1", "(".repeat(100_000)));
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::ExpressionTooComplex { location }) if location.column == 261
+        ));
+
+        let program = Program::from_assembly(&format!("STO 1{}", "+1".repeat(100_000)));
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::ExpressionTooComplex { .. })
+        ));
+
+        let program = Program::from_assembly("STO 1+\nNOP");
+        let bin = program.into_opcodes();
+        assert!(matches!(bin, Err(AssemblerError::ExpectedOperand { .. })));
+
+        let program = Program::from_assembly("STO 1<<64");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::ArithmeticOverflow { .. })
+        ));
+    }
+}
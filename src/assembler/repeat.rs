@@ -0,0 +1,105 @@
+//! `.rept N` and `.endr`, which paste their body into the program `N` times.
+
+use super::{Assembler, Builtin};
+use crate::lexer::Token;
+use crate::prelude::*;
+use crate::{AssemblerError, Location};
+
+impl Assembler {
+    pub(super) fn repeat(&mut self) -> Result<(), AssemblerError> {
+        let count = self.value(".rept")?;
+
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let queued = self.next().ok_or(AssemblerError::UnterminatedRepeat {
+                location: Location::UNKNOWN,
+            })?;
+
+            let directive = match &queued.token {
+                Token::Directive(name) => Builtin::from_name(name),
+                _ => None,
+            };
+
+            match directive {
+                Some(Builtin::Repeat) => depth += 1,
+                Some(Builtin::EndRepeat) if depth == 0 => break,
+                Some(Builtin::EndRepeat) => depth -= 1,
+                _ => {}
+            }
+
+            body.push(queued);
+        }
+
+        // Nearly every statement emits at least one nibble, so there's no point building an
+        // expansion that's bound to be too long (and possibly huge)
+        if count > self.options.max_length
+            && body.iter().any(|queued| queued.token != Token::Newline)
+        {
+            return Err(AssemblerError::ExceededMaxLength {
+                location: Location::UNKNOWN,
+            });
+        }
+
+        for _ in 0..count {
+            self.pending.extend(body.iter().rev().cloned());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AssemblerError, Program};
+
+    #[test]
+    fn handles_repeats() {
+        let program = Program::from_assembly("OEN 0\n.rept 3\nNOP\n.endr\nSTO 1");
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B000081")));
+    }
+
+    #[test]
+    fn handles_nested_repeats() {
+        let program =
+            Program::from_assembly(".equ PINS 2\n.rept PINS\nLD 1\n.rept 2\nSKZ\n.endr\n.endr");
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("11EE11EE")));
+    }
+
+    #[test]
+    fn handles_empty_repeats() {
+        let program = Program::from_assembly("NOP\n.rept 0\nSTO 1\n.endr");
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("0")));
+    }
+
+    #[test]
+    fn handles_repeats_before_length_check() {
+        let program = Program::from_assembly(".rept 65\nSTO 1\n.endr");
+        let bin = program.into_opcodes();
+        assert!(matches!(bin, Err(AssemblerError::ExceededMaxLength { .. })));
+
+        let program = Program::from_assembly(".rept 1000000\nNOP\n.endr");
+        let bin = program.into_opcodes();
+        assert!(matches!(bin, Err(AssemblerError::ExceededMaxLength { .. })));
+    }
+
+    #[test]
+    fn handles_unterminated_repeats() {
+        let program = Program::from_assembly(".rept 2\nNOP");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::UnterminatedRepeat { .. })
+        ));
+
+        let program = Program::from_assembly("NOP\n.endr");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::UnmatchedDirective { directive, .. }) if directive == "endr"
+        ));
+    }
+}
@@ -0,0 +1,209 @@
+//! The files a program is read from. Each one gets a [`SourceId`] when it's read in, and
+//! remembers the `.include` that brought it in, so a diagnostic anywhere can be traced back
+//! through every include and macro expansion that led to it.
+//!
+//! As well as files on disk, programs can include the standard library that's built into the
+//! assembler with `.include <std/NAME>`. Files on disk need the `std` feature; without it only the
+//! standard library can be included.
+
+use alloc::rc::Rc;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+use logos::Span;
+
+use super::Origin;
+use crate::normalize::normalize;
+use crate::prelude::*;
+use crate::preprocessor;
+use crate::{AssemblerError, AssemblerOptions, FilePath, Location};
+
+/// Macros for common circuits, embedded so they're always available.
+const LIBRARY: &[(&str, &str)] = &[
+    ("std/debounce", include_str!("../std/debounce.s")),
+    ("std/edge", include_str!("../std/edge.s")),
+    ("std/latch", include_str!("../std/latch.s")),
+    ("std/toggle", include_str!("../std/toggle.s")),
+];
+
+/// Identifies a file in a [`FileMap`]. The root source is always the first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(super) struct SourceId(usize);
+
+/// Every file read while assembling a program, in the order they were read.
+#[derive(Default)]
+pub(super) struct FileMap {
+    files: Vec<Rc<SourceFile>>,
+}
+
+/// Source text that tokens were read from.
+pub(super) struct SourceFile {
+    pub(super) id: SourceId,
+    /// `None` for source passed to [`crate::Program::from_assembly`]
+    pub(super) path: Option<FilePath>,
+    canonical: Option<FilePath>,
+    pub(super) text: String,
+    /// The `.include` that read this file in, or `None` for the root source
+    pub(super) included_from: Option<Origin>,
+}
+
+impl FileMap {
+    /// Adds the root source, which has to come before any includes.
+    pub(super) fn add_root(&mut self, text: &str, path: Option<FilePath>) -> SourceFile {
+        debug_assert!(self.files.is_empty());
+
+        #[cfg(feature = "std")]
+        let canonical = path
+            .as_ref()
+            .and_then(|path| fs::canonicalize(path.as_path()).ok())
+            .map(FilePath::from);
+        #[cfg(not(feature = "std"))]
+        let canonical = path.clone();
+        SourceFile {
+            id: self.next_id(),
+            path,
+            canonical,
+            text: normalize(text),
+            included_from: None,
+        }
+    }
+
+    /// Reads the file named by an `.include`, making sure it isn't already being included further
+    /// up the chain. Paths are relative to the file doing the including, or the working directory
+    /// if there isn't one. The file is only added once it's been preprocessed, with
+    /// [`FileMap::insert`].
+    #[cfg(feature = "std")]
+    pub(super) fn open(
+        &self,
+        path: &str,
+        included_from: Origin,
+    ) -> Result<SourceFile, AssemblerError> {
+        let path = match &included_from.file.path {
+            Some(including) => including
+                .as_path()
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(path),
+            None => PathBuf::from(path),
+        };
+
+        let name = FilePath::from(path.as_path());
+        let include_failed = |error: std::io::Error| AssemblerError::IncludeFailed {
+            path: name.clone(),
+            reason: error.to_string().into(),
+            location: Location::UNKNOWN,
+        };
+
+        let canonical = FilePath::from(fs::canonicalize(&path).map_err(include_failed)?);
+        check_circular(&name, &canonical, &included_from)?;
+        let text = normalize(&fs::read_to_string(&path).map_err(include_failed)?);
+
+        Ok(SourceFile {
+            id: self.next_id(),
+            path: Some(name),
+            canonical: Some(canonical),
+            text,
+            included_from: Some(included_from),
+        })
+    }
+
+    /// Without a filesystem there's nothing to read, so every include of a file fails.
+    #[cfg(not(feature = "std"))]
+    pub(super) fn open(&self, path: &str, _: Origin) -> Result<SourceFile, AssemblerError> {
+        Err(AssemblerError::IncludeFailed {
+            path: FilePath::from(path),
+            reason: "including files needs the `std` feature".into(),
+            location: Location::UNKNOWN,
+        })
+    }
+
+    /// Looks up a standard library file named by `.include <NAME>`. It's given the path
+    /// `<NAME>` for diagnostics, which can't clash with a real file.
+    pub(super) fn open_library(
+        &self,
+        name: &str,
+        included_from: Origin,
+    ) -> Result<SourceFile, AssemblerError> {
+        let text = LIBRARY
+            .iter()
+            .find_map(|(file, text)| (*file == name).then_some(*text))
+            .ok_or_else(|| AssemblerError::UnknownLibraryFile {
+                name: name.to_string(),
+                location: Location::UNKNOWN,
+            })?;
+
+        let path = FilePath::from(format!("<{name}>"));
+        check_circular(&path, &path, &included_from)?;
+
+        Ok(SourceFile {
+            id: self.next_id(),
+            path: Some(path.clone()),
+            canonical: Some(path),
+            text: normalize(text),
+            included_from: Some(included_from),
+        })
+    }
+
+    pub(super) fn insert(&mut self, file: SourceFile) -> Rc<SourceFile> {
+        debug_assert_eq!(file.id, self.next_id());
+
+        let file = Rc::new(file);
+        self.files.push(file.clone());
+        file
+    }
+
+    fn next_id(&self) -> SourceId {
+        SourceId(self.files.len())
+    }
+}
+
+/// Makes sure a file isn't already being included further up the chain.
+fn check_circular(
+    path: &FilePath,
+    canonical: &FilePath,
+    included_from: &Origin,
+) -> Result<(), AssemblerError> {
+    let mut ancestor = Some(&*included_from.file);
+    while let Some(file) = ancestor {
+        if file.canonical.as_ref() == Some(canonical) {
+            return Err(AssemblerError::CircularInclude {
+                path: path.clone(),
+                location: Location::UNKNOWN,
+            });
+        }
+
+        ancestor = file.included_from.as_ref().map(|origin| &*origin.file);
+    }
+
+    Ok(())
+}
+
+impl SourceFile {
+    /// Runs the preprocessor over the file's text. Defines are per-file, so the only ones that
+    /// carry over into included files are those from the options.
+    pub(super) fn preprocess(mut self, options: &AssemblerOptions) -> Result<Self, AssemblerError> {
+        self.text =
+            preprocessor::preprocess(&self.text, &options.defines, |span| self.location(&span))?;
+
+        Ok(self)
+    }
+
+    pub(super) fn location(&self, span: &Span) -> Location {
+        let before = &self.text[..span.start];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        let line_end = self.text[span.start..]
+            .find('\n')
+            .map_or(self.text.len(), |index| span.start + index);
+
+        Location {
+            file: self.path.clone(),
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            offset: span.start,
+            snippet: self.text[line_start..line_end].trim_end().to_string(),
+            length: span.len(),
+        }
+    }
+}
@@ -0,0 +1,505 @@
+//! A syntax tree of source as it's written, for formatters, lints and optimizers that work on the
+//! source rather than what it assembles to. Nothing is expanded or read in: macro invocations,
+//! `.include`s and preprocessor lines stay exactly where they are, and operands are kept as the
+//! text they're written as.
+
+use core::fmt;
+use core::ops::Range;
+
+use crate::prelude::*;
+use crate::{tokenize, Program, Token};
+
+/// Every statement in a file, in order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Ast {
+    pub nodes: Vec<Node>,
+}
+
+/// A statement along with where it is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node {
+    pub statement: Statement,
+    /// The bytes of the source it was parsed from. Nodes added by a [`Rewriter`] can use any
+    /// span, since it's only for reporting.
+    pub span: Range<usize>,
+    /// The 1-based line it starts on. Nodes with the same line are written out on the same line.
+    pub line: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Statement {
+    /// `name:`, where local labels keep their leading dot
+    Label { name: String },
+    Instruction {
+        mnemonic: &'static str,
+        /// `None` if an instruction that takes an operand is missing it
+        operand: Option<Argument>,
+    },
+    /// `.name` and its arguments, with the name not including the dot
+    Directive {
+        name: String,
+        arguments: Vec<Argument>,
+    },
+    /// A name that isn't a label, which is (or should be) a macro
+    MacroCall {
+        name: String,
+        arguments: Vec<Argument>,
+    },
+    /// A comment as it's written, including its `;`, `//`, `#` or `/*`. Preprocessor lines like
+    /// `#define` are comments too.
+    Comment { text: String, doc: bool },
+    /// Text that doesn't start a statement
+    Unknown { text: String },
+}
+
+/// An operand or argument, as the text it's written as.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Argument {
+    pub text: String,
+    pub span: Range<usize>,
+    /// Whether there's a comma between this and the next argument, rather than just a space
+    pub comma: bool,
+}
+
+/// Looks at every statement in an [`Ast`], through [`Ast::visit`]. Every method does nothing by
+/// default, so visitors only need to implement the ones they care about.
+pub trait Visitor {
+    fn visit_label(&mut self, _name: &str, _span: &Range<usize>) {}
+
+    fn visit_instruction(
+        &mut self,
+        _mnemonic: &'static str,
+        _operand: Option<&Argument>,
+        _span: &Range<usize>,
+    ) {
+    }
+
+    fn visit_directive(&mut self, _name: &str, _arguments: &[Argument], _span: &Range<usize>) {}
+
+    fn visit_macro_call(&mut self, _name: &str, _arguments: &[Argument], _span: &Range<usize>) {}
+
+    fn visit_comment(&mut self, _text: &str, _doc: bool, _span: &Range<usize>) {}
+
+    fn visit_unknown(&mut self, _text: &str, _span: &Range<usize>) {}
+}
+
+/// Changes the statements in an [`Ast`], through [`Ast::rewrite`].
+pub trait Rewriter {
+    /// Called with each node in turn, returning what to put in its place: the node itself to keep
+    /// it, nothing to remove it or several nodes to add some around it.
+    fn rewrite(&mut self, node: Node) -> Vec<Node>;
+}
+
+impl Ast {
+    /// Parses source into statements. Parsing never fails; anything that can't start a statement
+    /// becomes a [`Statement::Unknown`].
+    pub fn parse(source: &str) -> Self {
+        Parser {
+            source,
+            tokens: tokenize(source).collect(),
+            position: 0,
+        }
+        .parse()
+    }
+
+    pub fn visit(&self, visitor: &mut impl Visitor) {
+        for node in &self.nodes {
+            let span = &node.span;
+            match &node.statement {
+                Statement::Label { name } => visitor.visit_label(name, span),
+                Statement::Instruction { mnemonic, operand } => {
+                    visitor.visit_instruction(mnemonic, operand.as_ref(), span)
+                }
+                Statement::Directive { name, arguments } => {
+                    visitor.visit_directive(name, arguments, span)
+                }
+                Statement::MacroCall { name, arguments } => {
+                    visitor.visit_macro_call(name, arguments, span)
+                }
+                Statement::Comment { text, doc } => visitor.visit_comment(text, *doc, span),
+                Statement::Unknown { text } => visitor.visit_unknown(text, span),
+            }
+        }
+    }
+
+    pub fn rewrite(&mut self, rewriter: &mut impl Rewriter) {
+        let nodes = core::mem::take(&mut self.nodes);
+        self.nodes = nodes
+            .into_iter()
+            .flat_map(|node| rewriter.rewrite(node))
+            .collect();
+    }
+}
+
+impl Program {
+    /// The program's source as a syntax tree. Only the program's own source is parsed, not the
+    /// files it includes.
+    pub fn ast(&self) -> Ast {
+        Ast::parse(&self.source)
+    }
+}
+
+/// Writes the tree back out as source, keeping statements on the lines they were on. Operands and
+/// arguments are written as they were, but spacing within a line is normalized.
+impl fmt::Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut line = self.nodes.first().map_or(1, |node| node.line);
+        let mut first_on_line = true;
+
+        for node in &self.nodes {
+            if node.line > line {
+                for _ in line..node.line {
+                    f.write_str("\n")?;
+                }
+
+                line = node.line;
+                first_on_line = true;
+            }
+
+            if !first_on_line {
+                f.write_str(" ")?;
+            }
+
+            write!(f, "{}", node.statement)?;
+            first_on_line = false;
+
+            // Block comments can take up several lines
+            if let Statement::Comment { text, .. } | Statement::Unknown { text } = &node.statement {
+                line += text.matches('\n').count();
+            }
+        }
+
+        if !self.nodes.is_empty() {
+            f.write_str("\n")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Label { name } => write!(f, "{name}:"),
+            Self::Instruction { mnemonic, operand } => {
+                f.write_str(mnemonic)?;
+                if let Some(operand) = operand {
+                    write!(f, " {}", operand.text)?;
+                }
+
+                Ok(())
+            }
+            Self::Directive { name, arguments } => {
+                write!(f, ".{name}")?;
+                write_arguments(f, arguments)
+            }
+            Self::MacroCall { name, arguments } => {
+                f.write_str(name)?;
+                write_arguments(f, arguments)
+            }
+            Self::Comment { text, .. } | Self::Unknown { text } => f.write_str(text),
+        }
+    }
+}
+
+fn write_arguments(f: &mut fmt::Formatter<'_>, arguments: &[Argument]) -> fmt::Result {
+    let mut separator = " ";
+    for argument in arguments {
+        write!(f, "{separator}{}", argument.text)?;
+        separator = if argument.comma { ", " } else { " " };
+    }
+
+    Ok(())
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<(Token, Range<usize>)>,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn parse(mut self) -> Ast {
+        let mut nodes = Vec::new();
+        while let Some((token, span)) = self.tokens.get(self.position).cloned() {
+            self.position += 1;
+
+            let statement = match token {
+                Token::Newline => continue,
+                Token::Comment | Token::DocComment(_) => Statement::Comment {
+                    text: self.source[span.clone()].to_string(),
+                    doc: matches!(token, Token::DocComment(_)),
+                },
+                Token::Identifier(name) if self.next_if(&Token::Colon) => Statement::Label { name },
+                Token::Directive(name) if self.next_if(&Token::Colon) => Statement::Label {
+                    name: format!(".{name}"),
+                },
+                Token::Mnemonic(mnemonic) => Statement::Instruction {
+                    mnemonic,
+                    operand: match mnemonic {
+                        "NOP" | "RTN" | "SKZ" => None,
+                        _ => self.argument(),
+                    },
+                },
+                Token::Directive(name) => Statement::Directive {
+                    name,
+                    arguments: self.arguments(),
+                },
+                Token::Identifier(name) => Statement::MacroCall {
+                    name,
+                    arguments: self.arguments(),
+                },
+                _ => Statement::Unknown {
+                    text: self.source[span.clone()].to_string(),
+                },
+            };
+
+            let end = self.tokens[self.position - 1].1.end;
+            nodes.push(Node {
+                statement,
+                line: self.source[..span.start].matches('\n').count() + 1,
+                span: span.start..end,
+            });
+        }
+
+        Ast { nodes }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn next_if(&mut self, expected: &Token) -> bool {
+        let matches = self.peek() == Some(expected);
+        if matches {
+            self.position += 1;
+        }
+
+        matches
+    }
+
+    /// Arguments up to the end of the line, separated by commas or spaces.
+    fn arguments(&mut self) -> Vec<Argument> {
+        let mut arguments = Vec::new();
+        while let Some(mut argument) = self.argument() {
+            argument.comma = self.next_if(&Token::Comma);
+            arguments.push(argument);
+        }
+
+        arguments
+    }
+
+    /// One operand or argument: a value, or an expression made of several.
+    fn argument(&mut self) -> Option<Argument> {
+        let start = self.position;
+        if !self.term() {
+            self.position = start;
+            return None;
+        }
+
+        loop {
+            let before = self.position;
+            match self.peek() {
+                Some(Token::Operator(operator)) if *operator != "~" => self.position += 1,
+                _ => break,
+            }
+
+            if !self.term() {
+                self.position = before;
+                break;
+            }
+        }
+
+        let span = self.tokens[start].1.start..self.tokens[self.position - 1].1.end;
+        Some(Argument {
+            text: self.source[span.clone()].to_string(),
+            span,
+            comma: false,
+        })
+    }
+
+    fn term(&mut self) -> bool {
+        // Any number of signs can come first. Stepping over them rather than recursing keeps a
+        // long run of them from overflowing the stack.
+        let start = self.position;
+        while let Some(Token::Operator("+" | "-" | "~")) = self.peek() {
+            self.position += 1;
+        }
+
+        let Some(token) = self.peek().cloned() else {
+            self.position = start;
+            return false;
+        };
+
+        self.position += 1;
+        match token {
+            Token::Number(_)
+            | Token::Identifier(_)
+            | Token::Directive(_)
+            | Token::String(_)
+            | Token::LibraryPath(_) => true,
+            Token::LeftParen => {
+                let mut depth = 1;
+                while depth > 0 {
+                    match self.peek() {
+                        Some(Token::LeftParen) => depth += 1,
+                        Some(Token::RightParen) => depth -= 1,
+                        Some(Token::Newline) | None => return true,
+                        _ => {}
+                    }
+
+                    self.position += 1;
+                }
+
+                true
+            }
+            _ => {
+                self.position = start;
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Argument, Ast, Node, Program, Rewriter, Statement, Visitor};
+
+    fn argument(text: &str, start: usize, comma: bool) -> Argument {
+        Argument {
+            text: String::from(text),
+            span: start..start + text.len(),
+            comma,
+        }
+    }
+
+    #[test]
+    fn handles_ast() {
+        let ast = Ast::parse("loop: LD (1+2)*3 ; go\n.macro copy FROM, TO\nRTN\n.endm\ncopy 1, 2");
+        let statements: Vec<_> = ast
+            .nodes
+            .iter()
+            .map(|node| (node.statement.clone(), node.line))
+            .collect();
+        assert_eq!(
+            statements,
+            [
+                (
+                    Statement::Label {
+                        name: String::from("loop")
+                    },
+                    1
+                ),
+                (
+                    Statement::Instruction {
+                        mnemonic: "LD",
+                        operand: Some(argument("(1+2)*3", 9, false)),
+                    },
+                    1
+                ),
+                (
+                    Statement::Comment {
+                        text: String::from("; go"),
+                        doc: false
+                    },
+                    1
+                ),
+                (
+                    Statement::Directive {
+                        name: String::from("macro"),
+                        arguments: vec![
+                            argument("copy", 29, false),
+                            argument("FROM", 34, true),
+                            argument("TO", 40, false),
+                        ],
+                    },
+                    2
+                ),
+                (
+                    Statement::Instruction {
+                        mnemonic: "RTN",
+                        operand: None
+                    },
+                    3
+                ),
+                (
+                    Statement::Directive {
+                        name: String::from("endm"),
+                        arguments: vec![],
+                    },
+                    4
+                ),
+                (
+                    Statement::MacroCall {
+                        name: String::from("copy"),
+                        arguments: vec![argument("1", 58, true), argument("2", 61, false)],
+                    },
+                    5
+                ),
+            ]
+        );
+
+        // A long run of signs is stepped over rather than recursed into
+        let ast = Ast::parse(&format!("LD {}1", "-".repeat(100_000)));
+        assert_eq!(ast.nodes.len(), 1);
+    }
+
+    #[test]
+    fn handles_visitors() {
+        #[derive(Default)]
+        struct Stores(Vec<String>);
+
+        impl Visitor for Stores {
+            fn visit_instruction(
+                &mut self,
+                mnemonic: &'static str,
+                operand: Option<&Argument>,
+                _span: &core::ops::Range<usize>,
+            ) {
+                if mnemonic == "STO" {
+                    self.0.push(operand.unwrap().text.clone());
+                }
+            }
+        }
+
+        let program = Program::from_assembly("OEN 0\nSTO 3 STO DOOR+1\nLD 3");
+        let mut stores = Stores::default();
+        program.ast().visit(&mut stores);
+        assert_eq!(stores.0, ["3", "DOOR+1"]);
+    }
+
+    #[test]
+    fn handles_rewriters() {
+        /// Moves everything that used pin 3 over to pin 4, and drops comments.
+        struct Rewire;
+
+        impl Rewriter for Rewire {
+            fn rewrite(&mut self, mut node: Node) -> Vec<Node> {
+                match &mut node.statement {
+                    Statement::Comment { .. } => return vec![],
+                    Statement::Instruction {
+                        operand: Some(operand),
+                        ..
+                    } if operand.text == "3" => operand.text = String::from("4"),
+                    _ => {}
+                }
+
+                vec![node]
+            }
+        }
+
+        let program = Program::from_assembly(
+            "OEN   0 ; enable\n\nloop:  STO 3\n/* two\nlines */ LD 3\n.equ   PIN 3\nJMP loop",
+        );
+        let mut ast = program.ast();
+        ast.rewrite(&mut Rewire);
+        let source = ast.to_string();
+        assert_eq!(
+            source,
+            "OEN 0\n\nloop: STO 4\n\nLD 4\n.equ PIN 3\nJMP loop\n"
+        );
+
+        let rewired = Program::from_assembly(&source);
+        assert_eq!(rewired.into_opcodes(), Ok(String::from("B08414C2")));
+    }
+}
@@ -0,0 +1,153 @@
+//! Disassembling a whole directory of dumps at once, spread over as many threads as there are
+//! cores, for archiving collections of programs pulled out of the game. The crate has no
+//! command-line tool, so this is only the library side of it.
+
+use core::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::{fs, io, thread};
+
+use crate::{Program, ReadError};
+
+/// What [`Program::disassemble_dir`] did with each file, in order of their paths.
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    /// Each file that was disassembled, and where its assembly was written
+    pub disassembled: Vec<(PathBuf, PathBuf)>,
+    /// Each file that couldn't be read or disassembled, and why
+    pub failed: Vec<(PathBuf, ReadError)>,
+}
+
+impl Program {
+    /// Disassembles every file directly inside `input`, as by [`Program::disassemble`], writing
+    /// each one's assembly into `output` with `.s` added to its name. `output` is created if it
+    /// doesn't exist. A file that can't be disassembled doesn't stop the others, and ends up in
+    /// [`BatchReport::failed`]; only problems with the directories themselves are errors.
+    pub fn disassemble_dir(
+        input: impl AsRef<Path>,
+        output: impl AsRef<Path>,
+    ) -> io::Result<BatchReport> {
+        let output = output.as_ref();
+        fs::create_dir_all(output)?;
+
+        let mut files = Vec::new();
+        for entry in fs::read_dir(input)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                files.push(entry.path());
+            }
+        }
+        files.sort();
+
+        let next = AtomicUsize::new(0);
+        let report = Mutex::new(BatchReport::default());
+        let threads = thread::available_parallelism().map_or(1, usize::from);
+        thread::scope(|scope| {
+            for _ in 0..threads.min(files.len()) {
+                scope.spawn(|| {
+                    while let Some(file) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let mut name = file.file_name().unwrap_or_default().to_owned();
+                        name.push(".s");
+                        let path = output.join(name);
+
+                        let result = disassemble_file(file, &path);
+                        let mut report = report.lock().unwrap_or_else(|error| error.into_inner());
+                        match result {
+                            Ok(()) => report.disassembled.push((file.clone(), path)),
+                            Err(error) => report.failed.push((file.clone(), error)),
+                        }
+                    }
+                });
+            }
+        });
+
+        let mut report = report
+            .into_inner()
+            .unwrap_or_else(|error| error.into_inner());
+        report.disassembled.sort_by(|a, b| a.0.cmp(&b.0));
+        report.failed.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(report)
+    }
+}
+
+fn disassemble_file(input: &Path, output: &Path) -> Result<(), ReadError> {
+    let program = Program::disassemble(&fs::read(input)?)?;
+    fs::write(output, program.source)?;
+    Ok(())
+}
+
+/// A summary, like `Disassembled 2 of 3 files`, followed by a line for each file that failed.
+impl fmt::Display for BatchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Disassembled {} of {} files",
+            self.disassembled.len(),
+            self.disassembled.len() + self.failed.len()
+        )?;
+
+        for (path, error) in &self.failed {
+            write!(f, "\n{}: {error}", path.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::Program;
+
+    #[test]
+    fn handles_batch_disassembly() {
+        let directory =
+            std::env::temp_dir().join(format!("goonstation-asm batch {}", std::process::id()));
+        let input = directory.join("dumps");
+        let output = directory.join("sources");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(input.join("nested")).unwrap();
+
+        fs::write(input.join("blink.txt"), "B0 17 87 C2").unwrap();
+        fs::write(input.join("packed.bin"), [0xB0, 0x17]).unwrap();
+        fs::write(input.join("broken.hex"), ":02000000B01700\n").unwrap();
+
+        let report = Program::disassemble_dir(&input, &output).unwrap();
+        let disassembled: Vec<_> = report
+            .disassembled
+            .iter()
+            .map(|(input, output)| {
+                (
+                    input.file_name().unwrap().to_str(),
+                    output.file_name().unwrap().to_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            disassembled,
+            [
+                (Some("blink.txt"), Some("blink.txt.s")),
+                (Some("packed.bin"), Some("packed.bin.s")),
+            ]
+        );
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(
+            report.to_string(),
+            format!(
+                "Disassembled 2 of 3 files\n{}: Record on line 1 is damaged: its checksum should be 00, but is 37",
+                input.join("broken.hex").display()
+            )
+        );
+
+        let source = fs::read_to_string(output.join("blink.txt.s")).unwrap();
+        assert_eq!(
+            Program::from_assembly(&source).to_opcodes().unwrap(),
+            "B01787C2"
+        );
+        assert!(!output.join("broken.hex.s").exists());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
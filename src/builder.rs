@@ -0,0 +1,138 @@
+use crate::instruction;
+use crate::prelude::*;
+use crate::{AssemblerError, AssemblerOptions, Instruction, Program};
+
+/// Puts a program together one instruction at a time, for generating programs without writing
+/// out assembly for them, like `ProgramBuilder::new().oen(0).sto(0).build()`.
+#[derive(Clone, Debug, Default)]
+pub struct ProgramBuilder {
+    instructions: Vec<Instruction>,
+    options: AssemblerOptions,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Options for the built program. Only the length limit matters while building.
+    pub fn options(mut self, options: AssemblerOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn instruction(mut self, instruction: Instruction) -> Self {
+        self.instructions.push(instruction);
+        self
+    }
+
+    pub fn nop(self) -> Self {
+        self.instruction(Instruction::NoOp)
+    }
+
+    pub fn ld(self, address: u8) -> Self {
+        self.instruction(Instruction::Load(address))
+    }
+
+    pub fn ldc(self, address: u8) -> Self {
+        self.instruction(Instruction::LoadComplement(address))
+    }
+
+    pub fn and(self, address: u8) -> Self {
+        self.instruction(Instruction::And(address))
+    }
+
+    pub fn andc(self, address: u8) -> Self {
+        self.instruction(Instruction::AndComplement(address))
+    }
+
+    pub fn or(self, address: u8) -> Self {
+        self.instruction(Instruction::Or(address))
+    }
+
+    pub fn orc(self, address: u8) -> Self {
+        self.instruction(Instruction::OrComplement(address))
+    }
+
+    pub fn xnor(self, address: u8) -> Self {
+        self.instruction(Instruction::ExclusiveNor(address))
+    }
+
+    pub fn sto(self, address: u8) -> Self {
+        self.instruction(Instruction::Store(address))
+    }
+
+    pub fn stoc(self, address: u8) -> Self {
+        self.instruction(Instruction::StoreComplement(address))
+    }
+
+    pub fn ien(self, address: u8) -> Self {
+        self.instruction(Instruction::InputEnable(address))
+    }
+
+    pub fn oen(self, address: u8) -> Self {
+        self.instruction(Instruction::OutputEnable(address))
+    }
+
+    pub fn jmp(self, address: u8) -> Self {
+        self.instruction(Instruction::Jump(address))
+    }
+
+    pub fn rtn(self) -> Self {
+        self.instruction(Instruction::Return)
+    }
+
+    pub fn skz(self) -> Self {
+        self.instruction(Instruction::SkipIfZero)
+    }
+
+    /// Checks every operand fits in a nibble and the program fits within the length limit. The
+    /// program is checked as the assembly it's made into, which has one instruction per line, so
+    /// an error's line number is one more than the index of the instruction it's about.
+    pub fn build(self) -> Result<Program, AssemblerError> {
+        let assembly = instruction::to_assembly(&self.instructions);
+        let program = Program::from_assembly_with(&assembly, self.options);
+        program.to_opcodes()?;
+        Ok(program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AssemblerError, AssemblerOptions, Instruction, ProgramBuilder};
+
+    #[test]
+    fn handles_builder() {
+        let program = ProgramBuilder::new()
+            .oen(0)
+            .ld(7)
+            .skz()
+            .instruction(Instruction::StoreComplement(0xF))
+            .jmp(2)
+            .build()
+            .unwrap();
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B017E9FC2")));
+    }
+
+    #[test]
+    fn handles_invalid_builder_programs() {
+        let program = ProgramBuilder::new().oen(0).sto(0x10).build();
+        assert!(matches!(
+            program,
+            Err(AssemblerError::OperandOutOfRange { value: 0x10, location }) if location.line == 2
+        ));
+
+        let options = AssemblerOptions::new().max_length(4);
+        let program = ProgramBuilder::new()
+            .options(options)
+            .ld(1)
+            .sto(2)
+            .skz()
+            .build();
+        assert!(matches!(
+            program,
+            Err(AssemblerError::ExceededMaxLength { .. })
+        ));
+    }
+}
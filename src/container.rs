@@ -0,0 +1,344 @@
+//! A small binary format for saving assembled programs, which unlike bare opcodes can be told
+//! apart from other files and checked for damage.
+//!
+//! Every number is little-endian:
+//!
+//! | Bytes | Contents                                               |
+//! |-------|--------------------------------------------------------|
+//! | 0..4  | [`MAGIC`]                                              |
+//! | 4     | [`CONTAINER_VERSION`]                                  |
+//! | 5..7  | How many nibbles the program is, as a `u16`            |
+//! | 7..11 | The CRC-32 of the version, the length and the body     |
+//! | 11..  | The nibbles, packed as in [`Program::to_bytes`]        |
+//!
+//! After the nibbles comes the program's [`Metadata`]: its name, author, version and description
+//! in that order, each as a `u16` byte length followed by that much UTF-8. A field that wasn't
+//! given is empty. Version 1 containers, which don't have metadata and only check the nibbles,
+//! can still be read.
+
+use core::fmt;
+
+use crate::disassemble;
+use crate::prelude::*;
+use crate::{AssemblerError, DisassemblyOptions, Metadata, Program};
+
+/// What every container starts with.
+pub const MAGIC: [u8; 4] = *b"GASM";
+
+/// The version of the format [`Program::to_container`] writes. It goes up whenever the layout
+/// changes, and [`Program::from_container`] only reads versions it knows.
+pub const CONTAINER_VERSION: u8 = 2;
+
+const HEADER_LENGTH: usize = 11;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ContainerError {
+    /// The bytes don't start with [`MAGIC`], so they aren't a container at all
+    NotAContainer,
+    UnsupportedVersion {
+        version: u8,
+    },
+    /// There are fewer bytes than the header says there should be
+    Truncated {
+        expected: usize,
+        actual: usize,
+    },
+    /// There are more bytes than the header says there should be
+    TrailingBytes {
+        count: usize,
+    },
+    ChecksumMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    /// A metadata field isn't UTF-8, or has a character that can't go in a quoted string
+    InvalidMetadata,
+}
+
+impl Program {
+    /// Assembles the program and wraps its packed nibbles and metadata in a container, for saving
+    /// it so it can be checked when it's read back with [`Program::from_container`]. Metadata
+    /// fields longer than a `u16` are cut short, but a program longer than a `u16` doesn't fit,
+    /// and gives [`AssemblerError::ExceededMaxLength`] at the first nibble past the limit.
+    pub fn to_container(&self) -> Result<Vec<u8>, AssemblerError> {
+        let length = self.len()?;
+        let Ok(length) = u16::try_from(length) else {
+            let location = self.source_map()?.swap_remove(usize::from(u16::MAX));
+            return Err(AssemblerError::ExceededMaxLength { location });
+        };
+
+        let mut body = self.to_bytes()?;
+        let metadata = self.metadata()?;
+        for (_, field) in metadata.all() {
+            let field = field.map_or(&[][..], str::as_bytes);
+            let field = &field[..field.len().min(usize::from(u16::MAX))];
+            body.extend((field.len() as u16).to_le_bytes());
+            body.extend(field);
+        }
+
+        let mut container = Vec::with_capacity(HEADER_LENGTH + body.len());
+        container.extend(MAGIC);
+        container.push(CONTAINER_VERSION);
+        container.extend(length.to_le_bytes());
+        let checksum = crc32(container[4..].iter().chain(&body));
+        container.extend(checksum.to_le_bytes());
+        container.extend(body);
+
+        Ok(container)
+    }
+
+    /// Reads a program back from [`Program::to_container`], checking it's intact. The program is
+    /// the disassembly of exactly the nibbles that were saved, without the padding
+    /// [`Program::to_bytes`] adds, after the directives for any metadata that was saved.
+    pub fn from_container(container: &[u8]) -> Result<Self, ContainerError> {
+        if !container.starts_with(&MAGIC) {
+            return Err(ContainerError::NotAContainer);
+        }
+
+        let header = container
+            .get(..HEADER_LENGTH)
+            .ok_or(ContainerError::Truncated {
+                expected: HEADER_LENGTH,
+                actual: container.len(),
+            })?;
+        let version = header[4];
+        if !(1..=CONTAINER_VERSION).contains(&version) {
+            return Err(ContainerError::UnsupportedVersion { version });
+        }
+
+        let length = usize::from(u16::from_le_bytes([header[5], header[6]]));
+        let checksum = u32::from_le_bytes([header[7], header[8], header[9], header[10]]);
+
+        let mut reader = Reader {
+            container,
+            position: HEADER_LENGTH,
+        };
+        let bytes = reader.take(length.div_ceil(2))?;
+        let mut fields = Vec::new();
+        if version >= 2 {
+            for _ in 0..4 {
+                let field_length = reader.take(2)?;
+                let field_length =
+                    usize::from(u16::from_le_bytes([field_length[0], field_length[1]]));
+                fields.push(reader.take(field_length)?);
+            }
+        }
+
+        if reader.position < container.len() {
+            return Err(ContainerError::TrailingBytes {
+                count: container.len() - reader.position,
+            });
+        }
+
+        // Version 1 only covered the nibbles
+        let actual = match version {
+            1 => crc32(bytes),
+            _ => crc32(header[4..7].iter().chain(&container[HEADER_LENGTH..])),
+        };
+        if actual != checksum {
+            return Err(ContainerError::ChecksumMismatch {
+                expected: checksum,
+                actual,
+            });
+        }
+
+        let mut source = String::new();
+        let names = Metadata::default().all().map(|(name, _)| name);
+        for (name, field) in names.into_iter().zip(fields) {
+            let field = core::str::from_utf8(field).map_err(|_| ContainerError::InvalidMetadata)?;
+            if field.contains(['"', '\n', '\r']) {
+                return Err(ContainerError::InvalidMetadata);
+            }
+
+            if !field.is_empty() {
+                source.push_str(&format!(".{name} \"{field}\"\n"));
+            }
+        }
+
+        let mut nibbles = disassemble::unpack(bytes);
+        nibbles.truncate(length);
+        source.push_str(&disassemble::disassemble(&nibbles));
+
+        let options = DisassemblyOptions::default();
+        Ok(disassemble::program(&source, nibbles.len(), &options))
+    }
+}
+
+/// Reads through a container from the front, for the parts that come one after another.
+struct Reader<'a> {
+    container: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], ContainerError> {
+        let end = self.position + count;
+        let taken = self
+            .container
+            .get(self.position..end)
+            .ok_or(ContainerError::Truncated {
+                expected: end,
+                actual: self.container.len(),
+            })?;
+
+        self.position = end;
+        Ok(taken)
+    }
+}
+
+/// CRC-32 as used by zip and PNG, worked out a bit at a time since programs are tiny.
+fn crc32<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> u32 {
+    let crc = bytes.into_iter().fold(0xFFFF_FFFF, |crc, byte| {
+        (0..8).fold(crc ^ u32::from(*byte), |crc, _| {
+            if crc & 1 == 1 {
+                crc >> 1 ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    });
+
+    !crc
+}
+
+impl fmt::Display for ContainerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotAContainer => f.write_str("Not a program container"),
+            Self::UnsupportedVersion { version } => {
+                write!(f, "Program container version {version} isn't supported")
+            }
+            Self::Truncated { expected, actual } => write!(
+                f,
+                "Program container is cut short: expected {expected} bytes, but there are only {actual}"
+            ),
+            Self::TrailingBytes { count } => {
+                write!(f, "Program container has {count} bytes too many")
+            }
+            Self::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Program container is damaged: its checksum should be {expected:#010X}, but is {actual:#010X}"
+            ),
+            Self::InvalidMetadata => f.write_str("Program container has metadata that can't be read"),
+        }
+    }
+}
+
+impl core::error::Error for ContainerError {}
+
+#[cfg(test)]
+mod tests {
+    use super::crc32;
+    use crate::{AssemblerError, AssemblerOptions, ContainerError, Program};
+
+    #[test]
+    fn handles_containers() {
+        let program = Program::from_assembly("OEN 0\nLD 7\nRTN");
+        let container = program.to_container().unwrap();
+        assert_eq!(
+            container,
+            [
+                b'G', b'A', b'S', b'M', 0x02, 0x05, 0x00, 0x52, 0xAB, 0x04, 0xA3, 0xB0, 0x17, 0xD0,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+            ]
+        );
+
+        // The padding nibble isn't read back
+        let read = Program::from_container(&container).unwrap();
+        assert_eq!(read.to_opcodes().unwrap(), "B017D");
+
+        let version_1 = [
+            b'G', b'A', b'S', b'M', 0x01, 0x05, 0x00, 0x40, 0x71, 0x4F, 0xB9, 0xB0, 0x17, 0xD0,
+        ];
+        let read = Program::from_container(&version_1).unwrap();
+        assert_eq!(read.to_opcodes().unwrap(), "B017D");
+
+        let program = Program::from_assembly(".name \"Blink\"\n.version \"1.0\"\nOEN 0\nLD 7\nRTN");
+        let read = Program::from_container(&program.to_container().unwrap()).unwrap();
+        assert_eq!(read.metadata(), program.metadata());
+        assert_eq!(read.to_opcodes().unwrap(), "B017D");
+
+        let program = Program::from_assembly("");
+        let container = program.to_container().unwrap();
+        let read = Program::from_container(&container).unwrap();
+        assert_eq!(read.to_opcodes().unwrap(), "");
+
+        // The length has to fit in a `u16`
+        let options = AssemblerOptions::new().max_length(0x1_0000);
+        let program =
+            Program::from_assembly_with(".rept 0x7FFF\nLD 7\n.endr\nNOP", options.clone());
+        assert_eq!(program.to_container().unwrap()[5..7], [0xFF, 0xFF]);
+        let program = Program::from_assembly_with(".rept 0x8000\nLD 7\n.endr", options);
+        assert!(matches!(
+            program.to_container(),
+            Err(AssemblerError::ExceededMaxLength { location }) if location.line == 2
+        ));
+    }
+
+    #[test]
+    fn handles_container_errors() {
+        let container = Program::from_assembly("OEN 0\nLD 7\nRTN")
+            .to_container()
+            .unwrap();
+
+        assert_eq!(
+            Program::from_container(b"B017D").err(),
+            Some(ContainerError::NotAContainer)
+        );
+
+        let mut versioned = container.clone();
+        versioned[4] = 3;
+        assert_eq!(
+            Program::from_container(&versioned).err(),
+            Some(ContainerError::UnsupportedVersion { version: 3 })
+        );
+
+        assert_eq!(
+            Program::from_container(&container[..6]).err(),
+            Some(ContainerError::Truncated {
+                expected: 11,
+                actual: 6
+            })
+        );
+        assert_eq!(
+            Program::from_container(&container[..13]).err(),
+            Some(ContainerError::Truncated {
+                expected: 14,
+                actual: 13
+            })
+        );
+
+        let mut extended = container.clone();
+        extended.push(0x00);
+        assert_eq!(
+            Program::from_container(&extended).err(),
+            Some(ContainerError::TrailingBytes { count: 1 })
+        );
+
+        let mut damaged = container.clone();
+        damaged[12] = 0x18;
+        assert!(matches!(
+            Program::from_container(&damaged),
+            Err(ContainerError::ChecksumMismatch { .. })
+        ));
+
+        // The header is checked too, so a wrong length can't add a nibble unnoticed
+        let mut lengthened = container.clone();
+        lengthened[5] = 0x06;
+        assert!(matches!(
+            Program::from_container(&lengthened),
+            Err(ContainerError::ChecksumMismatch { .. })
+        ));
+
+        // A name that would end the string it's put back into early
+        let mut quoted = container[..14].to_vec();
+        quoted.extend([0x01, 0x00, b'"', 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        let checksum = crc32(quoted[4..7].iter().chain(&quoted[11..]));
+        quoted[7..11].copy_from_slice(&checksum.to_le_bytes());
+        assert_eq!(
+            Program::from_container(&quoted).err(),
+            Some(ContainerError::InvalidMetadata)
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! Disassembling straight from a reader, one instruction at a time, for dumps too big to want in
+//! memory all at once. Only instructions come out, not assembly: there's no going back to add
+//! labels once a jump has been passed.
+
+use core::fmt;
+use core::iter::FusedIterator;
+use std::io::{self, BufReader, Read};
+
+use crate::{DisassembleError, Instruction};
+
+/// Why reading instructions failed: either the reader gave up, or what it gave wasn't opcodes.
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    Disassemble(DisassembleError),
+}
+
+/// Reads instructions from opcodes as they come in, along with their addresses, the same as
+/// [`crate::Program::iter`] would for the whole dump. Stops after the first error.
+#[derive(Debug)]
+pub struct Decoder<R> {
+    bytes: io::Bytes<BufReader<R>>,
+    packed: bool,
+    /// The low nibble of the last packed byte, still to be read
+    pending: Option<u8>,
+    index: usize,
+    address: usize,
+    done: bool,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Reads hex digits, one per nibble, like [`crate::Program::from_opcodes`]. Whitespace is
+    /// skipped, so dumps can be split across lines. The `index` of an
+    /// [`DisassembleError::InvalidDigit`] counts bytes rather than characters, and anything that
+    /// isn't ASCII is reported as `U+FFFD`.
+    pub fn new(reader: R) -> Self {
+        Self::with_packing(reader, false)
+    }
+
+    /// Reads nibbles packed two to a byte, high nibble first, like
+    /// [`crate::Program::from_bytes`].
+    pub fn binary(reader: R) -> Self {
+        Self::with_packing(reader, true)
+    }
+
+    fn with_packing(reader: R, packed: bool) -> Self {
+        Self {
+            bytes: BufReader::new(reader).bytes(),
+            packed,
+            pending: None,
+            index: 0,
+            address: 0,
+            done: false,
+        }
+    }
+
+    fn nibble(&mut self) -> Option<Result<u8, ReadError>> {
+        if let Some(nibble) = self.pending.take() {
+            return Some(Ok(nibble));
+        }
+
+        loop {
+            let byte = match self.bytes.next()? {
+                Ok(byte) => byte,
+                Err(error) => return Some(Err(error.into())),
+            };
+            let index = self.index;
+            self.index += 1;
+
+            if self.packed {
+                self.pending = Some(byte & 0xF);
+                return Some(Ok(byte >> 4));
+            }
+
+            if byte.is_ascii_whitespace() {
+                continue;
+            }
+
+            let character = if byte.is_ascii() {
+                char::from(byte)
+            } else {
+                char::REPLACEMENT_CHARACTER
+            };
+            let nibble = character
+                .to_digit(16)
+                .map(|nibble| nibble as u8)
+                .ok_or(DisassembleError::InvalidDigit { character, index });
+            return Some(nibble.map_err(ReadError::from));
+        }
+    }
+
+    /// The same as `instruction::decode`, but an operand might not have arrived yet.
+    fn instruction(&mut self) -> Option<Result<Instruction, ReadError>> {
+        let opcode = match self.nibble()? {
+            Ok(opcode) => opcode,
+            Err(error) => return Some(Err(error)),
+        };
+
+        let instruction = match Instruction::new(opcode, None) {
+            Some(instruction) => instruction,
+            None if opcode < 0xF => match self.nibble() {
+                Some(Ok(operand)) => {
+                    Instruction::new(opcode, Some(operand)).unwrap_or(Instruction::Invalid(opcode))
+                }
+                Some(Err(error)) => return Some(Err(error)),
+                None => Instruction::Invalid(opcode),
+            },
+            None => Instruction::Invalid(opcode),
+        };
+
+        Some(Ok(instruction))
+    }
+}
+
+impl<R: Read> Iterator for Decoder<R> {
+    type Item = Result<(usize, Instruction), ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        // A reader can have more to give after saying it's done, like a terminal after Ctrl-D,
+        // but the iterator is fused, so it has to stay done
+        let Some(instruction) = self.instruction() else {
+            self.done = true;
+            return None;
+        };
+
+        let instruction = match instruction {
+            Ok(instruction) => instruction,
+            Err(error) => {
+                self.done = true;
+                return Some(Err(error));
+            }
+        };
+
+        let address = self.address;
+        self.address += instruction.size();
+        Some(Ok((address, instruction)))
+    }
+}
+
+impl<R: Read> FusedIterator for Decoder<R> {}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "Couldn't read the program: {error}"),
+            Self::Disassemble(error) => error.fmt(f),
+        }
+    }
+}
+
+impl core::error::Error for ReadError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::Disassemble(error) => Some(error),
+        }
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<DisassembleError> for ReadError {
+    fn from(error: DisassembleError) -> Self {
+        Self::Disassemble(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::{Decoder, DisassembleError, Instruction, Program, ReadError};
+
+    #[test]
+    fn handles_decoding() {
+        let program = Program::from_assembly("OEN 0\nloop: LD 7\nSKZ\nSTOC F\nJMP loop\nRTN");
+        let opcodes = program.to_opcodes().unwrap();
+        let decoded: Vec<_> = Decoder::new(opcodes.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(decoded, program.iter().unwrap().collect::<Vec<_>>());
+
+        let decoded: Vec<_> = Decoder::binary(&program.to_bytes().unwrap()[..])
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            decoded[..6],
+            program.iter().unwrap().collect::<Vec<_>>()[..]
+        );
+
+        let decoded: Vec<_> = Decoder::new("1\n7\nF 8".as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            decoded,
+            [
+                (0, Instruction::Load(7)),
+                (2, Instruction::Invalid(0xF)),
+                (3, Instruction::Invalid(0x8)),
+            ]
+        );
+    }
+
+    #[test]
+    fn handles_decoding_errors() {
+        let mut decoder = Decoder::new("17 G0".as_bytes());
+        assert!(matches!(
+            decoder.next(),
+            Some(Ok((0, Instruction::Load(7))))
+        ));
+        assert!(matches!(
+            decoder.next(),
+            Some(Err(ReadError::Disassemble(
+                DisassembleError::InvalidDigit {
+                    character: 'G',
+                    index: 3
+                }
+            )))
+        ));
+        assert!(decoder.next().is_none());
+
+        struct Broken;
+        impl io::Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("unplugged"))
+            }
+        }
+
+        let mut decoder = Decoder::new(Broken);
+        let error = decoder.next().unwrap().unwrap_err();
+        assert_eq!(error.to_string(), "Couldn't read the program: unplugged");
+        assert!(decoder.next().is_none());
+    }
+
+    #[test]
+    fn handles_end_of_input() {
+        /// Ends, then has more after all
+        struct Resuming(bool);
+        impl io::Read for Resuming {
+            fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+                if !self.0 {
+                    self.0 = true;
+                    return Ok(0);
+                }
+
+                buffer[..2].copy_from_slice(b"17");
+                Ok(2)
+            }
+        }
+
+        let mut decoder = Decoder::new(Resuming(false));
+        assert!(decoder.next().is_none());
+        assert!(decoder.next().is_none());
+    }
+}
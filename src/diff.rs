@@ -0,0 +1,131 @@
+//! Comparing two versions of a program instruction by instruction, for showing what reflashing a
+//! component will change.
+
+use crate::prelude::*;
+use crate::{AssemblerError, Instruction, Program};
+
+/// One difference between an old program and a new one. Instructions are compared exactly, so a
+/// jump whose target moved because of an insertion further up shows up as replaced.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Change {
+    /// `instruction` is at `address` in the new program, and wasn't in the old one
+    Insert {
+        address: usize,
+        instruction: Instruction,
+    },
+    /// `instruction` was at `address` in the old program, and isn't in the new one
+    Delete {
+        address: usize,
+        instruction: Instruction,
+    },
+    /// The instruction at `address` in the old program became `new`
+    Replace {
+        address: usize,
+        old: Instruction,
+        new: Instruction,
+    },
+}
+
+impl Program {
+    /// What changes between this program and `other`, in program order. Changes are kept as few
+    /// as possible, so every instruction that's in both programs in the same order is left alone.
+    pub fn diff(&self, other: &Program) -> Result<Vec<Change>, AssemblerError> {
+        let old: Vec<_> = self.iter()?.collect();
+        let new: Vec<_> = other.iter()?.collect();
+
+        // `common[i][j]` is the length of the longest common subsequence of `old[i..]` and
+        // `new[j..]`. Programs are at most a few hundred instructions, so the table stays small.
+        let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                common[i][j] = if old[i].1 == new[j].1 {
+                    common[i + 1][j + 1] + 1
+                } else {
+                    common[i + 1][j].max(common[i][j + 1])
+                };
+            }
+        }
+
+        let mut changes = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < old.len() || j < new.len() {
+            if i < old.len() && j < new.len() && old[i].1 == new[j].1 {
+                i += 1;
+                j += 1;
+            } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+                let (address, instruction) = old[i];
+                changes.push(Change::Delete {
+                    address,
+                    instruction,
+                });
+                i += 1;
+            } else {
+                let (address, instruction) = new[j];
+                // An instruction deleted and another inserted in its place is a replacement
+                match changes.pop() {
+                    Some(Change::Delete {
+                        address,
+                        instruction: old,
+                    }) => changes.push(Change::Replace {
+                        address,
+                        old,
+                        new: instruction,
+                    }),
+                    previous => {
+                        changes.extend(previous);
+                        changes.push(Change::Insert {
+                            address,
+                            instruction,
+                        });
+                    }
+                }
+                j += 1;
+            }
+        }
+
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Change, Instruction, Program};
+
+    #[test]
+    fn handles_diff() {
+        let old = Program::from_assembly("OEN 0\nLD 1\nSTO 2\nRTN");
+        let new = Program::from_assembly("OEN 0\nLD 3\nSTO 2\nSTOC 4\nRTN");
+        assert_eq!(
+            old.diff(&new),
+            Ok(vec![
+                Change::Replace {
+                    address: 2,
+                    old: Instruction::Load(1),
+                    new: Instruction::Load(3),
+                },
+                Change::Insert {
+                    address: 6,
+                    instruction: Instruction::StoreComplement(4),
+                },
+            ])
+        );
+
+        assert_eq!(
+            new.diff(&old),
+            Ok(vec![
+                Change::Replace {
+                    address: 2,
+                    old: Instruction::Load(3),
+                    new: Instruction::Load(1),
+                },
+                Change::Delete {
+                    address: 6,
+                    instruction: Instruction::StoreComplement(4),
+                },
+            ])
+        );
+
+        assert_eq!(old.diff(&old), Ok(vec![]));
+    }
+}
@@ -0,0 +1,877 @@
+//! Turning opcodes back into assembly, for recovering the source of a program that's only around
+//! as it was dumped from a Control Unit. Every input format is read into nibbles first, which are
+//! then disassembled the same way.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt::{self, Write};
+use core::ops::RangeFrom;
+
+use crate::instruction::{self, Iter};
+use crate::prelude::*;
+use crate::{AssemblerOptions, ContainerError, Instruction, Program, MAGIC, MAX_PROGRAM_LENGTH};
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DisassembleError {
+    /// A character that isn't a hex digit or whitespace. `index` counts characters from 0.
+    InvalidDigit {
+        character: char,
+        index: usize,
+    },
+    /// A nibble that can't be read as an instruction, only from
+    /// [`Program::from_opcodes_lenient`]: either `F`, or an opcode at the very end that's missing
+    /// its operand
+    InvalidInstruction {
+        nibble: u8,
+        address: usize,
+    },
+    Container(ContainerError),
+    /// A line of Intel HEX or S-records that isn't a record. `line` counts from 1.
+    InvalidRecord {
+        line: usize,
+    },
+    RecordChecksumMismatch {
+        line: usize,
+        expected: u8,
+        actual: u8,
+    },
+    /// A record that's valid, but isn't one of the kinds programs are written with, like an
+    /// Intel HEX start address record. `kind` is its type as written, like `03` or `S4`.
+    UnsupportedRecord {
+        line: usize,
+        kind: String,
+    },
+}
+
+/// How [`Program::from_opcodes_with`] lays out the disassembly. The defaults give the same as
+/// [`Program::from_opcodes`]: one instruction per line, uppercase, with nothing else added.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DisassemblyOptions {
+    align: bool,
+    addresses: bool,
+    annotate: bool,
+    lowercase: bool,
+    break_after_jumps: bool,
+    trim_nops: bool,
+}
+
+impl DisassemblyOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether to indent instructions under their labels and line up operands and comments in
+    /// columns.
+    pub fn align(mut self, align: bool) -> Self {
+        self.align = align;
+        self
+    }
+
+    /// Whether to end each instruction's line with a comment giving its address.
+    pub fn addresses(mut self, addresses: bool) -> Self {
+        self.addresses = addresses;
+        self
+    }
+
+    /// Whether to end each instruction's line with a comment saying what it does, like
+    /// `STO 3 ; write RR to output pin 3`. See [`Instruction::effect`].
+    pub fn annotate(mut self, annotate: bool) -> Self {
+        self.annotate = annotate;
+        self
+    }
+
+    /// Whether to write instructions in lowercase, like `ld 7`. The program is then assembled
+    /// without [`AssemblerOptions::case_sensitive`], so it still reads.
+    pub fn lowercase(mut self, lowercase: bool) -> Self {
+        self.lowercase = lowercase;
+        self
+    }
+
+    /// Whether to leave a blank line after every `JMP` and `RTN` that always happens, meaning
+    /// one that doesn't come straight after a `SKZ`, to split the program up where control
+    /// can't fall through.
+    pub fn break_after_jumps(mut self, break_after_jumps: bool) -> Self {
+        self.break_after_jumps = break_after_jumps;
+        self
+    }
+
+    /// Whether to leave out the `NOP`s at the end of the program, which padded components fill
+    /// the rest of their memory with, and end with a comment saying how many there were. The
+    /// program then assembles to fewer nibbles than were disassembled, but does the same.
+    pub fn trim_nops(mut self, trim_nops: bool) -> Self {
+        self.trim_nops = trim_nops;
+        self
+    }
+}
+
+/// A format for [`Program::disassemble_as`] to read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum InputFormat {
+    /// Hex digits, one per nibble, as read by [`Program::from_opcodes`]
+    Opcodes,
+    /// Nibbles packed two to a byte, as read by [`Program::from_bytes`]
+    Binary,
+    /// A container, as read by [`Program::from_container`]
+    Container,
+    /// Intel HEX data records, as from [`Program::to_intel_hex`]
+    IntelHex,
+    /// Motorola `S1`, `S2` or `S3` records, as from [`Program::to_srecords`]
+    SRecord,
+}
+
+impl InputFormat {
+    /// Works out which format `input` is in. Anything that starts with [`MAGIC`] is a container,
+    /// text starting with `:` is Intel HEX, text starting with `S` and a digit is S-records, and
+    /// text that's only hex digits and whitespace is opcodes. Anything else is taken to be packed
+    /// bytes, so packed bytes that happen to all be ASCII hex digits are read as opcodes instead.
+    pub fn detect(input: &[u8]) -> Self {
+        if input.starts_with(&MAGIC) {
+            return Self::Container;
+        }
+
+        if !input.is_ascii() {
+            return Self::Binary;
+        }
+
+        match input.trim_ascii_start() {
+            [b':', ..] => Self::IntelHex,
+            [b'S', digit, ..] if digit.is_ascii_digit() => Self::SRecord,
+            text if text
+                .iter()
+                .all(|byte| byte.is_ascii_hexdigit() || byte.is_ascii_whitespace()) =>
+            {
+                Self::Opcodes
+            }
+            _ => Self::Binary,
+        }
+    }
+}
+
+impl Program {
+    /// Reads a program from its opcodes, one hex digit per nibble as from
+    /// [`Program::to_opcodes`]. Digits can be either case, and whitespace between them is
+    /// ignored, so dumps that have been wrapped or grouped still read.
+    ///
+    /// The program's source is the disassembly: one instruction per line, with nibbles that aren't
+    /// instructions written as `.raw`. Every address a `JMP` lands on gets a label like `L_04`,
+    /// which the jump refers to instead of the number, unless it's partway through an
+    /// instruction.
+    ///
+    /// Disassembly never changes a nibble, so the program always assembles back to exactly the
+    /// opcodes it was read from, in uppercase and without whitespace. That holds even past the
+    /// usual length limit, which is raised to fit.
+    pub fn from_opcodes(opcodes: &str) -> Result<Self, DisassembleError> {
+        Self::from_opcodes_with(opcodes, &DisassemblyOptions::default())
+    }
+
+    /// Like [`Program::from_opcodes`], but with the disassembly laid out as `options` asks.
+    pub fn from_opcodes_with(
+        opcodes: &str,
+        options: &DisassemblyOptions,
+    ) -> Result<Self, DisassembleError> {
+        let mut nibbles = Vec::with_capacity(opcodes.len());
+        for (index, character) in opcodes.chars().enumerate() {
+            if character.is_whitespace() {
+                continue;
+            }
+
+            let nibble = character
+                .to_digit(16)
+                .ok_or(DisassembleError::InvalidDigit { character, index })?;
+            nibbles.push(nibble as u8);
+        }
+
+        let source = annotated(&nibbles, &BTreeMap::new(), options);
+        Ok(program(&source, nibbles.len(), options))
+    }
+
+    /// Like [`Program::from_opcodes`], but for dumps that have been damaged: characters that
+    /// aren't hex digits are skipped rather than being an error, and every problem is marked
+    /// with a comment in the disassembly and returned alongside it. Nibbles that aren't
+    /// instructions are kept as `.raw`, like always, but marked too.
+    pub fn from_opcodes_lenient(opcodes: &str) -> (Self, Vec<DisassembleError>) {
+        let mut nibbles = Vec::with_capacity(opcodes.len());
+        let mut problems = Vec::new();
+        let mut markers: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+        for (index, character) in opcodes.chars().enumerate() {
+            if character.is_whitespace() {
+                continue;
+            }
+
+            match character.to_digit(16) {
+                Some(nibble) => nibbles.push(nibble as u8),
+                None => {
+                    markers
+                        .entry(nibbles.len())
+                        .or_default()
+                        .push(format!("skipped `{}`", character.escape_default()));
+                    let problem = DisassembleError::InvalidDigit { character, index };
+                    problems.push((nibbles.len(), problem));
+                }
+            }
+        }
+
+        for (address, instruction) in Iter::new(instruction::decode(&nibbles)) {
+            if let Instruction::Invalid(nibble) = instruction {
+                markers
+                    .entry(address)
+                    .or_default()
+                    .push(String::from("not an instruction"));
+                problems.push((
+                    address,
+                    DisassembleError::InvalidInstruction { nibble, address },
+                ));
+            }
+        }
+
+        // In the order they come in the dump, with skipped characters before the nibble after them
+        problems.sort_by_key(|(address, _)| *address);
+
+        let options = DisassemblyOptions::default();
+        let source = annotated(&nibbles, &markers, &options);
+        let problems = problems.into_iter().map(|(_, problem)| problem).collect();
+        (program(&source, nibbles.len(), &options), problems)
+    }
+
+    /// Disassembles a program in whichever format [`InputFormat::detect`] takes `input` to be in.
+    pub fn disassemble(input: &[u8]) -> Result<Self, DisassembleError> {
+        Self::disassemble_as(input, InputFormat::detect(input))
+    }
+
+    /// Disassembles a program in the given format, as [`Program::from_opcodes`] does. Record
+    /// formats have their checksums checked, and any gaps between records are filled with `NOP`s.
+    pub fn disassemble_as(input: &[u8], format: InputFormat) -> Result<Self, DisassembleError> {
+        let bytes = match format {
+            InputFormat::Opcodes => return Self::from_opcodes(&String::from_utf8_lossy(input)),
+            InputFormat::Binary => return Ok(Self::from_bytes(input)),
+            InputFormat::Container => return Ok(Self::from_container(input)?),
+            InputFormat::IntelHex => read_records(input, intel_hex_record)?,
+            InputFormat::SRecord => read_records(input, srecord)?,
+        };
+
+        Ok(Self::from_bytes(&bytes))
+    }
+}
+
+/// What a record says to do.
+enum Record {
+    Data {
+        address: usize,
+        data: Vec<u8>,
+    },
+    /// Where the addresses of the data records after it count from, as set by an Intel HEX
+    /// extended address record
+    Base(usize),
+    /// A header, a count, or anything else that doesn't change the program
+    Skip,
+    End,
+}
+
+/// Reads lines of records into the bytes they describe, stopping at the first end record.
+fn read_records(
+    input: &[u8],
+    record: fn(&[u8], usize) -> Result<Record, DisassembleError>,
+) -> Result<Vec<u8>, DisassembleError> {
+    let mut bytes = Vec::new();
+    let mut base = 0;
+    for (index, line) in input.split(|byte| *byte == b'\n').enumerate() {
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            continue;
+        }
+
+        match record(line, index + 1)? {
+            Record::Data { address, data } => {
+                let address = base + address;
+                let end = address + data.len();
+                if bytes.len() < end {
+                    bytes.resize(end, 0x00);
+                }
+
+                bytes[address..end].copy_from_slice(&data);
+            }
+            Record::Base(address) => base = address,
+            Record::Skip => {}
+            Record::End => break,
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Reads a line like `:04000000B017C2F083`.
+fn intel_hex_record(line: &[u8], number: usize) -> Result<Record, DisassembleError> {
+    let invalid = DisassembleError::InvalidRecord { line: number };
+    let fields = decode_fields(line.strip_prefix(b":").ok_or(invalid)?, number)?;
+    if fields.first().map(|length| usize::from(*length) + 5) != Some(fields.len()) {
+        return Err(DisassembleError::InvalidRecord { line: number });
+    }
+
+    let fields = check(fields, number, u8::wrapping_neg)?;
+    let [_, high, low, kind, data @ ..] = fields.as_slice() else {
+        return Err(DisassembleError::InvalidRecord { line: number });
+    };
+
+    match kind {
+        0x00 => Ok(Record::Data {
+            address: usize::from(u16::from_be_bytes([*high, *low])),
+            data: data.to_vec(),
+        }),
+        0x01 => Ok(Record::End),
+        // Extended segment addresses count in 16-byte paragraphs, extended linear addresses in
+        // 64 KiB segments
+        0x02 | 0x04 => match data {
+            [high, low] => {
+                let shift = if *kind == 0x02 { 4 } else { 16 };
+                Ok(Record::Base(
+                    usize::from(u16::from_be_bytes([*high, *low])) << shift,
+                ))
+            }
+            _ => Err(DisassembleError::InvalidRecord { line: number }),
+        },
+        kind => Err(DisassembleError::UnsupportedRecord {
+            line: number,
+            kind: format!("{kind:02X}"),
+        }),
+    }
+}
+
+/// Reads a line like `S1070000B017C2F0D6`.
+fn srecord(line: &[u8], number: usize) -> Result<Record, DisassembleError> {
+    let [b'S', kind, digits @ ..] = line else {
+        return Err(DisassembleError::InvalidRecord { line: number });
+    };
+
+    // The count covers the address and checksum as well as the data
+    let fields = decode_fields(digits, number)?;
+    if fields.first().map(|count| usize::from(*count) + 1) != Some(fields.len()) {
+        return Err(DisassembleError::InvalidRecord { line: number });
+    }
+
+    let fields = check(fields, number, |sum| !sum)?;
+    match (kind, &fields[1..]) {
+        (b'0' | b'5' | b'6', _) => Ok(Record::Skip),
+        (b'1' | b'2' | b'3', fields) => {
+            // `S1` has a 16-bit address, `S2` 24-bit and `S3` 32-bit
+            let width = usize::from(kind - b'0') + 1;
+            if fields.len() < width {
+                return Err(DisassembleError::InvalidRecord { line: number });
+            }
+
+            let (address, data) = fields.split_at(width);
+            Ok(Record::Data {
+                address: address
+                    .iter()
+                    .fold(0, |address, byte| address << 8 | usize::from(*byte)),
+                data: data.to_vec(),
+            })
+        }
+        (b'7' | b'8' | b'9', _) => Ok(Record::End),
+        (kind, _) => Err(DisassembleError::UnsupportedRecord {
+            line: number,
+            kind: format!("S{}", char::from(*kind)),
+        }),
+    }
+}
+
+/// Decodes a record's hex digits into bytes.
+fn decode_fields(digits: &[u8], number: usize) -> Result<Vec<u8>, DisassembleError> {
+    let invalid = DisassembleError::InvalidRecord { line: number };
+    if digits.len() % 2 != 0 {
+        return Err(invalid);
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let digit = |digit: u8| char::from(digit).to_digit(16).map(|digit| digit as u8);
+            Some(digit(pair[0])? << 4 | digit(pair[1])?)
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or(invalid)
+}
+
+/// Checks that a record's last byte is the checksum of the rest, and takes it off.
+fn check(
+    mut fields: Vec<u8>,
+    number: usize,
+    checksum: fn(u8) -> u8,
+) -> Result<Vec<u8>, DisassembleError> {
+    let expected = fields
+        .pop()
+        .ok_or(DisassembleError::InvalidRecord { line: number })?;
+    let actual = checksum(
+        fields
+            .iter()
+            .fold(0u8, |sum, field| sum.wrapping_add(*field)),
+    );
+    if actual != expected {
+        return Err(DisassembleError::RecordChecksumMismatch {
+            line: number,
+            expected,
+            actual,
+        });
+    }
+
+    Ok(fields)
+}
+
+/// A program with a disassembly of `length` nibbles as its source, allowed to be as long as
+/// that so it always assembles back.
+pub(crate) fn program(source: &str, length: usize, options: &DisassemblyOptions) -> Program {
+    let options = AssemblerOptions::new()
+        .max_length(length.max(MAX_PROGRAM_LENGTH))
+        .case_sensitive(!options.lowercase);
+    Program::from_assembly_with(source, options)
+}
+
+/// Splits packed bytes into nibbles, high nibble first.
+pub(crate) fn unpack(bytes: &[u8]) -> Vec<u8> {
+    bytes
+        .iter()
+        .flat_map(|byte| [byte >> 4, byte & 0xF])
+        .collect()
+}
+
+/// Writes nibbles out as assembly, one instruction per line, with labels for where jumps land.
+pub(crate) fn disassemble(nibbles: &[u8]) -> String {
+    annotated(nibbles, &BTreeMap::new(), &DisassemblyOptions::default())
+}
+
+/// Like [`disassemble`], with comments for each instruction from `markers`, keyed by any address
+/// the instruction covers. Markers past the last instruction go at the end.
+fn annotated(
+    nibbles: &[u8],
+    markers: &BTreeMap<usize, Vec<String>>,
+    options: &DisassemblyOptions,
+) -> String {
+    let mut instructions: Vec<_> = Iter::new(instruction::decode(nibbles)).collect();
+    let mut trimmed = 0;
+    if options.trim_nops {
+        while instructions
+            .last()
+            .is_some_and(|(_, instruction)| *instruction == Instruction::NoOp)
+        {
+            instructions.pop();
+            trimmed += 1;
+        }
+    }
+
+    let end = instructions
+        .last()
+        .map_or(0, |(address, instruction)| address + instruction.size());
+
+    // Jumping just past the last instruction is the same as jumping to the start, but it's still
+    // somewhere a label can go
+    let starts: BTreeSet<_> = instructions
+        .iter()
+        .map(|(address, _)| *address)
+        .chain([end])
+        .collect();
+    let labels: BTreeSet<_> = instructions
+        .iter()
+        .filter_map(|(_, instruction)| match instruction {
+            Instruction::Jump(target) => Some(usize::from(*target)),
+            _ => None,
+        })
+        .filter(|target| starts.contains(target))
+        .collect();
+
+    let mut assembly = String::new();
+    let label = |assembly: &mut String, address: usize| {
+        if labels.contains(&address) {
+            let _ = writeln!(assembly, "L_{address:02X}:");
+        }
+    };
+
+    let mark = |assembly: &mut String, addresses: RangeFrom<usize>, end: usize| {
+        for (_, markers) in markers
+            .range(addresses)
+            .take_while(|(address, _)| **address < end)
+        {
+            for marker in markers {
+                let _ = writeln!(assembly, "; {marker}");
+            }
+        }
+    };
+
+    let mut previous = None;
+    for (address, instruction) in instructions {
+        label(&mut assembly, address);
+        mark(&mut assembly, address.., address + instruction.size());
+
+        let operand = match instruction {
+            Instruction::Jump(target) if labels.contains(&usize::from(target)) => {
+                Some(format!("L_{target:02X}"))
+            }
+            instruction => instruction.operand().map(|operand| format!("{operand:X}")),
+        };
+        let mnemonic = instruction.mnemonic().map(|mnemonic| {
+            if options.lowercase {
+                mnemonic.to_ascii_lowercase()
+            } else {
+                mnemonic.to_string()
+            }
+        });
+        let indent = if options.align { "    " } else { "" };
+        let mut line = match (mnemonic, operand) {
+            (Some(mnemonic), Some(operand)) if options.align => {
+                format!("{indent}{mnemonic:<5}{operand}")
+            }
+            (Some(mnemonic), Some(operand)) => format!("{mnemonic} {operand}"),
+            (Some(mnemonic), None) => format!("{indent}{mnemonic}"),
+            (None, _) => format!("{indent}{instruction}"),
+        };
+
+        let comment = match (options.addresses, options.annotate) {
+            (true, true) => Some(format!("{address:02X}: {}", instruction.effect())),
+            (true, false) => Some(format!("{address:02X}")),
+            (false, true) => Some(instruction.effect()),
+            (false, false) => None,
+        };
+        if let Some(comment) = comment {
+            // Comments line up when aligned, but always have a space before them
+            let column = if options.align { 20 } else { 0 }.max(line.len() + 1);
+            let _ = write!(
+                line,
+                "{:1$}; {comment}",
+                "",
+                column.saturating_sub(line.len())
+            );
+        }
+
+        let _ = writeln!(assembly, "{line}");
+
+        let always = previous != Some(Instruction::SkipIfZero);
+        if options.break_after_jumps
+            && always
+            && matches!(instruction, Instruction::Jump(_) | Instruction::Return)
+        {
+            assembly.push('\n');
+        }
+
+        previous = Some(instruction);
+    }
+
+    label(&mut assembly, end);
+    mark(&mut assembly, end.., usize::MAX);
+    match trimmed {
+        0 => {}
+        1 => assembly.push_str("; trimmed 1 trailing NOP\n"),
+        trimmed => {
+            let _ = writeln!(assembly, "; trimmed {trimmed} trailing NOPs");
+        }
+    }
+
+    assembly
+}
+
+impl fmt::Display for DisassembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidDigit { character, index } => write!(
+                f,
+                "`{character}` at position {} isn't a hex digit",
+                index + 1
+            ),
+            Self::InvalidInstruction { nibble, address } => write!(
+                f,
+                "`{nibble:X}` at address {address:#X} isn't a complete instruction"
+            ),
+            Self::Container(error) => error.fmt(f),
+            Self::InvalidRecord { line } => write!(f, "Line {line} isn't a valid record"),
+            Self::RecordChecksumMismatch {
+                line,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Record on line {line} is damaged: its checksum should be {expected:02X}, but is {actual:02X}"
+            ),
+            Self::UnsupportedRecord { line, kind } => {
+                write!(f, "Record on line {line} is of type {kind}, which isn't supported")
+            }
+        }
+    }
+}
+
+impl core::error::Error for DisassembleError {}
+
+impl From<ContainerError> for DisassembleError {
+    fn from(error: ContainerError) -> Self {
+        Self::Container(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DisassembleError, DisassemblyOptions, InputFormat, Program};
+
+    #[test]
+    fn handles_disassembly() {
+        let program = Program::from_opcodes("B080178F").unwrap();
+        assert_eq!(program.source, "OEN 0\nSTO 0\nLD 7\nSTO F\n");
+        assert_eq!(program.to_opcodes().unwrap(), "B080178F");
+
+        // Nibbles that aren't instructions still come back out the same
+        let program = Program::from_opcodes("d f\n1").unwrap();
+        assert_eq!(program.source, "RTN\n.raw \"F\"\n.raw \"1\"\n");
+        assert_eq!(program.to_opcodes().unwrap(), "DF1");
+
+        assert_eq!(Program::from_opcodes("").unwrap().source, "");
+    }
+
+    #[test]
+    fn handles_round_trips() {
+        let round_trip = |opcodes: &str| {
+            let program = Program::from_opcodes(opcodes).unwrap();
+            assert_eq!(program.to_opcodes().unwrap(), opcodes, "{}", program.source);
+        };
+
+        // Every program up to three nibbles long
+        for length in 0..=3 {
+            for value in 0..16usize.pow(length) {
+                let opcodes: String = (0..length)
+                    .rev()
+                    .map(|digit| format!("{:X}", value >> (4 * digit) & 0xF))
+                    .collect();
+                round_trip(&opcodes);
+            }
+        }
+
+        // And plenty of longer ones, some past the usual length limit
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut random = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..500 {
+            let length = random() % 300;
+            let opcodes: String = (0..length)
+                .map(|_| format!("{:X}", random() & 0xF))
+                .collect();
+            round_trip(&opcodes);
+        }
+
+        let program = Program::from_bytes(&[0xC0; 100]);
+        assert_eq!(program.to_bytes().unwrap(), [0xC0; 100]);
+    }
+
+    #[test]
+    fn handles_disassembly_options() {
+        let opcodes = "B0C617EC0D";
+        let options = DisassemblyOptions::new()
+            .align(true)
+            .addresses(true)
+            .lowercase(true)
+            .break_after_jumps(true);
+        let program = Program::from_opcodes_with(opcodes, &options).unwrap();
+        assert_eq!(
+            program.source,
+            concat!(
+                "L_00:\n",
+                "    oen  0          ; 00\n",
+                "    jmp  L_06       ; 02\n",
+                "\n",
+                "    ld   7          ; 04\n",
+                "L_06:\n",
+                "    skz             ; 06\n",
+                "    jmp  L_00       ; 07\n",
+                "    rtn             ; 09\n",
+                "\n",
+            )
+        );
+        assert_eq!(program.to_opcodes().unwrap(), opcodes);
+
+        let options = DisassemblyOptions::new().addresses(true);
+        let program = Program::from_opcodes_with("17F", &options).unwrap();
+        assert_eq!(program.source, "LD 7 ; 00\n.raw \"F\" ; 02\n");
+    }
+
+    #[test]
+    fn handles_trimming_nops() {
+        let options = DisassemblyOptions::new().trim_nops(true);
+        let program = Program::from_opcodes_with("17C0B8000000", &options).unwrap();
+        assert_eq!(
+            program.source,
+            "L_00:\nLD 7\nJMP L_00\nOEN 8\n; trimmed 6 trailing NOPs\n"
+        );
+        assert_eq!(program.to_opcodes().unwrap(), "17C0B8");
+
+        // A jump's operand isn't a `NOP`, even when it's 0
+        let program = Program::from_opcodes_with("C00", &options).unwrap();
+        assert_eq!(
+            program.source,
+            "L_00:\nJMP L_00\n; trimmed 1 trailing NOP\n"
+        );
+
+        let program = Program::from_opcodes_with("17", &options).unwrap();
+        assert_eq!(program.source, "LD 7\n");
+    }
+
+    #[test]
+    fn handles_annotations() {
+        let options = DisassemblyOptions::new().annotate(true);
+        let program = Program::from_opcodes_with("178AE", &options).unwrap();
+        assert_eq!(
+            program.source,
+            concat!(
+                "LD 7 ; set RR to input pin 7\n",
+                "STO A ; write RR to scratch RAM A\n",
+                "SKZ ; skip the next instruction if RR is 0\n",
+            )
+        );
+
+        let options = options.align(true).addresses(true);
+        let program = Program::from_opcodes_with("83C0", &options).unwrap();
+        assert_eq!(
+            program.source,
+            concat!(
+                "L_00:\n",
+                "    STO  3          ; 00: write RR to output pin 3\n",
+                "    JMP  L_00       ; 02: jump to 00\n",
+            )
+        );
+        assert_eq!(program.to_opcodes().unwrap(), "83C0");
+    }
+
+    #[test]
+    fn handles_labels() {
+        let program = Program::from_opcodes("B0C617EC0").unwrap();
+        assert_eq!(
+            program.source,
+            "L_00:\nOEN 0\nJMP L_06\nLD 7\nL_06:\nSKZ\nJMP L_00\n"
+        );
+        assert_eq!(program.to_opcodes().unwrap(), "B0C617EC0");
+
+        // Onto `LD 7`'s operand, which can't have a label, and just past the end, which can
+        let program = Program::from_opcodes("C317C6").unwrap();
+        assert_eq!(program.source, "JMP 3\nLD 7\nJMP L_06\nL_06:\n");
+        assert_eq!(program.to_opcodes().unwrap(), "C317C6");
+    }
+
+    #[test]
+    fn handles_invalid_digits() {
+        assert_eq!(
+            Program::from_opcodes("B0 8G").err(),
+            Some(DisassembleError::InvalidDigit {
+                character: 'G',
+                index: 4
+            })
+        );
+    }
+
+    #[test]
+    fn handles_lenient_disassembly() {
+        let (program, problems) = Program::from_opcodes_lenient("B0 ?1 7F\nC0x1");
+        assert_eq!(
+            program.source,
+            concat!(
+                "L_00:\n",
+                "OEN 0\n",
+                "; skipped `?`\n",
+                "LD 7\n",
+                "; not an instruction\n",
+                ".raw \"F\"\n",
+                "JMP L_00\n",
+                "; skipped `x`\n",
+                "; not an instruction\n",
+                ".raw \"1\"\n",
+            )
+        );
+        assert_eq!(program.to_opcodes().unwrap(), "B017FC01");
+        assert_eq!(
+            problems,
+            [
+                DisassembleError::InvalidDigit {
+                    character: '?',
+                    index: 3
+                },
+                DisassembleError::InvalidInstruction {
+                    nibble: 0xF,
+                    address: 4
+                },
+                DisassembleError::InvalidDigit {
+                    character: 'x',
+                    index: 11
+                },
+                DisassembleError::InvalidInstruction {
+                    nibble: 0x1,
+                    address: 7
+                },
+            ]
+        );
+
+        let (program, problems) = Program::from_opcodes_lenient("D!");
+        assert_eq!(program.source, "RTN\n; skipped `!`\n");
+        assert_eq!(problems.len(), 1);
+    }
+
+    #[test]
+    fn handles_input_formats() {
+        let program = Program::from_assembly("OEN 0\nloop: LD 7\nJMP loop\nSKZ\nRTN");
+        let inputs = [
+            (
+                program.to_opcodes().unwrap().into_bytes(),
+                InputFormat::Opcodes,
+            ),
+            (program.to_bytes().unwrap(), InputFormat::Binary),
+            (program.to_container().unwrap(), InputFormat::Container),
+            (
+                program.to_intel_hex(2).unwrap().into_bytes(),
+                InputFormat::IntelHex,
+            ),
+            (
+                program.to_srecords(2).unwrap().into_bytes(),
+                InputFormat::SRecord,
+            ),
+        ];
+
+        for (input, format) in inputs {
+            assert_eq!(InputFormat::detect(&input), format);
+            let read = Program::disassemble(&input).unwrap();
+            assert!(read.source.ends_with("L_02:\nLD 7\nJMP L_02\nSKZ\nRTN\n"));
+        }
+
+        // Records can come in any order, with gaps between them
+        let program = Program::disassemble(b":01000200D02D\n:01000000B04F\n:00000001FF\n").unwrap();
+        assert_eq!(program.to_opcodes().unwrap(), "B000D0");
+    }
+
+    #[test]
+    fn handles_record_errors() {
+        assert_eq!(
+            Program::disassemble(b":04000000B017C2F084\n").err(),
+            Some(DisassembleError::RecordChecksumMismatch {
+                line: 1,
+                expected: 0x84,
+                actual: 0x83
+            })
+        );
+        assert_eq!(
+            Program::disassemble(b"\n:0400000300000000F9\n").err(),
+            Some(DisassembleError::UnsupportedRecord {
+                line: 2,
+                kind: String::from("03")
+            })
+        );
+        assert_eq!(
+            Program::disassemble_as(b"S1070000B017", InputFormat::SRecord).err(),
+            Some(DisassembleError::InvalidRecord { line: 1 })
+        );
+        assert_eq!(
+            Program::disassemble(b"GASM").err(),
+            Some(DisassembleError::Container(
+                crate::ContainerError::Truncated {
+                    expected: 11,
+                    actual: 4
+                }
+            ))
+        );
+    }
+}
@@ -0,0 +1,31 @@
+use crate::prelude::*;
+use crate::Location;
+
+/// A `;;;` documentation comment, along with what it documents. Consecutive `;;;` lines are
+/// joined into one, with the `;;;` and surrounding whitespace removed from each.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Doc {
+    pub target: DocTarget,
+    pub text: String,
+    /// Where the first line of the comment is
+    pub location: Location,
+}
+
+/// What a documentation comment is attached to: whichever label, macro definition or instruction
+/// comes after it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DocTarget {
+    Label {
+        name: String,
+        address: usize,
+    },
+    Macro {
+        name: String,
+    },
+    /// The address of the instruction's opcode
+    Instruction {
+        address: usize,
+    },
+}
@@ -0,0 +1,420 @@
+//! Changing a program after it's been written, for optimizers and editors. Edits work on the
+//! program's instructions, so afterwards its source is the canonical assembly from
+//! [`Program`]'s `Display` impl rather than what it was read from. Its metadata and `.assert`s
+//! are kept as directives before the instructions, but its labels, constants, macros, comments
+//! and docs are lost, along with the path it was read from.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt::{self, Write};
+
+use crate::instruction;
+use crate::prelude::*;
+use crate::{AssemblerError, Instruction, Program};
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EditError {
+    /// The program didn't assemble, either before the edit or after it (say because it became
+    /// too long). Boxed to keep edit errors small
+    Assembler(Box<AssemblerError>),
+    NoInstructionAt {
+        address: usize,
+    },
+    JumpOutOfRange {
+        address: usize,
+        target: usize,
+    },
+    /// An address to remap from or to doesn't fit in an operand
+    AddressOutOfRange {
+        address: u8,
+    },
+    /// Remapping `from` would move it onto `to`, which the program already uses for something
+    /// else (or is where another address is being remapped to)
+    AddressConflict {
+        from: u8,
+        to: u8,
+    },
+}
+
+impl Program {
+    /// Adds an instruction to the end of the program.
+    pub fn push(&mut self, instruction: Instruction) -> Result<(), EditError> {
+        let end = self
+            .iter()?
+            .map(|(address, instruction)| address + instruction.size())
+            .last();
+        self.edit(end.unwrap_or(0), false, Some(instruction))
+            .map(|_| ())
+    }
+
+    /// Inserts an instruction before the one at `address`, or at the end if `address` is the
+    /// length of the program. Jumps to `address` or anywhere after it are moved along with the
+    /// instructions they point at.
+    pub fn insert(&mut self, address: usize, instruction: Instruction) -> Result<(), EditError> {
+        self.edit(address, false, Some(instruction)).map(|_| ())
+    }
+
+    /// Removes the instruction at `address`. Jumps to anywhere after it are moved back, and jumps
+    /// to it (or onto its operand) now land on whatever comes next.
+    pub fn remove(&mut self, address: usize) -> Result<Instruction, EditError> {
+        // An instruction was always removed
+        self.edit(address, true, None).map(Option::unwrap)
+    }
+
+    /// Swaps the instruction at `address` for another, moving jumps to anywhere after it if the
+    /// two are different sizes.
+    pub fn replace(
+        &mut self,
+        address: usize,
+        instruction: Instruction,
+    ) -> Result<Instruction, EditError> {
+        self.edit(address, true, Some(instruction))
+            .map(Option::unwrap)
+    }
+
+    /// Removes the instruction at `address` (if `remove` is set) and inserts another in its place
+    /// (if there is one), relocating jumps and checking the result still assembles. The program
+    /// is left as it was if anything goes wrong.
+    fn edit(
+        &mut self,
+        address: usize,
+        remove: bool,
+        insert: Option<Instruction>,
+    ) -> Result<Option<Instruction>, EditError> {
+        let mut instructions: Vec<_> = self.iter()?.collect();
+        let end = instructions
+            .last()
+            .map_or(0, |(address, instruction)| address + instruction.size());
+
+        let index = match instructions.iter().position(|(start, _)| *start == address) {
+            Some(index) => index,
+            // Inserting can also happen at the very end
+            None if address == end && !remove => instructions.len(),
+            None => return Err(EditError::NoInstructionAt { address }),
+        };
+
+        let removed = remove.then(|| instructions.remove(index).1);
+        let removed_size = removed.map_or(0, Instruction::size);
+        let inserted_size = insert.map_or(0, Instruction::size);
+
+        // Jumps to the instruction being removed stay where they are, so they land on whatever
+        // takes its place, and so do jumps into the middle of it
+        let moves = |target: usize| {
+            if remove {
+                target > address
+            } else {
+                target >= address
+            }
+        };
+
+        let mut relocated = Vec::with_capacity(instructions.len() + 1);
+        for (jump_address, instruction) in instructions {
+            let instruction = match instruction {
+                Instruction::Jump(target) if moves(target.into()) => {
+                    let target = match usize::from(target) {
+                        target if target < address + removed_size => address,
+                        target => target + inserted_size - removed_size,
+                    };
+                    let operand = u8::try_from(target)
+                        .ok()
+                        .filter(|target| *target <= 0xF)
+                        .ok_or(EditError::JumpOutOfRange {
+                            address: jump_address,
+                            target,
+                        })?;
+
+                    Instruction::Jump(operand)
+                }
+                instruction => instruction,
+            };
+
+            relocated.push(instruction);
+        }
+
+        if let Some(instruction) = insert {
+            relocated.insert(index, instruction);
+        }
+
+        self.set_instructions(&relocated)?;
+        Ok(removed)
+    }
+
+    /// Adds another program onto the end of this one. Jumps in `other` are moved along by the
+    /// length of this program, so they still land on the same instructions. The combined program
+    /// has to fit within this program's length limit.
+    pub fn append(&mut self, other: Program) -> Result<(), EditError> {
+        let mut instructions = self.instructions()?;
+        let offset: usize = instructions
+            .iter()
+            .map(|instruction| instruction.size())
+            .sum();
+
+        for (address, instruction) in other.iter()? {
+            let instruction = match instruction {
+                Instruction::Jump(target) => {
+                    let target = usize::from(target) + offset;
+                    let operand = u8::try_from(target)
+                        .ok()
+                        .filter(|target| *target <= 0xF)
+                        .ok_or(EditError::JumpOutOfRange {
+                            address: address + offset,
+                            target,
+                        })?;
+
+                    Instruction::Jump(operand)
+                }
+                instruction => instruction,
+            };
+
+            instructions.push(instruction);
+        }
+
+        self.set_instructions(&instructions)
+    }
+
+    /// Makes every instruction that uses address `from` use `to` instead, for when something is
+    /// wired up to a different pin. Jumps aren't touched, since their operand is a position in
+    /// the program rather than an address.
+    pub fn remap_address(&mut self, from: u8, to: u8) -> Result<(), EditError> {
+        self.remap_addresses(&BTreeMap::from([(from, to)]))
+    }
+
+    /// Like [`Program::remap_address`], but moves several addresses at once, so two addresses can
+    /// be swapped. Nothing is changed if any two addresses would end up the same.
+    pub fn remap_addresses(&mut self, map: &BTreeMap<u8, u8>) -> Result<(), EditError> {
+        let out_of_range = map
+            .iter()
+            .flat_map(|(from, to)| [from, to])
+            .find(|&&address| address > 0xF);
+        if let Some(&address) = out_of_range {
+            return Err(EditError::AddressOutOfRange { address });
+        }
+
+        let remap = |address: u8| map.get(&address).copied().unwrap_or(address);
+        let operand = |instruction: &Instruction| match instruction {
+            Instruction::Jump(_) => None,
+            instruction => instruction.operand(),
+        };
+
+        let instructions = self.instructions()?;
+        let used: BTreeSet<_> = instructions.iter().filter_map(operand).collect();
+
+        // Which address ends up at each address, to catch two ending up at the same one
+        let mut remapped = BTreeMap::new();
+        for address in used {
+            let to = remap(address);
+            if let Some(other) = remapped.insert(to, address) {
+                // At most one of the two was left where it was
+                let from = if address == to { other } else { address };
+                return Err(EditError::AddressConflict { from, to });
+            }
+        }
+
+        let instructions: Vec<_> = instructions
+            .iter()
+            .map(|instruction| match operand(instruction) {
+                // Both opcode and operand are known to be valid
+                Some(address) => {
+                    Instruction::new(instruction.opcode(), Some(remap(address))).unwrap()
+                }
+                None => *instruction,
+            })
+            .collect();
+
+        self.set_instructions(&instructions)
+    }
+
+    /// Replaces the program with the given instructions, as long as they assemble, keeping its
+    /// metadata and assertions.
+    fn set_instructions(&mut self, instructions: &[Instruction]) -> Result<(), EditError> {
+        let assembled = self
+            .assemble(false)
+            .map_err(|mut errors| errors.remove(0))?;
+        let mut assembly = String::new();
+        for (name, value) in assembled.metadata.fields() {
+            let _ = writeln!(assembly, ".{name} \"{value}\"");
+        }
+
+        for assertion in &assembled.assertions {
+            let _ = writeln!(assembly, ".assert {}", assertion.text);
+        }
+
+        assembly.push_str(&instruction::to_assembly(instructions));
+        let program = Program::from_assembly_with(&assembly, self.options.clone());
+        program.to_opcodes()?;
+
+        *self = program;
+        Ok(())
+    }
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Assembler(error) => error.fmt(f),
+            Self::NoInstructionAt { address } => {
+                write!(f, "No instruction starts at address {address:#X}")
+            }
+            Self::JumpOutOfRange { address, target } => write!(
+                f,
+                "Jump at address {address:#X} would have to move to {target:#X}, which doesn't fit in an operand"
+            ),
+            Self::AddressOutOfRange { address } => {
+                write!(f, "Address {address:#X} doesn't fit in an operand")
+            }
+            Self::AddressConflict { from, to } => write!(
+                f,
+                "Can't remap address {from:#X} to {to:#X}, since the program already uses {to:#X}"
+            ),
+        }
+    }
+}
+
+impl core::error::Error for EditError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Assembler(error) => error.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<AssemblerError> for EditError {
+    fn from(error: AssemblerError) -> Self {
+        Self::Assembler(Box::new(error))
+    }
+}
+
+/// Adds instructions to the end of the program, as-is.
+///
+/// # Panics
+///
+/// If the program doesn't assemble, or would be too long with the new instructions. Use
+/// [`Program::push`] to handle those instead.
+impl Extend<Instruction> for Program {
+    fn extend<T: IntoIterator<Item = Instruction>>(&mut self, iter: T) {
+        let mut instructions = self.instructions().unwrap();
+        instructions.extend(iter);
+        self.set_instructions(&instructions).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::BTreeMap;
+
+    use super::EditError;
+    use crate::{AssemblerError, AssemblerOptions, Instruction, Program};
+
+    #[test]
+    fn handles_insert() {
+        let mut program = Program::from_assembly("OEN 0\nloop: LD 1\nSTO 2\nJMP loop");
+        program.insert(2, Instruction::SkipIfZero).unwrap();
+        program.insert(0, Instruction::NoOp).unwrap();
+        program.push(Instruction::Return).unwrap();
+        assert_eq!(program.into_opcodes(), Ok(String::from("0B0E1182C4D")));
+    }
+
+    #[test]
+    fn handles_remove() {
+        let mut program = Program::from_assembly("OEN 0\nNOP\nend: SKZ\nJMP end");
+        let removed = program.remove(2);
+        assert_eq!(removed, Ok(Instruction::NoOp));
+        assert_eq!(program.to_opcodes(), Ok(String::from("B0EC2")));
+
+        let removed = program.remove(1);
+        assert_eq!(removed, Err(EditError::NoInstructionAt { address: 1 }));
+    }
+
+    #[test]
+    fn handles_replace() {
+        let mut program = Program::from_assembly("SKZ\nJMP end\nNOP\nend: RTN");
+        let replaced = program.replace(0, Instruction::Load(3));
+        assert_eq!(replaced, Ok(Instruction::SkipIfZero));
+        assert_eq!(program.into_opcodes(), Ok(String::from("13C50D")));
+    }
+
+    #[test]
+    fn handles_metadata_and_assertions() {
+        let mut program = Program::from_assembly(concat!(
+            ".name \"Copy\"\n",
+            ".author \"Nick\"\n",
+            "OEN 0\n",
+            "LD 1 ; the input\n",
+            "STO 3\n",
+            ".assert out[3] == 1 after 4 cycles with in[1]=1\n",
+        ));
+        program.insert(0, Instruction::NoOp).unwrap();
+        assert_eq!(program.to_opcodes(), Ok(String::from("0B01183")));
+        assert_eq!(program.metadata().unwrap().name.as_deref(), Some("Copy"));
+        assert_eq!(program.metadata().unwrap().author.as_deref(), Some("Nick"));
+
+        let results = program.check_assertions().unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].passed);
+    }
+
+    #[test]
+    fn handles_append() {
+        let mut program = Program::from_assembly("OEN 0\nloop: LD 1\nJMP loop");
+        let other = Program::from_assembly("start: SKZ\nJMP start");
+        program.append(other).unwrap();
+        program.extend([Instruction::Store(2), Instruction::Return]);
+        assert_eq!(program.into_opcodes(), Ok(String::from("B011C2EC682D")));
+
+        let options = AssemblerOptions::new().max_length(7);
+        let mut program = Program::from_assembly_with("OEN 0\nLD 1\nSTO 2", options);
+        let result = program.append(Program::from_assembly("STO 3"));
+        assert!(matches!(
+            result,
+            Err(EditError::Assembler(error))
+                if matches!(*error, AssemblerError::ExceededMaxLength { .. })
+        ));
+    }
+
+    #[test]
+    fn handles_remap_address() {
+        let mut program = Program::from_assembly("OEN 0\nloop: LD 7\nSTO 3\nSTOC 7\nJMP loop");
+        program.remap_address(7, 5).unwrap();
+        assert_eq!(program.to_opcodes(), Ok(String::from("B0158395C2")));
+
+        let result = program.remap_address(5, 3);
+        assert_eq!(result, Err(EditError::AddressConflict { from: 5, to: 3 }));
+
+        let result = program.remap_address(0x10, 3);
+        assert_eq!(result, Err(EditError::AddressOutOfRange { address: 0x10 }));
+
+        program
+            .remap_addresses(&BTreeMap::from([(5, 3), (3, 5)]))
+            .unwrap();
+        assert_eq!(program.into_opcodes(), Ok(String::from("B0138593C2")));
+    }
+
+    #[test]
+    fn handles_invalid_edits() {
+        let mut program = Program::from_assembly("JMP end\n.org 0xF\nend: NOP");
+        let result = program.insert(0, Instruction::NoOp);
+        assert_eq!(
+            result,
+            Err(EditError::JumpOutOfRange {
+                address: 0,
+                target: 0x10,
+            })
+        );
+
+        let options = AssemblerOptions::new().max_length(2);
+        let mut program = Program::from_assembly_with("LD 1", options);
+        let result = program.push(Instruction::NoOp);
+        assert!(matches!(
+            result,
+            Err(EditError::Assembler(error))
+                if matches!(*error, AssemblerError::ExceededMaxLength { .. })
+        ));
+        assert_eq!(program.into_opcodes(), Ok(String::from("11")));
+
+        // A jump onto the operand of the removed instruction can't go back past its start
+        let mut program = Program::from_assembly("LD 1\nJMP 1");
+        assert_eq!(program.remove(0), Ok(Instruction::Load(1)));
+        assert_eq!(program.into_opcodes(), Ok(String::from("C0")));
+    }
+}
@@ -0,0 +1,1380 @@
+//! Every format a program can be written out in, behind one method, so tools can let the user
+//! pick one by name.
+
+use alloc::borrow::Cow;
+use core::fmt::Write;
+use core::ops::Range;
+
+use crate::assembler::Assembled;
+use crate::instruction;
+use crate::normalize::normalize;
+use crate::output::Sink;
+use crate::prelude::*;
+use crate::{
+    encoding, AssemblerError, EdgeKind, Instruction, Iter, Location, Program, Stats, SymbolKind,
+    WriteError, DEFAULT_PAGE_LENGTH, MAX_PROGRAM_LENGTH,
+};
+
+/// A format for [`Program::emit`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum OutputFormat {
+    /// ASCII hex, one digit per nibble, as from [`Program::to_opcodes`]
+    Hex,
+    /// Nibbles packed two to a byte, as from [`Program::to_bytes`]
+    Binary,
+    /// A JSON object with the opcodes, instructions, symbols and stats, as from
+    /// [`Program::to_json`]
+    Json,
+    /// A classic assembler listing of the source next to what it assembled to, as from
+    /// [`Program::to_listing`]
+    Listing,
+    /// Rows of nibbles next to the instructions they decode to, as from [`Program::to_hexdump`]
+    Hexdump,
+    /// A spreadsheet-friendly table of instructions, as from [`Program::to_csv`]
+    Csv,
+    /// A Graphviz graph of the program's control flow, as from [`Program::to_dot`]
+    Dot,
+    /// Every symbol and macro expansion, as from [`Program::to_map`]
+    Map,
+    /// Where each nibble came from in the source, as JSON, as from [`Program::to_source_map`]
+    SourceMap,
+    /// Every error and warning as SARIF, as from [`Program::to_sarif`]. Unlike the other
+    /// formats, this still gives output when the program doesn't assemble.
+    Sarif,
+    /// A JSON manifest of the program split into pages, as from [`Program::to_manifest`] with
+    /// [`DEFAULT_PAGE_LENGTH`]
+    Pages,
+    /// The opcodes split into numbered parts that each fit in one paste, as from
+    /// [`Program::to_paste`] with [`DEFAULT_PASTE_LENGTH`]
+    Paste,
+    /// The packed bytes in base64, as from [`Program::to_base64`]
+    Base64,
+    /// The packed bytes compressed with deflate, then in base64, as from
+    /// [`Program::to_deflate_base64`]
+    DeflateBase64,
+    /// The packed bytes as a Rust `const` array named `PROGRAM`, as from
+    /// [`Program::to_rust_array`]
+    RustArray,
+    /// The packed bytes as a C array named `PROGRAM`, as from [`Program::to_c_array`]
+    CArray,
+    /// A `$readmemh` file with a nibble on each line, as from [`Program::to_memh`]
+    Memh,
+    /// Intel HEX records of the packed bytes, [`DEFAULT_RECORD_SIZE`] bytes to a record, as from
+    /// [`Program::to_intel_hex`]
+    IntelHex,
+    /// Motorola S-records (S19) of the packed bytes, [`DEFAULT_RECORD_SIZE`] bytes to a record,
+    /// as from [`Program::to_srecords`]
+    SRecord,
+    /// The packed bytes with a header and checksum, as from [`Program::to_container`]
+    Container,
+}
+
+/// How many bytes go in each record of the record-based formats, unless asked otherwise. Most
+/// tools write 16, and every tool reads them.
+pub const DEFAULT_RECORD_SIZE: usize = 16;
+
+/// How many digits [`Program::to_paste`] puts in each part unless asked otherwise: as many as the
+/// Control Unit holds, so a program within the usual length limit always goes in one paste.
+pub const DEFAULT_PASTE_LENGTH: usize = MAX_PROGRAM_LENGTH;
+
+/// How [`Program::to_opcodes_with`] lays out the hex digits, for pasting them somewhere other
+/// than a Control Unit. The defaults give the same as [`Program::to_opcodes`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HexOptions {
+    lowercase: bool,
+    grouping: Grouping,
+    separator: String,
+    groups_per_line: usize,
+}
+
+/// How the digits in [`HexOptions`] are split up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Grouping {
+    /// All the digits together, with no separators
+    Ungrouped,
+    /// Each instruction on its own, so an opcode stays next to its operand
+    Instructions,
+    /// Runs of this many digits. Zero is treated as one.
+    Nibbles(usize),
+}
+
+impl HexOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether to write `a` to `f` rather than `A` to `F`. The Control Unit reads either.
+    pub fn lowercase(mut self, lowercase: bool) -> Self {
+        self.lowercase = lowercase;
+        self
+    }
+
+    pub fn grouping(mut self, grouping: Grouping) -> Self {
+        self.grouping = grouping;
+        self
+    }
+
+    /// What goes between groups on the same line. Defaults to a space.
+    pub fn separator(mut self, separator: impl Into<String>) -> Self {
+        self.separator = separator.into();
+        self
+    }
+
+    /// Starts a new line after this many groups, or never if it's zero (the default).
+    pub fn groups_per_line(mut self, groups_per_line: usize) -> Self {
+        self.groups_per_line = groups_per_line;
+        self
+    }
+}
+
+impl Default for HexOptions {
+    fn default() -> Self {
+        Self {
+            lowercase: false,
+            grouping: Grouping::Ungrouped,
+            separator: String::from(" "),
+            groups_per_line: 0,
+        }
+    }
+}
+
+/// Changes [`Program::emit_with`] makes to the program before writing it out, so it behaves the
+/// same on a Control Unit whatever was loaded into it before.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EmitOptions {
+    pub(crate) pad: bool,
+    pub(crate) terminate: bool,
+}
+
+impl EmitOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether to fill the rest of the program with `NOP`s, up to the length limit in its
+    /// [`crate::AssemblerOptions`], so nothing left over from an older program runs after it.
+    pub fn pad(mut self, pad: bool) -> Self {
+        self.pad = pad;
+        self
+    }
+
+    /// Whether to add a `JMP 0` after the program, so it starts again straight away rather than
+    /// running whatever comes after it. This goes before any padding.
+    pub fn terminate(mut self, terminate: bool) -> Self {
+        self.terminate = terminate;
+        self
+    }
+}
+
+impl OutputFormat {
+    /// Every format, in the order they're listed in.
+    pub const ALL: [Self; 20] = [
+        Self::Hex,
+        Self::Binary,
+        Self::Json,
+        Self::Listing,
+        Self::Hexdump,
+        Self::Csv,
+        Self::Dot,
+        Self::Map,
+        Self::SourceMap,
+        Self::Sarif,
+        Self::Pages,
+        Self::Paste,
+        Self::Base64,
+        Self::DeflateBase64,
+        Self::RustArray,
+        Self::CArray,
+        Self::Memh,
+        Self::IntelHex,
+        Self::SRecord,
+        Self::Container,
+    ];
+
+    /// The format with the given name, as returned by [`OutputFormat::name`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|format| format.name() == name)
+    }
+
+    /// What the format is called, for picking it on a command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Hex => "hex",
+            Self::Binary => "binary",
+            Self::Json => "json",
+            Self::Listing => "listing",
+            Self::Hexdump => "hexdump",
+            Self::Csv => "csv",
+            Self::Dot => "dot",
+            Self::Map => "map",
+            Self::SourceMap => "source-map",
+            Self::Sarif => "sarif",
+            Self::Pages => "pages",
+            Self::Paste => "paste",
+            Self::Base64 => "base64",
+            Self::DeflateBase64 => "deflate-base64",
+            Self::RustArray => "rust",
+            Self::CArray => "c",
+            Self::Memh => "memh",
+            Self::IntelHex => "ihex",
+            Self::SRecord => "srec",
+            Self::Container => "container",
+        }
+    }
+}
+
+impl Program {
+    /// Like [`Program::to_opcodes`], but laid out as `options` asks.
+    pub fn to_opcodes_with(&self, options: &HexOptions) -> Result<String, AssemblerError> {
+        let mut opcodes = self.to_opcodes()?;
+        if options.lowercase {
+            opcodes.make_ascii_lowercase();
+        }
+
+        let groups: Vec<&str> = match options.grouping {
+            Grouping::Ungrouped => vec![&opcodes],
+            Grouping::Instructions => {
+                let mut rest = opcodes.as_str();
+                self.instructions()?
+                    .into_iter()
+                    .map(|instruction| {
+                        let (group, after) = rest.split_at(instruction.size());
+                        rest = after;
+                        group
+                    })
+                    .collect()
+            }
+            // Opcodes are all ASCII, so any split is on a character boundary
+            Grouping::Nibbles(size) => (0..opcodes.len())
+                .step_by(size.max(1))
+                .map(|start| &opcodes[start..opcodes.len().min(start + size.max(1))])
+                .collect(),
+        };
+
+        let lines: Vec<String> = match options.groups_per_line {
+            0 => vec![groups.join(&options.separator)],
+            per_line => groups
+                .chunks(per_line)
+                .map(|line| line.join(&options.separator))
+                .collect(),
+        };
+
+        Ok(lines.join("\n"))
+    }
+
+    /// The opcodes split into parts of at most `max_length` digits, for when the text field
+    /// they're being pasted into can't take them all at once. Parts only ever end between
+    /// instructions, so an opcode and its operand always go in together, which means
+    /// `max_length` is at least 2.
+    pub fn paste_chunks(&self, max_length: usize) -> Result<Vec<String>, AssemblerError> {
+        // Parts split up the same way pages do
+        let pages = self.pages(max_length)?;
+        Ok(pages.into_iter().map(|page| page.opcodes).collect())
+    }
+
+    /// The opcodes exactly as they're typed into the Control Unit, ready to copy. A program that
+    /// needs more than one part (see [`Program::paste_chunks`]) has each part on its own line
+    /// under a `Part N of M` heading, so they can be pasted in order.
+    pub fn to_paste(&self, max_length: usize) -> Result<String, AssemblerError> {
+        let chunks = self.paste_chunks(max_length)?;
+        if let [chunk] = chunks.as_slice() {
+            return Ok(format!("{chunk}\n"));
+        }
+
+        let parts: Vec<String> = chunks
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| format!("Part {} of {}\n{chunk}\n", index + 1, chunks.len()))
+            .collect();
+
+        Ok(parts.join("\n"))
+    }
+
+    /// Assembles the program and writes it out in `format`. Text formats are UTF-8.
+    pub fn emit(&self, format: OutputFormat) -> Result<Vec<u8>, AssemblerError> {
+        self.emit_with(format, &EmitOptions::default())
+    }
+
+    /// Like [`Program::emit`], but with the program changed as `options` asks first (see
+    /// [`Program::finished`]).
+    pub fn emit_with(
+        &self,
+        format: OutputFormat,
+        options: &EmitOptions,
+    ) -> Result<Vec<u8>, AssemblerError> {
+        let mut output = Vec::new();
+        match self.emit_into(format, options, &mut output) {
+            Ok(()) => Ok(output),
+            Err(WriteError::Assembler(error)) => Err(*error),
+            Err(error) => unreachable!("writing into a `Vec` failed: {error}"),
+        }
+    }
+
+    /// Writes the output for [`Program::emit_with`] into `sink`, which every way of emitting a
+    /// program goes through.
+    pub(crate) fn emit_into(
+        &self,
+        format: OutputFormat,
+        options: &EmitOptions,
+        sink: &mut impl Sink,
+    ) -> Result<(), WriteError> {
+        if options.pad || options.terminate {
+            return self
+                .finished(options)?
+                .emit_into(format, &EmitOptions::default(), sink);
+        }
+
+        match format {
+            OutputFormat::Hex => sink.write(self.to_opcodes()?.as_bytes()),
+            OutputFormat::Binary => sink.write(&self.to_bytes()?),
+            OutputFormat::Json => sink.write(self.to_json()?.as_bytes()),
+            OutputFormat::Listing => sink.write(self.to_listing()?.as_bytes()),
+            OutputFormat::Hexdump => sink.write(self.to_hexdump()?.as_bytes()),
+            OutputFormat::Csv => sink.write(self.to_csv()?.as_bytes()),
+            OutputFormat::Dot => sink.write(self.to_dot()?.as_bytes()),
+            OutputFormat::Map => sink.write(self.to_map()?.as_bytes()),
+            OutputFormat::SourceMap => sink.write(self.to_source_map()?.as_bytes()),
+            OutputFormat::Sarif => sink.write(self.to_sarif().as_bytes()),
+            OutputFormat::Pages => sink.write(self.to_manifest(DEFAULT_PAGE_LENGTH)?.as_bytes()),
+            OutputFormat::Paste => sink.write(self.to_paste(DEFAULT_PASTE_LENGTH)?.as_bytes()),
+            OutputFormat::Base64 => sink.write(self.to_base64()?.as_bytes()),
+            OutputFormat::DeflateBase64 => sink.write(self.to_deflate_base64()?.as_bytes()),
+            OutputFormat::RustArray => sink.write(self.to_rust_array("PROGRAM")?.as_bytes()),
+            OutputFormat::CArray => sink.write(self.to_c_array("PROGRAM")?.as_bytes()),
+            OutputFormat::Memh => sink.write(self.to_memh()?.as_bytes()),
+            OutputFormat::IntelHex => {
+                sink.write(self.to_intel_hex(DEFAULT_RECORD_SIZE)?.as_bytes())
+            }
+            OutputFormat::SRecord => sink.write(self.to_srecords(DEFAULT_RECORD_SIZE)?.as_bytes()),
+            OutputFormat::Container => sink.write(&self.to_container()?),
+        }
+    }
+
+    /// The program with the `JMP 0` and padding `options` asks for added after whatever it
+    /// assembles to. Its source stays as it is, and the added nibbles are mapped to the end of it.
+    /// Adding them can take the program over its length limit, which is an error like any other.
+    pub fn finished(&self, options: &EmitOptions) -> Result<Self, AssemblerError> {
+        let program = Self {
+            source: self.source.clone(),
+            path: self.path.clone(),
+            options: self.options.clone(),
+            finish: EmitOptions {
+                pad: self.finish.pad || options.pad,
+                terminate: self.finish.terminate || options.terminate,
+            },
+        };
+
+        // Make sure it still assembles
+        program.len()?;
+        Ok(program)
+    }
+
+    /// The program's packed bytes (see [`Program::to_bytes`]) in standard, padded base64, for
+    /// putting a program in a chat message or a JSON payload without worrying about escaping.
+    pub fn to_base64(&self) -> Result<String, AssemblerError> {
+        Ok(encoding::base64(&self.to_bytes()?))
+    }
+
+    /// Like [`Program::to_base64`], but compressed with raw deflate (no zlib or gzip header)
+    /// first, for programs that repeat themselves a lot. Short programs can come out longer than
+    /// they went in.
+    pub fn to_deflate_base64(&self) -> Result<String, AssemblerError> {
+        Ok(encoding::base64(&encoding::deflate(&self.to_bytes()?)))
+    }
+
+    /// The program's packed bytes (see [`Program::to_bytes`]) as a Rust constant called `name`,
+    /// like `const PROGRAM: [u8; 2] = [0xB0, 0x17];`, for embedding in firmware.
+    pub fn to_rust_array(&self, name: &str) -> Result<String, AssemblerError> {
+        let bytes = self.to_bytes()?;
+        Ok(format!(
+            "const {name}: [u8; {}] = [{}];\n",
+            bytes.len(),
+            array_items(&bytes),
+        ))
+    }
+
+    /// Like [`Program::to_rust_array`], but as a C array, like
+    /// `const unsigned char PROGRAM[2] = {0xB0, 0x17};`.
+    pub fn to_c_array(&self, name: &str) -> Result<String, AssemblerError> {
+        let bytes = self.to_bytes()?;
+        Ok(format!(
+            "const unsigned char {name}[{}] = {{{}}};\n",
+            bytes.len(),
+            array_items(&bytes),
+        ))
+    }
+
+    /// The program as a memory file for Verilog's `$readmemh`, one nibble per line so it loads
+    /// into a 4-bit wide memory with the same addresses the Control Unit uses. Each opcode is
+    /// commented with its instruction.
+    pub fn to_memh(&self) -> Result<String, AssemblerError> {
+        let mut memh = String::new();
+        for (_, instruction) in self.iter()? {
+            let _ = writeln!(memh, "{:X} // {instruction}", instruction.opcode());
+            if let Some(operand) = instruction.operand() {
+                let _ = writeln!(memh, "{operand:X}");
+            }
+        }
+
+        Ok(memh)
+    }
+
+    /// The program's packed bytes (see [`Program::to_bytes`]) as Intel HEX data records starting
+    /// at address 0, followed by an end-of-file record, for flashing tools and EPROM programmers.
+    /// Each record holds up to `record_size` bytes, which is kept between 1 and 255. Past the
+    /// first 64 KiB, an extended linear address record (type `04`) starts each 64 KiB segment.
+    pub fn to_intel_hex(&self, record_size: usize) -> Result<String, AssemblerError> {
+        let bytes = self.to_bytes()?;
+        let mut hex = String::new();
+        let mut segment = 0;
+        for (address, data) in records(&bytes, record_size.clamp(1, 0xFF)) {
+            let [upper_high, upper_low, high, low] = address.to_be_bytes();
+            if address >> 16 != segment {
+                segment = address >> 16;
+                push_record(
+                    &mut hex,
+                    ":",
+                    &[0x02, 0x00, 0x00, 0x04, upper_high, upper_low],
+                    u8::wrapping_neg,
+                );
+            }
+
+            let fields: Vec<u8> = [data.len() as u8, high, low, 0x00]
+                .into_iter()
+                .chain(data.iter().copied())
+                .collect();
+            push_record(&mut hex, ":", &fields, u8::wrapping_neg);
+        }
+
+        push_record(&mut hex, ":", &[0x00, 0x00, 0x00, 0x01], u8::wrapping_neg);
+        Ok(hex)
+    }
+
+    /// Like [`Program::to_intel_hex`], but as Motorola S-records: an `S1` data record for each
+    /// chunk, then an `S9` record to end. Each record holds up to `record_size` bytes, which is
+    /// kept between 1 and 252 so the byte count still fits. Programs too big for 16-bit addresses
+    /// use `S2` and `S8` records with 24-bit addresses instead, or `S3` and `S7` records with
+    /// 32-bit addresses, which hold one or two fewer bytes each.
+    pub fn to_srecords(&self, record_size: usize) -> Result<String, AssemblerError> {
+        let bytes = self.to_bytes()?;
+        let (data_kind, end_kind, width) = match bytes.len() {
+            0..=0x1_0000 => ("S1", "S9", 2),
+            0x1_0001..=0x100_0000 => ("S2", "S8", 3),
+            _ => ("S3", "S7", 4),
+        };
+
+        let mut srec = String::new();
+        for (address, data) in records(&bytes, record_size.clamp(1, 0xFE - width)) {
+            // The count covers the address and checksum as well as the data
+            let fields: Vec<u8> = [(data.len() + width + 1) as u8]
+                .into_iter()
+                .chain(address.to_be_bytes()[4 - width..].iter().copied())
+                .chain(data.iter().copied())
+                .collect();
+            push_record(&mut srec, data_kind, &fields, |sum| !sum);
+        }
+
+        let mut end = vec![0x00; width + 1];
+        end[0] = width as u8 + 1;
+        push_record(&mut srec, end_kind, &end, |sum| !sum);
+        Ok(srec)
+    }
+
+    /// Everything a web UI or build pipeline might want to know about the program, as a JSON
+    /// object:
+    ///
+    /// - `opcodes`: as from [`Program::to_opcodes`]
+    /// - `instructions`: each instruction's `address`, `opcode`, `mnemonic` and `operand` (both
+    ///   `null` when there isn't one), its `assembly`, and the `line` number and `source` text
+    ///   it came from
+    /// - `symbols`: each label's or constant's `name`, `kind` (`"label"` or `"constant"`),
+    ///   `value` and the `line` it's defined on, as from [`Program::symbols`]
+    /// - `stats`: the `size`, `instructions` counted by mnemonic, `invalid` nibbles and how many
+    ///   times each address is used as an `operand`, as from [`Program::stats`]
+    /// - `metadata`: the program's `name`, `author`, `version` and `description` (`null` when not
+    ///   given), as from [`Program::metadata`]
+    pub fn to_json(&self) -> Result<String, AssemblerError> {
+        let assembled = self
+            .assemble(false)
+            .map_err(|mut errors| errors.remove(0))?;
+        let instructions = annotated(&assembled).into_iter().map(|(address, instruction, location)| {
+            format!(
+                "{{\"address\":{address},\"opcode\":{},\"mnemonic\":{},\"operand\":{},\"assembly\":{},\"line\":{},\"source\":{}}}",
+                instruction.opcode(),
+                json_option(instruction.mnemonic().map(json_string)),
+                json_option(instruction.operand()),
+                json_string(&instruction.to_string()),
+                location.line,
+                json_string(location.snippet.trim()),
+            )
+        });
+
+        let symbols = assembled.symbols().into_iter().map(|symbol| {
+            let (kind, value) = kind_and_value(symbol.kind);
+
+            format!(
+                "{{\"name\":{},\"kind\":\"{kind}\",\"value\":{value},\"line\":{}}}",
+                json_string(&symbol.name),
+                symbol.location.line,
+            )
+        });
+
+        let stats = Stats::of(&assembled.opcodes);
+        let counts = stats
+            .instructions
+            .iter()
+            .map(|(mnemonic, count)| format!("{}:{count}", json_string(mnemonic)));
+
+        let metadata =
+            assembled.metadata.all().into_iter().map(|(field, value)| {
+                format!("\"{field}\":{}", json_option(value.map(json_string)))
+            });
+
+        Ok(format!(
+            "{{\"opcodes\":\"{}\",\"instructions\":[{}],\"symbols\":[{}],\"stats\":{{\"size\":{},\"instructions\":{{{}}},\"invalid\":{},\"operands\":[{}]}},\"metadata\":{{{}}}}}",
+            assembled.opcodes,
+            json_join(instructions),
+            json_join(symbols),
+            stats.size,
+            json_join(counts),
+            stats.invalid,
+            json_join(stats.operands.iter().map(usize::to_string)),
+            json_join(metadata),
+        ))
+    }
+
+    /// [`Program::source_map`] as JSON, for debuggers and editors to map a running address back
+    /// to the source without assembling it again. The object has:
+    ///
+    /// - `version`: 1, for telling this layout apart from any later one
+    /// - `files`: every file nibbles came from, in the order they first appear
+    /// - `mappings`: one entry per nibble, in address order, with the index of its `file` in
+    ///   `files` (`null` when the source didn't come from a file) and its 1-based `line` and
+    ///   `column`
+    pub fn to_source_map(&self) -> Result<String, AssemblerError> {
+        let mut files: Vec<String> = Vec::new();
+        let mappings: Vec<_> = self
+            .source_map()?
+            .into_iter()
+            .map(|location| {
+                let file = location.file.as_ref().map(|file| {
+                    let file = file.to_string();
+                    files
+                        .iter()
+                        .position(|known| *known == file)
+                        .unwrap_or_else(|| {
+                            files.push(file);
+                            files.len() - 1
+                        })
+                });
+
+                format!(
+                    "{{\"file\":{},\"line\":{},\"column\":{}}}",
+                    json_option(file),
+                    location.line,
+                    location.column,
+                )
+            })
+            .collect();
+
+        Ok(format!(
+            "{{\"version\":1,\"files\":[{}],\"mappings\":[{}]}}",
+            json_join(files.iter().map(|file| json_string(file))),
+            mappings.join(","),
+        ))
+    }
+
+    /// A classic assembler listing: every line of the source, comments and all, with the address
+    /// and nibbles of the instruction it assembled to alongside, and the instruction as assembled,
+    /// so symbols show up as their values. Instructions that came from somewhere else, like a
+    /// macro's body or an included file, are listed where they ended up, with their line marked
+    /// `+`, and anything [`Program::finished`] added comes after the source with no line at all.
+    /// The program's metadata, if it has any, and every symbol and its value follow at the end.
+    pub fn to_listing(&self) -> Result<String, AssemblerError> {
+        let assembled = self
+            .assemble(false)
+            .map_err(|mut errors| errors.remove(0))?;
+        let root_lines = assembled.root_lines();
+
+        let source = normalize(&self.source);
+        let lines: Vec<_> = source.lines().collect();
+        let source_row = |line: usize| {
+            let text = lines.get(line - 1).copied().unwrap_or_default();
+            format!("{line:>4} {:21}{text}", "")
+        };
+        let mut next_line = 1;
+        let mut rows = Vec::new();
+
+        for (address, instruction, location) in annotated(&assembled) {
+            let nibbles: String = [Some(instruction.opcode()), instruction.operand()]
+                .into_iter()
+                .flatten()
+                .map(|nibble| format!("{nibble:X}"))
+                .collect();
+
+            if assembled.added.contains(&address) {
+                rows.extend((next_line..=lines.len()).map(source_row));
+                next_line = lines.len() + 1;
+                rows.push(format!(
+                    "{:>4}+ {address:02X}  {nibbles:<2}  {instruction}",
+                    ""
+                ));
+                continue;
+            }
+
+            let root_line = root_lines[address];
+            let in_place = location.file == self.path && location.line == root_line;
+            if root_line >= next_line {
+                rows.extend((next_line..root_line).map(source_row));
+                if !in_place {
+                    rows.push(source_row(root_line));
+                }
+
+                next_line = root_line + 1;
+            }
+
+            rows.push(format!(
+                "{:>4}{} {address:02X}  {nibbles:<2}  {:<10}  {}",
+                location.line,
+                if in_place { ' ' } else { '+' },
+                instruction.to_string(),
+                location.snippet,
+            ));
+        }
+
+        rows.extend((next_line..=lines.len()).map(source_row));
+
+        let metadata = &assembled.metadata;
+        if !metadata.is_empty() {
+            rows.push(String::new());
+            rows.push(String::from("Metadata:"));
+        }
+
+        for (field, value) in metadata.fields() {
+            rows.push(format!("  {field:<16}{value}"));
+        }
+
+        let symbols = assembled.symbols();
+        if !symbols.is_empty() {
+            rows.push(String::new());
+            rows.push(String::from("Symbols:"));
+        }
+
+        for symbol in symbols {
+            let (kind, value) = kind_and_value(symbol.kind);
+
+            rows.push(format!("  {:<16}{kind:<10}{value:02X}", symbol.name));
+        }
+
+        Ok(join_rows(rows))
+    }
+
+    /// A hexdump in the style of `xxd`, for checking what's loaded into a Control Unit against the
+    /// source: each row is the address of its first nibble, 16 nibbles in groups of 4, then the
+    /// instructions that start on that row.
+    pub fn to_hexdump(&self) -> Result<String, AssemblerError> {
+        let opcodes = self.to_opcodes()?;
+        let instructions: Vec<_> = self.iter()?.collect();
+
+        let mut hexdump = String::new();
+        // Opcodes are all ASCII, so these splits are all on character boundaries
+        for start in (0..opcodes.len()).step_by(16) {
+            let row = &opcodes[start..opcodes.len().min(start + 16)];
+            let groups: Vec<_> = (0..row.len())
+                .step_by(4)
+                .map(|group| &row[group..row.len().min(group + 4)])
+                .collect();
+
+            let decoded: Vec<_> = instructions
+                .iter()
+                .filter(|(address, _)| (start..start + row.len()).contains(address))
+                .map(|(_, instruction)| instruction.to_string())
+                .collect();
+
+            let _ = writeln!(
+                hexdump,
+                "{start:02X}: {:<19}  {}",
+                groups.join(" "),
+                decoded.join("; "),
+            );
+        }
+
+        Ok(hexdump)
+    }
+
+    /// Every instruction as a row of CSV, under a header row: its `address`, `opcode`,
+    /// `mnemonic` and `operand` (both empty when there isn't one), then the `line` number and
+    /// `source` text it came from. Numbers are in decimal, so spreadsheets read them as numbers.
+    pub fn to_csv(&self) -> Result<String, AssemblerError> {
+        let assembled = self
+            .assemble(false)
+            .map_err(|mut errors| errors.remove(0))?;
+        let mut csv = String::from("address,opcode,mnemonic,operand,line,source\r\n");
+        for (address, instruction, location) in annotated(&assembled) {
+            let operand = instruction
+                .operand()
+                .map(|operand| operand.to_string())
+                .unwrap_or_default();
+
+            let _ = write!(
+                csv,
+                "{address},{},{},{operand},{},{}\r\n",
+                instruction.opcode(),
+                instruction.mnemonic().unwrap_or_default(),
+                location.line,
+                csv_field(location.snippet.trim()),
+            );
+        }
+
+        Ok(csv)
+    }
+
+    /// The program's control flow (see [`Program::control_flow`]) as a Graphviz DOT graph, with
+    /// a box for each instruction. Jumps are labelled, skips are dashed and restarts are dotted.
+    pub fn to_dot(&self) -> Result<String, AssemblerError> {
+        let mut dot =
+            String::from("digraph program {\n    node [shape=box, fontname=\"monospace\"];\n");
+        for (address, instruction) in self.iter()? {
+            // DOT quotes strings the same way JSON does
+            let _ = writeln!(
+                dot,
+                "    n{address} [label={}];",
+                json_string(&format!("{address:02X}: {instruction}")),
+            );
+        }
+
+        for edge in self.control_flow()? {
+            let style = match edge.kind {
+                EdgeKind::Next => "",
+                EdgeKind::Jump => " [label=\"JMP\"]",
+                EdgeKind::Skip => " [label=\"SKZ\", style=dashed]",
+                EdgeKind::Restart => " [style=dotted]",
+            };
+
+            let _ = writeln!(dot, "    n{} -> n{}{style};", edge.from, edge.to);
+        }
+
+        dot.push_str("}\n");
+        Ok(dot)
+    }
+
+    /// A map file, for finding your way around a program after it's assembled: every label and
+    /// constant with its value and where it's defined, then every macro expansion with the
+    /// addresses it produced and where it was invoked. Nested expansions are indented under the
+    /// one they're in.
+    pub fn to_map(&self) -> Result<String, AssemblerError> {
+        let assembled = self
+            .assemble(false)
+            .map_err(|mut errors| errors.remove(0))?;
+
+        let mut rows = vec![String::from("Symbols:")];
+        for symbol in assembled.symbols() {
+            let (kind, value) = kind_and_value(symbol.kind);
+            rows.push(format!(
+                "  {:<16}{kind:<10}{value:02X}  {}",
+                symbol.name, symbol.location
+            ));
+        }
+
+        rows.push(String::new());
+        rows.push(String::from("Macro expansions:"));
+        let mut open: Vec<Range<usize>> = Vec::new();
+        for (name, location, range) in assembled.expansions() {
+            // Anything that ended before this one starts isn't what it's nested in
+            open.retain(|outer| outer.end > range.start);
+            let name = format!("{}{name}", "  ".repeat(open.len()));
+            let addresses = format!("{:02X}..{:02X}", range.start, range.end);
+            rows.push(format!("  {name:<16}{addresses:<10}{location}"));
+            open.push(range);
+        }
+
+        Ok(join_rows(rows))
+    }
+}
+
+/// Bytes as a comma-separated list of hex literals for an array. Anything more than a line's worth
+/// is split over indented lines of 12.
+fn array_items(bytes: &[u8]) -> String {
+    let lines: Vec<String> = bytes
+        .chunks(12)
+        .map(|line| {
+            let items: Vec<_> = line.iter().map(|byte| format!("0x{byte:02X}")).collect();
+            items.join(", ")
+        })
+        .collect();
+
+    match lines.as_slice() {
+        [] => String::new(),
+        [line] => line.clone(),
+        lines => format!("\n    {},\n", lines.join(",\n    ")),
+    }
+}
+
+/// A symbol's kind as it's written out, along with its value.
+fn kind_and_value(kind: SymbolKind) -> (&'static str, usize) {
+    match kind {
+        SymbolKind::Label { address } => ("label", address),
+        SymbolKind::Constant { value } => ("constant", value),
+    }
+}
+
+/// Puts rows of text on their own lines, without any trailing whitespace.
+fn join_rows(rows: Vec<String>) -> String {
+    let mut text = String::new();
+    for row in rows {
+        text.push_str(row.trim_end());
+        text.push('\n');
+    }
+
+    text
+}
+
+/// Writes a line of `start` followed by `fields` and their checksum in hex. Each format works its
+/// checksum out from the fields' sum differently, so `checksum` turns the sum into one.
+fn push_record(output: &mut String, start: &str, fields: &[u8], checksum: fn(u8) -> u8) {
+    output.push_str(start);
+    for field in fields {
+        let _ = write!(output, "{field:02X}");
+    }
+
+    let sum = fields
+        .iter()
+        .fold(0u8, |sum, field| sum.wrapping_add(*field));
+    let _ = writeln!(output, "{:02X}", checksum(sum));
+}
+
+/// Every instruction along with its address and where it came from.
+fn annotated(assembled: &Assembled) -> Vec<(usize, Instruction, Location)> {
+    let source_map = assembled.source_map();
+    let instructions = instruction::decode(&instruction::nibbles(&assembled.opcodes));
+    Iter::new(instructions)
+        .map(|(address, instruction)| (address, instruction, source_map[address].clone()))
+        .collect()
+}
+
+/// `bytes` split into chunks of up to `record_size` bytes, each with the address it starts at.
+/// Chunks never cross from one 64 KiB segment into the next, so a 16-bit address plus the
+/// segment always reaches the whole chunk. Addresses are 32-bit, the most either record format
+/// has room for, which a program would need 8 GiB of opcodes to go past.
+fn records(bytes: &[u8], record_size: usize) -> impl Iterator<Item = (u32, &[u8])> {
+    bytes
+        .chunks(0x1_0000)
+        .enumerate()
+        .flat_map(move |(segment, bytes)| {
+            bytes
+                .chunks(record_size)
+                .enumerate()
+                .map(move |(index, chunk)| {
+                    ((segment * 0x1_0000 + index * record_size) as u32, chunk)
+                })
+        })
+}
+
+/// Quotes a CSV field if it needs it, doubling any quotes inside.
+fn csv_field(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\r', '\n']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// Joins JSON values up with commas, for the inside of an array or object.
+fn json_join(values: impl Iterator<Item = String>) -> String {
+    values.collect::<Vec<_>>().join(",")
+}
+
+/// A JSON value, or `null` for `None`.
+fn json_option(value: Option<impl ToString>) -> String {
+    value.map_or_else(|| String::from("null"), |value| value.to_string())
+}
+
+/// Quotes and escapes `value` as a JSON string.
+pub(crate) fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for char in value.chars() {
+        match char {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            char if char.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", u32::from(char));
+            }
+            char => escaped.push(char),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        AssemblerOptions, EmitOptions, Grouping, HexOptions, OutputFormat, Program,
+        DEFAULT_PASTE_LENGTH,
+    };
+
+    #[test]
+    fn handles_emit() {
+        let program = Program::from_assembly("OEN 0\nloop: LD 7 ; read\nJMP loop\n.raw \"F\"");
+        assert_eq!(program.emit(OutputFormat::Hex).unwrap(), b"B017C2F");
+        assert_eq!(
+            program.emit(OutputFormat::Binary).unwrap(),
+            [0xB0, 0x17, 0xC2, 0xF0]
+        );
+        assert_eq!(
+            String::from_utf8(program.emit(OutputFormat::Json).unwrap()).unwrap(),
+            concat!(
+                r#"{"opcodes":"B017C2F","instructions":["#,
+                r#"{"address":0,"opcode":11,"mnemonic":"OEN","operand":0,"assembly":"OEN 0","line":1,"source":"OEN 0"},"#,
+                r#"{"address":2,"opcode":1,"mnemonic":"LD","operand":7,"assembly":"LD 7","line":2,"source":"loop: LD 7 ; read"},"#,
+                r#"{"address":4,"opcode":12,"mnemonic":"JMP","operand":2,"assembly":"JMP 2","line":3,"source":"JMP loop"},"#,
+                r#"{"address":6,"opcode":15,"mnemonic":null,"operand":null,"assembly":".raw \"F\"","line":4,"source":".raw \"F\""}],"#,
+                r#""symbols":[{"name":"loop","kind":"label","value":2,"line":2}],"#,
+                r#""stats":{"size":7,"instructions":{"JMP":1,"LD":1,"OEN":1},"invalid":1,"#,
+                r#""operands":[1,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0]},"#,
+                r#""metadata":{"name":null,"author":null,"version":null,"description":null}}"#,
+            )
+        );
+        assert_eq!(
+            String::from_utf8(program.emit(OutputFormat::Listing).unwrap()).unwrap(),
+            concat!(
+                "   1  00  B0  OEN 0       OEN 0\n",
+                "   2  02  17  LD 7        loop: LD 7 ; read\n",
+                "   3  04  C2  JMP 2       JMP loop\n",
+                "   4  06  F   .raw \"F\"    .raw \"F\"\n",
+                "\n",
+                "Symbols:\n",
+                "  loop            label     02\n",
+            )
+        );
+
+        assert_eq!(
+            String::from_utf8(program.emit(OutputFormat::IntelHex).unwrap()).unwrap(),
+            ":04000000B017C2F083\n:00000001FF\n"
+        );
+        assert_eq!(
+            String::from_utf8(program.emit(OutputFormat::SRecord).unwrap()).unwrap(),
+            "S1070000B017C2F07F\nS9030000FC\n"
+        );
+
+        let program = Program::from_assembly("STO");
+        assert!(program.emit(OutputFormat::Listing).is_err());
+
+        for format in OutputFormat::ALL {
+            assert_eq!(OutputFormat::from_name(format.name()), Some(format));
+        }
+        assert_eq!(OutputFormat::from_name("elf"), None);
+    }
+
+    #[test]
+    fn handles_hex_options() {
+        let program = Program::from_assembly("OEN 0\nLD 7\nSKZ\nJMP 0\nSTOC A");
+        assert_eq!(
+            program.to_opcodes_with(&HexOptions::new()).unwrap(),
+            program.to_opcodes().unwrap()
+        );
+        assert_eq!(
+            program
+                .to_opcodes_with(&HexOptions::new().lowercase(true))
+                .unwrap(),
+            "b017ec09a"
+        );
+        assert_eq!(
+            program
+                .to_opcodes_with(&HexOptions::new().grouping(Grouping::Instructions))
+                .unwrap(),
+            "B0 17 E C0 9A"
+        );
+        assert_eq!(
+            program
+                .to_opcodes_with(
+                    &HexOptions::new()
+                        .grouping(Grouping::Nibbles(4))
+                        .separator("_")
+                        .groups_per_line(2)
+                )
+                .unwrap(),
+            "B017_EC09\nA"
+        );
+        assert_eq!(
+            program
+                .to_opcodes_with(
+                    &HexOptions::new()
+                        .grouping(Grouping::Instructions)
+                        .groups_per_line(2)
+                )
+                .unwrap(),
+            "B0 17\nE C0\n9A"
+        );
+        assert_eq!(
+            program
+                .to_opcodes_with(&HexOptions::new().grouping(Grouping::Nibbles(0)))
+                .unwrap(),
+            "B 0 1 7 E C 0 9 A"
+        );
+
+        let program = Program::from_assembly("");
+        assert_eq!(
+            program
+                .to_opcodes_with(&HexOptions::new().grouping(Grouping::Nibbles(2)))
+                .unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn handles_paste() {
+        let program = Program::from_assembly("OEN 0\nLD 7\nSKZ\nJMP 0\nSTOC A");
+        assert_eq!(
+            program.to_paste(DEFAULT_PASTE_LENGTH).unwrap(),
+            "B017EC09A\n"
+        );
+        assert_eq!(program.emit(OutputFormat::Paste).unwrap(), b"B017EC09A\n");
+
+        // `SKZ` fits after `LD 7`, but `JMP 0` doesn't
+        assert_eq!(program.paste_chunks(5).unwrap(), ["B017E", "C09A"]);
+        assert_eq!(
+            program.to_paste(5).unwrap(),
+            "Part 1 of 2\nB017E\n\nPart 2 of 2\nC09A\n"
+        );
+        assert_eq!(program.paste_chunks(0), program.paste_chunks(2));
+
+        let program = Program::from_assembly("");
+        assert_eq!(program.paste_chunks(4).unwrap(), [""]);
+    }
+
+    #[test]
+    fn handles_source_maps() {
+        let program = Program::from_assembly(".macro twice\n  LD 7\n  LD 7\n.endm\nOEN 0\n  twice");
+        assert_eq!(
+            program.to_source_map().unwrap(),
+            concat!(
+                r#"{"version":1,"files":[],"mappings":["#,
+                r#"{"file":null,"line":5,"column":1},{"file":null,"line":5,"column":5},"#,
+                r#"{"file":null,"line":2,"column":3},{"file":null,"line":2,"column":6},"#,
+                r#"{"file":null,"line":3,"column":3},{"file":null,"line":3,"column":6}]}"#,
+            )
+        );
+        assert_eq!(
+            Program::from_assembly("").to_source_map().unwrap(),
+            r#"{"version":1,"files":[],"mappings":[]}"#
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn handles_source_map_files() {
+        let path = std::env::temp_dir().join(format!(
+            "goonstation-asm source map {}.s",
+            std::process::id()
+        ));
+        std::fs::write(&path, "LD 7\nRTN").unwrap();
+
+        let program = Program::from_file(&path).unwrap();
+        let map = program.to_source_map().unwrap();
+        assert!(map.starts_with(&format!(
+            r#"{{"version":1,"files":[{}],"mappings":[{{"file":0,"line":1,"column":1}}"#,
+            crate::emit::json_string(&path.display().to_string())
+        )));
+        assert!(map.ends_with(r#"{"file":0,"line":2,"column":1}]}"#));
+    }
+
+    #[test]
+    fn handles_emit_options() {
+        let program = Program::from_assembly("OEN 0\nLD 7");
+        let terminate = EmitOptions::new().terminate(true);
+        assert_eq!(
+            program.emit_with(OutputFormat::Hex, &terminate).unwrap(),
+            b"B017C0"
+        );
+
+        let pad = EmitOptions::new().pad(true);
+        let padded = program.emit_with(OutputFormat::Hex, &pad).unwrap();
+        assert_eq!(padded, format!("B017{}", "0".repeat(124)).as_bytes());
+
+        let both = EmitOptions::new().pad(true).terminate(true);
+        let finished = program.finished(&both).unwrap();
+        assert_eq!(
+            finished.to_opcodes().unwrap(),
+            format!("B017C0{}", "0".repeat(122))
+        );
+        assert_eq!(finished.source, program.source);
+        let listing = finished.to_listing().unwrap();
+        assert!(listing.contains("   2  02  17  LD 7        LD 7\n    + 04  C0  JMP 0\n"));
+        assert!(listing.contains("    + 7F  0   NOP\n"));
+
+        // Nothing gets added to the source, so it works whatever directives are allowed
+        let options = AssemblerOptions::new().allowed_directives(["org"]);
+        let program = Program::from_assembly_with("OEN 0\nLD 7", options);
+        assert_eq!(program.emit_with(OutputFormat::Hex, &pad).unwrap(), padded);
+
+        // Padding goes up to whatever the limit is
+        let program = Program::from_assembly_with("LD 7", AssemblerOptions::new().max_length(6));
+        assert_eq!(
+            program.finished(&both).unwrap().to_opcodes().unwrap(),
+            "17C000"
+        );
+
+        // There's no room for the `JMP 0`
+        let program = Program::from_assembly_with("LD 7", AssemblerOptions::new().max_length(3));
+        assert!(program.finished(&terminate).is_err());
+        assert_eq!(program.finished(&pad).unwrap().to_opcodes().unwrap(), "170");
+    }
+
+    #[test]
+    fn handles_base64() {
+        let program = Program::from_assembly("OEN 0\nLD 7\nSTO 8\nRTN");
+        assert_eq!(program.to_base64().unwrap(), "sBeI0A==");
+        assert_eq!(program.emit(OutputFormat::Base64).unwrap(), b"sBeI0A==");
+
+        let program = Program::from_assembly(".rept 16\nLD 7\nSTO 8\n.endr");
+        let compressed = program.to_deflate_base64().unwrap();
+        assert_eq!(compressed, "E+/ADwE=");
+        assert!(compressed.len() < program.to_base64().unwrap().len());
+    }
+
+    #[test]
+    fn handles_arrays() {
+        let program = Program::from_assembly("OEN 0\nLD 7\nRTN");
+        assert_eq!(
+            program.to_rust_array("BLINK").unwrap(),
+            "const BLINK: [u8; 3] = [0xB0, 0x17, 0xD0];\n"
+        );
+        assert_eq!(
+            program.emit(OutputFormat::CArray).unwrap(),
+            b"const unsigned char PROGRAM[3] = {0xB0, 0x17, 0xD0};\n"
+        );
+
+        let program = Program::from_assembly(".rept 13\nLD 7\n.endr");
+        assert_eq!(
+            program.to_c_array("PROGRAM").unwrap(),
+            concat!(
+                "const unsigned char PROGRAM[13] = {\n",
+                "    0x17, 0x17, 0x17, 0x17, 0x17, 0x17, 0x17, 0x17, 0x17, 0x17, 0x17, 0x17,\n",
+                "    0x17,\n",
+                "};\n",
+            )
+        );
+
+        let program = Program::from_assembly("");
+        assert_eq!(
+            program.to_rust_array("EMPTY").unwrap(),
+            "const EMPTY: [u8; 0] = [];\n"
+        );
+    }
+
+    #[test]
+    fn handles_memh() {
+        let program = Program::from_assembly("OEN 0\nLD 7\nRTN\n.raw \"F\"");
+        assert_eq!(
+            String::from_utf8(program.emit(OutputFormat::Memh).unwrap()).unwrap(),
+            "B // OEN 0\n0\n1 // LD 7\n7\nD // RTN\nF // .raw \"F\"\n"
+        );
+    }
+
+    #[test]
+    fn handles_hexdump() {
+        let program = Program::from_assembly(concat!(
+            "OEN 0\nLD 7\nSTO 8\nLD 1\nSTO 9\nLD 2\nSTO A\nSKZ\n",
+            "JMP 0\nRTN\n.raw \"F\"",
+        ));
+        assert_eq!(
+            String::from_utf8(program.emit(OutputFormat::Hexdump).unwrap()).unwrap(),
+            concat!(
+                "00: B017 8811 8912 8AEC  OEN 0; LD 7; STO 8; LD 1; STO 9; LD 2; STO A; SKZ; JMP 0\n",
+                "10: 0DF                  RTN; .raw \"F\"\n",
+            )
+        );
+
+        assert_eq!(Program::from_assembly("").to_hexdump().unwrap(), "");
+    }
+
+    #[test]
+    fn handles_csv() {
+        let program =
+            Program::from_assembly("OEN 0\n.equ PIN 7\nLD PIN ; read, then store\n.raw \"F\"");
+        assert_eq!(
+            String::from_utf8(program.emit(OutputFormat::Csv).unwrap()).unwrap(),
+            concat!(
+                "address,opcode,mnemonic,operand,line,source\r\n",
+                "0,11,OEN,0,1,OEN 0\r\n",
+                "2,1,LD,7,3,\"LD PIN ; read, then store\"\r\n",
+                "4,15,,,4,\".raw \"\"F\"\"\"\r\n",
+            )
+        );
+    }
+
+    #[test]
+    fn handles_dot() {
+        let program = Program::from_assembly("loop: LD 1\nSKZ\nJMP loop\n.raw \"F\"");
+        assert_eq!(
+            String::from_utf8(program.emit(OutputFormat::Dot).unwrap()).unwrap(),
+            concat!(
+                "digraph program {\n",
+                "    node [shape=box, fontname=\"monospace\"];\n",
+                "    n0 [label=\"00: LD 1\"];\n",
+                "    n2 [label=\"02: SKZ\"];\n",
+                "    n3 [label=\"03: JMP 0\"];\n",
+                "    n5 [label=\"05: .raw \\\"F\\\"\"];\n",
+                "    n0 -> n2;\n",
+                "    n2 -> n3;\n",
+                "    n2 -> n5 [label=\"SKZ\", style=dashed];\n",
+                "    n3 -> n0 [label=\"JMP\"];\n",
+                "    n5 -> n0 [style=dotted];\n",
+                "}\n",
+            )
+        );
+    }
+
+    #[test]
+    fn handles_listing() {
+        let program = Program::from_assembly(concat!(
+            "; Copies the input through\n",
+            ".equ PIN 7\n",
+            ".macro copy FROM, TO\n",
+            "LD FROM\n",
+            "STO TO\n",
+            ".endm\n",
+            "\n",
+            "OEN 0 ; enable output\n",
+            "copy PIN, 8\n",
+            "RTN\n",
+        ));
+        assert_eq!(
+            program.to_listing().unwrap(),
+            concat!(
+                "   1                      ; Copies the input through\n",
+                "   2                      .equ PIN 7\n",
+                "   3                      .macro copy FROM, TO\n",
+                "   4                      LD FROM\n",
+                "   5                      STO TO\n",
+                "   6                      .endm\n",
+                "   7\n",
+                "   8  00  B0  OEN 0       OEN 0 ; enable output\n",
+                "   9                      copy PIN, 8\n",
+                "   4+ 02  17  LD 7        LD FROM\n",
+                "   5+ 04  88  STO 8       STO TO\n",
+                "  10  06  D   RTN         RTN\n",
+                "\n",
+                "Symbols:\n",
+                "  PIN             constant  07\n",
+            )
+        );
+    }
+
+    #[test]
+    fn handles_metadata() {
+        let program = Program::from_assembly(".name \"Blink\"\n.author \"Nick\"\nRTN");
+        assert!(program.to_json().unwrap().ends_with(
+            r#""metadata":{"name":"Blink","author":"Nick","version":null,"description":null}}"#
+        ));
+        assert_eq!(
+            program.to_listing().unwrap(),
+            concat!(
+                "   1                      .name \"Blink\"\n",
+                "   2                      .author \"Nick\"\n",
+                "   3  00  D   RTN         RTN\n",
+                "\n",
+                "Metadata:\n",
+                "  name            Blink\n",
+                "  author          Nick\n",
+            )
+        );
+    }
+
+    #[test]
+    fn handles_map() {
+        let program = Program::from_assembly(concat!(
+            ".equ PIN 7\n",
+            ".macro copy FROM, TO\n",
+            "LD FROM\n",
+            "STO TO\n",
+            ".endm\n",
+            ".macro twice FROM, TO\n",
+            "copy FROM, TO\n",
+            "copy FROM, TO\n",
+            ".endm\n",
+            "OEN 0\n",
+            "twice PIN, 8\n",
+            "loop: copy 1, 9\n",
+        ));
+        assert_eq!(
+            program.to_map().unwrap(),
+            concat!(
+                "Symbols:\n",
+                "  PIN             constant  07  1:6\n",
+                "  loop            label     0A  12:1\n",
+                "\n",
+                "Macro expansions:\n",
+                "  twice           02..0A    11:1\n",
+                "    copy          02..06    7:1\n",
+                "    copy          06..0A    8:1\n",
+                "  copy            0A..0E    12:7\n",
+            )
+        );
+    }
+
+    #[test]
+    fn handles_intel_hex() {
+        let program = Program::from_assembly("OEN 0\nLD 7\nSTO 8\nJMP 0\nRTN");
+        assert_eq!(
+            program.to_intel_hex(2).unwrap(),
+            ":02000000B01737\n:0200020088C0B4\n:01000400D02B\n:00000001FF\n"
+        );
+
+        // Record sizes are kept to what fits in a record
+        assert_eq!(program.to_intel_hex(0), program.to_intel_hex(1));
+        assert_eq!(program.to_intel_hex(1000), program.to_intel_hex(255));
+
+        let program = Program::from_assembly("");
+        assert_eq!(program.to_intel_hex(16).unwrap(), ":00000001FF\n");
+
+        // Past 64 KiB, each segment gets its own extended address, and no record crosses into it
+        let raw = format!(".raw \"{}\"", "17".repeat(0x1_0002));
+        let program =
+            Program::from_assembly_with(&raw, AssemblerOptions::new().max_length(0x2_0004));
+        let hex = program.to_intel_hex(255).unwrap();
+        assert!(hex.contains(":01FFFF0017EA\n:020000040001F9\n:020000001717D0\n:00000001FF\n"));
+        assert_eq!(
+            Program::disassemble(hex.as_bytes()).unwrap().to_bytes(),
+            program.to_bytes()
+        );
+    }
+
+    #[test]
+    fn handles_srecords() {
+        let program = Program::from_assembly("OEN 0\nLD 7\nSTO 8\nJMP 0\nRTN");
+        assert_eq!(
+            program.to_srecords(2).unwrap(),
+            "S1050000B01733\nS105000288C0B0\nS1040004D027\nS9030000FC\n"
+        );
+
+        assert_eq!(program.to_srecords(0), program.to_srecords(1));
+        assert_eq!(program.to_srecords(1000), program.to_srecords(252));
+
+        let program = Program::from_assembly("");
+        assert_eq!(program.to_srecords(16).unwrap(), "S9030000FC\n");
+
+        // Past 64 KiB, the addresses get a third byte
+        let raw = format!(".raw \"{}\"", "17".repeat(0x1_0002));
+        let program =
+            Program::from_assembly_with(&raw, AssemblerOptions::new().max_length(0x2_0004));
+        let srec = program.to_srecords(16).unwrap();
+        assert!(srec.starts_with("S2140000001717"));
+        assert!(srec.ends_with("S2060100001717CA\nS804000000FB\n"));
+        assert_eq!(program.to_srecords(1000), program.to_srecords(251));
+        assert_eq!(
+            Program::disassemble(srec.as_bytes()).unwrap().to_bytes(),
+            program.to_bytes()
+        );
+    }
+}
@@ -0,0 +1,818 @@
+//! Running programs the way the Control Unit does, for trying them out without loading them into
+//! the game.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+
+use crate::assembler::{NOT_RR, SCRATCH_RAM};
+use crate::instruction;
+use crate::prelude::*;
+use crate::{AssemblerError, Instruction, Program};
+
+pub use assertion::{Assertion, AssertionResult};
+pub use condition::{Condition, ConditionError};
+pub use ticks::{Reaction, DEFAULT_CYCLES_PER_TICK};
+pub use trace::{Registers, Trace, TraceEntry};
+pub use vectors::{TestVector, TestVectors, VectorError, VectorResult};
+
+use trace::Snapshot;
+
+mod assertion;
+mod condition;
+mod ticks;
+mod trace;
+mod vcd;
+mod vectors;
+
+/// How many cycles [`Emulator::run_until_halt`] runs for before giving up, unless
+/// [`Emulator::set_budget`] says otherwise.
+pub const DEFAULT_BUDGET: u64 = 100_000;
+
+/// Why [`Emulator::run_for`] or [`Emulator::run_until_halt`] stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Stop {
+    /// Nothing can happen any more: the program is empty, or about to run a `JMP` to itself
+    Halted,
+    /// The cycles ran out first
+    BudgetExhausted,
+    /// The program counter reached a breakpoint at this address, which hasn't run yet
+    Breakpoint(usize),
+    /// The instruction at `pc` stored to a watched `address`, and has run
+    Watchpoint { address: u8, pc: usize },
+    /// A condition from [`Emulator::break_when`] held, numbered in the order they were added
+    Condition(usize),
+}
+
+/// Which stores to an address stop the emulator, from [`Emulator::set_watchpoint`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Watch {
+    /// Every store, even one that leaves the value as it was
+    Write,
+    /// Only stores that change the value
+    Change,
+}
+
+/// What an address refers to on the Control Unit, from [`Port::of`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Port {
+    /// One of the I/O pins, `0` to `7`. Reading it gives the pin's input and writing it sets
+    /// the pin's output, which are separate: storing to a pin doesn't change what it reads. Pin
+    /// `0` has no input, and reads the complement of the result register instead.
+    Pin(u8),
+    /// A bit of scratch RAM, `8` to `F`, which reads back whatever was last stored to it
+    Scratch(u8),
+}
+
+impl Port {
+    /// The port at `address`, or `None` if it doesn't fit in a nibble.
+    pub fn of(address: u8) -> Option<Self> {
+        match usize::from(address) {
+            address if address < SCRATCH_RAM.start => Some(Self::Pin(address as u8)),
+            address if SCRATCH_RAM.contains(&address) => Some(Self::Scratch(address as u8)),
+            _ => None,
+        }
+    }
+}
+
+/// Which chip [`Emulator`] behaves like.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Variant {
+    /// Goonstation's Control Unit
+    #[default]
+    Goonstation,
+    /// Motorola's MC14500B, for programs meant for real hardware. It differs from the Control
+    /// Unit in that:
+    ///
+    /// - Resetting clears `IEN` and `OEN` as well as the result register, so nothing is read or
+    ///   written until the program enables them.
+    /// - `RTN` doesn't go anywhere by itself: on real hardware it's up to whatever pops the
+    ///   return address off a stack, which isn't emulated. It does skip the instruction after it.
+    /// - `F` is `NOPF` rather than an invalid nibble. It does nothing but raise flag F, the same
+    ///   as `NOP` (`NOPO` on the MC14500B) raises flag O.
+    Mc14500b,
+}
+
+/// The MC14500B's pulse outputs, from [`Emulator::flags`]. The Control Unit doesn't have pins
+/// for these, but they're kept for either [`Variant`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Flags {
+    /// From `STO` or `STOC` while `OEN` is set
+    pub write: bool,
+    pub jmp: bool,
+    pub rtn: bool,
+    /// From `NOP`
+    pub flag_o: bool,
+    /// From `F`
+    pub flag_f: bool,
+}
+
+/// A Control Unit running a program, one instruction per [`Emulator::step`].
+///
+/// Instructions are read straight from the nibbles at the program counter, so a jump onto an
+/// operand runs it as an opcode, the same as in the game. `LD`, `AND`, `OR`, `XNOR` and their
+/// complements only see an input while `IEN` is set, and read `0` otherwise. `STO` and `STOC`
+/// only write while `OEN` is set. `IEN` and `OEN` load their operand regardless. `SKZ` skips
+/// the whole instruction after it when the result register is `0`, `RTN` goes back to the
+/// start, and so does running off the end. Nibbles that aren't instructions do nothing. See
+/// [`Variant`] for how a real MC14500B differs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Emulator {
+    variant: Variant,
+    nibbles: Vec<u8>,
+    pc: usize,
+    rr: bool,
+    ien: bool,
+    oen: bool,
+    skip: bool,
+    flags: Flags,
+    inputs: [bool; 8],
+    outputs: [bool; 8],
+    scratch: [bool; 8],
+    cycles: u64,
+    budget: u64,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeMap<u8, Watch>,
+    conditions: Vec<Condition>,
+    trace: Option<Trace>,
+    /// The watched address the last instruction stored to, if any
+    watched: Option<u8>,
+    cycles_per_tick: u64,
+    max_latency: Option<u64>,
+    /// The last input to change and when, until an output changes in response
+    unanswered: Option<(u8, u64)>,
+    /// Reactions so far, whose ticks [`Emulator::reactions`] works out again when read
+    reactions: Vec<Reaction>,
+}
+
+impl Emulator {
+    /// Assembles `program` and starts it from the beginning, with the result register clear,
+    /// inputs and outputs enabled, and every pin and bit of scratch RAM `0`.
+    pub fn new(program: &Program) -> Result<Self, AssemblerError> {
+        Self::with_variant(program, Variant::default())
+    }
+
+    /// Like [`Emulator::new`], but behaving like `variant`.
+    pub fn with_variant(program: &Program, variant: Variant) -> Result<Self, AssemblerError> {
+        let nibbles = instruction::nibbles(&program.to_opcodes()?);
+        Ok(Self::from_nibbles(nibbles, variant))
+    }
+
+    pub(crate) fn from_nibbles(nibbles: Vec<u8>, variant: Variant) -> Self {
+        let enabled = variant == Variant::Goonstation;
+        Self {
+            variant,
+            nibbles,
+            pc: 0,
+            rr: false,
+            ien: enabled,
+            oen: enabled,
+            skip: false,
+            flags: Flags::default(),
+            inputs: [false; 8],
+            outputs: [false; 8],
+            scratch: [false; 8],
+            cycles: 0,
+            budget: DEFAULT_BUDGET,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+            conditions: Vec::new(),
+            trace: None,
+            watched: None,
+            cycles_per_tick: DEFAULT_CYCLES_PER_TICK,
+            max_latency: None,
+            unanswered: None,
+            reactions: Vec::new(),
+        }
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// The address of the next instruction to run.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// The result register.
+    pub fn rr(&self) -> bool {
+        self.rr
+    }
+
+    pub fn ien(&self) -> bool {
+        self.ien
+    }
+
+    pub fn oen(&self) -> bool {
+        self.oen
+    }
+
+    /// Which flags the last instruction raised. Skipped instructions don't raise any.
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    /// How many instructions have been run, including skipped ones.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// How many cycles [`Emulator::run_until_halt`] runs for at most. Defaults to
+    /// [`DEFAULT_BUDGET`].
+    pub fn set_budget(&mut self, budget: u64) {
+        self.budget = budget;
+    }
+
+    /// Stops [`Emulator::run_for`] and [`Emulator::run_until_halt`] before they run the
+    /// instruction at `address`.
+    pub fn set_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn clear_breakpoint(&mut self, address: usize) {
+        self.breakpoints.remove(&address);
+    }
+
+    /// Stops [`Emulator::run_for`] and [`Emulator::run_until_halt`] after a store to `address`
+    /// that `watch` covers. Stores while `OEN` is clear don't happen, so they don't count.
+    pub fn set_watchpoint(&mut self, address: u8, watch: Watch) {
+        self.watchpoints.insert(address, watch);
+    }
+
+    pub fn clear_watchpoint(&mut self, address: u8) {
+        self.watchpoints.remove(&address);
+    }
+
+    /// Stops [`Emulator::run_for`] and [`Emulator::run_until_halt`] before running an
+    /// instruction while `condition` holds, with [`Stop::Condition`] giving the number this
+    /// returns.
+    pub fn break_when(&mut self, condition: Condition) -> usize {
+        self.conditions.push(condition);
+        self.conditions.len() - 1
+    }
+
+    pub fn clear_conditions(&mut self) {
+        self.conditions.clear();
+    }
+
+    /// Sets the input of pin `address`, as if a signal came in.
+    ///
+    /// Panics if `address` isn't a [`Port::Pin`], or is pin `0`, which has no input.
+    pub fn set_input(&mut self, address: u8, value: bool) {
+        self.time_input(address, value);
+        self.inputs[input(address)] = value;
+    }
+
+    /// What pin `address` is reading.
+    ///
+    /// Panics if `address` isn't a [`Port::Pin`], or is pin `0`, which has no input.
+    pub fn input(&self, address: u8) -> bool {
+        self.inputs[input(address)]
+    }
+
+    /// What the program last stored to pin `address`.
+    ///
+    /// Panics if `address` isn't a [`Port::Pin`].
+    pub fn output(&self, address: u8) -> bool {
+        self.outputs[pin(address)]
+    }
+
+    /// What the program last stored to scratch RAM at `address`.
+    ///
+    /// Panics if `address` isn't [`Port::Scratch`].
+    pub fn scratch(&self, address: u8) -> bool {
+        self.scratch[scratch(address)]
+    }
+
+    /// Sets scratch RAM at `address`, as if the program had stored it.
+    ///
+    /// Panics if `address` isn't [`Port::Scratch`].
+    pub fn set_scratch(&mut self, address: u8, value: bool) {
+        self.scratch[scratch(address)] = value;
+    }
+
+    /// What reading `address` gives, ignoring `IEN`.
+    fn read(&self, address: u8) -> bool {
+        match Port::of(address) {
+            Some(Port::Pin(NOT_RR)) => !self.rr,
+            Some(Port::Pin(address)) => self.input(address),
+            Some(Port::Scratch(address)) => self.scratch(address),
+            None => false,
+        }
+    }
+
+    /// The instruction at the program counter, as it would be run next. `None` for an empty
+    /// program.
+    pub fn next_instruction(&self) -> Option<Instruction> {
+        let opcode = *self.nibbles.get(self.pc)?;
+        let instruction = match Instruction::new(opcode, None) {
+            Some(instruction) => instruction,
+            None if opcode < 0xF => self
+                .nibbles
+                .get(self.pc + 1)
+                .and_then(|&operand| Instruction::new(opcode, Some(operand)))
+                .unwrap_or(Instruction::Invalid(opcode)),
+            None => Instruction::Invalid(opcode),
+        };
+
+        Some(instruction)
+    }
+
+    /// Starts recording every instruction that runs from now on, replacing any trace already
+    /// being recorded.
+    pub fn start_trace(&mut self) {
+        self.trace = Some(Trace::new(self));
+    }
+
+    /// Stops recording, giving back everything recorded since [`Emulator::start_trace`].
+    pub fn stop_trace(&mut self) -> Option<Trace> {
+        self.trace.take()
+    }
+
+    /// What's been recorded so far, if anything is being.
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
+
+    /// Runs the instruction at the program counter, or skips it after a `SKZ` that saw `0`, and
+    /// gives it back along with its address. `None` for an empty program, which never does
+    /// anything.
+    pub fn step(&mut self) -> Option<(usize, Instruction)> {
+        if self.trace.is_none() {
+            return self.execute();
+        }
+
+        let cycle = self.cycles;
+        let skipped = self.skip;
+        let before = Snapshot::of(self);
+        let step = self.execute()?;
+        let after = Snapshot::of(self);
+        if let Some(trace) = &mut self.trace {
+            trace.record(cycle, step, skipped, before, after);
+        }
+
+        Some(step)
+    }
+
+    fn execute(&mut self) -> Option<(usize, Instruction)> {
+        let address = self.pc;
+        let instruction = self.next_instruction()?;
+        self.cycles += 1;
+        self.pc = address + instruction.size();
+        self.flags = Flags::default();
+        self.watched = None;
+
+        if core::mem::take(&mut self.skip) {
+            self.wrap();
+            return Some((address, instruction));
+        }
+
+        let input = instruction
+            .operand()
+            .is_some_and(|operand| self.read(operand));
+        let data = self.ien && input;
+        match instruction {
+            Instruction::NoOp => self.flags.flag_o = true,
+            Instruction::Invalid(0xF) => self.flags.flag_f = true,
+            Instruction::Invalid(_) => {}
+            Instruction::Load(_) => self.rr = data,
+            Instruction::LoadComplement(_) => self.rr = !data,
+            Instruction::And(_) => self.rr &= data,
+            Instruction::AndComplement(_) => self.rr &= !data,
+            Instruction::Or(_) => self.rr |= data,
+            Instruction::OrComplement(_) => self.rr |= !data,
+            Instruction::ExclusiveNor(_) => self.rr = self.rr == data,
+            Instruction::Store(operand) => self.store(operand, self.rr),
+            Instruction::StoreComplement(operand) => self.store(operand, !self.rr),
+            Instruction::InputEnable(_) => self.ien = input,
+            Instruction::OutputEnable(_) => self.oen = input,
+            Instruction::Jump(target) => {
+                self.flags.jmp = true;
+                self.pc = usize::from(target);
+            }
+            Instruction::Return => {
+                self.flags.rtn = true;
+                match self.variant {
+                    Variant::Goonstation => self.pc = 0,
+                    Variant::Mc14500b => self.skip = true,
+                }
+            }
+            Instruction::SkipIfZero => self.skip = !self.rr,
+        }
+
+        self.wrap();
+        Some((address, instruction))
+    }
+
+    /// Runs for up to `cycles` cycles, stopping early if the program halts or reaches a
+    /// breakpoint, watchpoint or condition. Breakpoints and conditions don't count before the
+    /// first instruction, so running again carries on from where the last run stopped.
+    pub fn run_for(&mut self, cycles: u64) -> Stop {
+        for cycle in 0..cycles {
+            if self.is_halted() {
+                return Stop::Halted;
+            }
+
+            if cycle > 0 && self.breakpoints.contains(&self.pc) {
+                return Stop::Breakpoint(self.pc);
+            }
+
+            let holds = self
+                .conditions
+                .iter()
+                .position(|condition| condition.holds(self));
+            if let Some(index) = holds.filter(|_| cycle > 0) {
+                return Stop::Condition(index);
+            }
+
+            if let Some((pc, _)) = self.step() {
+                if let Some(address) = self.watched {
+                    return Stop::Watchpoint { address, pc };
+                }
+            }
+        }
+
+        if self.is_halted() {
+            Stop::Halted
+        } else {
+            Stop::BudgetExhausted
+        }
+    }
+
+    /// Runs until the program halts or reaches a breakpoint or watchpoint, giving up after the budget from
+    /// [`Emulator::set_budget`] so that programs that loop forever, like most do, still return.
+    pub fn run_until_halt(&mut self) -> Stop {
+        self.run_for(self.budget)
+    }
+
+    /// Whether nothing can happen any more: there's no instruction to run, or the next one is a
+    /// `JMP` to itself that isn't going to be skipped.
+    pub fn is_halted(&self) -> bool {
+        match self.next_instruction() {
+            None => true,
+            Some(Instruction::Jump(target)) => !self.skip && usize::from(target) == self.pc,
+            Some(_) => false,
+        }
+    }
+
+    fn store(&mut self, address: u8, value: bool) {
+        if !self.oen {
+            return;
+        }
+
+        self.flags.write = true;
+
+        let previous = match Port::of(address) {
+            Some(Port::Pin(address)) => core::mem::replace(&mut self.outputs[pin(address)], value),
+            Some(Port::Scratch(address)) => {
+                core::mem::replace(&mut self.scratch[scratch(address)], value)
+            }
+            None => return,
+        };
+
+        match self.watchpoints.get(&address) {
+            Some(Watch::Write) => self.watched = Some(address),
+            Some(Watch::Change) if previous != value => self.watched = Some(address),
+            _ => {}
+        }
+
+        if previous != value && Port::of(address) == Some(Port::Pin(address)) {
+            self.time_output(address);
+        }
+    }
+
+    /// Back to the start after running off the end, like the Control Unit.
+    fn wrap(&mut self) {
+        if self.pc >= self.nibbles.len() {
+            self.pc = 0;
+        }
+    }
+}
+
+/// The index of pin `address` in the emulator's pins.
+fn pin(address: u8) -> usize {
+    match Port::of(address) {
+        Some(Port::Pin(address)) => usize::from(address),
+        _ => panic!("{address:#X} isn't an I/O pin"),
+    }
+}
+
+/// The index of `address` in the emulator's inputs, which are the pins other than [`NOT_RR`].
+fn input(address: u8) -> usize {
+    match address {
+        NOT_RR => panic!("{address:#X} reads !RR rather than an input"),
+        address => pin(address),
+    }
+}
+
+/// The index of `address` in the emulator's scratch RAM.
+fn scratch(address: u8) -> usize {
+    match Port::of(address) {
+        Some(Port::Scratch(address)) => usize::from(address) - SCRATCH_RAM.start,
+        _ => panic!("{address:#X} isn't scratch RAM"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Condition, Emulator, Flags, Instruction, Port, Program, Stop, Variant, Watch};
+
+    fn start(assembly: &str) -> Emulator {
+        Emulator::new(&Program::from_assembly(assembly)).unwrap()
+    }
+
+    #[test]
+    fn handles_emulation() {
+        let mut emulator = start("LD 1\nSTOC 2\nRTN");
+        emulator.set_input(1, true);
+        assert_eq!(emulator.step(), Some((0, Instruction::Load(1))));
+        assert!(emulator.rr());
+        assert_eq!(emulator.step(), Some((2, Instruction::StoreComplement(2))));
+        assert!(!emulator.output(2));
+        assert_eq!(emulator.step(), Some((4, Instruction::Return)));
+        assert_eq!(emulator.pc(), 0);
+
+        emulator.set_input(1, false);
+        emulator.step();
+        emulator.step();
+        assert!(emulator.output(2));
+        assert_eq!(emulator.cycles(), 5);
+    }
+
+    #[test]
+    fn handles_logic() {
+        let mut emulator = start("LD 1\nAND 2\nSTO 8\nLD 1\nORC 2\nSTO 9\nLD 1\nXNOR 2\nSTO A");
+        emulator.set_input(1, true);
+        for _ in 0..9 {
+            emulator.step();
+        }
+        assert!(!emulator.scratch(8));
+        assert!(emulator.scratch(9));
+        assert!(!emulator.scratch(0xA));
+    }
+
+    #[test]
+    fn handles_enables() {
+        let mut emulator = start("IEN 1\nLD 2\nOEN 1\nSTOC 3");
+        emulator.set_input(2, true);
+        for _ in 0..4 {
+            emulator.step();
+        }
+        // With inputs disabled everything reads 0, and with outputs disabled nothing is written
+        assert!(!emulator.ien());
+        assert!(!emulator.rr());
+        assert!(!emulator.oen());
+        assert!(!emulator.output(3));
+    }
+
+    #[test]
+    fn handles_ports() {
+        assert_eq!(Port::of(0), Some(Port::Pin(0)));
+        assert_eq!(Port::of(7), Some(Port::Pin(7)));
+        assert_eq!(Port::of(8), Some(Port::Scratch(8)));
+        assert_eq!(Port::of(0xF), Some(Port::Scratch(0xF)));
+        assert_eq!(Port::of(0x10), None);
+
+        // Pins read their input no matter what's stored to them, while scratch RAM reads back
+        let mut emulator = start("LDC 3\nSTO 3\nLD 3\nSTO 9\nLDC 9\nSTO C");
+        emulator.run_for(6);
+        assert!(!emulator.input(3));
+        assert!(emulator.output(3));
+        assert!(!emulator.scratch(9));
+        assert!(emulator.scratch(0xC));
+
+        emulator.set_scratch(0xC, false);
+        assert!(!emulator.scratch(0xC));
+    }
+
+    #[test]
+    #[should_panic(expected = "0x8 isn't an I/O pin")]
+    fn handles_invalid_pins() {
+        start("").set_input(8, true);
+    }
+
+    #[test]
+    fn handles_not_rr() {
+        // The program from the reference, which turns the outputs on because RR starts at zero
+        let mut emulator = start("OEN 0\nSTO 0");
+        emulator.run_for(2);
+        assert!(emulator.oen());
+        assert!(!emulator.output(0));
+
+        let mut emulator = start("OEN 0\nSTOC 1\nhalt: JMP halt");
+        assert_eq!(emulator.run_until_halt(), Stop::Halted);
+        assert!(emulator.oen());
+        assert!(emulator.output(1));
+
+        // Loading it flips RR each time, whatever's been stored to pin 0
+        let mut emulator = start("LD 0\nSTO 0\nSTO 2\nLD 0\nSTO 3");
+        emulator.run_for(5);
+        assert!(emulator.output(0));
+        assert!(emulator.output(2));
+        assert!(!emulator.output(3));
+    }
+
+    #[test]
+    #[should_panic(expected = "0x0 reads !RR rather than an input")]
+    fn handles_setting_not_rr() {
+        start("").set_input(0, true);
+    }
+
+    #[test]
+    fn handles_skips_and_jumps() {
+        let mut emulator = start("LD 1\nSKZ\nJMP 0\nSTO 2");
+        assert_eq!(emulator.step(), Some((0, Instruction::Load(1))));
+        assert_eq!(emulator.step(), Some((2, Instruction::SkipIfZero)));
+        // Skipped instructions still take their turn
+        assert_eq!(emulator.step(), Some((3, Instruction::Jump(0))));
+        assert_eq!(emulator.step(), Some((5, Instruction::Store(2))));
+        assert_eq!(emulator.pc(), 0);
+
+        emulator.set_input(1, true);
+        emulator.step();
+        emulator.step();
+        emulator.step();
+        assert_eq!(emulator.pc(), 0);
+
+        // Onto `LD 0`'s operand, which is then read as a `NOP`
+        let mut emulator = start("JMP 3\nLD 0\nSTO 2");
+        emulator.step();
+        assert_eq!(emulator.next_instruction(), Some(Instruction::NoOp));
+        assert_eq!(emulator.step(), Some((3, Instruction::NoOp)));
+        assert_eq!(emulator.pc(), 4);
+    }
+
+    #[test]
+    fn handles_running() {
+        let mut emulator = start("LD 1\nSTO 2\nhalt: JMP halt");
+        assert_eq!(emulator.run_until_halt(), Stop::Halted);
+        assert_eq!(emulator.pc(), 4);
+        assert_eq!(emulator.cycles(), 2);
+        assert_eq!(emulator.run_for(10), Stop::Halted);
+        assert_eq!(emulator.cycles(), 2);
+
+        let mut emulator = start("LD 1\nSTO 2");
+        assert_eq!(emulator.run_for(3), Stop::BudgetExhausted);
+        assert_eq!(emulator.cycles(), 3);
+        emulator.set_budget(7);
+        assert_eq!(emulator.run_until_halt(), Stop::BudgetExhausted);
+        assert_eq!(emulator.cycles(), 10);
+
+        // A `JMP` to itself that gets skipped isn't stuck yet
+        let mut emulator = start("SKZ\nJMP 1\nSTO 2");
+        assert!(!emulator.is_halted());
+        emulator.step();
+        assert_eq!(emulator.pc(), 1);
+        assert!(!emulator.is_halted());
+        assert_eq!(emulator.run_for(10), Stop::BudgetExhausted);
+    }
+
+    #[test]
+    fn handles_breakpoints() {
+        let mut emulator = start("LD 1\nSTO 2\nSTO 3");
+        emulator.set_breakpoint(4);
+        assert_eq!(emulator.run_for(100), Stop::Breakpoint(4));
+        assert_eq!(emulator.cycles(), 2);
+
+        // Carries on past the breakpoint it stopped at, and reaches it again next time round
+        assert_eq!(emulator.run_for(100), Stop::Breakpoint(4));
+        assert_eq!(emulator.cycles(), 5);
+
+        emulator.clear_breakpoint(4);
+        assert_eq!(emulator.run_for(100), Stop::BudgetExhausted);
+    }
+
+    #[test]
+    fn handles_watchpoints() {
+        let mut emulator = start("LD 1\nSTO 2\nSTO 9\nhalt: JMP halt");
+        emulator.set_watchpoint(2, Watch::Change);
+        emulator.set_watchpoint(9, Watch::Write);
+        assert_eq!(
+            emulator.run_until_halt(),
+            Stop::Watchpoint { address: 9, pc: 4 }
+        );
+        assert_eq!(emulator.pc(), 6);
+        assert_eq!(emulator.run_until_halt(), Stop::Halted);
+
+        let mut emulator = start("LD 1\nSTO 2\nSTO 9");
+        emulator.set_watchpoint(2, Watch::Change);
+        emulator.set_input(1, true);
+        assert_eq!(
+            emulator.run_for(100),
+            Stop::Watchpoint { address: 2, pc: 2 }
+        );
+        assert!(emulator.output(2));
+
+        // Storing the same value again isn't a change
+        assert_eq!(emulator.run_for(100), Stop::BudgetExhausted);
+
+        emulator.set_input(1, false);
+        assert_eq!(
+            emulator.run_for(100),
+            Stop::Watchpoint { address: 2, pc: 2 }
+        );
+        emulator.clear_watchpoint(2);
+        emulator.set_input(1, true);
+        assert_eq!(emulator.run_for(100), Stop::BudgetExhausted);
+    }
+
+    #[test]
+    fn handles_conditional_breakpoints() {
+        let mut emulator = start("LD 1\nSTO 2\nLDC 1\nSTO 3");
+        let condition = Condition::parse("RR == 1 and PC > 2").unwrap();
+        assert_eq!(emulator.break_when(condition), 0);
+        assert_eq!(emulator.run_for(100), Stop::Condition(0));
+        assert_eq!(emulator.pc(), 6);
+
+        // Doesn't stop straight away again, but does next time it holds
+        assert_eq!(emulator.run_for(100), Stop::Condition(0));
+        assert_eq!(emulator.cycles(), 7);
+
+        emulator.clear_conditions();
+        assert_eq!(emulator.run_for(100), Stop::BudgetExhausted);
+    }
+
+    #[test]
+    fn handles_flags() {
+        let mut emulator = start("NOP\nSTO 1\nOEN 1\nSTO 1\nJMP 0");
+        emulator.step();
+        assert_eq!(
+            emulator.flags(),
+            Flags {
+                flag_o: true,
+                ..Flags::default()
+            }
+        );
+        emulator.step();
+        assert_eq!(
+            emulator.flags(),
+            Flags {
+                write: true,
+                ..Flags::default()
+            }
+        );
+        emulator.step();
+        emulator.step();
+        assert_eq!(emulator.flags(), Flags::default());
+        emulator.step();
+        assert_eq!(
+            emulator.flags(),
+            Flags {
+                jmp: true,
+                ..Flags::default()
+            }
+        );
+    }
+
+    #[test]
+    fn handles_mc14500b() {
+        let program = Program::from_assembly("ORC 0\nIEN 1\nOEN 1\nRTN\nSTO 2\nSTO 3\n.raw \"F\"");
+        let mut emulator = Emulator::with_variant(&program, Variant::Mc14500b).unwrap();
+        assert_eq!(emulator.variant(), Variant::Mc14500b);
+        assert!(!emulator.ien());
+        assert!(!emulator.oen());
+
+        // Inputs read 0 until `IEN`, but `IEN` and `OEN` read their pin regardless
+        emulator.set_input(1, true);
+        emulator.run_for(3);
+        assert!(emulator.rr());
+        assert!(emulator.ien());
+        assert!(emulator.oen());
+
+        // `RTN` skips the next instruction rather than going back to the start
+        emulator.step();
+        assert_eq!(
+            emulator.flags(),
+            Flags {
+                rtn: true,
+                ..Flags::default()
+            }
+        );
+        emulator.run_for(2);
+        assert!(!emulator.output(2));
+        assert!(emulator.output(3));
+
+        emulator.step();
+        assert_eq!(
+            emulator.flags(),
+            Flags {
+                flag_f: true,
+                ..Flags::default()
+            }
+        );
+        assert_eq!(emulator.pc(), 0);
+    }
+
+    #[test]
+    fn handles_empty_programs() {
+        let mut emulator = start("");
+        assert_eq!(emulator.step(), None);
+        assert_eq!(emulator.run_until_halt(), Stop::Halted);
+        assert_eq!(emulator.cycles(), 0);
+    }
+}
@@ -0,0 +1,259 @@
+//! `.assert` directives, which keep tests for a program next to the code they test. They don't
+//! emit anything, and are only checked by [`Program::check_assertions`], each in an emulator of
+//! its own.
+
+use core::fmt;
+
+use super::vectors::assignment;
+use super::{Condition, Emulator, Variant};
+use crate::prelude::*;
+use crate::{instruction, AssemblerError, Location, Program};
+
+/// An `.assert` in a program, like `.assert out[3] == 1 after 12 cycles with in[1]=1`: a
+/// [`Condition`], then optionally `after` and how many cycles to run first, then optionally
+/// `with` and the inputs to set before starting.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Assertion {
+    /// Everything after `.assert`, with any macro arguments filled in
+    pub text: String,
+    pub condition: Condition,
+    /// How many cycles to run before checking, or `None` to run until the program halts
+    pub cycles: Option<u64>,
+    /// Each pin to set before starting, and what to set its input to
+    pub inputs: Vec<(u8, bool)>,
+    pub location: Location,
+}
+
+/// How an [`Assertion`] went, from [`Program::check_assertions`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AssertionResult {
+    pub assertion: Assertion,
+    pub passed: bool,
+    /// How many cycles actually ran, which is fewer than asked for if the program halted
+    pub cycles: u64,
+}
+
+impl Assertion {
+    /// Reads the text after `.assert`, giving a message saying what's wrong if it can't.
+    pub(crate) fn parse(text: &str, location: Location) -> Result<Self, String> {
+        let (rest, inputs) = split_at_word(text, "with");
+        let (condition, cycles) = split_at_word(rest, "after");
+
+        let condition = Condition::parse(condition.trim()).map_err(|error| error.to_string())?;
+        let cycles = cycles
+            .map(|cycles| match cycles.split_whitespace().collect::<Vec<_>>()[..] {
+                [cycles] | [cycles, "cycle" | "cycles"] => cycles
+                    .parse()
+                    .map_err(|_| format!("`{cycles}` isn't a number of cycles")),
+                _ => Err(format!(
+                    "Expected a number of cycles after `after`, like `after 12 cycles`, but found `{}`",
+                    cycles.trim()
+                )),
+            })
+            .transpose()?;
+        let inputs = inputs
+            .unwrap_or_default()
+            .split(|character: char| character == ',' || character.is_whitespace())
+            .filter(|input| !input.is_empty())
+            .map(|input| {
+                assignment(input, true)
+                    .ok_or_else(|| format!("`{input}` isn't an input, like `in[1]=1`"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            text: text.trim().to_string(),
+            condition,
+            cycles,
+            inputs,
+            location,
+        })
+    }
+
+    /// Runs `nibbles` in a fresh emulator with the assertion's inputs, and checks the condition.
+    fn check(self, nibbles: Vec<u8>) -> AssertionResult {
+        let mut emulator = Emulator::from_nibbles(nibbles, Variant::default());
+        for &(pin, value) in &self.inputs {
+            emulator.set_input(pin, value);
+        }
+
+        match self.cycles {
+            Some(cycles) => emulator.run_for(cycles),
+            None => emulator.run_until_halt(),
+        };
+
+        AssertionResult {
+            passed: self.condition.holds(&emulator),
+            cycles: emulator.cycles(),
+            assertion: self,
+        }
+    }
+}
+
+/// Splits `text` around the first whitespace-separated `word`, ignoring case.
+fn split_at_word<'a>(text: &'a str, word: &str) -> (&'a str, Option<&'a str>) {
+    let mut offset = 0;
+    for segment in text.split_inclusive(char::is_whitespace) {
+        if segment.trim_end().eq_ignore_ascii_case(word) {
+            return (&text[..offset], Some(&text[offset + word.len()..]));
+        }
+
+        offset += segment.len();
+    }
+
+    (text, None)
+}
+
+impl Program {
+    /// Runs every `.assert` in the program, in the order they appear, each in a fresh emulator
+    /// behaving like [`Variant::Goonstation`].
+    pub fn check_assertions(&self) -> Result<Vec<AssertionResult>, AssemblerError> {
+        let assembled = self
+            .assemble(false)
+            .map_err(|mut errors| errors.remove(0))?;
+        let nibbles = instruction::nibbles(&assembled.opcodes);
+
+        Ok(assembled
+            .assertions
+            .into_iter()
+            .map(|assertion| assertion.check(nibbles.clone()))
+            .collect())
+    }
+}
+
+/// Like ``3:1: `out[3] == 1 after 12 cycles` failed after 12 cycles``, or `passed`.
+impl fmt::Display for AssertionResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: `{}` ", self.assertion.location, self.assertion.text)?;
+        if self.passed {
+            f.write_str("passed")
+        } else {
+            write!(f, "failed after {} cycles", self.cycles)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AssemblerError, Program};
+
+    #[test]
+    fn handles_assertions() {
+        let program = Program::from_assembly(concat!(
+            "LD 1\n",
+            ".assert out[3] == 1 after 2 cycles with in[1]=1 ; copies the input\n",
+            "STO 3\n",
+            ".assert out[3] == 1 after 2 cycles\n",
+            ".assert ram[9] == 0 and in[1] AFTER 1 cycle WITH in[1]=1, in[2]=1\n",
+            "loop: JMP loop\n",
+            ".assert pc == 4 && out[3] == 0\n",
+        ));
+        assert_eq!(program.to_opcodes().unwrap(), "1183C4");
+
+        let assertions = program.assertions().unwrap();
+        assert_eq!(assertions.len(), 4);
+        assert_eq!(
+            assertions[0].text,
+            "out[3] == 1 after 2 cycles with in[1]=1"
+        );
+        assert_eq!(assertions[0].cycles, Some(2));
+        assert_eq!(assertions[0].inputs, [(1, true)]);
+        assert_eq!(assertions[0].location.line, 2);
+        assert_eq!(assertions[2].inputs, [(1, true), (2, true)]);
+        assert_eq!(assertions[3].cycles, None);
+
+        let results = program.check_assertions().unwrap();
+        let passed: Vec<_> = results.iter().map(|result| result.passed).collect();
+        assert_eq!(passed, [true, false, true, true]);
+        assert_eq!(results[3].cycles, 2);
+        assert_eq!(
+            results[1].to_string(),
+            "4:1: `out[3] == 1 after 2 cycles` failed after 2 cycles"
+        );
+        assert_eq!(
+            results[0].to_string(),
+            "2:1: `out[3] == 1 after 2 cycles with in[1]=1` passed"
+        );
+    }
+
+    #[test]
+    fn handles_assertions_in_macros() {
+        let program = Program::from_assembly(concat!(
+            ".macro COPY from, to\n",
+            "LD from\n",
+            "STO to\n",
+            ".assert out[to] == in[from] after 2 cycles with in[from]=1 ; to is out[to]\n",
+            ".endm\n",
+            "COPY 1, 3\n",
+            "COPY 0x2, 5\n",
+            ".rept 2\n",
+            ".assert out[3] == 1 after 2 cycles with in[1]=1\n",
+            ".endr\n",
+        ));
+        assert_eq!(program.to_opcodes().unwrap(), "11831285");
+
+        let assertions = program.assertions().unwrap();
+        let texts: Vec<_> = assertions
+            .iter()
+            .map(|assertion| assertion.text.as_str())
+            .collect();
+        assert_eq!(
+            texts,
+            [
+                "out[3] == in[1] after 2 cycles with in[1]=1",
+                "out[5] == in[2] after 2 cycles with in[2]=1",
+                "out[3] == 1 after 2 cycles with in[1]=1",
+                "out[3] == 1 after 2 cycles with in[1]=1",
+            ]
+        );
+        assert_eq!(assertions[1].inputs, [(2, true)]);
+
+        let results = program.check_assertions().unwrap();
+        let passed: Vec<_> = results.iter().map(|result| result.passed).collect();
+        assert_eq!(passed, [true, false, true, true]);
+    }
+
+    #[test]
+    fn handles_assertion_errors() {
+        let error = |source| match Program::from_assembly(source).to_opcodes().unwrap_err() {
+            AssemblerError::InvalidAssertion { message, location } => (message, location.line),
+            error => panic!("unexpected error {error:?}"),
+        };
+
+        assert_eq!(
+            error("NOP\n.assert out[3] = 1"),
+            ("`=` at position 8 can't start anything".to_string(), 2)
+        );
+        assert_eq!(
+            error(".assert rr after soon"),
+            ("`soon` isn't a number of cycles".to_string(), 1)
+        );
+        assert_eq!(
+            error(".assert rr after 3 days").0,
+            "Expected a number of cycles after `after`, like `after 12 cycles`, but found `3 days`"
+        );
+        assert_eq!(
+            error(".assert rr with out[1]=1").0,
+            "`out[1]=1` isn't an input, like `in[1]=1`"
+        );
+        assert_eq!(
+            Program::from_assembly(".assert")
+                .to_opcodes()
+                .unwrap_err()
+                .to_string(),
+            "1:1: Invalid `.assert`: Expected a number, a name or `(`, but the condition ended"
+        );
+
+        let nested = format!(
+            "NOP\n.assert {}RR == 1{}",
+            "(".repeat(200_000),
+            ")".repeat(200_000)
+        );
+        assert_eq!(
+            error(&nested),
+            ("The condition is too complex from position 257 on, since it can only be 256 numbers, names and symbols long".to_string(), 2)
+        );
+    }
+}
@@ -0,0 +1,603 @@
+//! Conditions over the emulator's state, like `RR == 1 and PC > 0x20`, for breakpoints that only
+//! stop when something's true.
+//!
+//! A condition compares `RR`, `IEN`, `OEN`, `PC`, `CYCLES`, `in[N]`, `out[N]` and `ram[N]` with
+//! numbers (decimal or `0x` hex) using `==`, `!=`, `<`, `<=`, `>` and `>=`, and combines them with
+//! `and`, `or` and `not` (or `&&`, `||` and `!`) and parentheses. Flags read as `0` or `1`, and
+//! anything that isn't `0` counts as true. Names are case-insensitive.
+
+use core::fmt;
+use core::str::FromStr;
+
+use super::{Emulator, Port};
+use crate::assembler::NOT_RR;
+use crate::prelude::*;
+
+/// How many numbers, names, operators and brackets a condition can be made of. Parsing and
+/// evaluating both recurse, so without a limit a long enough condition would overflow the stack.
+const MAX_TOKENS: usize = 256;
+
+/// A parsed condition, for [`Emulator::break_when`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Condition {
+    expression: Expression,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Expression {
+    Number(u64),
+    Value(Value),
+    Not(Box<Expression>),
+    Binary(Box<Expression>, Operator, Box<Expression>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Value {
+    Rr,
+    Ien,
+    Oen,
+    Pc,
+    Cycles,
+    Input(u8),
+    Output(u8),
+    Scratch(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Operator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Number(u64),
+    Name(String),
+    Operator(Operator),
+    Not,
+    Open,
+    Close,
+    OpenBracket,
+    CloseBracket,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConditionError {
+    /// A character that can't start anything. `index` counts characters from 0, like the rest.
+    UnexpectedCharacter {
+        character: char,
+        index: usize,
+    },
+    /// A number that doesn't fit in 64 bits
+    InvalidNumber {
+        index: usize,
+    },
+    /// Something other than what was `expected`, or the end of the condition if `found` is `None`
+    Expected {
+        expected: &'static str,
+        found: Option<String>,
+        index: usize,
+    },
+    UnknownName {
+        name: String,
+        index: usize,
+    },
+    /// `in[N]` or `out[N]` with `N` that isn't a pin, `in[0]`, which has no input, or `ram[N]`
+    /// with `N` that isn't scratch RAM
+    InvalidAddress {
+        name: String,
+        address: u64,
+        index: usize,
+    },
+    /// More than 256 numbers, names, operators and brackets, the first one too many
+    /// being at `index`
+    TooComplex {
+        index: usize,
+    },
+}
+
+impl Condition {
+    pub fn parse(condition: &str) -> Result<Self, ConditionError> {
+        let tokens = tokenize(condition)?;
+        if let Some((index, _)) = tokens.get(MAX_TOKENS) {
+            return Err(ConditionError::TooComplex { index: *index });
+        }
+
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            end: condition.chars().count(),
+        };
+
+        let expression = parser.or()?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(Self { expression }),
+            Some((index, token)) => Err(ConditionError::Expected {
+                expected: "`and`, `or` or the end",
+                found: Some(token.to_string()),
+                index: *index,
+            }),
+        }
+    }
+
+    /// Whether the condition holds for `emulator` as it is now.
+    pub fn holds(&self, emulator: &Emulator) -> bool {
+        self.expression.evaluate(emulator) != 0
+    }
+}
+
+impl FromStr for Condition {
+    type Err = ConditionError;
+
+    fn from_str(condition: &str) -> Result<Self, Self::Err> {
+        Self::parse(condition)
+    }
+}
+
+impl Expression {
+    fn evaluate(&self, emulator: &Emulator) -> u64 {
+        match self {
+            Self::Number(number) => *number,
+            Self::Value(value) => value.read(emulator),
+            Self::Not(expression) => u64::from(expression.evaluate(emulator) == 0),
+            Self::Binary(left, operator, right) => {
+                let left = || left.evaluate(emulator);
+                let right = || right.evaluate(emulator);
+                let result = match operator {
+                    Operator::Or => left() != 0 || right() != 0,
+                    Operator::And => left() != 0 && right() != 0,
+                    Operator::Equal => left() == right(),
+                    Operator::NotEqual => left() != right(),
+                    Operator::Less => left() < right(),
+                    Operator::LessOrEqual => left() <= right(),
+                    Operator::Greater => left() > right(),
+                    Operator::GreaterOrEqual => left() >= right(),
+                };
+
+                u64::from(result)
+            }
+        }
+    }
+}
+
+impl Value {
+    fn read(self, emulator: &Emulator) -> u64 {
+        match self {
+            Self::Rr => u64::from(emulator.rr()),
+            Self::Ien => u64::from(emulator.ien()),
+            Self::Oen => u64::from(emulator.oen()),
+            Self::Pc => emulator.pc() as u64,
+            Self::Cycles => emulator.cycles(),
+            Self::Input(address) => u64::from(emulator.input(address)),
+            Self::Output(address) => u64::from(emulator.output(address)),
+            Self::Scratch(address) => u64::from(emulator.scratch(address)),
+        }
+    }
+}
+
+fn tokenize(condition: &str) -> Result<Vec<(usize, Token)>, ConditionError> {
+    let characters: Vec<char> = condition.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+
+    while let Some(&character) = characters.get(index) {
+        let start = index;
+        let two = |token| (token, 2);
+        let one = |token| (token, 1);
+
+        let (token, width) = match (character, characters.get(index + 1)) {
+            (character, _) if character.is_whitespace() => {
+                index += 1;
+                continue;
+            }
+            ('=', Some('=')) => two(Token::Operator(Operator::Equal)),
+            ('!', Some('=')) => two(Token::Operator(Operator::NotEqual)),
+            ('<', Some('=')) => two(Token::Operator(Operator::LessOrEqual)),
+            ('>', Some('=')) => two(Token::Operator(Operator::GreaterOrEqual)),
+            ('&', Some('&')) => two(Token::Operator(Operator::And)),
+            ('|', Some('|')) => two(Token::Operator(Operator::Or)),
+            ('<', _) => one(Token::Operator(Operator::Less)),
+            ('>', _) => one(Token::Operator(Operator::Greater)),
+            ('!', _) => one(Token::Not),
+            ('(', _) => one(Token::Open),
+            (')', _) => one(Token::Close),
+            ('[', _) => one(Token::OpenBracket),
+            (']', _) => one(Token::CloseBracket),
+            (character, _) if is_word(character) => {
+                let width = characters[start..]
+                    .iter()
+                    .take_while(|character| is_word(**character))
+                    .count();
+                let word = characters[start..start + width].iter().collect();
+                (word_token(word, start)?, width)
+            }
+            (character, _) => {
+                return Err(ConditionError::UnexpectedCharacter {
+                    character,
+                    index: start,
+                })
+            }
+        };
+
+        tokens.push((start, token));
+        index += width;
+    }
+
+    Ok(tokens)
+}
+
+fn is_word(character: char) -> bool {
+    character.is_ascii_alphanumeric() || character == '_'
+}
+
+fn word_token(word: String, index: usize) -> Result<Token, ConditionError> {
+    if word.starts_with(|character: char| character.is_ascii_digit()) {
+        let number = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => word.parse(),
+        };
+
+        return number
+            .map(Token::Number)
+            .map_err(|_| ConditionError::InvalidNumber { index });
+    }
+
+    let token = match word.to_ascii_lowercase().as_str() {
+        "and" => Token::Operator(Operator::And),
+        "or" => Token::Operator(Operator::Or),
+        "not" => Token::Not,
+        _ => Token::Name(word),
+    };
+
+    Ok(token)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+    /// Where the end of the condition is, for errors about it ending early
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(_, token)| token)
+    }
+
+    fn next(&mut self) -> Option<(usize, Token)> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expected(&self, expected: &'static str) -> ConditionError {
+        match self.tokens.get(self.position) {
+            Some((index, token)) => ConditionError::Expected {
+                expected,
+                found: Some(token.to_string()),
+                index: *index,
+            },
+            None => ConditionError::Expected {
+                expected,
+                found: None,
+                index: self.end,
+            },
+        }
+    }
+
+    fn or(&mut self) -> Result<Expression, ConditionError> {
+        let mut expression = self.and()?;
+        while self.peek() == Some(&Token::Operator(Operator::Or)) {
+            self.position += 1;
+            let right = self.and()?;
+            expression = Expression::Binary(Box::new(expression), Operator::Or, Box::new(right));
+        }
+
+        Ok(expression)
+    }
+
+    fn and(&mut self) -> Result<Expression, ConditionError> {
+        let mut expression = self.not()?;
+        while self.peek() == Some(&Token::Operator(Operator::And)) {
+            self.position += 1;
+            let right = self.not()?;
+            expression = Expression::Binary(Box::new(expression), Operator::And, Box::new(right));
+        }
+
+        Ok(expression)
+    }
+
+    fn not(&mut self) -> Result<Expression, ConditionError> {
+        if self.peek() == Some(&Token::Not) {
+            self.position += 1;
+            return Ok(Expression::Not(Box::new(self.not()?)));
+        }
+
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expression, ConditionError> {
+        let left = self.operand()?;
+        let operator = match self.peek() {
+            Some(Token::Operator(operator))
+                if !matches!(operator, Operator::And | Operator::Or) =>
+            {
+                *operator
+            }
+            _ => return Ok(left),
+        };
+
+        self.position += 1;
+        let right = self.operand()?;
+        Ok(Expression::Binary(
+            Box::new(left),
+            operator,
+            Box::new(right),
+        ))
+    }
+
+    fn operand(&mut self) -> Result<Expression, ConditionError> {
+        let expected = "a number, a name or `(`";
+        let Some((index, token)) = self.next() else {
+            self.position -= 1;
+            return Err(self.expected(expected));
+        };
+
+        match token {
+            Token::Number(number) => Ok(Expression::Number(number)),
+            Token::Open => {
+                let expression = self.or()?;
+                match self.next() {
+                    Some((_, Token::Close)) => Ok(expression),
+                    _ => {
+                        self.position -= 1;
+                        Err(self.expected("`)`"))
+                    }
+                }
+            }
+            Token::Name(name) => self.value(name, index).map(Expression::Value),
+            _ => {
+                self.position -= 1;
+                Err(self.expected(expected))
+            }
+        }
+    }
+
+    fn value(&mut self, name: String, index: usize) -> Result<Value, ConditionError> {
+        let value = match name.to_ascii_lowercase().as_str() {
+            "rr" => Value::Rr,
+            "ien" => Value::Ien,
+            "oen" => Value::Oen,
+            "pc" => Value::Pc,
+            "cycles" => Value::Cycles,
+            "in" => match self.address(&name, index, true)? {
+                NOT_RR => {
+                    return Err(ConditionError::InvalidAddress {
+                        name,
+                        address: u64::from(NOT_RR),
+                        index,
+                    })
+                }
+                address => Value::Input(address),
+            },
+            "out" => Value::Output(self.address(&name, index, true)?),
+            "ram" => Value::Scratch(self.address(&name, index, false)?),
+            _ => return Err(ConditionError::UnknownName { name, index }),
+        };
+
+        Ok(value)
+    }
+
+    /// Reads the `[N]` after `in`, `out` or `ram`, checking it's a pin or in scratch RAM.
+    fn address(&mut self, name: &str, index: usize, is_pin: bool) -> Result<u8, ConditionError> {
+        let expect = |parser: &mut Self, token: Token, expected| match parser.next() {
+            Some((_, next)) if next == token => Ok(()),
+            _ => {
+                parser.position -= 1;
+                Err(parser.expected(expected))
+            }
+        };
+
+        expect(self, Token::OpenBracket, "`[`")?;
+        let address = match self.next() {
+            Some((_, Token::Number(address))) => address,
+            _ => {
+                self.position -= 1;
+                return Err(self.expected("an address"));
+            }
+        };
+        expect(self, Token::CloseBracket, "`]`")?;
+
+        let port = u8::try_from(address).ok().and_then(Port::of);
+        match port {
+            Some(Port::Pin(address)) if is_pin => Ok(address),
+            Some(Port::Scratch(address)) if !is_pin => Ok(address),
+            _ => Err(ConditionError::InvalidAddress {
+                name: name.to_string(),
+                address,
+                index,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Self::Number(number) => return write!(f, "`{number}`"),
+            Self::Name(name) => return write!(f, "`{name}`"),
+            Self::Operator(Operator::Or) => "or",
+            Self::Operator(Operator::And) => "and",
+            Self::Operator(Operator::Equal) => "==",
+            Self::Operator(Operator::NotEqual) => "!=",
+            Self::Operator(Operator::Less) => "<",
+            Self::Operator(Operator::LessOrEqual) => "<=",
+            Self::Operator(Operator::Greater) => ">",
+            Self::Operator(Operator::GreaterOrEqual) => ">=",
+            Self::Not => "not",
+            Self::Open => "(",
+            Self::Close => ")",
+            Self::OpenBracket => "[",
+            Self::CloseBracket => "]",
+        };
+
+        write!(f, "`{symbol}`")
+    }
+}
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedCharacter { character, index } => write!(
+                f,
+                "`{character}` at position {} can't start anything",
+                index + 1
+            ),
+            Self::InvalidNumber { index } => {
+                write!(f, "Number at position {} is too large", index + 1)
+            }
+            Self::Expected {
+                expected,
+                found: Some(found),
+                index,
+            } => write!(
+                f,
+                "Expected {expected} at position {}, but found {found}",
+                index + 1
+            ),
+            Self::Expected {
+                expected,
+                found: None,
+                ..
+            } => write!(f, "Expected {expected}, but the condition ended"),
+            Self::UnknownName { name, index } => write!(
+                f,
+                "`{name}` at position {} isn't something a condition can read",
+                index + 1
+            ),
+            Self::InvalidAddress {
+                name,
+                address,
+                index,
+            } => write!(
+                f,
+                "`{name}[{address}]` at position {} is out of range",
+                index + 1
+            ),
+            Self::TooComplex { index } => write!(
+                f,
+                "The condition is too complex from position {} on, since it can only be {MAX_TOKENS} numbers, names and symbols long",
+                index + 1
+            ),
+        }
+    }
+}
+
+impl core::error::Error for ConditionError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Condition, ConditionError, Emulator, Program};
+
+    fn holds(condition: &str, emulator: &Emulator) -> bool {
+        condition.parse::<Condition>().unwrap().holds(emulator)
+    }
+
+    #[test]
+    fn handles_conditions() {
+        let mut emulator =
+            Emulator::new(&Program::from_assembly("LD 1\nSTO 2\nSTO 9\nOEN 3")).unwrap();
+        emulator.set_input(1, true);
+        emulator.run_for(3);
+
+        assert!(holds("RR == 1 and PC > 0x4", &emulator));
+        assert!(holds("rr && pc >= 6 && cycles == 3", &emulator));
+        assert!(holds(
+            "out[2] and ram[9] and in[1] and not in[3]",
+            &emulator
+        ));
+        assert!(holds("IEN == OEN", &emulator));
+        assert!(holds("!(pc < 6 or rr != 1)", &emulator));
+        assert!(!holds("RR == 0 || PC > 0x20", &emulator));
+        assert!(!holds("0", &emulator));
+    }
+
+    #[test]
+    fn handles_condition_errors() {
+        let error = |condition: &str| Condition::parse(condition).unwrap_err();
+
+        assert_eq!(
+            error("RR = 1"),
+            ConditionError::UnexpectedCharacter {
+                character: '=',
+                index: 3
+            }
+        );
+        assert_eq!(
+            error("PC > "),
+            ConditionError::Expected {
+                expected: "a number, a name or `(`",
+                found: None,
+                index: 5
+            }
+        );
+        assert_eq!(
+            error("(RR"),
+            ConditionError::Expected {
+                expected: "`)`",
+                found: None,
+                index: 3
+            }
+        );
+        assert_eq!(
+            error("RR 1").to_string(),
+            "Expected `and`, `or` or the end at position 4, but found `1`"
+        );
+        assert_eq!(
+            error("acc == 1"),
+            ConditionError::UnknownName {
+                name: "acc".to_string(),
+                index: 0
+            }
+        );
+        assert_eq!(
+            error("RR and ram[3]").to_string(),
+            "`ram[3]` at position 8 is out of range"
+        );
+        assert_eq!(
+            error("in[0x10]").to_string(),
+            "`in[16]` at position 1 is out of range"
+        );
+        assert_eq!(
+            error("out[0] and in[0]").to_string(),
+            "`in[0]` at position 12 is out of range"
+        );
+        assert_eq!(
+            error("PC > 99999999999999999999"),
+            ConditionError::InvalidNumber { index: 5 }
+        );
+
+        let nested = |depth| format!("{}RR{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(Condition::parse(&nested(127)).is_ok());
+        assert_eq!(
+            error(&nested(128)),
+            ConditionError::TooComplex { index: 257 }
+        );
+        assert!(Condition::parse(&format!("{}RR", "!".repeat(255))).is_ok());
+        assert_eq!(
+            error(&"!".repeat(200_000)).to_string(),
+            "The condition is too complex from position 257 on, since it can only be 256 numbers, names and symbols long"
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! Cycles in terms of game ticks, for checking a program reacts to its inputs quickly enough to
+//! keep up with the machines around it.
+
+use core::fmt;
+
+use super::{input, Emulator};
+
+/// How many cycles the emulator counts as a game tick, unless
+/// [`Emulator::set_cycles_per_tick`] says otherwise.
+pub const DEFAULT_CYCLES_PER_TICK: u64 = 1;
+
+/// An output pin changing after an input did, from [`Emulator::reactions`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Reaction {
+    /// The pin whose input changed
+    pub input: u8,
+    /// How many cycles had been run when it changed
+    pub changed_at: u64,
+    /// The pin whose output changed next
+    pub output: u8,
+    /// How many cycles it took, counting the one that changed the output
+    pub cycles: u64,
+    /// How many game ticks those cycles take at the emulator's current
+    /// [`Emulator::cycles_per_tick`], rounding up
+    pub ticks: u64,
+}
+
+impl Emulator {
+    /// How many cycles make up a game tick, for [`Emulator::ticks`] and reaction times. Treated
+    /// as `1` if given `0`.
+    pub fn set_cycles_per_tick(&mut self, cycles: u64) {
+        self.cycles_per_tick = cycles.max(1);
+    }
+
+    pub fn cycles_per_tick(&self) -> u64 {
+        self.cycles_per_tick
+    }
+
+    /// How many game ticks the cycles run so far take, rounding up.
+    pub fn ticks(&self) -> u64 {
+        self.to_ticks(self.cycles)
+    }
+
+    /// How many game ticks `cycles` cycles take, rounding up.
+    pub fn to_ticks(&self, cycles: u64) -> u64 {
+        cycles.div_ceil(self.cycles_per_tick)
+    }
+
+    /// Flags reactions that take more than `ticks` game ticks in
+    /// [`Emulator::slow_reactions`], or none of them if `None`.
+    pub fn set_max_latency(&mut self, ticks: Option<u64>) {
+        self.max_latency = ticks;
+    }
+
+    /// Every time an output pin changed after an input did, in order. The emulator can't tell
+    /// which input an output is reacting to, so it's timed from the last input change, and only
+    /// the first output change after that counts. Ticks are worked out as they're read, so they
+    /// follow any later [`Emulator::set_cycles_per_tick`].
+    pub fn reactions(&self) -> impl Iterator<Item = Reaction> + '_ {
+        self.reactions.iter().map(|&reaction| Reaction {
+            ticks: self.to_ticks(reaction.cycles),
+            ..reaction
+        })
+    }
+
+    /// The reactions that took longer than [`Emulator::set_max_latency`] allows.
+    pub fn slow_reactions(&self) -> impl Iterator<Item = Reaction> + '_ {
+        let max = self.max_latency;
+        self.reactions()
+            .filter(move |reaction| max.is_some_and(|max| reaction.ticks > max))
+    }
+
+    /// Starts timing a reaction if the input at `address` is about to change.
+    pub(super) fn time_input(&mut self, address: u8, value: bool) {
+        if self.inputs[input(address)] != value {
+            self.unanswered = Some((address, self.cycles));
+        }
+    }
+
+    /// Finishes timing a reaction, now that the output at `address` has changed.
+    pub(super) fn time_output(&mut self, address: u8) {
+        if let Some((input, changed_at)) = self.unanswered.take() {
+            let cycles = self.cycles - changed_at;
+            self.reactions.push(Reaction {
+                input,
+                changed_at,
+                output: address,
+                cycles,
+                ticks: self.to_ticks(cycles),
+            });
+        }
+    }
+}
+
+/// Like `in[1] changed at cycle 3, and out[2] followed 14 cycles (4 ticks) later`.
+impl fmt::Display for Reaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |count: u64| if count == 1 { "" } else { "s" };
+        write!(
+            f,
+            "in[{}] changed at cycle {}, and out[{}] followed {} cycle{} ({} tick{}) later",
+            self.input,
+            self.changed_at,
+            self.output,
+            self.cycles,
+            plural(self.cycles),
+            self.ticks,
+            plural(self.ticks)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Emulator, Program, Reaction};
+
+    #[test]
+    fn handles_ticks() {
+        let program = Program::from_assembly("LD 1\nNOP\nNOP\nSTO 3");
+        let mut emulator = Emulator::new(&program).unwrap();
+        emulator.set_cycles_per_tick(3);
+        emulator.set_max_latency(Some(1));
+        assert_eq!(emulator.to_ticks(0), 0);
+        assert_eq!(emulator.to_ticks(3), 1);
+        assert_eq!(emulator.to_ticks(4), 2);
+
+        // Changes right before the `LD`, so it takes the whole loop
+        emulator.set_input(1, true);
+        emulator.set_input(1, true);
+        emulator.run_for(5);
+        assert_eq!(emulator.ticks(), 2);
+
+        // Changes right before the `STO`, so it's only the loop after that
+        emulator.run_for(2);
+        emulator.set_input(1, false);
+        emulator.set_input(1, true);
+        emulator.set_input(1, false);
+        emulator.run_for(5);
+
+        // Storing what's already there isn't a reaction, and nothing reads pin 2
+        emulator.set_input(2, true);
+        emulator.run_for(8);
+
+        assert_eq!(
+            emulator.reactions().collect::<Vec<_>>(),
+            [
+                Reaction {
+                    input: 1,
+                    changed_at: 0,
+                    output: 3,
+                    cycles: 4,
+                    ticks: 2
+                },
+                Reaction {
+                    input: 1,
+                    changed_at: 7,
+                    output: 3,
+                    cycles: 5,
+                    ticks: 2
+                },
+            ]
+        );
+        assert_eq!(emulator.slow_reactions().count(), 2);
+        assert_eq!(
+            emulator.reactions().next().unwrap().to_string(),
+            "in[1] changed at cycle 0, and out[3] followed 4 cycles (2 ticks) later"
+        );
+
+        // Reactions already timed follow the new tick length too
+        emulator.set_cycles_per_tick(5);
+        emulator.set_input(1, true);
+        emulator.run_for(4);
+        let ticks: Vec<_> = emulator
+            .reactions()
+            .map(|reaction| reaction.ticks)
+            .collect();
+        assert_eq!(ticks, [1, 1, 1]);
+        assert_eq!(emulator.slow_reactions().count(), 0);
+
+        emulator.set_cycles_per_tick(2);
+        let ticks: Vec<_> = emulator
+            .reactions()
+            .map(|reaction| reaction.ticks)
+            .collect();
+        assert_eq!(ticks, [2, 3, 2]);
+        assert_eq!(emulator.slow_reactions().count(), 3);
+    }
+}
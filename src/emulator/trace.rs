@@ -0,0 +1,313 @@
+//! Recording every instruction the emulator runs, along with what it changed, for working out
+//! after the fact how a program got into the state it's in.
+
+use core::fmt::{self, Write};
+
+use super::Emulator;
+use crate::emit::json_string;
+use crate::prelude::*;
+use crate::Instruction;
+
+/// The registers, before or after an instruction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Registers {
+    pub rr: bool,
+    pub ien: bool,
+    pub oen: bool,
+}
+
+/// One instruction in a [`Trace`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TraceEntry {
+    /// How many cycles had been run before this one, so the first is `0`
+    pub cycle: u64,
+    pub pc: usize,
+    pub instruction: Instruction,
+    /// Whether a `SKZ` skipped the instruction, so it did nothing
+    pub skipped: bool,
+    pub before: Registers,
+    pub after: Registers,
+    /// Each pin whose input changed since the last entry, and what it changed to
+    pub inputs: Vec<(u8, bool)>,
+    /// Each output pin or bit of scratch RAM the instruction changed, and what it changed to
+    pub stores: Vec<(u8, bool)>,
+}
+
+/// Every instruction run since [`Emulator::start_trace`], in order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Trace {
+    entries: Vec<TraceEntry>,
+    /// The cycle, registers, inputs, outputs and scratch RAM when recording started
+    pub(super) start_cycle: u64,
+    pub(super) start_registers: Registers,
+    pub(super) start_inputs: [bool; 8],
+    pub(super) start_stored: [bool; 16],
+    /// The inputs as of the last entry, to tell which have changed since
+    inputs: [bool; 8],
+}
+
+/// Everything an instruction can change, to compare before and after.
+pub(super) struct Snapshot {
+    registers: Registers,
+    inputs: [bool; 8],
+    stored: [bool; 16],
+}
+
+impl Snapshot {
+    pub(super) fn of(emulator: &Emulator) -> Self {
+        let mut stored = [false; 16];
+        stored[..8].copy_from_slice(&emulator.outputs);
+        stored[8..].copy_from_slice(&emulator.scratch);
+
+        Self {
+            registers: Registers {
+                rr: emulator.rr,
+                ien: emulator.ien,
+                oen: emulator.oen,
+            },
+            inputs: emulator.inputs,
+            stored,
+        }
+    }
+}
+
+impl Trace {
+    pub(super) fn new(emulator: &Emulator) -> Self {
+        let start = Snapshot::of(emulator);
+        Self {
+            entries: Vec::new(),
+            start_cycle: emulator.cycles,
+            start_registers: start.registers,
+            start_inputs: start.inputs,
+            start_stored: start.stored,
+            inputs: start.inputs,
+        }
+    }
+
+    pub(super) fn record(
+        &mut self,
+        cycle: u64,
+        (pc, instruction): (usize, Instruction),
+        skipped: bool,
+        before: Snapshot,
+        after: Snapshot,
+    ) {
+        let changes = |before: &[bool], after: &[bool]| -> Vec<(u8, bool)> {
+            (0u8..)
+                .zip(before.iter().zip(after))
+                .filter(|(_, (before, after))| before != after)
+                .map(|(address, (_, after))| (address, *after))
+                .collect()
+        };
+
+        self.entries.push(TraceEntry {
+            cycle,
+            pc,
+            instruction,
+            skipped,
+            before: before.registers,
+            after: after.registers,
+            inputs: changes(&self.inputs, &before.inputs),
+            stores: changes(&before.stored, &after.stored),
+        });
+        self.inputs = before.inputs;
+    }
+
+    pub fn entries(&self) -> &[TraceEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Every time the instruction at `pc` ran.
+    pub fn at(&self, pc: usize) -> impl Iterator<Item = &TraceEntry> {
+        self.entries.iter().filter(move |entry| entry.pc == pc)
+    }
+
+    /// Every instruction that changed the output pin or scratch RAM at `address`.
+    pub fn changes_to(&self, address: u8) -> impl Iterator<Item = &TraceEntry> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.stores.iter().any(|(store, _)| *store == address))
+    }
+
+    /// The trace as JSON: an array of entries, with each register as `0` or `1` and each change
+    /// as an `{"address","value"}` object.
+    pub fn to_json(&self) -> String {
+        let registers = |registers: Registers| {
+            format!(
+                "{{\"rr\":{},\"ien\":{},\"oen\":{}}}",
+                u8::from(registers.rr),
+                u8::from(registers.ien),
+                u8::from(registers.oen)
+            )
+        };
+        let changes = |changes: &[(u8, bool)]| {
+            let changes: Vec<_> = changes
+                .iter()
+                .map(|(address, value)| {
+                    format!("{{\"address\":{address},\"value\":{}}}", u8::from(*value))
+                })
+                .collect();
+            changes.join(",")
+        };
+
+        let entries: Vec<_> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                format!(
+                    "{{\"cycle\":{},\"pc\":{},\"instruction\":{},\"skipped\":{},\"before\":{},\"after\":{},\"inputs\":[{}],\"stores\":[{}]}}",
+                    entry.cycle,
+                    entry.pc,
+                    json_string(&entry.instruction.to_string()),
+                    entry.skipped,
+                    registers(entry.before),
+                    registers(entry.after),
+                    changes(&entry.inputs),
+                    changes(&entry.stores),
+                )
+            })
+            .collect();
+
+        format!("[{}]", entries.join(","))
+    }
+}
+
+/// One line per entry, like `12  04  STO 2     RR=1 IEN=1 OEN=0>1  in[1]=1 out[2]=1`, giving
+/// the cycle, address and instruction, then the registers, with `>` where they changed, then
+/// what changed. Skipped instructions are marked `(skipped)`.
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.entries {
+            let mut line = format!(
+                "{:<6}{:02X}  {:<10}",
+                entry.cycle,
+                entry.pc,
+                entry.instruction.to_string()
+            );
+
+            let registers = [
+                ("RR", entry.before.rr, entry.after.rr),
+                ("IEN", entry.before.ien, entry.after.ien),
+                ("OEN", entry.before.oen, entry.after.oen),
+            ];
+            for (index, (name, before, after)) in registers.into_iter().enumerate() {
+                if index > 0 {
+                    line.push(' ');
+                }
+
+                let _ = write!(line, "{name}={}", u8::from(before));
+                if before != after {
+                    let _ = write!(line, ">{}", u8::from(after));
+                }
+            }
+
+            if entry.skipped {
+                line.push_str("  (skipped)");
+            }
+
+            let mut changes = entry
+                .inputs
+                .iter()
+                .map(|(address, value)| format!("in[{address:X}]={}", u8::from(*value)))
+                .chain(entry.stores.iter().map(|(address, value)| {
+                    let name = if *address < 8 { "out" } else { "ram" };
+                    format!("{name}[{address:X}]={}", u8::from(*value))
+                }))
+                .peekable();
+            if changes.peek().is_some() {
+                line.push(' ');
+            }
+            for change in changes {
+                let _ = write!(line, " {change}");
+            }
+
+            writeln!(f, "{line}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Emulator, Instruction, Program, Registers};
+
+    #[test]
+    fn handles_traces() {
+        let mut emulator =
+            Emulator::new(&Program::from_assembly("LD 1\nSKZ\nSTO 2\nSTO 9\nOEN 3")).unwrap();
+        assert!(emulator.trace().is_none());
+
+        emulator.start_trace();
+        emulator.run_for(5);
+        emulator.set_input(1, true);
+        emulator.run_for(3);
+        let trace = emulator.stop_trace().unwrap();
+        assert!(emulator.trace().is_none());
+        assert_eq!(trace.len(), 8);
+
+        let entry = &trace.entries()[2];
+        assert_eq!(entry.cycle, 2);
+        assert_eq!(entry.pc, 3);
+        assert_eq!(entry.instruction, Instruction::Store(2));
+        assert!(entry.skipped);
+
+        let entry = &trace.entries()[5];
+        assert_eq!(entry.instruction, Instruction::Load(1));
+        assert_eq!(entry.inputs, [(1, true)]);
+        assert_eq!(
+            entry.after,
+            Registers {
+                rr: true,
+                ien: true,
+                oen: false
+            }
+        );
+
+        // Outputs are off by now, so nothing is stored the second time round
+        assert_eq!(trace.at(3).count(), 2);
+        assert_eq!(trace.changes_to(2).count(), 0);
+
+        assert_eq!(
+            trace.to_string(),
+            concat!(
+                "0     00  LD 1      RR=0 IEN=1 OEN=1\n",
+                "1     02  SKZ       RR=0 IEN=1 OEN=1\n",
+                "2     03  STO 2     RR=0 IEN=1 OEN=1  (skipped)\n",
+                "3     05  STO 9     RR=0 IEN=1 OEN=1\n",
+                "4     07  OEN 3     RR=0 IEN=1 OEN=1>0\n",
+                "5     00  LD 1      RR=0>1 IEN=1 OEN=0  in[1]=1\n",
+                "6     02  SKZ       RR=1 IEN=1 OEN=0\n",
+                "7     03  STO 2     RR=1 IEN=1 OEN=0\n",
+            )
+        );
+    }
+
+    #[test]
+    fn handles_trace_json() {
+        let mut emulator = Emulator::new(&Program::from_assembly("LDC 1\nSTO A")).unwrap();
+        emulator.start_trace();
+        emulator.run_for(2);
+        assert_eq!(
+            emulator.trace().unwrap().to_json(),
+            concat!(
+                r#"[{"cycle":0,"pc":0,"instruction":"LDC 1","skipped":false,"#,
+                r#""before":{"rr":0,"ien":1,"oen":1},"after":{"rr":1,"ien":1,"oen":1},"inputs":[],"stores":[]},"#,
+                r#"{"cycle":1,"pc":2,"instruction":"STO A","skipped":false,"#,
+                r#""before":{"rr":1,"ien":1,"oen":1},"after":{"rr":1,"ien":1,"oen":1},"inputs":[],"#,
+                r#""stores":[{"address":10,"value":1}]}]"#,
+            )
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! Traces as Value Change Dump files, the waveform format GTKWave and other logic analysers read,
+//! for seeing how signals line up over time rather than reading through a text trace.
+
+use core::fmt::Write;
+
+use super::Trace;
+use crate::prelude::*;
+
+/// Where each pin's input is in the signals, after `RR`, `IEN` and `OEN`.
+const INPUTS: usize = 3;
+
+/// Where the outputs and then scratch RAM are in the signals, in address order.
+const STORED: usize = INPUTS + 8;
+
+/// The signals in a dump, in order, each given the next printable identifier from `!`.
+const SIGNALS: usize = STORED + 16 + 1;
+
+/// The program counter is the last signal, and the only one that's more than a bit wide.
+const PC: usize = SIGNALS - 1;
+
+impl Trace {
+    /// The trace as a Value Change Dump, with one time unit per cycle, counting cycles the same as
+    /// [`super::TraceEntry::cycle`]. There's a wire for `RR`, `IEN` and `OEN`, for each pin's
+    /// input and output, and for each bit of scratch RAM, along with the 8-bit program counter.
+    /// Inputs and the program counter change at the start of the cycle that sees them, and
+    /// everything else changes at the end of the cycle that changed it.
+    pub fn to_vcd(&self) -> String {
+        let mut vcd = format!(
+            "$version {} {} $end\n$timescale 1 us $end\n$scope module control_unit $end\n",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+        );
+
+        let mut names: Vec<String> = ["rr", "ien", "oen"].map(String::from).to_vec();
+        names.extend((0..8).map(|pin| format!("in{pin}")));
+        names.extend((0..8).map(|pin| format!("out{pin}")));
+        names.extend((8..16).map(|address| format!("ram{address:X}")));
+        for (signal, name) in names.iter().enumerate() {
+            let _ = writeln!(vcd, "$var wire 1 {} {name} $end", identifier(signal));
+        }
+        let _ = writeln!(vcd, "$var wire 8 {} pc $end", identifier(PC));
+        vcd.push_str("$upscope $end\n$enddefinitions $end\n");
+
+        let mut values = [0usize; SIGNALS];
+        let registers = self.start_registers;
+        values[0] = usize::from(registers.rr);
+        values[1] = usize::from(registers.ien);
+        values[2] = usize::from(registers.oen);
+        for pin in 0..8 {
+            values[INPUTS + pin] = usize::from(self.start_inputs[pin]);
+        }
+        for address in 0..16 {
+            values[STORED + address] = usize::from(self.start_stored[address]);
+        }
+        values[PC] = self.entries().first().map_or(0, |entry| entry.pc);
+
+        let _ = writeln!(vcd, "#{}\n$dumpvars", self.start_cycle);
+        for (signal, value) in values.iter().enumerate() {
+            vcd.push_str(&change(signal, *value));
+        }
+        vcd.push_str("$end\n");
+
+        let mut time = self.start_cycle;
+        let mut set = |vcd: &mut String, at: u64, signal: usize, value: usize| {
+            if values[signal] == value {
+                return;
+            }
+
+            if at != time {
+                time = at;
+                let _ = writeln!(vcd, "#{time}");
+            }
+
+            values[signal] = value;
+            vcd.push_str(&change(signal, value));
+        };
+
+        for entry in self.entries() {
+            set(&mut vcd, entry.cycle, PC, entry.pc);
+            for &(pin, value) in &entry.inputs {
+                set(
+                    &mut vcd,
+                    entry.cycle,
+                    INPUTS + usize::from(pin),
+                    usize::from(value),
+                );
+            }
+
+            let end = entry.cycle + 1;
+            set(&mut vcd, end, 0, usize::from(entry.after.rr));
+            set(&mut vcd, end, 1, usize::from(entry.after.ien));
+            set(&mut vcd, end, 2, usize::from(entry.after.oen));
+            for &(address, value) in &entry.stores {
+                set(
+                    &mut vcd,
+                    end,
+                    STORED + usize::from(address),
+                    usize::from(value),
+                );
+            }
+        }
+
+        // Mark where the trace ends, even if nothing changed in the last cycles
+        let end = self
+            .entries()
+            .last()
+            .map_or(self.start_cycle, |entry| entry.cycle + 1);
+        if end != time {
+            let _ = writeln!(vcd, "#{end}");
+        }
+
+        vcd
+    }
+}
+
+fn identifier(signal: usize) -> char {
+    char::from(b'!' + signal as u8)
+}
+
+fn change(signal: usize, value: usize) -> String {
+    if signal == PC {
+        format!("b{value:b} {}\n", identifier(signal))
+    } else {
+        format!("{value}{}\n", identifier(signal))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Emulator, Program};
+
+    #[test]
+    fn handles_vcd() {
+        let mut emulator = Emulator::new(&Program::from_assembly("LD 1\nSTO 2")).unwrap();
+        emulator.set_input(1, true);
+        emulator.start_trace();
+        emulator.run_for(3);
+        emulator.set_input(1, false);
+        emulator.run_for(1);
+
+        let vcd = emulator.trace().unwrap().to_vcd();
+        let (header, changes) = vcd.split_once("$enddefinitions $end\n").unwrap();
+        assert!(header.starts_with("$version goonstation-asm 0.1.0 $end\n"));
+        assert!(header.contains("$var wire 1 ! rr $end\n"));
+        assert!(header.contains("$var wire 1 % in1 $end\n"));
+        assert!(header.contains("$var wire 1 . out2 $end\n"));
+        assert!(header.contains("$var wire 1 4 ram8 $end\n"));
+        assert!(header.contains("$var wire 8 < pc $end\n"));
+
+        let dump = concat!(
+            "#0\n$dumpvars\n0!\n1\"\n1#\n0$\n1%\n0&\n0'\n0(\n0)\n0*\n0+\n",
+            "0,\n0-\n0.\n0/\n00\n01\n02\n03\n04\n05\n06\n07\n08\n09\n0:\n0;\nb0 <\n$end\n",
+        );
+        assert!(changes.starts_with(dump));
+        assert_eq!(
+            &changes[dump.len()..],
+            "#1\n1!\nb10 <\n#2\n1.\nb0 <\n#3\nb10 <\n0%\n#4\n"
+        );
+    }
+}
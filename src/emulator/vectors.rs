@@ -0,0 +1,287 @@
+//! Test vectors: inputs to apply and outputs to expect at given cycles, for checking a program
+//! does what it should without loading it into the game.
+//!
+//! Each line is a cycle, a colon, the inputs to set, and then optionally `->` and the outputs
+//! and scratch RAM to expect, like `12: in[1]=1 in[2]=0 -> out[3]=1 ram[9]=0`. Inputs are set
+//! and outputs checked once that many cycles have run. Cycles can't go backwards. Addresses are
+//! decimal or `0x` hex, and anything after a `;` is a comment. There's no `in[0]`, since pin `0`
+//! reads the complement of the result register rather than an input.
+
+use core::fmt;
+
+use super::{Emulator, Port, Stop};
+use crate::assembler::NOT_RR;
+use crate::prelude::*;
+
+/// Test vectors parsed by [`TestVectors::parse`], for [`Emulator::apply`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TestVectors {
+    vectors: Vec<TestVector>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TestVector {
+    /// The line the vector is on, counting from 1
+    pub line: usize,
+    pub cycle: u64,
+    /// Each pin to set, and what to set its input to
+    pub inputs: Vec<(u8, bool)>,
+    /// Each output pin or bit of scratch RAM to check, and what it should be
+    pub expected: Vec<(u8, bool)>,
+}
+
+/// How a [`TestVector`] went, from [`Emulator::apply`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VectorResult {
+    pub line: usize,
+    pub cycle: u64,
+    /// Each address that wasn't as expected, and what it was instead
+    pub mismatches: Vec<(u8, bool)>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VectorError {
+    /// A line that doesn't start with a cycle and a colon
+    InvalidCycle { line: usize },
+    /// Something that isn't `in[N]=V` before the `->`, or `out[N]=V` or `ram[N]=V` after it
+    InvalidAssignment { line: usize, assignment: String },
+    /// A cycle before the one on the line above
+    OutOfOrder { line: usize },
+}
+
+impl TestVectors {
+    pub fn parse(vectors: &str) -> Result<Self, VectorError> {
+        let mut parsed: Vec<TestVector> = Vec::new();
+        for (index, text) in vectors.lines().enumerate() {
+            let line = index + 1;
+            let text = text.split(';').next().unwrap_or_default().trim();
+            if text.is_empty() {
+                continue;
+            }
+
+            let (cycle, rest) = text
+                .split_once(':')
+                .and_then(|(cycle, rest)| Some((cycle.trim().parse().ok()?, rest)))
+                .ok_or(VectorError::InvalidCycle { line })?;
+            if parsed.last().is_some_and(|last| last.cycle > cycle) {
+                return Err(VectorError::OutOfOrder { line });
+            }
+
+            let (inputs, expected) = rest.split_once("->").unwrap_or((rest, ""));
+            parsed.push(TestVector {
+                line,
+                cycle,
+                inputs: assignments(inputs, line, true)?,
+                expected: assignments(expected, line, false)?,
+            });
+        }
+
+        Ok(Self { vectors: parsed })
+    }
+
+    pub fn vectors(&self) -> &[TestVector] {
+        &self.vectors
+    }
+}
+
+/// Reads `in[N]=V` assignments if `inputs` is set, or `out[N]=V` and `ram[N]=V` otherwise.
+fn assignments(text: &str, line: usize, inputs: bool) -> Result<Vec<(u8, bool)>, VectorError> {
+    text.split_whitespace()
+        .map(|text| {
+            assignment(text, inputs).ok_or_else(|| VectorError::InvalidAssignment {
+                line,
+                assignment: text.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Reads one assignment, like `in[1]=1`, as its address and value.
+pub(super) fn assignment(text: &str, inputs: bool) -> Option<(u8, bool)> {
+    let (target, value) = text.split_once('=')?;
+    let value = match value {
+        "0" => false,
+        "1" => true,
+        _ => return None,
+    };
+
+    let (name, address) = target.strip_suffix(']')?.split_once('[')?;
+    let address = match address.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => address.parse(),
+    }
+    .ok()?;
+
+    match (name, Port::of(address)?) {
+        ("in", Port::Pin(pin)) if inputs && pin != NOT_RR => Some((address, value)),
+        ("out", Port::Pin(_)) | ("ram", Port::Scratch(_)) if !inputs => Some((address, value)),
+        _ => None,
+    }
+}
+
+impl VectorResult {
+    pub fn passed(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl Emulator {
+    /// Runs the program through each of `vectors` in turn: runs until the vector's cycle, sets
+    /// its inputs, and checks its outputs. A program that halts early is checked as it was when
+    /// it halted. Breakpoints and the like don't stop it, though watchpoints and conditions
+    /// still check along the way.
+    pub fn apply(&mut self, vectors: &TestVectors) -> Vec<VectorResult> {
+        let mut results = Vec::with_capacity(vectors.vectors.len());
+        for vector in &vectors.vectors {
+            while self.cycles < vector.cycle {
+                if self.run_for(vector.cycle - self.cycles) == Stop::Halted {
+                    break;
+                }
+            }
+
+            for &(address, value) in &vector.inputs {
+                self.set_input(address, value);
+            }
+
+            let mismatches = vector
+                .expected
+                .iter()
+                .filter_map(|&(address, expected)| {
+                    let actual = match Port::of(address) {
+                        Some(Port::Pin(_)) => self.output(address),
+                        _ => self.scratch(address),
+                    };
+                    (actual != expected).then_some((address, actual))
+                })
+                .collect();
+
+            results.push(VectorResult {
+                line: vector.line,
+                cycle: vector.cycle,
+                mismatches,
+            });
+        }
+
+        results
+    }
+}
+
+/// Like `Line 3, cycle 12: out[3] was 0`, with every mismatch, or `passed` if there weren't any.
+impl fmt::Display for VectorResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Line {}, cycle {}: ", self.line, self.cycle)?;
+        if self.passed() {
+            return f.write_str("passed");
+        }
+
+        for (index, (address, actual)) in self.mismatches.iter().enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+
+            let name = match Port::of(*address) {
+                Some(Port::Pin(_)) => "out",
+                _ => "ram",
+            };
+            write!(f, "{name}[{address}] was {}", u8::from(*actual))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for VectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidCycle { line } => {
+                write!(f, "Line {line} should start with a cycle, like `12:`")
+            }
+            Self::InvalidAssignment { line, assignment } => {
+                write!(f, "`{assignment}` on line {line} isn't a valid assignment")
+            }
+            Self::OutOfOrder { line } => {
+                write!(
+                    f,
+                    "Line {line} is for an earlier cycle than the line before"
+                )
+            }
+        }
+    }
+}
+
+impl core::error::Error for VectorError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Emulator, Program, TestVectors, VectorError};
+
+    #[test]
+    fn handles_test_vectors() {
+        let vectors = TestVectors::parse(concat!(
+            "; Copies pin 1 to pin 2 and its complement to scratch RAM\n",
+            "0: in[1]=1\n",
+            "2: -> out[2]=1\n",
+            "4: in[1]=0 -> out[2]=1 ram[0x9]=0\n",
+            "\n",
+            "9: -> out[2]=0 ram[9]=1\n",
+            "9: -> out[2]=1 ram[9]=0 ; wrong on purpose\n",
+        ))
+        .unwrap();
+        assert_eq!(vectors.vectors().len(), 5);
+        assert_eq!(vectors.vectors()[2].inputs, [(1, false)]);
+
+        let program = Program::from_assembly("LD 1\nSTO 2\nSTOC 9");
+        let mut emulator = Emulator::new(&program).unwrap();
+        let results = emulator.apply(&vectors);
+        let passed: Vec<_> = results.iter().map(|result| result.passed()).collect();
+        assert_eq!(passed, [true, true, true, true, false]);
+        assert_eq!(results[4].mismatches, [(2, false), (9, true)]);
+        assert_eq!(
+            results[4].to_string(),
+            "Line 7, cycle 9: out[2] was 0, ram[9] was 1"
+        );
+        assert_eq!(results[1].to_string(), "Line 3, cycle 2: passed");
+    }
+
+    #[test]
+    fn handles_test_vector_errors() {
+        assert_eq!(
+            TestVectors::parse("in[1]=1"),
+            Err(VectorError::InvalidCycle { line: 1 })
+        );
+        assert_eq!(
+            TestVectors::parse("0: out[1]=1"),
+            Err(VectorError::InvalidAssignment {
+                line: 1,
+                assignment: "out[1]=1".to_string()
+            })
+        );
+        assert_eq!(
+            TestVectors::parse("0: -> ram[3]=1")
+                .unwrap_err()
+                .to_string(),
+            "`ram[3]=1` on line 1 isn't a valid assignment"
+        );
+        assert_eq!(
+            TestVectors::parse("0: -> out[3]=2")
+                .unwrap_err()
+                .to_string(),
+            "`out[3]=2` on line 1 isn't a valid assignment"
+        );
+        assert_eq!(
+            TestVectors::parse("0: in[0]=1"),
+            Err(VectorError::InvalidAssignment {
+                line: 1,
+                assignment: "in[0]=1".to_string()
+            })
+        );
+        assert_eq!(
+            TestVectors::parse("5:\n; later\n3:"),
+            Err(VectorError::OutOfOrder { line: 3 })
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! Encodings for embedding packed programs in text. Programs are only ever a few dozen bytes, so
+//! these are written out here rather than pulled in as dependencies, and favour being simple over
+//! being fast.
+
+use crate::prelude::*;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64, with padding.
+pub(crate) fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (index, byte)| {
+            group | u32::from(*byte) << (16 - 8 * index)
+        });
+
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let digit = (group >> (18 - 6 * index)) & 0x3F;
+                encoded.push(char::from(BASE64_ALPHABET[digit as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// The first length each length code stands for, from 257 on.
+const LENGTH_BASES: [usize; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// The first distance each distance code stands for.
+const DISTANCE_BASES: [usize; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const WINDOW: usize = 32768;
+
+/// Raw deflate (RFC 1951, without a zlib or gzip wrapper) as a single block with the fixed
+/// Huffman codes, finding repeats by checking every earlier position.
+pub(crate) fn deflate(bytes: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    // The only block, using the fixed codes
+    writer.bits(1, 1);
+    writer.bits(0b01, 2);
+
+    let mut position = 0;
+    while position < bytes.len() {
+        let (length, distance) = longest_match(bytes, position);
+        if length < MIN_MATCH {
+            writer.symbol(usize::from(bytes[position]));
+            position += 1;
+            continue;
+        }
+
+        let code = LENGTH_BASES.partition_point(|&base| base <= length) - 1;
+        writer.symbol(257 + code);
+        writer.bits(length - LENGTH_BASES[code], LENGTH_EXTRA_BITS[code]);
+
+        let code = DISTANCE_BASES.partition_point(|&base| base <= distance) - 1;
+        writer.huffman(code, 5);
+        writer.bits(distance - DISTANCE_BASES[code], DISTANCE_EXTRA_BITS[code]);
+
+        position += length;
+    }
+
+    writer.symbol(256);
+    writer.bytes
+}
+
+/// The longest run at `position` that also starts somewhere in the window before it, as its
+/// length and how far back it is.
+fn longest_match(bytes: &[u8], position: usize) -> (usize, usize) {
+    let limit = MAX_MATCH.min(bytes.len() - position);
+    (position.saturating_sub(WINDOW)..position)
+        .map(|start| {
+            let length = (0..limit)
+                .take_while(|&offset| bytes[start + offset] == bytes[position + offset])
+                .count();
+            (length, position - start)
+        })
+        // Prefer the closest of equally long matches
+        .max_by_key(|&(length, distance)| (length, usize::MAX - distance))
+        .unwrap_or((0, 0))
+}
+
+/// Packs bits into bytes starting from the least significant bit, as deflate does.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// How many bits of the last byte are used, with 0 meaning it's full (or there isn't one)
+    used: u8,
+}
+
+impl BitWriter {
+    /// Writes the lowest `count` bits of `value`, least significant first.
+    fn bits(&mut self, value: usize, count: u8) {
+        for bit in 0..count {
+            if self.used == 0 {
+                self.bytes.push(0);
+            }
+
+            if let Some(last) = self.bytes.last_mut() {
+                *last |= (((value >> bit) & 1) as u8) << self.used;
+            }
+            self.used = (self.used + 1) % 8;
+        }
+    }
+
+    /// Writes a Huffman code, which goes most significant bit first.
+    fn huffman(&mut self, code: usize, length: u8) {
+        for bit in (0..length).rev() {
+            self.bits(code >> bit, 1);
+        }
+    }
+
+    /// Writes a literal/length symbol with its fixed Huffman code.
+    fn symbol(&mut self, symbol: usize) {
+        match symbol {
+            0..=143 => self.huffman(0x30 + symbol, 8),
+            144..=255 => self.huffman(0x190 + symbol - 144, 9),
+            256..=279 => self.huffman(symbol - 256, 7),
+            _ => self.huffman(0xC0 + symbol - 280, 8),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base64, deflate};
+
+    #[test]
+    fn handles_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0xFB, 0xFF, 0xBF]), "+/+/");
+    }
+
+    #[test]
+    fn handles_deflate() {
+        // Checked by inflating with zlib
+        assert_eq!(deflate(b""), [0x03, 0x00]);
+        assert_eq!(deflate(b"a"), [0x4B, 0x04, 0x00]);
+        assert_eq!(
+            deflate(b"abcabcabcabc"),
+            [0x4B, 0x4C, 0x4A, 0x86, 0x23, 0x00]
+        );
+    }
+}
@@ -0,0 +1,812 @@
+use core::fmt;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+use crate::prelude::*;
+
+/// Every error carries the location of the code that caused it. Errors that come from inside a
+/// macro are wrapped in [`AssemblerError::InMacro`], once for each expansion that led there, and
+/// errors from an included file in [`AssemblerError::InInclude`], once for each `.include`.
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AssemblerError {
+    ExpectedOperand {
+        location: Location,
+    },
+    ExceededMaxLength {
+        location: Location,
+    },
+    UnexpectedToken {
+        text: String,
+        location: Location,
+    },
+    InvalidCharacter {
+        character: char,
+        location: Location,
+    },
+    MissingSeparator {
+        text: String,
+        location: Location,
+    },
+    UnterminatedComment {
+        location: Location,
+    },
+    UndefinedSymbol {
+        name: String,
+        location: Location,
+    },
+    DuplicateSymbol {
+        name: String,
+        /// Boxed to keep errors small
+        previous: Box<Location>,
+        location: Location,
+    },
+    SymbolOutOfRange {
+        name: String,
+        value: usize,
+        location: Location,
+    },
+    OperandOutOfRange {
+        value: usize,
+        location: Location,
+    },
+    UnexpectedOperand {
+        mnemonic: String,
+        location: Location,
+    },
+    JumpOutOfRange {
+        offset: isize,
+        address: usize,
+        location: Location,
+    },
+    ExpressionOutOfRange {
+        value: i64,
+        location: Location,
+    },
+    NegativeValue {
+        context: String,
+        value: i64,
+        location: Location,
+    },
+    DivisionByZero {
+        location: Location,
+    },
+    ArithmeticOverflow {
+        location: Location,
+    },
+    ExpressionTooComplex {
+        location: Location,
+    },
+    UnclosedParenthesis {
+        location: Location,
+    },
+    UnexpectedIdentifier {
+        name: String,
+        location: Location,
+    },
+    ExpectedLabelName {
+        location: Location,
+    },
+    UnknownDirective {
+        name: String,
+        location: Location,
+    },
+    DirectiveNotAllowed {
+        name: String,
+        location: Location,
+    },
+    ExpectedSymbolName {
+        directive: String,
+        location: Location,
+    },
+    ExpectedValue {
+        context: String,
+        location: Location,
+    },
+    OrgBehind {
+        address: usize,
+        current: usize,
+        location: Location,
+    },
+    ExpectedRawNibbles {
+        location: Location,
+    },
+    InvalidNibble {
+        character: char,
+        location: Location,
+    },
+    ScratchRamExhausted {
+        name: String,
+        location: Location,
+    },
+    ExpectedMacroName {
+        location: Location,
+    },
+    ExpectedParameterName {
+        name: String,
+        location: Location,
+    },
+    DuplicateMacro {
+        name: String,
+        location: Location,
+    },
+    NestedMacro {
+        name: String,
+        location: Location,
+    },
+    UnterminatedMacro {
+        name: String,
+        location: Location,
+    },
+    UnmatchedDirective {
+        directive: String,
+        location: Location,
+    },
+    MacroArgumentCount {
+        name: String,
+        expected: usize,
+        found: usize,
+        location: Location,
+    },
+    ExpectedMacroArgument {
+        name: String,
+        location: Location,
+    },
+    MacroRecursionLimit {
+        name: String,
+        location: Location,
+    },
+    UnterminatedConditional {
+        location: Location,
+    },
+    DuplicateElse {
+        location: Location,
+    },
+    UnterminatedRepeat {
+        location: Location,
+    },
+    ExpectedDefineName {
+        directive: String,
+        location: Location,
+    },
+    UnknownPreprocessorDirective {
+        name: String,
+        location: Location,
+    },
+    UnmatchedPreprocessorDirective {
+        directive: String,
+        location: Location,
+    },
+    DuplicatePreprocessorElse {
+        location: Location,
+    },
+    UnterminatedPreprocessorConditional {
+        location: Location,
+    },
+    ErrorDirective {
+        message: String,
+        location: Location,
+    },
+    ExpectedString {
+        directive: String,
+        location: Location,
+    },
+    /// A metadata directive like `.name` was given more than once
+    DuplicateMetadata {
+        directive: String,
+        location: Location,
+    },
+    /// An `.assert` that can't be read, with a message saying why
+    InvalidAssertion {
+        message: String,
+        location: Location,
+    },
+    DirectiveFailed {
+        name: String,
+        message: Box<str>,
+        location: Location,
+    },
+    ExpectedIncludePath {
+        location: Location,
+    },
+    UnknownLibraryFile {
+        name: String,
+        location: Location,
+    },
+    IncludeFailed {
+        path: FilePath,
+        reason: Box<str>,
+        location: Location,
+    },
+    CircularInclude {
+        path: FilePath,
+        location: Location,
+    },
+    InMacro {
+        name: String,
+        location: Location,
+        source: Box<AssemblerError>,
+    },
+    InInclude {
+        location: Location,
+        source: Box<AssemblerError>,
+    },
+}
+
+/// A problem found while assembling a program, along with what's needed to explain it: what the
+/// assembler was after, what it found instead, and the line with the offending text underlined.
+/// See [`crate::Program::to_opcodes_with_diagnostics`] for getting errors and warnings together.
+///
+/// For errors inside macros and includes, everything but `kind` describes the innermost error,
+/// where the problem actually is.
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diagnostic {
+    /// The error or warning itself, for telling diagnostics apart
+    pub kind: DiagnosticKind,
+    /// What should have been there, for errors about something missing
+    pub expected: Option<String>,
+    /// The text the diagnostic is about, or `None` if it's about the end of a line
+    pub found: Option<String>,
+    /// Where `found` is, as a byte range of the innermost file, like [`Location::offset`]
+    pub span: Range<usize>,
+    /// Where `found` is, as a byte range of the line in the innermost [`Location::snippet`]
+    pub snippet_span: Range<usize>,
+    /// The line the diagnostic is on with `found` underlined, the way a compiler shows it
+    pub snippet: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DiagnosticKind {
+    /// Stops the program from assembling
+    Error(AssemblerError),
+    /// Doesn't stop the program from assembling, but is probably a mistake
+    Warning(Warning),
+}
+
+/// How serious a [`Diagnostic`] is, in increasing order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// Where in the source an error was found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Location {
+    /// The file the error is in, or `None` if the source didn't come from a file.
+    pub file: Option<FilePath>,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column number, counted in characters
+    pub column: usize,
+    /// Where `column` is as a byte offset from the start of the file, counting the file the way
+    /// `snippet` shows it, with line endings normalized and the preprocessor run
+    pub offset: usize,
+    /// The full text of the line the error is on
+    pub snippet: String,
+    /// How many bytes of the source the error is about, starting at `column`
+    pub length: usize,
+}
+
+impl AssemblerError {
+    /// For [`AssemblerError::InMacro`], this is where the macro was invoked, and for
+    /// [`AssemblerError::InInclude`] it's the `.include`. The error inside either has its own
+    /// location.
+    pub fn location(&self) -> &Location {
+        match self {
+            Self::ExpectedOperand { location, .. }
+            | Self::ExceededMaxLength { location, .. }
+            | Self::UnexpectedToken { location, .. }
+            | Self::InvalidCharacter { location, .. }
+            | Self::MissingSeparator { location, .. }
+            | Self::UnterminatedComment { location, .. }
+            | Self::UndefinedSymbol { location, .. }
+            | Self::DuplicateSymbol { location, .. }
+            | Self::SymbolOutOfRange { location, .. }
+            | Self::OperandOutOfRange { location, .. }
+            | Self::UnexpectedOperand { location, .. }
+            | Self::JumpOutOfRange { location, .. }
+            | Self::ExpressionOutOfRange { location, .. }
+            | Self::NegativeValue { location, .. }
+            | Self::DivisionByZero { location, .. }
+            | Self::ArithmeticOverflow { location, .. }
+            | Self::ExpressionTooComplex { location, .. }
+            | Self::UnclosedParenthesis { location, .. }
+            | Self::UnexpectedIdentifier { location, .. }
+            | Self::ExpectedLabelName { location, .. }
+            | Self::UnknownDirective { location, .. }
+            | Self::DirectiveNotAllowed { location, .. }
+            | Self::ExpectedSymbolName { location, .. }
+            | Self::ExpectedValue { location, .. }
+            | Self::OrgBehind { location, .. }
+            | Self::ScratchRamExhausted { location, .. }
+            | Self::ExpectedRawNibbles { location, .. }
+            | Self::InvalidNibble { location, .. }
+            | Self::ExpectedMacroName { location, .. }
+            | Self::ExpectedParameterName { location, .. }
+            | Self::DuplicateMacro { location, .. }
+            | Self::NestedMacro { location, .. }
+            | Self::UnterminatedMacro { location, .. }
+            | Self::UnmatchedDirective { location, .. }
+            | Self::MacroArgumentCount { location, .. }
+            | Self::ExpectedMacroArgument { location, .. }
+            | Self::MacroRecursionLimit { location, .. }
+            | Self::UnterminatedConditional { location, .. }
+            | Self::DuplicateElse { location, .. }
+            | Self::UnterminatedRepeat { location, .. }
+            | Self::ExpectedDefineName { location, .. }
+            | Self::UnknownPreprocessorDirective { location, .. }
+            | Self::UnmatchedPreprocessorDirective { location, .. }
+            | Self::DuplicatePreprocessorElse { location, .. }
+            | Self::UnterminatedPreprocessorConditional { location, .. }
+            | Self::ErrorDirective { location, .. }
+            | Self::ExpectedString { location, .. }
+            | Self::DuplicateMetadata { location, .. }
+            | Self::InvalidAssertion { location, .. }
+            | Self::DirectiveFailed { location, .. }
+            | Self::ExpectedIncludePath { location, .. }
+            | Self::UnknownLibraryFile { location, .. }
+            | Self::IncludeFailed { location, .. }
+            | Self::CircularInclude { location, .. }
+            | Self::InMacro { location, .. }
+            | Self::InInclude { location, .. } => location,
+        }
+    }
+
+    /// Errors are built without a location wherever it's inconvenient to know one, and filled in
+    /// further up once it's known which token was being processed.
+    pub(crate) fn locate(mut self, location: impl FnOnce() -> Location) -> Self {
+        if self.location().is_unknown() {
+            *self.location_mut() = location();
+        }
+
+        self
+    }
+
+    /// The error that's actually at fault, rather than the macro invocations and includes that
+    /// led to it.
+    pub fn innermost(&self) -> &AssemblerError {
+        match self {
+            Self::InMacro { source, .. } | Self::InInclude { source, .. } => source.innermost(),
+            error => error,
+        }
+    }
+
+    /// A code for the kind of error, like `E001` for [`AssemblerError::ExpectedOperand`], for
+    /// tools that need to pick out particular errors. Codes never change meaning between
+    /// versions, and new errors get new codes. [`AssemblerError::InMacro`] and
+    /// [`AssemblerError::InInclude`] give the code of the error inside them.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::ExpectedOperand { .. } => "E001",
+            Self::ExceededMaxLength { .. } => "E002",
+            Self::UnexpectedToken { .. } => "E003",
+            Self::InvalidCharacter { .. } => "E004",
+            Self::MissingSeparator { .. } => "E005",
+            Self::UnterminatedComment { .. } => "E006",
+            Self::UndefinedSymbol { .. } => "E007",
+            Self::DuplicateSymbol { .. } => "E008",
+            Self::SymbolOutOfRange { .. } => "E009",
+            Self::OperandOutOfRange { .. } => "E010",
+            Self::UnexpectedOperand { .. } => "E011",
+            Self::JumpOutOfRange { .. } => "E012",
+            Self::ExpressionOutOfRange { .. } => "E013",
+            Self::NegativeValue { .. } => "E014",
+            Self::DivisionByZero { .. } => "E015",
+            Self::ArithmeticOverflow { .. } => "E016",
+            Self::UnclosedParenthesis { .. } => "E017",
+            Self::UnexpectedIdentifier { .. } => "E018",
+            Self::ExpectedLabelName { .. } => "E019",
+            Self::UnknownDirective { .. } => "E020",
+            Self::DirectiveNotAllowed { .. } => "E021",
+            Self::ExpectedSymbolName { .. } => "E022",
+            Self::ExpectedValue { .. } => "E023",
+            Self::OrgBehind { .. } => "E024",
+            Self::ExpectedRawNibbles { .. } => "E025",
+            Self::InvalidNibble { .. } => "E026",
+            Self::ScratchRamExhausted { .. } => "E027",
+            Self::ExpectedMacroName { .. } => "E028",
+            Self::ExpectedParameterName { .. } => "E029",
+            Self::DuplicateMacro { .. } => "E030",
+            Self::NestedMacro { .. } => "E031",
+            Self::UnterminatedMacro { .. } => "E032",
+            Self::UnmatchedDirective { .. } => "E033",
+            Self::MacroArgumentCount { .. } => "E034",
+            Self::ExpectedMacroArgument { .. } => "E035",
+            Self::MacroRecursionLimit { .. } => "E036",
+            Self::UnterminatedConditional { .. } => "E037",
+            Self::DuplicateElse { .. } => "E038",
+            Self::UnterminatedRepeat { .. } => "E039",
+            Self::ExpectedDefineName { .. } => "E040",
+            Self::UnknownPreprocessorDirective { .. } => "E041",
+            Self::UnmatchedPreprocessorDirective { .. } => "E042",
+            Self::DuplicatePreprocessorElse { .. } => "E043",
+            Self::UnterminatedPreprocessorConditional { .. } => "E044",
+            Self::ErrorDirective { .. } => "E045",
+            Self::ExpectedString { .. } => "E046",
+            Self::DirectiveFailed { .. } => "E047",
+            Self::ExpectedIncludePath { .. } => "E048",
+            Self::UnknownLibraryFile { .. } => "E049",
+            Self::IncludeFailed { .. } => "E050",
+            Self::CircularInclude { .. } => "E051",
+            Self::ExpressionTooComplex { .. } => "E052",
+            Self::DuplicateMetadata { .. } => "E053",
+            Self::InvalidAssertion { .. } => "E054",
+            Self::InMacro { source, .. } | Self::InInclude { source, .. } => source.code(),
+        }
+    }
+
+    /// What should have been where the error is, for errors about something missing.
+    fn expected(&self) -> Option<&'static str> {
+        let expected = match self {
+            Self::ExpectedOperand { .. } | Self::ExpectedMacroArgument { .. } => "an operand",
+            Self::ExpectedLabelName { .. } => "a label name",
+            Self::ExpectedSymbolName { .. } | Self::ExpectedDefineName { .. } => "a name",
+            Self::ExpectedValue { .. } => "a value",
+            Self::ExpectedRawNibbles { .. } | Self::ExpectedString { .. } => "a quoted string",
+            Self::ExpectedMacroName { .. } => "a macro name",
+            Self::ExpectedParameterName { .. } => "a parameter name",
+            Self::ExpectedIncludePath { .. } => "a quoted path or `<std/...>`",
+            Self::UnclosedParenthesis { .. } => "`)`",
+            Self::MissingSeparator { .. } => "a space",
+            _ => return None,
+        };
+
+        Some(expected)
+    }
+
+    fn location_mut(&mut self) -> &mut Location {
+        match self {
+            Self::ExpectedOperand { location, .. }
+            | Self::ExceededMaxLength { location, .. }
+            | Self::UnexpectedToken { location, .. }
+            | Self::InvalidCharacter { location, .. }
+            | Self::MissingSeparator { location, .. }
+            | Self::UnterminatedComment { location, .. }
+            | Self::UndefinedSymbol { location, .. }
+            | Self::DuplicateSymbol { location, .. }
+            | Self::SymbolOutOfRange { location, .. }
+            | Self::OperandOutOfRange { location, .. }
+            | Self::UnexpectedOperand { location, .. }
+            | Self::JumpOutOfRange { location, .. }
+            | Self::ExpressionOutOfRange { location, .. }
+            | Self::NegativeValue { location, .. }
+            | Self::DivisionByZero { location, .. }
+            | Self::ArithmeticOverflow { location, .. }
+            | Self::ExpressionTooComplex { location, .. }
+            | Self::UnclosedParenthesis { location, .. }
+            | Self::UnexpectedIdentifier { location, .. }
+            | Self::ExpectedLabelName { location, .. }
+            | Self::UnknownDirective { location, .. }
+            | Self::DirectiveNotAllowed { location, .. }
+            | Self::ExpectedSymbolName { location, .. }
+            | Self::ExpectedValue { location, .. }
+            | Self::OrgBehind { location, .. }
+            | Self::ScratchRamExhausted { location, .. }
+            | Self::ExpectedRawNibbles { location, .. }
+            | Self::InvalidNibble { location, .. }
+            | Self::ExpectedMacroName { location, .. }
+            | Self::ExpectedParameterName { location, .. }
+            | Self::DuplicateMacro { location, .. }
+            | Self::NestedMacro { location, .. }
+            | Self::UnterminatedMacro { location, .. }
+            | Self::UnmatchedDirective { location, .. }
+            | Self::MacroArgumentCount { location, .. }
+            | Self::ExpectedMacroArgument { location, .. }
+            | Self::MacroRecursionLimit { location, .. }
+            | Self::UnterminatedConditional { location, .. }
+            | Self::DuplicateElse { location, .. }
+            | Self::UnterminatedRepeat { location, .. }
+            | Self::ExpectedDefineName { location, .. }
+            | Self::UnknownPreprocessorDirective { location, .. }
+            | Self::UnmatchedPreprocessorDirective { location, .. }
+            | Self::DuplicatePreprocessorElse { location, .. }
+            | Self::UnterminatedPreprocessorConditional { location, .. }
+            | Self::ErrorDirective { location, .. }
+            | Self::ExpectedString { location, .. }
+            | Self::DuplicateMetadata { location, .. }
+            | Self::InvalidAssertion { location, .. }
+            | Self::DirectiveFailed { location, .. }
+            | Self::ExpectedIncludePath { location, .. }
+            | Self::UnknownLibraryFile { location, .. }
+            | Self::IncludeFailed { location, .. }
+            | Self::CircularInclude { location, .. }
+            | Self::InMacro { location, .. }
+            | Self::InInclude { location, .. } => location,
+        }
+    }
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ExpectedOperand { location } => write!(f, "{location}: Expected operand"),
+            Self::ExceededMaxLength { location } => write!(f, "{location}: Exceeded max program length"),
+            Self::UnexpectedToken { text, location } => write!(f, "{location}: Unexpected `{text}`"),
+            Self::InvalidCharacter { character, location } => write!(f, "{location}: Invalid character `{character}` (U+{:04X})", u32::from(*character)),
+            Self::MissingSeparator { text, location } => write!(f, "{location}: Missing space in `{text}`"),
+            Self::UnterminatedComment { location } => write!(f, "{location}: `/*` is missing `*/`"),
+            Self::UndefinedSymbol { name, location } => write!(f, "{location}: Undefined symbol `{name}`"),
+            Self::DuplicateSymbol { name, previous, location } => write!(f, "{location}: Symbol `{name}` is defined more than once\n  first defined at {previous}"),
+            Self::SymbolOutOfRange { name, value, location } => write!(f, "{location}: Symbol `{name}` has value {value:#X}, which doesn't fit in an operand"),
+            Self::OperandOutOfRange { value, location } => write!(f, "{location}: Operand {value:#X} doesn't fit in a nibble"),
+            Self::UnexpectedOperand { mnemonic, location } => write!(f, "{location}: `{mnemonic}` doesn't take an operand"),
+            Self::JumpOutOfRange { offset, address, location } => write!(f, "{location}: Jump of {offset:+} from address {address:#X} lands outside the program"),
+            Self::ExpressionOutOfRange { value, location } => write!(f, "{location}: Operand evaluates to {value}, which doesn't fit in 0x0..=0xF"),
+            Self::NegativeValue { context, value, location } => write!(f, "{location}: Value for `{context}` can't be negative, but evaluates to {value}"),
+            Self::DivisionByZero { location } => write!(f, "{location}: Division by zero"),
+            Self::ArithmeticOverflow { location } => write!(f, "{location}: Arithmetic overflow"),
+            Self::ExpressionTooComplex { location } => write!(f, "{location}: Expression is too long or nested too deeply"),
+            Self::UnclosedParenthesis { location } => write!(f, "{location}: `(` is missing `)`"),
+            Self::UnexpectedIdentifier { name, location } => write!(f, "{location}: Unexpected identifier `{name}`"),
+            Self::ExpectedLabelName { location } => write!(f, "{location}: Expected label name before `:`"),
+            Self::UnknownDirective { name, location } => write!(f, "{location}: Unknown directive `.{name}`"),
+            Self::DirectiveNotAllowed { name, location } => write!(f, "{location}: Directive `.{name}` isn't allowed here"),
+            Self::ExpectedSymbolName { directive, location } => write!(f, "{location}: Expected symbol name after `.{directive}`"),
+            Self::ExpectedValue { context, location } => write!(f, "{location}: Expected value for `{context}`"),
+            Self::OrgBehind { address, current, location } => write!(f, "{location}: `.org {address:#X}` is behind the current address {current:#X}"),
+            Self::ExpectedRawNibbles { location } => write!(f, "{location}: Expected a string of hex digits after `.raw`"),
+            Self::InvalidNibble { character, location } => write!(f, "{location}: `{character}` isn't a hex digit"),
+            Self::ScratchRamExhausted { name, location } => write!(f, "{location}: No scratch RAM left for variable `{name}`"),
+            Self::ExpectedMacroName { location } => write!(f, "{location}: Expected macro name after `.macro`"),
+            Self::ExpectedParameterName { name, location } => write!(f, "{location}: Expected parameter name in definition of macro `{name}`"),
+            Self::DuplicateMacro { name, location } => write!(f, "{location}: Macro `{name}` is defined more than once"),
+            Self::NestedMacro { name, location } => write!(f, "{location}: Macro `{name}` can't be defined inside another macro"),
+            Self::UnterminatedMacro { name, location } => write!(f, "{location}: Macro `{name}` is missing `.endm`"),
+            Self::UnmatchedDirective { directive, location } => write!(f, "{location}: `.{directive}` without a matching opening directive"),
+            Self::MacroArgumentCount { name, expected, found, location } => write!(f, "{location}: Macro `{name}` takes {expected} argument(s), but {found} were given"),
+            Self::ExpectedMacroArgument { name, location } => write!(f, "{location}: Expected argument for macro `{name}`"),
+            Self::MacroRecursionLimit { name, location } => write!(f, "{location}: Macro `{name}` expanded too deeply; does it invoke itself?"),
+            Self::UnterminatedConditional { location } => write!(f, "{location}: `.if` is missing `.endif`"),
+            Self::DuplicateElse { location } => write!(f, "{location}: `.else` appears more than once in the same `.if`"),
+            Self::UnterminatedRepeat { location } => write!(f, "{location}: `.rept` is missing `.endr`"),
+            Self::ExpectedDefineName { directive, location } => write!(f, "{location}: Expected name after `#{directive}`"),
+            Self::UnknownPreprocessorDirective { name, location } => write!(f, "{location}: Unknown preprocessor directive `#{name}`"),
+            Self::UnmatchedPreprocessorDirective { directive, location } => write!(f, "{location}: `#{directive}` without a matching `#ifdef` or `#ifndef`"),
+            Self::DuplicatePreprocessorElse { location } => write!(f, "{location}: `#else` appears more than once in the same `#ifdef`"),
+            Self::UnterminatedPreprocessorConditional { location } => write!(f, "{location}: `#ifdef` is missing `#endif`"),
+            Self::ErrorDirective { message, location } => write!(f, "{location}: {message}"),
+            Self::ExpectedString { directive, location } => write!(f, "{location}: Expected quoted string after `.{directive}`"),
+            Self::DuplicateMetadata { directive, location } => write!(f, "{location}: `.{directive}` is given more than once"),
+            Self::InvalidAssertion { message, location } => write!(f, "{location}: Invalid `.assert`: {message}"),
+            Self::DirectiveFailed { name, message, location } => write!(f, "{location}: `.{name}`: {message}"),
+            Self::ExpectedIncludePath { location } => write!(f, "{location}: Expected quoted path or `<std/...>` after `.include`"),
+            Self::UnknownLibraryFile { name, location } => write!(f, "{location}: There's no standard library file called `<{name}>`"),
+            Self::IncludeFailed { path, reason, location } => write!(f, "{location}: Couldn't include {path}: {reason}"),
+            Self::CircularInclude { path, location } => write!(f, "{location}: {path} ends up including itself"),
+            Self::InMacro { name, location, source } => write!(f, "{source}\n  expanded from macro `{name}` at {location}"),
+            Self::InInclude { location, source } => write!(f, "{source}\n  included from {location}"),
+        }
+    }
+}
+
+impl core::error::Error for AssemblerError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::InMacro { source, .. } | Self::InInclude { source, .. } => Some(&**source),
+            _ => None,
+        }
+    }
+}
+
+impl Diagnostic {
+    fn new(kind: DiagnosticKind, location: &Location, expected: Option<&str>) -> Self {
+        // Columns count characters, but the span is in bytes
+        let start = location
+            .snippet
+            .char_indices()
+            .nth(location.column.saturating_sub(1))
+            .map_or(location.snippet.len(), |(index, _)| index);
+        let end = (start + location.length).min(location.snippet.len());
+        let found = &location.snippet[start..end];
+
+        let gutter = " ".repeat(location.line.to_string().len());
+        let underline = format!(
+            "{}{}",
+            " ".repeat(location.snippet[..start].chars().count()),
+            "^".repeat(found.chars().count().max(1)),
+        );
+        let snippet = format!(
+            "{gutter} |\n{} | {}\n{gutter} | {underline}",
+            location.line, location.snippet
+        );
+
+        Self {
+            expected: expected.map(String::from),
+            found: (!found.is_empty()).then(|| found.to_string()),
+            span: location.offset..location.offset + found.len(),
+            snippet_span: start..end,
+            snippet,
+            kind,
+        }
+    }
+
+    /// The code of the error or warning, from [`AssemblerError::code`] or [`Warning::code`].
+    pub fn code(&self) -> &'static str {
+        match &self.kind {
+            DiagnosticKind::Error(error) => error.code(),
+            DiagnosticKind::Warning(warning) => warning.code(),
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self.kind {
+            DiagnosticKind::Error(_) => Severity::Error,
+            DiagnosticKind::Warning(_) => Severity::Warning,
+        }
+    }
+}
+
+impl From<AssemblerError> for Diagnostic {
+    fn from(error: AssemblerError) -> Self {
+        let innermost = error.innermost();
+        let (location, expected) = (innermost.location().clone(), innermost.expected());
+        Self::new(DiagnosticKind::Error(error), &location, expected)
+    }
+}
+
+impl From<Warning> for Diagnostic {
+    fn from(warning: Warning) -> Self {
+        let location = warning.location().clone();
+        Self::new(DiagnosticKind::Warning(warning), &location, None)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\n{}", self.kind, self.snippet)
+    }
+}
+
+impl core::error::Error for Diagnostic {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        Some(&self.kind)
+    }
+}
+
+impl DiagnosticKind {
+    /// Where the error or warning is. For errors, this is [`AssemblerError::location`].
+    pub fn location(&self) -> &Location {
+        match self {
+            Self::Error(error) => error.location(),
+            Self::Warning(warning) => warning.location(),
+        }
+    }
+}
+
+impl fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error(error) => error.fmt(f),
+            Self::Warning(warning) => warning.fmt(f),
+        }
+    }
+}
+
+impl core::error::Error for DiagnosticKind {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Error(error) => error.source(),
+            Self::Warning(warning) => warning.source(),
+        }
+    }
+}
+
+/// Something suspicious that doesn't stop a program from assembling.
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Warning {
+    UnusedLabel { name: String, location: Location },
+}
+
+impl Warning {
+    /// Like [`AssemblerError::code`], but starting with `W`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UnusedLabel { .. } => "W001",
+        }
+    }
+
+    pub fn location(&self) -> &Location {
+        match self {
+            Self::UnusedLabel { location, .. } => location,
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnusedLabel { name, location } => {
+                write!(f, "{location}: Label `{name}` is never used")
+            }
+        }
+    }
+}
+
+impl core::error::Error for Warning {}
+
+impl Location {
+    /// Placeholder for errors whose location gets filled in later.
+    pub(crate) const UNKNOWN: Location = Location {
+        file: None,
+        line: 0,
+        column: 0,
+        offset: 0,
+        snippet: String::new(),
+        length: 0,
+    };
+
+    fn is_unknown(&self) -> bool {
+        self.line == 0
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{file}:")?;
+        }
+
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// What a file is named by in diagnostics: the path it was read from, or `<NAME>` for a file from
+/// the standard library. It's the same with or without the `std` feature. Any part of a path
+/// that isn't UTF-8 is replaced with `�`, as `Path::to_string_lossy` does.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct FilePath(String);
+
+impl FilePath {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    #[cfg(feature = "std")]
+    pub fn as_path(&self) -> &Path {
+        Path::new(&self.0)
+    }
+}
+
+impl fmt::Display for FilePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<String> for FilePath {
+    fn from(path: String) -> Self {
+        Self(path)
+    }
+}
+
+impl From<&str> for FilePath {
+    fn from(path: &str) -> Self {
+        Self(path.to_string())
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<&Path> for FilePath {
+    fn from(path: &Path) -> Self {
+        Self(path.to_string_lossy().into_owned())
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<PathBuf> for FilePath {
+    fn from(path: PathBuf) -> Self {
+        Self::from(path.as_path())
+    }
+}
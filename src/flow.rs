@@ -0,0 +1,110 @@
+//! Where control can go from each instruction, for drawing a program or checking which parts of
+//! it can ever run.
+
+use crate::prelude::*;
+use crate::{AssemblerError, Instruction, Program};
+
+/// A way control can get from the instruction at one address to another, from
+/// [`Program::control_flow`]. Addresses count nibbles, like `JMP` does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EdgeKind {
+    /// On to the next instruction
+    Next,
+    /// A `JMP` to its target. Jumps can land partway through an instruction, on its operand.
+    Jump,
+    /// A `SKZ` skipping the instruction after it, when the result register is zero
+    Skip,
+    /// Back to the start of the program, either from `RTN` or from running off the end
+    Restart,
+}
+
+impl Program {
+    /// Every way control can move between instructions, in program order. Programs run over and
+    /// over, so the last instruction leads back to the first, and so does `RTN`. An empty program
+    /// has no edges.
+    pub fn control_flow(&self) -> Result<Vec<Edge>, AssemblerError> {
+        let instructions: Vec<_> = self.iter()?.collect();
+        // Where the instruction `offset` after the one at `index` is, or `None` past the end
+        let after = |index: usize, offset: usize| {
+            instructions
+                .get(index + offset)
+                .map(|(address, _)| *address)
+        };
+
+        let mut edges = Vec::new();
+        for (index, &(from, instruction)) in instructions.iter().enumerate() {
+            let mut edge = |to: Option<usize>, kind| {
+                let edge = match to {
+                    Some(to) => Edge { from, to, kind },
+                    None => Edge {
+                        from,
+                        to: 0,
+                        kind: EdgeKind::Restart,
+                    },
+                };
+
+                // A `SKZ` at the very end restarts either way
+                if !edges.contains(&edge) {
+                    edges.push(edge);
+                }
+            };
+
+            match instruction {
+                Instruction::Jump(target) => edge(Some(usize::from(target)), EdgeKind::Jump),
+                Instruction::Return => edge(None, EdgeKind::Restart),
+                Instruction::SkipIfZero => {
+                    edge(after(index, 1), EdgeKind::Next);
+                    edge(after(index, 2), EdgeKind::Skip);
+                }
+                _ => edge(after(index, 1), EdgeKind::Next),
+            }
+        }
+
+        Ok(edges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Edge, EdgeKind, Program};
+
+    #[test]
+    fn handles_control_flow() {
+        let program = Program::from_assembly("loop: LD 1\nSKZ\nJMP loop\nRTN\nSTO 2");
+        let edges = program.control_flow().unwrap();
+        let edge = |from, to, kind| Edge { from, to, kind };
+        assert_eq!(
+            edges,
+            [
+                edge(0, 2, EdgeKind::Next),
+                edge(2, 3, EdgeKind::Next),
+                edge(2, 5, EdgeKind::Skip),
+                edge(3, 0, EdgeKind::Jump),
+                edge(5, 0, EdgeKind::Restart),
+                edge(6, 0, EdgeKind::Restart),
+            ]
+        );
+
+        // Skipping past the end goes back to the start
+        let program = Program::from_assembly("LD 1\nSKZ");
+        let edges = program.control_flow().unwrap();
+        assert_eq!(
+            edges,
+            [edge(0, 2, EdgeKind::Next), edge(2, 0, EdgeKind::Restart)]
+        );
+
+        assert!(Program::from_assembly("")
+            .control_flow()
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,353 @@
+use alloc::vec;
+use core::fmt;
+
+use crate::assembler::{NOT_RR, SCRATCH_RAM};
+use crate::prelude::*;
+
+/// A single instruction, as the Control Unit sees it. Operands are nibbles, so anything above
+/// `0xF` doesn't make for a valid instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Instruction {
+    NoOp,
+    Load(u8),
+    LoadComplement(u8),
+    And(u8),
+    AndComplement(u8),
+    Or(u8),
+    OrComplement(u8),
+    ExclusiveNor(u8),
+    Store(u8),
+    StoreComplement(u8),
+    InputEnable(u8),
+    OutputEnable(u8),
+    Jump(u8),
+    Return,
+    SkipIfZero,
+    /// A nibble that can't be read as an instruction: either `0xF`, which isn't an opcode, or an
+    /// opcode that needs an operand but is the last nibble of the program. These can only come
+    /// from `.raw` or from outside the assembler.
+    Invalid(u8),
+}
+
+impl Instruction {
+    /// The instruction with the given opcode and operand, if that's a valid combination.
+    pub fn new(opcode: u8, operand: Option<u8>) -> Option<Self> {
+        let with_operand = |instruction: fn(u8) -> Self| match operand {
+            Some(operand) if operand <= 0xF => Some(instruction(operand)),
+            _ => None,
+        };
+        let without_operand = |instruction: Self| operand.is_none().then_some(instruction);
+
+        match opcode {
+            0x0 => without_operand(Self::NoOp),
+            0x1 => with_operand(Self::Load),
+            0x2 => with_operand(Self::LoadComplement),
+            0x3 => with_operand(Self::And),
+            0x4 => with_operand(Self::AndComplement),
+            0x5 => with_operand(Self::Or),
+            0x6 => with_operand(Self::OrComplement),
+            0x7 => with_operand(Self::ExclusiveNor),
+            0x8 => with_operand(Self::Store),
+            0x9 => with_operand(Self::StoreComplement),
+            0xA => with_operand(Self::InputEnable),
+            0xB => with_operand(Self::OutputEnable),
+            0xC => with_operand(Self::Jump),
+            0xD => without_operand(Self::Return),
+            0xE => without_operand(Self::SkipIfZero),
+            _ => None,
+        }
+    }
+
+    /// The first nibble of the instruction.
+    pub fn opcode(self) -> u8 {
+        match self {
+            Self::NoOp => 0x0,
+            Self::Load(_) => 0x1,
+            Self::LoadComplement(_) => 0x2,
+            Self::And(_) => 0x3,
+            Self::AndComplement(_) => 0x4,
+            Self::Or(_) => 0x5,
+            Self::OrComplement(_) => 0x6,
+            Self::ExclusiveNor(_) => 0x7,
+            Self::Store(_) => 0x8,
+            Self::StoreComplement(_) => 0x9,
+            Self::InputEnable(_) => 0xA,
+            Self::OutputEnable(_) => 0xB,
+            Self::Jump(_) => 0xC,
+            Self::Return => 0xD,
+            Self::SkipIfZero => 0xE,
+            Self::Invalid(nibble) => nibble,
+        }
+    }
+
+    pub fn operand(self) -> Option<u8> {
+        match self {
+            Self::Load(operand)
+            | Self::LoadComplement(operand)
+            | Self::And(operand)
+            | Self::AndComplement(operand)
+            | Self::Or(operand)
+            | Self::OrComplement(operand)
+            | Self::ExclusiveNor(operand)
+            | Self::Store(operand)
+            | Self::StoreComplement(operand)
+            | Self::InputEnable(operand)
+            | Self::OutputEnable(operand)
+            | Self::Jump(operand) => Some(operand),
+            Self::NoOp | Self::Return | Self::SkipIfZero | Self::Invalid(_) => None,
+        }
+    }
+
+    /// How the instruction is written in assembly, or `None` for [`Instruction::Invalid`].
+    pub fn mnemonic(self) -> Option<&'static str> {
+        let mnemonic = match self {
+            Self::NoOp => "NOP",
+            Self::Load(_) => "LD",
+            Self::LoadComplement(_) => "LDC",
+            Self::And(_) => "AND",
+            Self::AndComplement(_) => "ANDC",
+            Self::Or(_) => "OR",
+            Self::OrComplement(_) => "ORC",
+            Self::ExclusiveNor(_) => "XNOR",
+            Self::Store(_) => "STO",
+            Self::StoreComplement(_) => "STOC",
+            Self::InputEnable(_) => "IEN",
+            Self::OutputEnable(_) => "OEN",
+            Self::Jump(_) => "JMP",
+            Self::Return => "RTN",
+            Self::SkipIfZero => "SKZ",
+            Self::Invalid(_) => return None,
+        };
+
+        Some(mnemonic)
+    }
+
+    /// How many nibbles the instruction takes up: one for the opcode, plus one for the operand
+    /// if it has one.
+    pub fn size(self) -> usize {
+        1 + usize::from(self.operand().is_some())
+    }
+
+    /// Whether the operand (if any) fits in a nibble and the instruction isn't
+    /// [`Instruction::Invalid`].
+    pub fn is_valid(self) -> bool {
+        !matches!(self, Self::Invalid(_)) && self.operand().map_or(true, |operand| operand <= 0xF)
+    }
+
+    /// What the instruction does on the Control Unit, in words, like `write RR to output pin 3`.
+    /// Addresses `8` to `F` are scratch RAM and the rest are I/O pins, except that reading pin `0`
+    /// gives not RR.
+    pub fn effect(self) -> String {
+        let place = |operand: u8, pin: &str| {
+            if SCRATCH_RAM.contains(&usize::from(operand)) {
+                format!("scratch RAM {operand:X}")
+            } else {
+                format!("{pin} pin {operand:X}")
+            }
+        };
+        let input = |operand| match operand {
+            NOT_RR => "not RR".to_string(),
+            operand => place(operand, "input"),
+        };
+        let not_input = |operand| match operand {
+            NOT_RR => "RR".to_string(),
+            operand => format!("not {}", input(operand)),
+        };
+
+        match self {
+            Self::NoOp => "do nothing".to_string(),
+            Self::Load(operand) => format!("set RR to {}", input(operand)),
+            Self::LoadComplement(operand) => format!("set RR to {}", not_input(operand)),
+            Self::And(operand) => format!("AND RR with {}", input(operand)),
+            Self::AndComplement(operand) => format!("AND RR with {}", not_input(operand)),
+            Self::Or(operand) => format!("OR RR with {}", input(operand)),
+            Self::OrComplement(operand) => format!("OR RR with {}", not_input(operand)),
+            Self::ExclusiveNor(operand) => {
+                format!("set RR to whether it equals {}", input(operand))
+            }
+            Self::Store(operand) => format!("write RR to {}", place(operand, "output")),
+            Self::StoreComplement(operand) => {
+                format!("write not RR to {}", place(operand, "output"))
+            }
+            Self::InputEnable(operand) => format!("enable inputs if {} is set", input(operand)),
+            Self::OutputEnable(operand) => format!("enable outputs if {} is set", input(operand)),
+            Self::Jump(target) => format!("jump to {target:02X}"),
+            Self::Return => "return to the start".to_string(),
+            Self::SkipIfZero => "skip the next instruction if RR is 0".to_string(),
+            Self::Invalid(_) => "not an instruction".to_string(),
+        }
+    }
+}
+
+/// Writes the instruction as assembly, like `LD 7`. Invalid instructions are written as `.raw`
+/// so they still assemble to the same nibble. Operands that don't fit in a nibble are written in
+/// full, so that assembling them gives an error rather than a different operand.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.mnemonic(), self.operand()) {
+            (Some(mnemonic), Some(operand)) if operand > 0xF => {
+                write!(f, "{mnemonic} {operand:#X}")
+            }
+            (Some(mnemonic), Some(operand)) => write!(f, "{mnemonic} {operand:X}"),
+            (Some(mnemonic), None) => f.write_str(mnemonic),
+            (None, _) => write!(f, ".raw \"{:X}\"", self.opcode()),
+        }
+    }
+}
+
+/// Every instruction in a program along with its address, from [`crate::Program::iter`].
+#[derive(Clone, Debug)]
+pub struct Iter {
+    instructions: vec::IntoIter<Instruction>,
+    address: usize,
+}
+
+impl Iter {
+    pub(crate) fn new(instructions: Vec<Instruction>) -> Self {
+        Self {
+            instructions: instructions.into_iter(),
+            address: 0,
+        }
+    }
+}
+
+impl Iterator for Iter {
+    type Item = (usize, Instruction);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let instruction = self.instructions.next()?;
+        let address = self.address;
+        self.address += instruction.size();
+        Some((address, instruction))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.instructions.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Iter {}
+
+/// Writes instructions out as assembly, one per line.
+pub(crate) fn to_assembly(instructions: &[Instruction]) -> String {
+    instructions
+        .iter()
+        .map(|instruction| format!("{instruction}\n"))
+        .collect()
+}
+
+/// Splits a program's nibbles back up into instructions, the same way the Control Unit reads
+/// them: each opcode that takes an operand takes the nibble after it.
+pub(crate) fn decode(nibbles: &[u8]) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut nibbles = nibbles.iter().copied();
+
+    while let Some(opcode) = nibbles.next() {
+        let instruction = match Instruction::new(opcode, None) {
+            Some(instruction) => instruction,
+            // Every opcode except 0xF is either valid by itself or with an operand
+            None if opcode < 0xF => nibbles
+                .next()
+                .and_then(|operand| Instruction::new(opcode, Some(operand)))
+                .unwrap_or(Instruction::Invalid(opcode)),
+            None => Instruction::Invalid(opcode),
+        };
+
+        instructions.push(instruction);
+    }
+
+    instructions
+}
+
+/// The nibbles of a program's hex output.
+pub(crate) fn nibbles(opcodes: &str) -> Vec<u8> {
+    opcodes
+        .chars()
+        .filter_map(|digit| digit.to_digit(16))
+        .map(|nibble| nibble as u8)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Instruction;
+    use crate::Program;
+
+    #[test]
+    fn handles_instructions() {
+        let program = Program::from_assembly("OEN 0\nloop: LD 7\nSKZ\nSTOC F\nJMP loop\nRTN");
+        let instructions = program.instructions();
+        assert_eq!(
+            instructions,
+            Ok(vec![
+                Instruction::OutputEnable(0x0),
+                Instruction::Load(0x7),
+                Instruction::SkipIfZero,
+                Instruction::StoreComplement(0xF),
+                Instruction::Jump(0x2),
+                Instruction::Return,
+            ])
+        );
+    }
+
+    #[test]
+    fn handles_invalid_instructions() {
+        let program = Program::from_assembly("NOP\n.raw \"F1\"");
+        let instructions = program.instructions();
+        assert_eq!(
+            instructions,
+            Ok(vec![
+                Instruction::NoOp,
+                Instruction::Invalid(0xF),
+                Instruction::Invalid(0x1),
+            ])
+        );
+
+        assert_eq!(Instruction::new(0x1, Some(0x10)), None);
+        assert_eq!(Instruction::new(0xD, Some(0x1)), None);
+        assert!(!Instruction::Store(0x10).is_valid());
+    }
+
+    #[test]
+    fn handles_instruction_addresses() {
+        let program = Program::from_assembly("OEN 0\nSKZ\nJMP 0\nRTN");
+        let addresses: Vec<_> = program.iter().unwrap().collect();
+        assert_eq!(
+            addresses,
+            [
+                (0x0, Instruction::OutputEnable(0x0)),
+                (0x2, Instruction::SkipIfZero),
+                (0x3, Instruction::Jump(0x0)),
+                (0x5, Instruction::Return),
+            ]
+        );
+    }
+
+    #[test]
+    fn handles_instruction_effects() {
+        assert_eq!(Instruction::Store(3).effect(), "write RR to output pin 3");
+        assert_eq!(
+            Instruction::StoreComplement(0xA).effect(),
+            "write not RR to scratch RAM A"
+        );
+        assert_eq!(
+            Instruction::LoadComplement(7).effect(),
+            "set RR to not input pin 7"
+        );
+        assert_eq!(
+            Instruction::OutputEnable(0).effect(),
+            "enable outputs if not RR is set"
+        );
+        assert_eq!(Instruction::LoadComplement(0).effect(), "set RR to RR");
+        assert_eq!(Instruction::Jump(0x1F).effect(), "jump to 1F");
+        assert_eq!(Instruction::Invalid(0xF).effect(), "not an instruction");
+    }
+
+    #[test]
+    fn handles_instruction_display() {
+        assert_eq!(Instruction::StoreComplement(0xF).to_string(), "STOC F");
+        assert_eq!(Instruction::SkipIfZero.to_string(), "SKZ");
+        assert_eq!(Instruction::Invalid(0xF).to_string(), ".raw \"F\"");
+    }
+}
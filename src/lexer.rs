@@ -0,0 +1,353 @@
+use logos::{Filter, Logos, Span};
+
+use crate::prelude::*;
+use crate::AssemblerOptions;
+
+#[derive(Logos, Clone, Debug, PartialEq)]
+pub(crate) enum Token {
+    #[token("NOP")]
+    NoOp,
+
+    #[token("LD")]
+    Load,
+
+    #[token("LDC")]
+    LoadComplement,
+
+    #[token("AND")]
+    And,
+
+    #[token("ANDC")]
+    AndComplement,
+
+    #[token("OR")]
+    Or,
+
+    #[token("ORC")]
+    OrComplement,
+
+    #[token("XNOR")]
+    ExclusiveNor,
+
+    #[token("STO")]
+    Store,
+
+    #[token("STOC")]
+    StoreComplement,
+
+    #[token("IEN")]
+    InputEnable,
+
+    #[token("OEN")]
+    OutputEnable,
+
+    #[token("JMP")]
+    Jump,
+
+    #[token("RTN")]
+    Return,
+
+    #[token("SKZ")]
+    SkipIfZero,
+
+    #[regex(r"[a-fA-F0-9]", |lex| u8::from_str_radix(lex.slice(), 16), priority = 2)]
+    Operand(u8),
+
+    #[regex(r"0x[a-fA-F0-9]+", |lex| usize::from_str_radix(&lex.slice()[2..], 16))]
+    #[regex(r"[0-9][0-9]+", |lex| lex.slice().parse())]
+    Number(usize),
+
+    #[regex(r"\.[A-Za-z_][A-Za-z0-9_]*", |lex| lex.slice()[1..].to_string())]
+    Directive(String),
+
+    #[regex(r"[A-Za-z_][A-Za-z0-9_]*", |lex| lex.slice().to_string())]
+    Identifier(String),
+
+    #[regex(r#""[^"\n]*""#, |lex| unquote(lex.slice()))]
+    String(String),
+
+    /// `<std/latch>` and the like, naming a standard library file to include
+    #[regex(r"<[A-Za-z0-9_/]+>", |lex| unquote(lex.slice()))]
+    LibraryPath(String),
+
+    #[token(":")]
+    Colon,
+
+    #[token(",")]
+    Comma,
+
+    #[token("+")]
+    Plus,
+
+    #[token("-")]
+    Minus,
+
+    #[token("*")]
+    Star,
+
+    #[token("/")]
+    Slash,
+
+    #[token("%")]
+    Percent,
+
+    #[token("&")]
+    Ampersand,
+
+    #[token("|")]
+    Pipe,
+
+    #[token("^")]
+    Caret,
+
+    #[token("~")]
+    Tilde,
+
+    #[token("<<")]
+    ShiftLeft,
+
+    #[token(">>")]
+    ShiftRight,
+
+    #[token("(")]
+    LeftParen,
+
+    #[token(")")]
+    RightParen,
+
+    #[regex(r"\r?\n")]
+    Newline,
+
+    /// `;`, `//` and `#` all start a comment. A `#` at the start of a line is normally a
+    /// preprocessor directive, but those never make it as far as the lexer. `;;;` documentation
+    /// comments hold the text after the `;;;`, and are the only comments `tokenize` keeps.
+    #[regex(r"(;|//|#).*", comment)]
+    Comment(Option<String>),
+
+    /// `/* ... */`, which can span several lines. Only ever left in the output of `tokenize` if
+    /// it's missing its `*/`.
+    #[token("/*", block_comment)]
+    BlockComment(BlockComment),
+
+    /// Text that didn't match any other token. Never produced by the lexer directly; `tokenize`
+    /// swaps [`Token::Error`]s for these so the offending text can be reported.
+    Unknown(String),
+
+    /// Words that ran straight into each other, like `1STO`. Never produced by the lexer directly;
+    /// `tokenize` swaps these in for the tokens they were made of, since they're nearly always a
+    /// missing space.
+    MissingSeparator(String),
+
+    #[error]
+    #[regex(r"[ \t\f]+", logos::skip)]
+    Error,
+}
+
+/// How a block comment ended, which decides what it's replaced with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BlockComment {
+    /// Skipped entirely
+    SingleLine,
+    /// Stands in for a newline, so the lines either side of it don't run together
+    MultiLine,
+    /// Ran to the end of the source without a `*/`
+    Unterminated,
+}
+
+fn block_comment(lexer: &mut logos::Lexer<Token>) -> BlockComment {
+    let remainder = lexer.remainder();
+    match remainder.find("*/") {
+        Some(end) => {
+            lexer.bump(end + 2);
+            if remainder[..end].contains('\n') {
+                BlockComment::MultiLine
+            } else {
+                BlockComment::SingleLine
+            }
+        }
+        None => {
+            lexer.bump(remainder.len());
+            BlockComment::Unterminated
+        }
+    }
+}
+
+/// Returning an `Option` on its own would make `None` an error, rather than a comment that
+/// isn't documentation.
+fn comment(lexer: &mut logos::Lexer<Token>) -> Filter<Option<String>> {
+    let text = lexer.slice().strip_prefix(";;;");
+    Filter::Emit(text.map(|text| text.trim().to_string()))
+}
+
+fn unquote(slice: &str) -> String {
+    slice[1..slice.len() - 1].to_string()
+}
+
+/// Turns identifiers like `oen` and `Oen` into the mnemonic they'd be if they were uppercase.
+fn fold_case(name: String) -> Token {
+    let uppercase = name.to_ascii_uppercase();
+    let mut lexer = Token::lexer(&uppercase);
+
+    match (lexer.next(), lexer.next()) {
+        (Some(token), None) if token.is_mnemonic() => token,
+        _ => Token::Identifier(name),
+    }
+}
+
+/// A token along with the byte range of the source it was lexed from.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Spanned {
+    pub token: Token,
+    pub span: Span,
+}
+
+impl Token {
+    fn is_mnemonic(&self) -> bool {
+        self.mnemonic().is_some()
+    }
+
+    /// Whether the token is made of letters and digits, and so needs something between it and
+    /// the next one.
+    fn is_word(&self) -> bool {
+        self.is_mnemonic()
+            || matches!(
+                self,
+                Token::Operand(_)
+                    | Token::Number(_)
+                    | Token::Directive(_)
+                    | Token::Identifier(_)
+                    | Token::MissingSeparator(_)
+            )
+    }
+
+    /// How the token can be written, for turning tokens back into text. Comments and anything
+    /// the lexer skips come out empty.
+    pub(crate) fn spelling(&self) -> String {
+        if let Some(mnemonic) = self.mnemonic() {
+            return mnemonic.to_string();
+        }
+
+        let spelling = match self {
+            Token::Operand(value) => return format!("{value:X}"),
+            Token::Number(value) => return value.to_string(),
+            Token::Directive(name) => return format!(".{name}"),
+            Token::Identifier(text) | Token::Unknown(text) | Token::MissingSeparator(text) => {
+                return text.clone()
+            }
+            Token::String(text) => return format!("\"{text}\""),
+            Token::LibraryPath(path) => return format!("<{path}>"),
+            Token::Colon => ":",
+            Token::Comma => ",",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::Percent => "%",
+            Token::Ampersand => "&",
+            Token::Pipe => "|",
+            Token::Caret => "^",
+            Token::Tilde => "~",
+            Token::ShiftLeft => "<<",
+            Token::ShiftRight => ">>",
+            Token::LeftParen => "(",
+            Token::RightParen => ")",
+            Token::Newline => "\n",
+            _ => "",
+        };
+
+        spelling.to_string()
+    }
+
+    /// The canonical spelling of an instruction token.
+    pub(crate) fn mnemonic(&self) -> Option<&'static str> {
+        let mnemonic = match self {
+            Token::NoOp => "NOP",
+            Token::Load => "LD",
+            Token::LoadComplement => "LDC",
+            Token::And => "AND",
+            Token::AndComplement => "ANDC",
+            Token::Or => "OR",
+            Token::OrComplement => "ORC",
+            Token::ExclusiveNor => "XNOR",
+            Token::Store => "STO",
+            Token::StoreComplement => "STOC",
+            Token::InputEnable => "IEN",
+            Token::OutputEnable => "OEN",
+            Token::Jump => "JMP",
+            Token::Return => "RTN",
+            Token::SkipIfZero => "SKZ",
+            _ => return None,
+        };
+
+        Some(mnemonic)
+    }
+}
+
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "lex", level = "debug", skip_all, fields(bytes = source.len()))
+)]
+pub(crate) fn tokenize(source: &str, options: &AssemblerOptions) -> Vec<Spanned> {
+    let mut tokens: Vec<Spanned> = Vec::new();
+
+    let mut lexer = Token::lexer(source);
+    while let Some(token) = lexer.next() {
+        let token = match token {
+            Token::Error if options.strict => Token::Unknown(lexer.slice().to_string()),
+            Token::Error => continue,
+            Token::BlockComment(BlockComment::SingleLine) => continue,
+            Token::BlockComment(BlockComment::MultiLine) => Token::Newline,
+            Token::Comment(None) => continue,
+            // Only comments on a line of their own document anything
+            Token::Comment(Some(_))
+                if tokens
+                    .last()
+                    .is_some_and(|previous| previous.token != Token::Newline) =>
+            {
+                continue
+            }
+            Token::Identifier(name) if !options.case_sensitive => fold_case(name),
+            token => token,
+        };
+
+        let span = lexer.span();
+        if let Some(previous) = tokens.last_mut() {
+            if previous.span.end == span.start && previous.token.is_word() && token.is_word() {
+                previous.span.end = span.end;
+                previous.token = Token::MissingSeparator(source[previous.span.clone()].to_string());
+                continue;
+            }
+        }
+
+        tokens.push(Spanned { token, span });
+    }
+
+    #[cfg(feature = "tracing")]
+    tracing::debug!(tokens = tokens.len(), "lexed");
+
+    tokens
+}
+
+/// Whether an identifier is really an instruction and its operand with the space between them
+/// missing, like `STOF` or `LD7`. The lexer can't tell these apart from names by itself.
+pub(crate) fn is_unseparated_instruction(name: &str, options: &AssemblerOptions) -> bool {
+    let lexes_as = |text: &str, expected: fn(&Token) -> bool| {
+        let mut lexer = Token::lexer(text);
+        matches!((lexer.next(), lexer.next()), (Some(token), None) if expected(&token))
+    };
+
+    // Identifiers are always ASCII, so every index is a character boundary
+    (1..name.len()).any(|split| {
+        let (mnemonic, operand) = name.split_at(split);
+        let mnemonic = if options.case_sensitive {
+            mnemonic.to_string()
+        } else {
+            mnemonic.to_ascii_uppercase()
+        };
+
+        lexes_as(&mnemonic, Token::is_mnemonic)
+            && lexes_as(operand, |token| {
+                matches!(token, Token::Operand(_) | Token::Number(_))
+            })
+    })
+}
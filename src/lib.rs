@@ -1,165 +1,369 @@
-use logos::Logos;
-use thiserror::Error;
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+use core::fmt;
+use core::ops::Range;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::path::Path;
+#[cfg(feature = "std")]
+use std::{fs, io};
+
+use crate::prelude::*;
+
+pub use assembler::{Directive, DirectiveContext};
+pub use ast::{Argument, Ast, Node, Rewriter, Statement, Visitor};
+#[cfg(feature = "std")]
+pub use batch::BatchReport;
+pub use builder::ProgramBuilder;
+pub use container::{ContainerError, CONTAINER_VERSION, MAGIC};
+#[cfg(feature = "std")]
+pub use decoder::{Decoder, ReadError};
+pub use diff::Change;
+pub use disassemble::{DisassembleError, DisassemblyOptions, InputFormat};
+pub use docs::{Doc, DocTarget};
+pub use edit::EditError;
+pub use emit::{
+    EmitOptions, Grouping, HexOptions, OutputFormat, DEFAULT_PASTE_LENGTH, DEFAULT_RECORD_SIZE,
+};
+pub use emulator::{
+    Assertion, AssertionResult, Condition, ConditionError, Emulator, Flags, Port, Reaction,
+    Registers, Stop, TestVector, TestVectors, Trace, TraceEntry, Variant, VectorError,
+    VectorResult, Watch, DEFAULT_BUDGET, DEFAULT_CYCLES_PER_TICK,
+};
+pub use error::{
+    AssemblerError, Diagnostic, DiagnosticKind, FilePath, Location, Severity, Warning,
+};
+pub use flow::{Edge, EdgeKind};
+pub use instruction::{Instruction, Iter};
+pub use metadata::Metadata;
+pub use options::AssemblerOptions;
+pub use output::WriteError;
+pub use pages::{Page, DEFAULT_PAGE_LENGTH};
+pub use stats::Stats;
+pub use symbols::{Symbol, SymbolKind};
+pub use tokens::{tokenize, Token};
+pub use xref::{Access, Reference};
+
+mod assembler;
+mod ast;
+#[cfg(feature = "std")]
+mod batch;
+mod builder;
+mod container;
+#[cfg(feature = "std")]
+mod decoder;
+mod diff;
+mod disassemble;
+mod docs;
+mod edit;
+mod emit;
+mod emulator;
+mod encoding;
+mod error;
+mod flow;
+mod instruction;
+mod lexer;
+mod metadata;
+mod normalize;
+mod options;
+mod output;
+mod pages;
+mod preprocessor;
+#[cfg(any(feature = "arbitrary", feature = "proptest"))]
+mod random;
+mod sarif;
+mod stats;
+mod symbols;
+mod tokens;
+mod xref;
+
+/// The parts of `std` that are really in `alloc`, for modules to glob-import so they read the same
+/// with or without the `std` feature.
+pub(crate) mod prelude {
+    pub(crate) use alloc::boxed::Box;
+    pub(crate) use alloc::string::{String, ToString};
+    pub(crate) use alloc::vec::Vec;
+    pub(crate) use alloc::{format, vec};
+}
 
 // See Goonstation source code for more details: https://github.com/goonstation/goonstation/blob/master/code/modules/mechanics/MechanicMC14500.dm
 
 const MAX_PROGRAM_LENGTH: usize = 128;
 
-#[derive(Error, Debug, PartialEq, Eq)]
-pub enum AssemblerError {
-    #[error("Expected operand")]
-    ExpectedOperand,
-    #[error("Exceeded max program length")]
-    ExceededMaxLength,
+// Programs get handed between threads, so anything they hold has to be safe to share
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Program>();
+    assert_send_sync::<AssemblerOptions>();
+};
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Program {
+    source: String,
+    path: Option<FilePath>,
+    options: AssemblerOptions,
+    /// What [`Program::finished`] adds after the assembled program
+    #[cfg_attr(feature = "serde", serde(default))]
+    finish: EmitOptions,
 }
 
-#[derive(Logos, Debug, PartialEq)]
-enum Token {
-    #[token("NOP")]
-    NoOp,
+impl Program {
+    pub fn from_assembly(assembly: &str) -> Self {
+        Self::from_assembly_with(assembly, AssemblerOptions::default())
+    }
 
-    #[token("LD")]
-    Load,
+    pub fn from_assembly_with(assembly: &str, options: AssemblerOptions) -> Self {
+        Self {
+            source: assembly.to_string(),
+            path: None,
+            options,
+            finish: EmitOptions::default(),
+        }
+    }
 
-    #[token("LDC")]
-    LoadComplement,
+    /// The inverse of [`Program::to_bytes`], disassembled as by [`Program::from_opcodes`]. Each
+    /// byte holds two nibbles, high nibble first. The nibbles are kept exactly as they are, so a
+    /// padding `NOP` added by `to_bytes` stays.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let nibbles = disassemble::unpack(bytes);
+        let source = disassemble::disassemble(&nibbles);
+        disassemble::program(&source, nibbles.len(), &DisassemblyOptions::default())
+    }
 
-    #[token("AND")]
-    And,
+    /// Reads a program from a file. Any `.include` directives in it are resolved relative to the
+    /// file's directory.
+    #[cfg(feature = "std")]
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_file_with(path, AssemblerOptions::default())
+    }
 
-    #[token("ANDC")]
-    AndComplement,
+    #[cfg(feature = "std")]
+    pub fn from_file_with(path: impl AsRef<Path>, options: AssemblerOptions) -> io::Result<Self> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)?;
 
-    #[token("OR")]
-    Or,
+        Ok(Self {
+            source,
+            path: Some(FilePath::from(path)),
+            options,
+            finish: EmitOptions::default(),
+        })
+    }
 
-    #[token("ORC")]
-    OrComplement,
+    /// Assembles the program into a string of hex digits, one per nibble, ready to be pasted into
+    /// a Control Unit.
+    pub fn to_opcodes(&self) -> Result<String, AssemblerError> {
+        self.assemble(false)
+            .map(|assembled| assembled.opcodes)
+            .map_err(|mut errors| errors.remove(0))
+    }
 
-    #[token("XNOR")]
-    ExclusiveNor,
+    /// Like [`Program::to_opcodes`], for when the program isn't needed afterwards.
+    pub fn into_opcodes(self) -> Result<String, AssemblerError> {
+        self.to_opcodes()
+    }
 
-    #[token("STO")]
-    Store,
+    /// Every instruction along with its address, which counts nibbles rather than instructions
+    /// (so it's what `JMP` takes).
+    pub fn iter(&self) -> Result<Iter, AssemblerError> {
+        self.instructions().map(Iter::new)
+    }
 
-    #[token("STOC")]
-    StoreComplement,
+    /// The instruction that `address` is part of, along with the addresses it takes up. An address
+    /// that falls on an operand gives the instruction the operand belongs to. `None` if the
+    /// address is past the end of the program.
+    pub fn instruction_at(
+        &self,
+        address: usize,
+    ) -> Result<Option<(Instruction, Range<usize>)>, AssemblerError> {
+        let found = self.iter()?.find_map(|(start, instruction)| {
+            let span = start..start + instruction.size();
+            span.contains(&address).then_some((instruction, span))
+        });
 
-    #[token("IEN")]
-    InputEnable,
+        Ok(found)
+    }
 
-    #[token("OEN")]
-    OutputEnable,
+    /// A hash of the program's opcodes, so two programs that assemble to the same thing have the
+    /// same fingerprint however they're written. It's 64-bit FNV-1a, which won't change between
+    /// versions or platforms, so fingerprints can be stored and compared later.
+    pub fn fingerprint(&self) -> Result<u64, AssemblerError> {
+        let hash = self
+            .to_opcodes()?
+            .bytes()
+            .fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01B3)
+            });
 
-    #[token("JMP")]
-    Jump,
+        Ok(hash)
+    }
 
-    #[token("RTN")]
-    Return,
+    /// How many nibbles the program assembles to, after every macro and `.rept` is expanded. This
+    /// is what [`AssemblerOptions::max_length`] limits.
+    pub fn len(&self) -> Result<usize, AssemblerError> {
+        self.to_opcodes().map(|opcodes| opcodes.len())
+    }
 
-    #[token("SKZ")]
-    SkipIfZero,
+    pub fn is_empty(&self) -> Result<bool, AssemblerError> {
+        self.len().map(|len| len == 0)
+    }
 
-    #[regex(r"[a-fA-F0-9]", |lex| u8::from_str_radix(lex.slice(), 16))]
-    Operand(u8),
+    /// How many more nibbles fit before the program reaches its length limit.
+    pub fn remaining_capacity(&self) -> Result<usize, AssemblerError> {
+        self.len()
+            .map(|len| self.options.max_length.saturating_sub(len))
+    }
 
-    #[regex(r";.*", logos::skip)]
-    Comment,
+    /// The program's opcodes packed two to a byte, high nibble first, for storing compactly. A
+    /// program with an odd number of nibbles gets a `NOP` on the end to fill the last byte.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, AssemblerError> {
+        let nibbles = instruction::nibbles(&self.to_opcodes()?);
+        let bytes = nibbles
+            .chunks(2)
+            .map(|pair| pair[0] << 4 | pair.get(1).copied().unwrap_or(0x0))
+            .collect();
 
-    #[error]
-    #[regex(r"[ \t\n\f]+", logos::skip)]
-    Error,
-}
+        Ok(bytes)
+    }
 
-pub struct Program {
-    tokens: Vec<Token>,
-}
+    /// The program's instructions, read back from its opcodes the same way the Control Unit reads
+    /// them.
+    pub fn instructions(&self) -> Result<Vec<Instruction>, AssemblerError> {
+        let opcodes = self.to_opcodes()?;
+        Ok(instruction::decode(&instruction::nibbles(&opcodes)))
+    }
 
-impl Program {
-    pub fn from_assembly(assembly: &str) -> Self {
-        let lexer = Token::lexer(assembly);
-        let tokens = lexer.collect();
+    /// Like [`Program::to_opcodes`], but also returns anything that looks wrong without being
+    /// an error, such as labels that are never used.
+    pub fn to_opcodes_with_warnings(&self) -> Result<(String, Vec<Warning>), AssemblerError> {
+        self.assemble(false)
+            .map(|assembled| (assembled.opcodes, assembled.warnings))
+            .map_err(|mut errors| errors.remove(0))
+    }
 
-        Self { tokens }
+    #[deprecated = "renamed to `to_opcodes_with_warnings`, since it doesn't consume the program"]
+    pub fn into_opcodes_with_warnings(&self) -> Result<(String, Vec<Warning>), AssemblerError> {
+        self.to_opcodes_with_warnings()
     }
 
-    pub fn into_opcodes(&self) -> Result<String, AssemblerError> {
+    /// What the program says about itself through `.name`, `.author`, `.version` and
+    /// `.description`, for sharing programs that describe themselves.
+    pub fn metadata(&self) -> Result<Metadata, AssemblerError> {
+        self.assemble(false)
+            .map(|assembled| assembled.metadata)
+            .map_err(|mut errors| errors.remove(0))
+    }
 
-        if self.tokens.len() > MAX_PROGRAM_LENGTH {
-            return Err(AssemblerError::ExceededMaxLength);
-        }
+    /// Every `.assert` in the program, in the order they appear. See
+    /// [`Program::check_assertions`] for running them.
+    pub fn assertions(&self) -> Result<Vec<Assertion>, AssemblerError> {
+        self.assemble(false)
+            .map(|assembled| assembled.assertions)
+            .map_err(|mut errors| errors.remove(0))
+    }
 
-        let mut output = String::new();
+    /// The program's `;;;` documentation comments, in the order they appear. Each one documents
+    /// the label, macro definition or instruction right after it; one that's followed by anything
+    /// else, like another directive, is dropped.
+    pub fn docs(&self) -> Result<Vec<Doc>, AssemblerError> {
+        self.assemble(false)
+            .map(|assembled| assembled.docs)
+            .map_err(|mut errors| errors.remove(0))
+    }
 
-        let mut expecting_operand = false;
-        for token in &self.tokens {
-            dbg!(&token);
+    /// Where each nibble of the output came from, indexed by address, for pointing at the line
+    /// that's running. A nibble from a macro points at the line in the macro's body rather than
+    /// where it was invoked.
+    pub fn source_map(&self) -> Result<Vec<Location>, AssemblerError> {
+        self.assemble(false)
+            .map(|assembled| assembled.source_map())
+            .map_err(|mut errors| errors.remove(0))
+    }
 
-            // If we're expecting an operand, make sure this token is one
-            if expecting_operand {
-                match token {
-                    Token::Operand(_) => {}
-                    _ => {
-                        return Err(AssemblerError::ExpectedOperand);
-                    }
-                }
-            }
+    /// Every label and constant the program defines, sorted by name, for things like
+    /// go-to-definition and map files.
+    pub fn symbols(&self) -> Result<Vec<Symbol>, AssemblerError> {
+        self.assemble(false)
+            .map(|assembled| assembled.symbols())
+            .map_err(|mut errors| errors.remove(0))
+    }
 
-            // Push the token representation to the output
-            if let Some(token_repr) = get_token_representation(token) {
-                output.push(token_repr);
-            }
+    /// Like [`Program::to_opcodes`], but carries on past errors instead of stopping at the first
+    /// one, so everything wrong with a program can be fixed in one go. The rest of a line with an
+    /// error on it is skipped, which can occasionally lead to knock-on errors further down.
+    pub fn to_opcodes_with_recovery(&self) -> Result<String, Vec<Diagnostic>> {
+        self.assemble(true)
+            .map(|assembled| assembled.opcodes)
+            .map_err(|errors| errors.into_iter().map(Diagnostic::from).collect())
+    }
 
-            // Flag whether we're expecting an operand as the next token
-            expecting_operand = does_token_require_operand(token);
-        }
+    #[deprecated = "renamed to `to_opcodes_with_recovery`, since it doesn't consume the program"]
+    pub fn into_opcodes_with_recovery(&self) -> Result<String, Vec<Diagnostic>> {
+        self.to_opcodes_with_recovery()
+    }
 
-        if expecting_operand {
-            return Err(AssemblerError::ExpectedOperand);
-        }
+    /// Like [`Program::to_opcodes_with_recovery`], but gives back warnings as diagnostics too,
+    /// alongside the opcodes if the program assembles. Warnings are only looked for once there
+    /// are no errors, so a program that doesn't assemble only gets errors.
+    pub fn to_opcodes_with_diagnostics(
+        &self,
+    ) -> Result<(String, Vec<Diagnostic>), Vec<Diagnostic>> {
+        self.assemble(true)
+            .map(|assembled| {
+                let warnings = assembled.warnings.into_iter().map(Diagnostic::from);
+                (assembled.opcodes, warnings.collect())
+            })
+            .map_err(|errors| errors.into_iter().map(Diagnostic::from).collect())
+    }
 
-        Ok(output)
+    /// Assembles the program, then adds anything [`Program::finished`] asked for.
+    pub(crate) fn assemble(
+        &self,
+        recover: bool,
+    ) -> Result<assembler::Assembled, Vec<AssemblerError>> {
+        let mut assembled =
+            assembler::assemble(&self.source, self.path.as_ref(), &self.options, recover)?;
+        assembled
+            .finish(&self.finish, self.options.max_length)
+            .map_err(|error| vec![error])?;
+        Ok(assembled)
+    }
+
+    /// Runs every check [`Program::to_opcodes_with_diagnostics`] does and returns what it found,
+    /// for editors that only want to know what's wrong. The program assembles as long as none of
+    /// them are [`Severity::Error`]s.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        match self.to_opcodes_with_diagnostics() {
+            Ok((_, warnings)) => warnings,
+            Err(errors) => errors,
+        }
     }
 }
 
-fn get_token_representation(token: &Token) -> Option<char> {
-    match token {
-        Token::NoOp => Some('0'),
-        Token::Load => Some('1'),
-        Token::LoadComplement => Some('2'),
-        Token::And => Some('3'),
-        Token::AndComplement => Some('4'),
-        Token::Or => Some('5'),
-        Token::OrComplement => Some('6'),
-        Token::ExclusiveNor => Some('7'),
-        Token::Store => Some('8'),
-        Token::StoreComplement => Some('9'),
-        Token::InputEnable => Some('A'),
-        Token::OutputEnable => Some('B'),
-        Token::Jump => Some('C'),
-        Token::Return => Some('D'),
-        Token::SkipIfZero => Some('E'),
-        Token::Operand(operand) => Some(format!("{:X}", operand).chars().next().unwrap()),
-        Token::Comment | Token::Error => None,
+/// Writes the program out as canonical assembly: one instruction per line, with every label and
+/// expression worked out and every macro expanded. This assembles back to the same opcodes. A
+/// program that doesn't assemble is written out as its source instead.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.instructions() {
+            Ok(instructions) => f.write_str(&instruction::to_assembly(&instructions)),
+            Err(_) => f.write_str(&self.source),
+        }
     }
 }
 
-fn does_token_require_operand(token: &Token) -> bool {
-    matches!(
-        token,
-        Token::Load
-            | Token::LoadComplement
-            | Token::And
-            | Token::AndComplement
-            | Token::Or
-            | Token::OrComplement
-            | Token::ExclusiveNor
-            | Token::Store
-            | Token::StoreComplement
-            | Token::InputEnable
-            | Token::OutputEnable
-            | Token::Jump
-    )
+/// Reads a program from assembly, failing if it doesn't assemble.
+impl FromStr for Program {
+    type Err = AssemblerError;
+
+    fn from_str(assembly: &str) -> Result<Self, Self::Err> {
+        let program = Self::from_assembly(assembly);
+        program.to_opcodes()?;
+        Ok(program)
+    }
 }
 
 #[cfg(test)]
@@ -184,14 +388,14 @@ mod tests {
     fn handles_missing_final_operand() {
         let program = Program::from_assembly("OEN 0\nSTO 0\nLD 7\nSTO");
         let bin = program.into_opcodes();
-        assert_eq!(bin, Err(AssemblerError::ExpectedOperand));
+        assert!(matches!(bin, Err(AssemblerError::ExpectedOperand { .. })));
     }
 
     #[test]
     fn handles_missing_middle_operand() {
         let program = Program::from_assembly("OEN 0\nSTO \nLD 7\nSTO F");
         let bin = program.into_opcodes();
-        assert_eq!(bin, Err(AssemblerError::ExpectedOperand));
+        assert!(matches!(bin, Err(AssemblerError::ExpectedOperand { .. })));
     }
 
     #[test]
@@ -200,4 +404,643 @@ mod tests {
         let bin = program.into_opcodes();
         assert_eq!(bin, Ok(String::from("B080")));
     }
+
+    #[test]
+    fn handles_other_comment_styles() {
+        let program = Program::from_assembly(
+            "// enable the output\nOEN 0 // because RR is zero\n# store it\nSTO 0 # in output 0\nLD 4/2 ; division still works",
+        );
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B08012")));
+    }
+
+    #[test]
+    fn handles_block_comments() {
+        let program = Program::from_assembly(
+            "OEN 0 /* enable */ STO 0\n/*\nLD 7\nSTO F ; */\nLD/**/1 /* two\nlines */STO 2",
+        );
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B0801182")));
+
+        let program = Program::from_assembly("OEN 0\n/* STO 0\nLD 7");
+        let bin = program.into_opcodes();
+        assert_eq!(
+            bin,
+            Err(AssemblerError::UnterminatedComment {
+                location: Location {
+                    file: None,
+                    line: 2,
+                    column: 1,
+                    offset: 6,
+                    snippet: String::from("/* STO 0"),
+                    length: 13,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn handles_docs() {
+        let program = Program::from_assembly(
+            ";;; Turns the output on\n;;; once RR is zero\nOEN 0\n\n;;; Main loop\nloop: LD 1 ;;; not a doc\n;;; Dropped\n.equ DOOR 3\n;;; Stores it\nSTO DOOR\nJMP loop",
+        );
+        let docs = program.docs().unwrap();
+        let summary: Vec<_> = docs
+            .iter()
+            .map(|doc| (doc.target.clone(), doc.text.as_str(), doc.location.line))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    DocTarget::Instruction { address: 0 },
+                    "Turns the output on\nonce RR is zero",
+                    1
+                ),
+                (
+                    DocTarget::Label {
+                        name: String::from("loop"),
+                        address: 2
+                    },
+                    "Main loop",
+                    5
+                ),
+                (DocTarget::Instruction { address: 4 }, "Stores it", 9),
+            ]
+        );
+    }
+
+    #[test]
+    fn handles_macro_docs() {
+        let program = Program::from_assembly(
+            ";;; Copies one pin to another\n.macro COPY FROM, TO\n;;; Read it\nLD FROM\nSTO TO\n.endm\nCOPY 1, 2\nCOPY 3, 4",
+        );
+        let docs = program.docs().unwrap();
+        let targets: Vec<_> = docs.iter().map(|doc| &doc.target).collect();
+        assert_eq!(
+            targets,
+            [
+                &DocTarget::Macro {
+                    name: String::from("COPY")
+                },
+                &DocTarget::Instruction { address: 0 },
+                &DocTarget::Instruction { address: 4 },
+            ]
+        );
+    }
+
+    #[test]
+    fn handles_source_map() {
+        let program = Program::from_assembly(
+            ".macro copy FROM, TO\nLD FROM\nSTO TO\n.endm\nOEN 0\n  copy 1, 2\n.raw \"7\"",
+        );
+        let map = program.source_map().unwrap();
+        let lines: Vec<_> = map
+            .iter()
+            .map(|location| (location.line, location.column))
+            .collect();
+        assert_eq!(
+            lines,
+            [(5, 1), (5, 5), (2, 1), (2, 4), (3, 1), (3, 5), (7, 7)]
+        );
+        assert_eq!(map[0].snippet, "OEN 0");
+    }
+
+    #[test]
+    fn handles_symbols() {
+        let program = Program::from_assembly(
+            ".equ DOOR 3\n.macro wait\n.loop: SKZ\nJMP .loop\n.endm\nOEN 0\nloop: wait\nwait",
+        );
+        let symbols = program.symbols().unwrap();
+        let summary: Vec<_> = symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.kind, symbol.location.line))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (".loop", SymbolKind::Label { address: 2 }, 3),
+                (".loop", SymbolKind::Label { address: 5 }, 3),
+                ("DOOR", SymbolKind::Constant { value: 3 }, 1),
+                ("loop", SymbolKind::Label { address: 2 }, 7),
+            ]
+        );
+    }
+
+    #[test]
+    fn handles_bytes() {
+        let program = Program::from_assembly("OEN 0\nSTO 0\nLD 7\nSKZ");
+        let bytes = program.to_bytes();
+        assert_eq!(bytes, Ok(vec![0xB0, 0x80, 0x17, 0xE0]));
+
+        let program = Program::from_bytes(&bytes.unwrap());
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B08017E0")));
+    }
+
+    #[test]
+    fn handles_instruction_at() {
+        let program = Program::from_assembly("OEN 0\nRTN\nSTO 3");
+        assert_eq!(
+            program.instruction_at(0),
+            Ok(Some((Instruction::OutputEnable(0), 0..2)))
+        );
+        assert_eq!(
+            program.instruction_at(1),
+            Ok(Some((Instruction::OutputEnable(0), 0..2)))
+        );
+        assert_eq!(
+            program.instruction_at(2),
+            Ok(Some((Instruction::Return, 2..3)))
+        );
+        assert_eq!(
+            program.instruction_at(4),
+            Ok(Some((Instruction::Store(3), 3..5)))
+        );
+        assert_eq!(program.instruction_at(5), Ok(None));
+    }
+
+    #[test]
+    fn handles_fingerprint() {
+        let program = Program::from_assembly("OEN 0\nSTO 0");
+        let fingerprint = program.fingerprint().unwrap();
+        assert_eq!(fingerprint, 0x2C14_A0A6_C39B_9E3F);
+
+        let rewritten = Program::from_assembly("; same thing\n.equ OUT 0\noen 0 STO OUT");
+        let options = AssemblerOptions::new().case_sensitive(false);
+        let rewritten = Program::from_assembly_with(&rewritten.source, options);
+        assert_eq!(rewritten.fingerprint(), Ok(fingerprint));
+
+        let different = Program::from_assembly("OEN 0\nSTO 1");
+        assert_ne!(different.fingerprint(), Ok(fingerprint));
+    }
+
+    #[test]
+    fn handles_len() {
+        let program = Program::from_assembly(".rept 3\nSTO 1\n.endr\nRTN");
+        assert_eq!(program.len(), Ok(7));
+        assert_eq!(program.is_empty(), Ok(false));
+        assert_eq!(program.remaining_capacity(), Ok(121));
+
+        let program = Program::from_assembly("; nothing here");
+        assert_eq!(program.is_empty(), Ok(true));
+
+        let program = Program::from_assembly("STO");
+        assert!(program.len().is_err());
+    }
+
+    #[test]
+    fn handles_display() {
+        let program: Program =
+            ".macro copy FROM, TO\nLD FROM\nSTO TO\n.endm\nOEN 0\nloop: copy 1, 2\nJMP loop"
+                .parse()
+                .unwrap();
+        let text = program.to_string();
+        assert_eq!(text, "OEN 0\nLD 1\nSTO 2\nJMP 2\n");
+
+        let reparsed: Program = text.parse().unwrap();
+        assert_eq!(reparsed.into_opcodes(), program.into_opcodes());
+
+        let error = "STO".parse::<Program>();
+        assert!(matches!(error, Err(AssemblerError::ExpectedOperand { .. })));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn handles_serde() {
+        fn assert_serde<T: serde::Serialize + serde::de::DeserializeOwned>() {}
+
+        assert_serde::<Program>();
+        assert_serde::<AssemblerOptions>();
+        assert_serde::<Instruction>();
+        assert_serde::<AssemblerError>();
+        assert_serde::<Warning>();
+        assert_serde::<Doc>();
+        assert_serde::<EditError>();
+        assert_serde::<Change>();
+        assert_serde::<Symbol>();
+    }
+
+    #[test]
+    fn handles_labels() {
+        let program = Program::from_assembly("OEN 0\nstart:\nLD 1\nSTO 0\nJMP start");
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B01180C2")));
+    }
+
+    #[test]
+    fn handles_forward_labels() {
+        let program = Program::from_assembly("JMP skip\nSTO 0\nskip: LD 1");
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("C48011")));
+    }
+
+    #[test]
+    fn handles_undefined_labels() {
+        let program = Program::from_assembly("JMP nowhere");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::UndefinedSymbol { name, .. }) if name == "nowhere"
+        ));
+    }
+
+    #[test]
+    fn handles_duplicate_labels() {
+        let program = Program::from_assembly("loop: NOP\nloop: JMP loop");
+        let bin = program.into_opcodes();
+        assert_eq!(
+            bin,
+            Err(AssemblerError::DuplicateSymbol {
+                name: String::from("loop"),
+                previous: Box::new(Location {
+                    file: None,
+                    line: 1,
+                    column: 1,
+                    offset: 0,
+                    snippet: String::from("loop: NOP"),
+                    length: 4,
+                }),
+                location: Location {
+                    file: None,
+                    line: 2,
+                    column: 1,
+                    offset: 10,
+                    snippet: String::from("loop: JMP loop"),
+                    length: 4,
+                },
+            })
+        );
+        assert_eq!(
+            bin.unwrap_err().to_string(),
+            "2:1: Symbol `loop` is defined more than once\n  first defined at 1:1"
+        );
+    }
+
+    #[test]
+    fn handles_out_of_range_labels() {
+        let program = Program::from_assembly(
+            "JMP end\nNOP NOP NOP NOP NOP NOP NOP NOP NOP NOP NOP NOP NOP NOP\nend: NOP",
+        );
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::SymbolOutOfRange { name, value, .. }) if name == "end" && value == 0x10
+        ));
+    }
+
+    #[test]
+    fn handles_unused_labels() {
+        let program = Program::from_assembly("start: OEN 0\nloop: LD 1\nSTO 0\nJMP loop\nend:");
+        let bin = program.to_opcodes_with_warnings();
+        assert_eq!(
+            bin,
+            Ok((
+                String::from("B01180C2"),
+                vec![
+                    Warning::UnusedLabel {
+                        name: String::from("start"),
+                        location: Location {
+                            file: None,
+                            line: 1,
+                            column: 1,
+                            offset: 0,
+                            snippet: String::from("start: OEN 0"),
+                            length: 5,
+                        },
+                    },
+                    Warning::UnusedLabel {
+                        name: String::from("end"),
+                        location: Location {
+                            file: None,
+                            line: 5,
+                            column: 1,
+                            offset: 39,
+                            snippet: String::from("end:"),
+                            length: 3,
+                        },
+                    },
+                ]
+            ))
+        );
+    }
+
+    #[test]
+    fn handles_constants() {
+        let program = Program::from_assembly(
+            ".equ DOOR_OUT 3\n.define LIGHT 0xC\nOEN 0\nSTO DOOR_OUT\nSTOC LIGHT",
+        );
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B0839C")));
+    }
+
+    #[test]
+    fn handles_redefined_constants() {
+        let program = Program::from_assembly(".equ DOOR_OUT 3\n.equ DOOR_OUT 4");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::DuplicateSymbol { name, .. }) if name == "DOOR_OUT"
+        ));
+    }
+
+    #[test]
+    fn handles_out_of_range_constants() {
+        let program = Program::from_assembly(".equ BIG 16\nSTO BIG");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::SymbolOutOfRange { name, value, .. }) if name == "BIG" && value == 16
+        ));
+    }
+
+    #[test]
+    fn handles_case_insensitive_mnemonics() {
+        let options = AssemblerOptions::new().case_sensitive(false);
+        let program = Program::from_assembly_with("oen 0\nSto 0\nld 7\nsTo f", options);
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B080178F")));
+    }
+
+    #[test]
+    fn handles_lowercase_mnemonics_when_case_sensitive() {
+        let program = Program::from_assembly("oen 0");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::UnexpectedIdentifier { name, .. }) if name == "oen"
+        ));
+    }
+
+    #[test]
+    fn handles_max_length_in_nibbles() {
+        // Directives, labels and comments don't take up any space in the output
+        let body = "STO OUT ; store\n".repeat(63);
+        let program = Program::from_assembly(&format!(".equ OUT 1\nstart:\n{body}JMP start"));
+        let bin = program.into_opcodes();
+        assert_eq!(bin.map(|bin| bin.len()), Ok(128));
+
+        let program = Program::from_assembly(&format!("{body}NOP\nNOP\nSTO 1"));
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::ExceededMaxLength { location }) if location.line == 66
+        ));
+    }
+
+    #[test]
+    fn handles_custom_max_length() {
+        let options = AssemblerOptions::new().max_length(4);
+        let program = Program::from_assembly_with("OEN 0\nSTO 0", options.clone());
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B080")));
+
+        let program = Program::from_assembly_with("OEN 0\nSTO 0\nNOP", options);
+        let bin = program.into_opcodes();
+        assert!(matches!(bin, Err(AssemblerError::ExceededMaxLength { .. })));
+    }
+
+    #[test]
+    fn handles_allowed_directives() {
+        let options = AssemblerOptions::new().allowed_directives(["equ", "raw"]);
+        let program =
+            Program::from_assembly_with(".equ OUT 3\n.nibble \"0\"\nSTO OUT", options.clone());
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("083")));
+
+        let program = Program::from_assembly_with(".include \"other.s\"", options);
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::DirectiveNotAllowed { name, .. }) if name == "include"
+        ));
+    }
+
+    #[test]
+    fn handles_unrecognized_tokens() {
+        let program = Program::from_assembly("OEN 0\nSTO @3");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::UnexpectedToken { text, .. }) if text == "@"
+        ));
+
+        let program = Program::from_assembly("OEN 0 !\nSTO 3");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::UnexpectedToken { text, .. }) if text == "!"
+        ));
+    }
+
+    #[test]
+    fn handles_missing_separators() {
+        for (source, text, column) in [
+            ("OEN 0\nLD7", "LD7", 1),
+            ("OEN 0\nSTOF", "STOF", 1),
+            ("OEN 0\nLD 1STO 2", "1STO", 4),
+            ("OEN 0\nSTO 0x1FOO", "0x1FOO", 5),
+        ] {
+            let program = Program::from_assembly(source);
+            let bin = program.into_opcodes();
+            assert!(
+                matches!(
+                    &bin,
+                    Err(AssemblerError::MissingSeparator { text: found, location })
+                        if found == text && location.line == 2 && location.column == column
+                ),
+                "{source:?} gave {bin:?}"
+            );
+        }
+
+        let options = AssemblerOptions::new().case_sensitive(false);
+        let program = Program::from_assembly_with("sto1", options);
+        let bin = program.into_opcodes();
+        assert!(matches!(bin, Err(AssemblerError::MissingSeparator { .. })));
+
+        // Names that happen to start with a mnemonic are fine
+        let program = Program::from_assembly(".equ ORDER 1\nLD1: STO ORDER\nJMP LD1");
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("81C0")));
+    }
+
+    #[test]
+    fn handles_unrecognized_tokens_when_lenient() {
+        let options = AssemblerOptions::new().strict(false);
+        let program = Program::from_assembly_with("OEN 0 !\nSTO 3", options);
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B083")));
+    }
+
+    #[test]
+    fn handles_crlf_line_endings() {
+        let program = Program::from_assembly("OEN 0\r\nSTO 0\r\n");
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B080")));
+    }
+
+    #[test]
+    fn handles_error_locations() {
+        let program = Program::from_assembly("OEN 0\nSTO 0\n  LD 7 STO @");
+        let bin = program.into_opcodes();
+        assert_eq!(
+            bin,
+            Err(AssemblerError::UnexpectedToken {
+                text: String::from("@"),
+                location: Location {
+                    file: None,
+                    line: 3,
+                    column: 12,
+                    offset: 23,
+                    snippet: String::from("  LD 7 STO @"),
+                    length: 1,
+                },
+            })
+        );
+        assert_eq!(bin.unwrap_err().to_string(), "3:12: Unexpected `@`");
+    }
+
+    #[test]
+    fn handles_error_locations_for_late_symbols() {
+        let program = Program::from_assembly("JMP start\nSTO 0 ; done\nJMP nowhere\nstart: NOP");
+        let bin = program.into_opcodes();
+        assert_eq!(
+            bin.unwrap_err().location(),
+            &Location {
+                file: None,
+                line: 3,
+                column: 5,
+                offset: 27,
+                snippet: String::from("JMP nowhere"),
+                length: 7,
+            }
+        );
+    }
+
+    #[test]
+    fn handles_multiple_errors() {
+        let program = Program::from_assembly(
+            "OEN 0\nSTO\nLD 7\nJMP nowhere\nLD @ STO 1\n.bogus 3\nSTO 23\nJMP 0",
+        );
+        let errors = program.to_opcodes_with_recovery().unwrap_err();
+
+        let lines: Vec<_> = errors
+            .iter()
+            .map(|error| error.kind.location().line)
+            .collect();
+        assert_eq!(lines, [2, 5, 6, 7, 4]);
+        assert!(matches!(
+            errors[0].kind,
+            DiagnosticKind::Error(AssemblerError::ExpectedOperand { .. })
+        ));
+        assert!(matches!(
+            errors[4].kind,
+            DiagnosticKind::Error(AssemblerError::UndefinedSymbol { .. })
+        ));
+    }
+
+    #[test]
+    fn handles_recovery_without_errors() {
+        let program = Program::from_assembly("OEN 0\nSTO 0");
+        let bin = program.to_opcodes_with_recovery();
+        assert_eq!(bin, Ok(String::from("B080")));
+    }
+
+    #[test]
+    fn handles_validate() {
+        let program = Program::from_assembly("OEN 0\nSTO 0");
+        assert_eq!(program.validate(), []);
+
+        let program = Program::from_assembly("STO 0x10\nJMP nowhere\nLD 1");
+        let errors = program.validate();
+        let kinds: Vec<_> = errors.iter().map(|error| &error.kind).collect();
+        assert!(matches!(
+            kinds.as_slice(),
+            [
+                DiagnosticKind::Error(AssemblerError::OperandOutOfRange { .. }),
+                DiagnosticKind::Error(AssemblerError::UndefinedSymbol { .. })
+            ]
+        ));
+    }
+
+    #[test]
+    fn handles_warning_diagnostics() {
+        let program = Program::from_assembly("OEN 0\nunused: STO 0");
+        let (opcodes, warnings) = program.to_opcodes_with_diagnostics().unwrap();
+        assert_eq!(opcodes, "B080");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].severity(), Severity::Warning);
+        assert_eq!(warnings[0].found.as_deref(), Some("unused"));
+        assert!(matches!(
+            &warnings[0].kind,
+            DiagnosticKind::Warning(Warning::UnusedLabel { name, .. }) if name == "unused"
+        ));
+        assert_eq!(program.validate(), warnings);
+
+        let program = Program::from_assembly("unused: STO");
+        let errors = program.to_opcodes_with_diagnostics().unwrap_err();
+        let severities: Vec<_> = errors.iter().map(Diagnostic::severity).collect();
+        assert_eq!(severities, [Severity::Error]);
+    }
+
+    #[test]
+    fn handles_diagnostics() {
+        let program = Program::from_assembly("OEN 0\nJMP nowhere");
+        let errors = program.validate();
+        assert_eq!(errors[0].found.as_deref(), Some("nowhere"));
+        assert_eq!(errors[0].expected, None);
+        assert_eq!(errors[0].span, 10..17);
+        assert_eq!(errors[0].snippet_span, 4..11);
+        assert_eq!(
+            errors[0].to_string(),
+            "2:5: Undefined symbol `nowhere`\n  |\n2 | JMP nowhere\n  |     ^^^^^^^"
+        );
+
+        let program = Program::from_assembly("OEN\nSTO 0");
+        let errors = program.validate();
+        assert_eq!(errors[0].expected.as_deref(), Some("an operand"));
+        assert_eq!(errors[0].found.as_deref(), Some("OEN"));
+        assert_eq!(errors[0].snippet, "  |\n1 | OEN\n  | ^^^");
+    }
+
+    #[test]
+    fn handles_diagnostic_codes() {
+        let program = Program::from_assembly("OEN\nJMP nowhere");
+        let codes: Vec<_> = program.validate().iter().map(Diagnostic::code).collect();
+        assert_eq!(codes, ["E001", "E007"]);
+
+        let program = Program::from_assembly(".macro jump\nJMP nowhere\n.endm\njump");
+        let error = program.to_opcodes().unwrap_err();
+        assert!(matches!(error, AssemblerError::InMacro { .. }));
+        assert_eq!(error.code(), "E007");
+
+        let program = Program::from_assembly("unused: NOP");
+        let (_, warnings) = program.to_opcodes_with_warnings().unwrap();
+        assert_eq!(warnings[0].code(), "W001");
+    }
+
+    #[test]
+    fn handles_operands_on_operandless_instructions() {
+        let program = Program::from_assembly("OEN 0\nSKZ 5\nSTO 0");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::UnexpectedOperand { mnemonic, location })
+                if mnemonic == "SKZ" && location.column == 5
+        ));
+
+        let program = Program::from_assembly("NOP 0x3");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::UnexpectedOperand { mnemonic, .. }) if mnemonic == "NOP"
+        ));
+
+        let program = Program::from_assembly("OEN 0\n7");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::UnexpectedToken { text, .. }) if text == "7"
+        ));
+    }
 }
@@ -0,0 +1,36 @@
+use crate::prelude::*;
+
+/// What a program says about itself with `.name`, `.author`, `.version` and `.description`, each
+/// of which takes a quoted string and can be given once. Anything not given is `None`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Metadata {
+    pub name: Option<String>,
+    pub author: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+}
+
+impl Metadata {
+    /// Each field as its directive's name and value, in the order above, skipping any that
+    /// weren't given.
+    pub fn fields(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        self.all()
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, value?)))
+    }
+
+    /// Every field as its directive's name and value, given or not, in the order above.
+    pub(crate) fn all(&self) -> [(&'static str, Option<&str>); 4] {
+        [
+            ("name", self.name.as_deref()),
+            ("author", self.author.as_deref()),
+            ("version", self.version.as_deref()),
+            ("description", self.description.as_deref()),
+        ]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields().next().is_none()
+    }
+}
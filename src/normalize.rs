@@ -0,0 +1,66 @@
+//! Cleans up source text before anything else looks at it. Programs get pasted in from all sorts
+//! of places, which tend to leave behind byte order marks, Windows or old Mac line endings and
+//! Unicode spaces that look identical to normal ones.
+
+use crate::prelude::*;
+
+/// Strips byte order marks and zero-width characters, turns every kind of line ending into `\n`
+/// and every other kind of whitespace into a plain space. Anything else is left for the lexer to
+/// report.
+pub(crate) fn normalize(source: &str) -> String {
+    let mut output = String::with_capacity(source.len());
+
+    let mut chars = source.chars().peekable();
+    while let Some(character) = chars.next() {
+        match character {
+            '\r' => {
+                chars.next_if_eq(&'\n');
+                output.push('\n');
+            }
+            '\n' | '\t' => output.push(character),
+            '\u{200B}' | '\u{200C}' | '\u{200D}' | '\u{2060}' | '\u{FEFF}' => {}
+            character if character.is_whitespace() => output.push(' '),
+            character => output.push(character),
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AssemblerError, Location, Program};
+
+    #[test]
+    fn handles_pasted_source() {
+        let program = Program::from_assembly(
+            "\u{FEFF}OEN\u{00A0}0\r\nSTO\u{200B} 0\rLD\u{3000}7\u{2028}STO F",
+        );
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B080178F")));
+    }
+
+    #[test]
+    fn handles_invalid_characters() {
+        let program = Program::from_assembly("\u{FEFF}OEN 0\r\nSTO 3\u{2026}");
+        let bin = program.into_opcodes();
+        assert_eq!(
+            bin,
+            Err(AssemblerError::InvalidCharacter {
+                character: '\u{2026}',
+                location: Location {
+                    file: None,
+                    line: 2,
+                    column: 6,
+                    offset: 11,
+                    snippet: String::from("STO 3\u{2026}"),
+                    length: 3,
+                },
+            })
+        );
+        assert_eq!(
+            bin.unwrap_err().to_string(),
+            "2:6: Invalid character `\u{2026}` (U+2026)"
+        );
+    }
+}
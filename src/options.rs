@@ -0,0 +1,123 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use core::fmt;
+
+use crate::prelude::*;
+use crate::{Directive, MAX_PROGRAM_LENGTH};
+
+/// Settings that change how source is read and assembled. The defaults match what the in-game
+/// component documentation uses, so most programs won't need to touch these.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AssemblerOptions {
+    pub(crate) case_sensitive: bool,
+    pub(crate) strict: bool,
+    pub(crate) max_length: usize,
+    /// `None` allows every directive
+    pub(crate) allowed_directives: Option<BTreeSet<String>>,
+    pub(crate) defines: BTreeMap<String, String>,
+    /// Custom directives can't be serialized, so they have to be added back after deserializing
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) directives: Directives,
+}
+
+/// Custom directives, keyed by name. They're shared between threads along with the options, so
+/// they have to be `Send` and `Sync`.
+#[derive(Clone, Default)]
+pub(crate) struct Directives(BTreeMap<String, Arc<dyn Directive + Send + Sync>>);
+
+impl AssemblerOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether mnemonics have to be written in uppercase. When this is turned off, `oen 0` and
+    /// `Oen 0` both mean `OEN 0`. Labels, constants and macro names are always case-sensitive.
+    pub fn case_sensitive(mut self, case_sensitive: bool) -> Self {
+        self.case_sensitive = case_sensitive;
+        self
+    }
+
+    /// Whether text that isn't part of the language is an error. When this is turned off, stray
+    /// characters are skipped over instead, which is how the assembler originally behaved.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// The most a program can hold before it's rejected. Defaults to the in-game limit of 128.
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    /// Restricts programs to the given directives, named without the leading dot (`"equ"`).
+    /// Using any other directive is an error. Aliases like `.nibble` are covered by the name of
+    /// the directive they stand for (here `"raw"`). By default every directive is allowed.
+    pub fn allowed_directives<I, S>(mut self, directives: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.allowed_directives = Some(directives.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Defines a name for the preprocessor before any source is read, like passing `-D` to a C
+    /// compiler. The name is replaced with `value` wherever it appears, and counts as defined for
+    /// `#ifdef` (`value` can be empty if that's all it's needed for).
+    pub fn define(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.defines.insert(name.into(), value.into());
+        self
+    }
+
+    /// Adds a directive of your own, invoked by its [`Directive::name`]. Built-in directives always
+    /// take priority, so one with the same name as a built-in is never used. Adding another with
+    /// the same name as an earlier one replaces it. Options can be sent between threads, so the
+    /// directive has to be `Send` and `Sync` too.
+    pub fn directive(mut self, directive: impl Directive + Send + Sync + 'static) -> Self {
+        let name = directive.name().to_string();
+        self.directives.0.insert(name, Arc::new(directive));
+        self
+    }
+}
+
+impl Directives {
+    pub(crate) fn get(&self, name: &str) -> Option<&Arc<dyn Directive + Send + Sync>> {
+        self.0.get(name)
+    }
+}
+
+impl fmt::Debug for Directives {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// Directives are the same if they're the same instances under the same names.
+impl PartialEq for Directives {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len()
+            && self.0.iter().all(|(name, directive)| {
+                other
+                    .0
+                    .get(name)
+                    .is_some_and(|other| Arc::ptr_eq(directive, other))
+            })
+    }
+}
+
+impl Eq for Directives {}
+
+impl Default for AssemblerOptions {
+    fn default() -> Self {
+        Self {
+            case_sensitive: true,
+            strict: true,
+            max_length: MAX_PROGRAM_LENGTH,
+            allowed_directives: None,
+            defines: BTreeMap::new(),
+            directives: Directives::default(),
+        }
+    }
+}
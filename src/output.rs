@@ -0,0 +1,172 @@
+//! Writing a program's output straight into a writer, for callers that are going to send it
+//! somewhere anyway and would rather not hold onto a copy.
+
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
+
+use crate::prelude::*;
+use crate::{AssemblerError, EmitOptions, OutputFormat, Program};
+
+/// Why writing a program out failed: either it didn't assemble, or the writer gave up.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum WriteError {
+    /// Boxed to keep write errors small
+    Assembler(Box<AssemblerError>),
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    Fmt(fmt::Error),
+}
+
+impl Program {
+    /// Like [`Program::to_opcodes`], but writes the opcodes into `writer` as ASCII hex.
+    #[cfg(feature = "std")]
+    pub fn write_opcodes<W: io::Write>(&self, writer: W) -> Result<(), WriteError> {
+        self.emit_into(OutputFormat::Hex, &EmitOptions::default(), &mut Io(writer))
+    }
+
+    /// Like [`Program::to_opcodes`], but writes the opcodes into a text writer, like a `String`
+    /// or a [`fmt::Formatter`].
+    pub fn write_opcodes_fmt<W: fmt::Write>(&self, writer: W) -> Result<(), WriteError> {
+        self.emit_into(OutputFormat::Hex, &EmitOptions::default(), &mut Fmt(writer))
+    }
+
+    /// Like [`Program::emit_with`], but writes the output into `writer`.
+    #[cfg(feature = "std")]
+    pub fn emit_to<W: io::Write>(
+        &self,
+        format: OutputFormat,
+        options: &EmitOptions,
+        writer: W,
+    ) -> Result<(), WriteError> {
+        self.emit_into(format, options, &mut Io(writer))
+    }
+}
+
+/// Somewhere a program's output can be written, whatever kind of writer is behind it.
+pub(crate) trait Sink {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), WriteError>;
+}
+
+impl Sink for Vec<u8> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), WriteError> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+struct Io<W>(W);
+
+#[cfg(feature = "std")]
+impl<W: io::Write> Sink for Io<W> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), WriteError> {
+        Ok(self.0.write_all(bytes)?)
+    }
+}
+
+/// Only takes text, so binary output fails to write.
+struct Fmt<W>(W);
+
+impl<W: fmt::Write> Sink for Fmt<W> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), WriteError> {
+        let text = core::str::from_utf8(bytes).map_err(|_| fmt::Error)?;
+        Ok(self.0.write_str(text)?)
+    }
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Assembler(error) => error.fmt(f),
+            #[cfg(feature = "std")]
+            Self::Io(error) => write!(f, "Couldn't write the program: {error}"),
+            Self::Fmt(_) => f.write_str("Couldn't write the program"),
+        }
+    }
+}
+
+impl core::error::Error for WriteError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Assembler(error) => Some(&**error),
+            #[cfg(feature = "std")]
+            Self::Io(error) => Some(error),
+            Self::Fmt(error) => Some(error),
+        }
+    }
+}
+
+impl From<AssemblerError> for WriteError {
+    fn from(error: AssemblerError) -> Self {
+        Self::Assembler(Box::new(error))
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for WriteError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<fmt::Error> for WriteError {
+    fn from(error: fmt::Error) -> Self {
+        Self::Fmt(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AssemblerError, Program, WriteError};
+    #[cfg(feature = "std")]
+    use crate::{EmitOptions, OutputFormat};
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn handles_write_opcodes() {
+        let program = Program::from_assembly("OEN 0\nSTO 0");
+        let mut output = Vec::new();
+        program.write_opcodes(&mut output).unwrap();
+        assert_eq!(output, b"B080");
+
+        let program = Program::from_assembly("STO");
+        let error = program.write_opcodes(&mut output);
+        assert!(matches!(
+            error,
+            Err(WriteError::Assembler(error)) if matches!(*error, AssemblerError::ExpectedOperand { .. })
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn handles_emit_to() {
+        let program = Program::from_assembly(".name \"Blink\"\nOEN 0\nloop: STO 0\nJMP loop");
+        let options = EmitOptions::new().terminate(true);
+        for format in [
+            OutputFormat::Hex,
+            OutputFormat::Container,
+            OutputFormat::Listing,
+        ] {
+            let mut output = Vec::new();
+            program.emit_to(format, &options, &mut output).unwrap();
+            assert_eq!(output, program.emit_with(format, &options).unwrap());
+        }
+    }
+
+    #[test]
+    fn handles_write_opcodes_fmt() {
+        let program = Program::from_assembly("OEN 0\nSTO 0");
+        let mut output = String::from("opcodes: ");
+        program.write_opcodes_fmt(&mut output).unwrap();
+        assert_eq!(output, "opcodes: B080");
+
+        let program = Program::from_assembly("STO");
+        let error = program.write_opcodes_fmt(&mut output);
+        assert!(matches!(
+            error,
+            Err(WriteError::Assembler(error)) if matches!(*error, AssemblerError::ExpectedOperand { .. })
+        ));
+    }
+}
@@ -0,0 +1,131 @@
+//! Splitting a program that's too long for one Control Unit into pages that each fit in one, for
+//! setups that chain several together.
+
+use crate::emit::json_string;
+use crate::prelude::*;
+use crate::{AssemblerError, Program, MAX_PROGRAM_LENGTH};
+
+/// One page of a program, from [`Program::pages`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Page {
+    /// Where the page starts in the whole program, as a nibble address
+    pub start: usize,
+    pub opcodes: String,
+}
+
+impl Page {
+    /// The page as a program of its own, for loading into a Control Unit.
+    pub fn program(&self) -> Program {
+        Program::from_assembly(&format!(".raw \"{}\"", self.opcodes))
+    }
+}
+
+impl Program {
+    /// The program split into pages of at most `page_length` nibbles, in order. Pages only ever
+    /// end between instructions, so each one is a valid program by itself, which means
+    /// `page_length` is at least 2. Jumps are left as they are, so a jump lands at that address
+    /// in whichever page it's in. A program that fits in one page gives just the one page.
+    ///
+    /// Programs longer than the usual limit need [`crate::AssemblerOptions::max_length`] raised
+    /// to assemble in the first place.
+    pub fn pages(&self, page_length: usize) -> Result<Vec<Page>, AssemblerError> {
+        let page_length = page_length.max(2);
+        let mut pages = vec![Page {
+            start: 0,
+            opcodes: String::new(),
+        }];
+
+        for (address, instruction) in self.iter()? {
+            let nibbles = [Some(instruction.opcode()), instruction.operand()]
+                .into_iter()
+                .flatten();
+
+            if pages.last().map_or(0, |page| page.opcodes.len()) + instruction.size() > page_length
+            {
+                pages.push(Page {
+                    start: address,
+                    opcodes: String::new(),
+                });
+            }
+
+            if let Some(page) = pages.last_mut() {
+                page.opcodes
+                    .extend(nibbles.map(|nibble| format!("{nibble:X}")));
+            }
+        }
+
+        Ok(pages)
+    }
+
+    /// A JSON manifest of the program's pages (see [`Program::pages`]), each as its `page`
+    /// number counting from 1, its `start` address in the whole program, its `length` in nibbles
+    /// and its `opcodes`, in the order they go in.
+    pub fn to_manifest(&self, page_length: usize) -> Result<String, AssemblerError> {
+        let pages: Vec<_> = self
+            .pages(page_length)?
+            .into_iter()
+            .enumerate()
+            .map(|(index, page)| {
+                format!(
+                    "{{\"page\":{},\"start\":{},\"length\":{},\"opcodes\":{}}}",
+                    index + 1,
+                    page.start,
+                    page.opcodes.len(),
+                    json_string(&page.opcodes),
+                )
+            })
+            .collect();
+
+        Ok(format!(
+            "{{\"page_length\":{},\"pages\":[{}]}}",
+            page_length.max(2),
+            pages.join(",")
+        ))
+    }
+}
+
+/// How many nibbles each page holds unless asked otherwise: as many as a Control Unit does.
+pub const DEFAULT_PAGE_LENGTH: usize = MAX_PROGRAM_LENGTH;
+
+#[cfg(test)]
+mod tests {
+    use crate::{AssemblerOptions, Page, Program};
+
+    #[test]
+    fn handles_pages() {
+        let program = Program::from_assembly_with(
+            ".rept 100\nLD 7\n.endr\nSKZ\nRTN",
+            AssemblerOptions::new().max_length(256),
+        );
+        let pages = program.pages(128).unwrap();
+        assert_eq!(
+            pages,
+            [
+                Page {
+                    start: 0,
+                    opcodes: "17".repeat(64),
+                },
+                Page {
+                    start: 128,
+                    opcodes: format!("{}ED", "17".repeat(36)),
+                },
+            ]
+        );
+        assert_eq!(pages[1].program().len().unwrap(), 74);
+
+        // An instruction that would straddle two pages starts the next one
+        let pages = Program::from_assembly("SKZ\nLD 7\nLD 7").pages(2).unwrap();
+        let starts: Vec<_> = pages.iter().map(|page| page.start).collect();
+        assert_eq!(starts, [0, 1, 3]);
+
+        assert_eq!(
+            Program::from_assembly("SKZ\nLD 7").to_manifest(2).unwrap(),
+            concat!(
+                r#"{"page_length":2,"pages":["#,
+                r#"{"page":1,"start":0,"length":1,"opcodes":"E"},"#,
+                r#"{"page":2,"start":1,"length":2,"opcodes":"17"}]}"#,
+            )
+        );
+    }
+}
@@ -0,0 +1,362 @@
+//! A C-style preprocessor that runs over each file before it's lexed, for configuration that's
+//! decided outside of the program itself (see [`crate::AssemblerOptions::define`]).
+//!
+//! Lines starting with `#` and a word are preprocessor directives: `#define NAME [VALUE]`, `#undef NAME`,
+//! `#ifdef NAME`/`#ifndef NAME`, `#else`, `#endif` and `#error MESSAGE`. Everywhere else, names
+//! that have been defined are replaced with their value. Directives and lines that are skipped
+//! are blanked out rather than removed, so line numbers stay the same.
+
+use alloc::collections::BTreeMap;
+use core::ops::Range;
+
+use crate::prelude::*;
+use crate::{AssemblerError, Location};
+
+/// An `#ifdef` or `#ifndef` block that's been entered but not yet closed.
+struct Conditional {
+    /// Whether the lines in the current branch are kept
+    active: bool,
+    /// Whether the enclosing block's lines are kept, which overrides this block's condition
+    parent_active: bool,
+    seen_else: bool,
+    /// Where the block was opened, for reporting it if it's never closed
+    span: Range<usize>,
+}
+
+/// Runs the preprocessor over a file, starting out with `defines` already defined. `locate` turns
+/// a byte range of the source into a location for errors.
+pub(crate) fn preprocess(
+    source: &str,
+    defines: &BTreeMap<String, String>,
+    locate: impl Fn(Range<usize>) -> Location,
+) -> Result<String, AssemblerError> {
+    let mut defines = defines.clone();
+    let mut conditionals: Vec<Conditional> = Vec::new();
+    let mut output = String::with_capacity(source.len());
+
+    let mut start = 0;
+    let mut in_block_comment = false;
+    for line in source.split_inclusive('\n') {
+        let span = start..start + line.len();
+        start = span.end;
+
+        let (content, ending) = split_line_ending(line);
+
+        // Lines that start inside a block comment are left alone, so commenting out a section
+        // also comments out any directives in it
+        let commented = in_block_comment;
+        in_block_comment = ends_in_block_comment(content, in_block_comment);
+        if commented {
+            output.push_str(line);
+            continue;
+        }
+
+        let active = conditionals
+            .last()
+            .map_or(true, |conditional| conditional.active);
+
+        // `# like this` is a comment rather than a directive
+        let directive = match content.trim_start().strip_prefix('#') {
+            Some(directive) if directive.starts_with(|c: char| !c.is_whitespace()) => directive,
+            _ => {
+                if active {
+                    output.push_str(&substitute(content, &defines));
+                }
+
+                output.push_str(ending);
+                continue;
+            }
+        };
+
+        // Point at the directive itself rather than any indentation before it
+        let indent = content.len() - content.trim_start().len();
+        let directive_span = span.start + indent..span.start + content.trim_end().len();
+
+        let (name, rest) = split_word(directive);
+        let rest = strip_comment(rest).trim();
+        let error = |error: AssemblerError| Err(error.locate(|| locate(directive_span.clone())));
+
+        match name {
+            "define" | "undef" | "ifdef" | "ifndef" => {
+                let (symbol, value) = split_word(rest);
+                if symbol.is_empty() || !is_name(symbol) {
+                    return error(AssemblerError::ExpectedDefineName {
+                        directive: name.to_string(),
+                        location: Location::UNKNOWN,
+                    });
+                }
+
+                match name {
+                    "define" if active => {
+                        defines.insert(symbol.to_string(), value.trim().to_string());
+                    }
+                    "undef" if active => {
+                        defines.remove(symbol);
+                    }
+                    "ifdef" | "ifndef" => conditionals.push(Conditional {
+                        active: active && defines.contains_key(symbol) == (name == "ifdef"),
+                        parent_active: active,
+                        seen_else: false,
+                        span: directive_span.clone(),
+                    }),
+                    _ => {}
+                }
+            }
+            "else" => {
+                let conditional = match conditionals.last_mut() {
+                    Some(conditional) if !conditional.seen_else => conditional,
+                    Some(_) => {
+                        return error(AssemblerError::DuplicatePreprocessorElse {
+                            location: Location::UNKNOWN,
+                        })
+                    }
+                    None => return error(unmatched(name)),
+                };
+
+                conditional.seen_else = true;
+                conditional.active = conditional.parent_active && !conditional.active;
+            }
+            "endif" => {
+                if conditionals.pop().is_none() {
+                    return error(unmatched(name));
+                }
+            }
+            "error" if active => {
+                return error(AssemblerError::ErrorDirective {
+                    message: rest.to_string(),
+                    location: Location::UNKNOWN,
+                })
+            }
+            "error" => {}
+            _ => {
+                return error(AssemblerError::UnknownPreprocessorDirective {
+                    name: name.to_string(),
+                    location: Location::UNKNOWN,
+                })
+            }
+        }
+
+        output.push_str(ending);
+    }
+
+    if let Some(conditional) = conditionals.pop() {
+        let error = AssemblerError::UnterminatedPreprocessorConditional {
+            location: Location::UNKNOWN,
+        };
+
+        return Err(error.locate(|| locate(conditional.span)));
+    }
+
+    Ok(output)
+}
+
+fn unmatched(directive: &str) -> AssemblerError {
+    AssemblerError::UnmatchedPreprocessorDirective {
+        directive: directive.to_string(),
+        location: Location::UNKNOWN,
+    }
+}
+
+/// Replaces every defined name in a line with its value, leaving strings, comments and directive
+/// names alone. Values aren't themselves expanded.
+fn substitute(line: &str, defines: &BTreeMap<String, String>) -> String {
+    let (line, comment) = line.split_at(comment_start(line));
+    let mut output = String::with_capacity(line.len());
+    let mut in_string = false;
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((index, character)) = chars.next() {
+        if in_string || !is_word_character(character) {
+            if character == '"' {
+                in_string = !in_string;
+            }
+
+            output.push(character);
+            continue;
+        }
+
+        let mut end = index + character.len_utf8();
+        while let Some((next, _)) = chars.next_if(|(_, next)| is_word_character(*next)) {
+            end = next + 1;
+        }
+
+        let word = &line[index..end];
+        let after_dot = line[..index].ends_with('.');
+        match defines.get(word) {
+            Some(value) if is_name(word) && !after_dot => output.push_str(value),
+            _ => output.push_str(word),
+        }
+    }
+
+    output.push_str(comment);
+    output
+}
+
+/// Whether a line leaves a `/* ... */` comment open, given whether one was open at the start.
+fn ends_in_block_comment(line: &str, mut in_comment: bool) -> bool {
+    let mut in_string = false;
+    let mut chars = line.chars().peekable();
+    while let Some(character) = chars.next() {
+        match character {
+            '*' if in_comment && chars.next_if_eq(&'/').is_some() => in_comment = false,
+            _ if in_comment => {}
+            '"' => in_string = !in_string,
+            _ if in_string => {}
+            '/' if chars.next_if_eq(&'*').is_some() => in_comment = true,
+            '/' if chars.peek() == Some(&'/') => break,
+            ';' | '#' => break,
+            _ => {}
+        }
+    }
+
+    in_comment
+}
+
+/// The index a comment starts at, or the end of the line if there isn't one.
+fn comment_start(line: &str) -> usize {
+    let mut in_string = false;
+    for (index, character) in line.char_indices() {
+        match character {
+            '"' => in_string = !in_string,
+            ';' | '#' if !in_string => return index,
+            '/' if !in_string && line[index + 1..].starts_with('/') => return index,
+            _ => {}
+        }
+    }
+
+    line.len()
+}
+
+fn split_line_ending(line: &str) -> (&str, &str) {
+    let content = line.trim_end_matches(['\r', '\n']);
+    (content, &line[content.len()..])
+}
+
+/// Splits off the first whitespace-separated word.
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    (&text[..end], &text[end..])
+}
+
+fn strip_comment(text: &str) -> &str {
+    &text[..comment_start(text)]
+}
+
+fn is_word_character(character: char) -> bool {
+    character.is_ascii_alphanumeric() || character == '_'
+}
+
+/// Whether a word could be a name, rather than a number.
+fn is_name(word: &str) -> bool {
+    word.chars()
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && word.chars().all(is_word_character)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AssemblerError, AssemblerOptions, Program};
+
+    #[test]
+    fn handles_defines() {
+        let program = Program::from_assembly(
+            "#define DOOR 3\n#define GATE DOOR\nOEN 0\nSTO DOOR ; DOOR\n.equ GATE_OUT 4\nSTO GATE_OUT",
+        );
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B08384")));
+    }
+
+    #[test]
+    fn handles_ifdef() {
+        let source = "OEN 0\n#ifdef TWO_DOORS\nSTO 3\n  #ifndef QUIET\nSTO 4\n  #endif\n#else\nSTO 3\n#endif";
+
+        let program = Program::from_assembly(source);
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B083")));
+
+        let options = AssemblerOptions::new().define("TWO_DOORS", "");
+        let program = Program::from_assembly_with(source, options);
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B08384")));
+
+        let options = AssemblerOptions::new()
+            .define("TWO_DOORS", "")
+            .define("QUIET", "1");
+        let program = Program::from_assembly_with(source, options);
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("B083")));
+    }
+
+    #[test]
+    fn handles_undef() {
+        let options = AssemblerOptions::new().define("DOOR", "5");
+        let program = Program::from_assembly_with(
+            "STO DOOR\n#undef DOOR\n#ifndef DOOR\nSTO 6\n#endif",
+            options,
+        );
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("8586")));
+    }
+
+    #[test]
+    fn handles_error_directive() {
+        let program = Program::from_assembly(
+            "OEN 0\n#ifndef DOOR\n  #error DOOR must be set ; with -D\n#endif",
+        );
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::ErrorDirective { message, location })
+                if message == "DOOR must be set" && location.line == 3 && location.column == 3
+        ));
+    }
+
+    #[test]
+    fn handles_hash_comments() {
+        let program = Program::from_assembly(
+            "#define OUT 3\n# OUT isn't replaced here\n  #\nSTO OUT # or here: OUT\nSTO OUT // OUT",
+        );
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("8383")));
+    }
+
+    #[test]
+    fn handles_directives_in_block_comments() {
+        let program = Program::from_assembly("STO 1 /*\n#error unreachable\n*/ STO 2");
+        let bin = program.into_opcodes();
+        assert_eq!(bin, Ok(String::from("8182")));
+    }
+
+    #[test]
+    fn handles_malformed_preprocessor_directives() {
+        let program = Program::from_assembly("#ifdef DOOR\nSTO 3");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::UnterminatedPreprocessorConditional { location }) if location.line == 1
+        ));
+
+        let program = Program::from_assembly("#endif");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::UnmatchedPreprocessorDirective { directive, .. }) if directive == "endif"
+        ));
+
+        let program = Program::from_assembly("#define 3");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::ExpectedDefineName { .. })
+        ));
+
+        let program = Program::from_assembly("#pragma once");
+        let bin = program.into_opcodes();
+        assert!(matches!(
+            bin,
+            Err(AssemblerError::UnknownPreprocessorDirective { name, .. }) if name == "pragma"
+        ));
+    }
+}
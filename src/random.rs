@@ -0,0 +1,127 @@
+//! Random instructions and programs, through `arbitrary` for fuzzing and `proptest` for property
+//! tests. Both only ever make valid instructions, and programs that fit in the Control Unit and
+//! assemble without errors.
+
+use crate::prelude::*;
+use crate::{instruction, Instruction, Program, MAX_PROGRAM_LENGTH};
+
+/// The instruction for an opcode from `0x0` to `0xE`, using `operand` if it takes one.
+fn instruction(opcode: u8, operand: u8) -> Instruction {
+    Instruction::new(opcode, None)
+        .or_else(|| Instruction::new(opcode, Some(operand)))
+        .unwrap_or(Instruction::NoOp)
+}
+
+/// As many of `instructions` as fit in a program, as a program.
+fn program(instructions: impl IntoIterator<Item = Instruction>) -> Program {
+    let mut size = 0;
+    let instructions: Vec<_> = instructions
+        .into_iter()
+        .take_while(|instruction| {
+            size += instruction.size();
+            size <= MAX_PROGRAM_LENGTH
+        })
+        .collect();
+
+    Program::from_assembly(&instruction::to_assembly(&instructions))
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Instruction {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(instruction(
+            u.int_in_range(0..=0xE)?,
+            u.int_in_range(0..=0xF)?,
+        ))
+    }
+
+    fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+        (2, Some(2))
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Program {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let instructions = u
+            .arbitrary_iter::<Instruction>()?
+            .collect::<arbitrary::Result<Vec<_>>>()?;
+        Ok(program(instructions))
+    }
+}
+
+#[cfg(feature = "proptest")]
+impl proptest::arbitrary::Arbitrary for Instruction {
+    type Parameters = ();
+    type Strategy = proptest::strategy::Map<
+        (core::ops::RangeInclusive<u8>, core::ops::RangeInclusive<u8>),
+        fn((u8, u8)) -> Self,
+    >;
+
+    fn arbitrary_with((): ()) -> Self::Strategy {
+        use proptest::strategy::Strategy;
+
+        (0..=0xE, 0..=0xF).prop_map(|(opcode, operand)| instruction(opcode, operand))
+    }
+}
+
+#[cfg(feature = "proptest")]
+impl proptest::arbitrary::Arbitrary for Program {
+    type Parameters = ();
+    type Strategy = proptest::strategy::BoxedStrategy<Self>;
+
+    fn arbitrary_with((): ()) -> Self::Strategy {
+        use proptest::strategy::Strategy;
+
+        proptest::collection::vec(
+            proptest::arbitrary::any::<Instruction>(),
+            0..=MAX_PROGRAM_LENGTH,
+        )
+        .prop_map(program)
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn handles_arbitrary() {
+        use arbitrary::{Arbitrary, Unstructured};
+
+        use crate::{Instruction, Program};
+
+        // Odd bytes so `arbitrary_iter` keeps going until they run out
+        let bytes: Vec<u8> = (0..1024).map(|index| (index * 7) as u8 | 1).collect();
+        let mut u = Unstructured::new(&bytes);
+        let program = Program::arbitrary(&mut u).unwrap();
+        let instructions = program.instructions().unwrap();
+        assert!(!instructions.is_empty());
+        assert!(instructions
+            .iter()
+            .all(|instruction| instruction.is_valid()));
+        assert!(program.to_opcodes().unwrap().len() <= crate::MAX_PROGRAM_LENGTH);
+
+        let mut u = Unstructured::new(&[0xD, 0x0, 0xC, 0x7]);
+        assert_eq!(Instruction::arbitrary(&mut u), Ok(Instruction::Return));
+        assert_eq!(Instruction::arbitrary(&mut u), Ok(Instruction::Jump(7)));
+    }
+
+    #[cfg(feature = "proptest")]
+    proptest::proptest! {
+        #[test]
+        fn handles_round_trips(program: crate::Program) {
+            let opcodes = program.to_opcodes().unwrap();
+            let disassembled = crate::Program::from_opcodes(&opcodes).unwrap();
+            proptest::prop_assert_eq!(disassembled.to_opcodes().unwrap(), opcodes);
+        }
+
+        #[test]
+        fn handles_emulating_anything(program: crate::Program) {
+            let mut emulator = crate::Emulator::new(&program).unwrap();
+            emulator.set_input(1, true);
+            emulator.run_for(512);
+            proptest::prop_assert!(emulator.pc() <= crate::MAX_PROGRAM_LENGTH);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Diagnostics as SARIF, the static analysis format that code review tools and editors read, so
+//! they can show errors and warnings on the lines they're about.
+
+use alloc::collections::BTreeSet;
+use core::fmt::Write;
+
+use crate::emit::json_string;
+use crate::prelude::*;
+use crate::{Diagnostic, DiagnosticKind, Program, Severity};
+
+impl Program {
+    /// Everything [`Program::validate`] finds, as a SARIF 2.1.0 log with one run. Each result's
+    /// rule is its [`Diagnostic::code`]. Results only get a location when the source came from a
+    /// file, since SARIF needs one to point at; the message always says where the problem is.
+    pub fn to_sarif(&self) -> String {
+        sarif(&self.validate())
+    }
+}
+
+fn sarif(diagnostics: &[Diagnostic]) -> String {
+    let rules: BTreeSet<_> = diagnostics.iter().map(Diagnostic::code).collect();
+    let rules: Vec<_> = rules
+        .into_iter()
+        .map(|code| format!("{{\"id\":\"{code}\"}}"))
+        .collect();
+
+    let results: Vec<_> = diagnostics.iter().map(result).collect();
+
+    format!(
+        concat!(
+            "{{\"$schema\":\"https://json.schemastore.org/sarif-2.1.0.json\",\"version\":\"2.1.0\",",
+            "\"runs\":[{{\"tool\":{{\"driver\":{{\"name\":\"{}\",\"version\":\"{}\",\"rules\":[{}]}}}},",
+            "\"results\":[{}]}}]}}",
+        ),
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        rules.join(","),
+        results.join(","),
+    )
+}
+
+fn result(diagnostic: &Diagnostic) -> String {
+    let level = match diagnostic.severity() {
+        Severity::Warning => "warning",
+        Severity::Error => "error",
+    };
+
+    let mut result = format!(
+        "{{\"ruleId\":\"{}\",\"level\":\"{level}\",\"message\":{{\"text\":{}}}",
+        diagnostic.code(),
+        json_string(&diagnostic.kind.to_string()),
+    );
+
+    // Errors in macros and includes are located at the innermost error, where the problem is
+    let location = match &diagnostic.kind {
+        DiagnosticKind::Error(error) => error.innermost().location(),
+        DiagnosticKind::Warning(warning) => warning.location(),
+    };
+
+    if let Some(file) = &location.file {
+        let start = location.column;
+        let end = start
+            + diagnostic
+                .found
+                .as_deref()
+                .map_or(0, |found| found.chars().count());
+        let _ = write!(
+            result,
+            ",\"locations\":[{{\"physicalLocation\":{{\"artifactLocation\":{{\"uri\":{}}},\"region\":{{\"startLine\":{},\"startColumn\":{start},\"endColumn\":{}}}}}}}]",
+            json_string(&uri(file.as_str())),
+            location.line,
+            end.max(start + 1),
+        );
+    }
+
+    result.push('}');
+    result
+}
+
+/// A path as a relative or absolute URI reference, with forward slashes and anything that isn't
+/// allowed in a URI percent-encoded.
+fn uri(path: &str) -> String {
+    let mut uri = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'\\' => uri.push('/'),
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                uri.push(char::from(byte));
+            }
+            byte => {
+                let _ = write!(uri, "%{byte:02X}");
+            }
+        }
+    }
+
+    uri
+}
+
+#[cfg(test)]
+mod tests {
+    use super::uri;
+    use crate::Program;
+
+    #[test]
+    fn handles_sarif() {
+        let program = Program::from_assembly("unused: STO\nNOP");
+        assert_eq!(
+            program.to_sarif(),
+            concat!(
+                r#"{"$schema":"https://json.schemastore.org/sarif-2.1.0.json","version":"2.1.0","#,
+                r#""runs":[{"tool":{"driver":{"name":"goonstation-asm","version":"0.1.0","rules":[{"id":"E001"}]}},"#,
+                r#""results":[{"ruleId":"E001","level":"error","message":{"text":"1:9: Expected operand"}}]}]}"#,
+            )
+        );
+
+        let program = Program::from_assembly("unused: NOP");
+        assert!(program
+            .to_sarif()
+            .contains(r#""results":[{"ruleId":"W001","level":"warning","#));
+
+        assert!(Program::from_assembly("NOP")
+            .to_sarif()
+            .contains(r#""rules":[]}},"results":[]"#));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn handles_sarif_locations() {
+        let path =
+            std::env::temp_dir().join(format!("goonstation-asm sarif {}.s", std::process::id()));
+        std::fs::write(&path, "LD 7\nSTO 99").unwrap();
+
+        let program = Program::from_file(&path).unwrap();
+        let sarif = program.to_sarif();
+        assert!(sarif.contains(&format!(
+            r#""artifactLocation":{{"uri":"{}"}},"region":{{"startLine":2,"startColumn":5,"endColumn":7}}"#,
+            uri(&path.display().to_string())
+        )));
+        assert!(sarif.contains(&format!(
+            "goonstation-asm%20sarif%20{}.s",
+            std::process::id()
+        )));
+    }
+
+    #[test]
+    fn handles_uris() {
+        assert_eq!(uri("src/main.s"), "src/main.s");
+        assert_eq!(uri(r"C:\My Programs\blink.s"), "C:/My%20Programs/blink.s");
+    }
+}
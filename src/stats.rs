@@ -0,0 +1,84 @@
+//! Counting what a program is made of, for fitting it under the length limit or checking which
+//! I/O pins it touches.
+
+use alloc::collections::BTreeMap;
+
+use crate::instruction::{self, Iter};
+use crate::prelude::*;
+use crate::{AssemblerError, Instruction, Program};
+
+/// What a program is made of, from [`Program::stats`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stats {
+    /// How many nibbles the program assembles to
+    pub size: usize,
+    /// How many times each instruction is used, by mnemonic
+    pub instructions: BTreeMap<String, usize>,
+    /// How many nibbles couldn't be read as instructions
+    pub invalid: usize,
+    /// How many instructions use each address as their operand. Jumps aren't counted, since
+    /// their operand is a position in the program rather than an address.
+    pub operands: [usize; 16],
+}
+
+impl Program {
+    /// Counts the instructions in the program and the addresses they use.
+    pub fn stats(&self) -> Result<Stats, AssemblerError> {
+        self.to_opcodes().map(|opcodes| Stats::of(&opcodes))
+    }
+}
+
+impl Stats {
+    /// Counts what a program that assembled to `opcodes` is made of.
+    pub(crate) fn of(opcodes: &str) -> Self {
+        let mut stats = Self {
+            size: opcodes.len(),
+            ..Self::default()
+        };
+
+        let instructions = instruction::decode(&instruction::nibbles(opcodes));
+        for (_, instruction) in Iter::new(instructions) {
+            let Some(mnemonic) = instruction.mnemonic() else {
+                stats.invalid += 1;
+                continue;
+            };
+
+            *stats.instructions.entry(mnemonic.to_string()).or_default() += 1;
+            match (instruction, instruction.operand()) {
+                (Instruction::Jump(_), _) | (_, None) => {}
+                (_, Some(operand)) => stats.operands[usize::from(operand)] += 1,
+            }
+        }
+
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Program;
+
+    #[test]
+    fn handles_stats() {
+        let program =
+            Program::from_assembly("OEN 0\nloop: LD 7\nSTO 7\nSTOC 3\nJMP loop\n.raw \"F\"");
+        let stats = program.stats().unwrap();
+        assert_eq!(stats.size, 11);
+        assert_eq!(
+            stats.instructions.into_iter().collect::<Vec<_>>(),
+            [
+                (String::from("JMP"), 1),
+                (String::from("LD"), 1),
+                (String::from("OEN"), 1),
+                (String::from("STO"), 1),
+                (String::from("STOC"), 1),
+            ]
+        );
+        assert_eq!(stats.invalid, 1);
+        assert_eq!(
+            stats.operands,
+            [1, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+    }
+}
@@ -0,0 +1,15 @@
+;;; Only lets STATE follow IN once IN has held the same value for two passes in a row, to ignore
+;;; a signal that flickers. PREV, STATE and TEMP all have to be somewhere that can be read back,
+;;; like scratch RAM, and TEMP is overwritten.
+.macro debounce IN, PREV, STATE, TEMP
+LD IN
+OR PREV
+AND STATE
+STO TEMP
+LD IN
+AND PREV
+OR TEMP
+STO STATE
+LD IN
+STO PREV
+.endm
@@ -0,0 +1,9 @@
+;;; Sets OUT for a single pass whenever IN goes from low to high. PREV holds IN from the last
+;;; pass, so it has to be somewhere that can be read back, like scratch RAM.
+.macro edge IN, PREV, OUT
+LD IN
+ANDC PREV
+STO OUT
+LD IN
+STO PREV
+.endm
@@ -0,0 +1,9 @@
+;;; Set/reset latch. STATE turns on while SET is high and turns off while RESET is high, with
+;;; RESET winning if both are. STATE has to be somewhere that can be read back, like scratch RAM.
+;;; Leaves the new state in RR.
+.macro latch SET, RESET, STATE
+LD STATE
+OR SET
+ANDC RESET
+STO STATE
+.endm
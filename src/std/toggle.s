@@ -0,0 +1,10 @@
+;;; Flips STATE each time IN goes from low to high. PREV holds IN from the last pass and STATE
+;;; holds the output, so both have to be somewhere that can be read back, like scratch RAM.
+.macro toggle IN, PREV, STATE
+LD IN
+ANDC PREV
+XNOR STATE
+STOC STATE
+LD IN
+STO PREV
+.endm
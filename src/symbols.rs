@@ -0,0 +1,22 @@
+use crate::prelude::*;
+use crate::Location;
+
+/// A label or constant defined by a program, along with where it was defined.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Symbol {
+    /// The name as written, so local labels keep their leading dot. Local labels from different
+    /// macro expansions can share a name.
+    pub name: String,
+    pub kind: SymbolKind,
+    pub location: Location,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SymbolKind {
+    /// A position in the program, as a nibble address
+    Label { address: usize },
+    /// A value from `.equ`, `.define` or `.var`
+    Constant { value: usize },
+}
@@ -0,0 +1,116 @@
+//! The assembler's lexer, for tools like syntax highlighters that want to split source up the same
+//! way the assembler does rather than keeping their own copy of the grammar.
+
+use core::ops::Range;
+
+use logos::Logos;
+
+use crate::lexer;
+use crate::prelude::*;
+
+/// A piece of source, as [`tokenize`] splits it up. New kinds of token may be added as the
+/// language grows, so matches on this need a catch-all arm.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Token {
+    /// An instruction, as its canonical uppercase mnemonic
+    Mnemonic(&'static str),
+    /// A single hex digit, a `0x` hex number or a decimal number
+    Number(usize),
+    /// `.equ` and the like, named without the leading dot
+    Directive(String),
+    Identifier(String),
+    /// A quoted string, without its quotes
+    String(String),
+    /// `<std/NAME>` after an `.include`, without its angle brackets
+    LibraryPath(String),
+    Colon,
+    Comma,
+    LeftParen,
+    RightParen,
+    /// An arithmetic operator, as written
+    Operator(&'static str),
+    Newline,
+    /// Any comment other than a documentation one, including `/* ... */` comments. Preprocessor
+    /// lines like `#define` are comments too, since the assembler never lexes them.
+    Comment,
+    /// A `;;;` documentation comment, holding the text after the `;;;`
+    DocComment(String),
+    /// Text that isn't part of the language
+    Unknown,
+}
+
+/// Splits source into tokens, along with the byte range each came from. Whitespace other than
+/// newlines is skipped, and mnemonics are only recognised in uppercase.
+pub fn tokenize(source: &str) -> impl Iterator<Item = (Token, Range<usize>)> + '_ {
+    let mut lexer = lexer::Token::lexer(source);
+    core::iter::from_fn(move || {
+        let token = lexer.next()?;
+        Some((Token::from_lexer(token), lexer.span()))
+    })
+}
+
+impl Token {
+    fn from_lexer(token: lexer::Token) -> Self {
+        if let Some(mnemonic) = token.mnemonic() {
+            return Self::Mnemonic(mnemonic);
+        }
+
+        match token {
+            lexer::Token::Operand(value) => Self::Number(value.into()),
+            lexer::Token::Number(value) => Self::Number(value),
+            lexer::Token::Directive(name) => Self::Directive(name),
+            lexer::Token::Identifier(name) => Self::Identifier(name),
+            lexer::Token::String(text) => Self::String(text),
+            lexer::Token::LibraryPath(name) => Self::LibraryPath(name),
+            lexer::Token::Colon => Self::Colon,
+            lexer::Token::Comma => Self::Comma,
+            lexer::Token::LeftParen => Self::LeftParen,
+            lexer::Token::RightParen => Self::RightParen,
+            lexer::Token::Plus => Self::Operator("+"),
+            lexer::Token::Minus => Self::Operator("-"),
+            lexer::Token::Star => Self::Operator("*"),
+            lexer::Token::Slash => Self::Operator("/"),
+            lexer::Token::Percent => Self::Operator("%"),
+            lexer::Token::Ampersand => Self::Operator("&"),
+            lexer::Token::Pipe => Self::Operator("|"),
+            lexer::Token::Caret => Self::Operator("^"),
+            lexer::Token::Tilde => Self::Operator("~"),
+            lexer::Token::ShiftLeft => Self::Operator("<<"),
+            lexer::Token::ShiftRight => Self::Operator(">>"),
+            lexer::Token::Newline => Self::Newline,
+            lexer::Token::Comment(Some(text)) => Self::DocComment(text),
+            lexer::Token::Comment(None) | lexer::Token::BlockComment(_) => Self::Comment,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{tokenize, Token};
+
+    #[test]
+    fn handles_tokenize() {
+        let tokens: Vec<_> =
+            tokenize(";;; Docs\nloop: STO 0x10+1 ; done\n.include <std/latch> @").collect();
+        assert_eq!(
+            tokens,
+            [
+                (Token::DocComment(String::from("Docs")), 0..8),
+                (Token::Newline, 8..9),
+                (Token::Identifier(String::from("loop")), 9..13),
+                (Token::Colon, 13..14),
+                (Token::Mnemonic("STO"), 15..18),
+                (Token::Number(0x10), 19..23),
+                (Token::Operator("+"), 23..24),
+                (Token::Number(1), 24..25),
+                (Token::Comment, 26..32),
+                (Token::Newline, 32..33),
+                (Token::Directive(String::from("include")), 33..41),
+                (Token::LibraryPath(String::from("std/latch")), 42..53),
+                (Token::Unknown, 54..55),
+            ]
+        );
+    }
+}
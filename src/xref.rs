@@ -0,0 +1,88 @@
+//! Which instructions use each address, for working out what a change to the wiring affects.
+
+use crate::prelude::*;
+use crate::{AssemblerError, Instruction, Location, Program};
+
+/// An instruction that uses an address, from [`Program::xref`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Reference {
+    /// Where the instruction is in the program, as a nibble address
+    pub address: usize,
+    pub instruction: Instruction,
+    pub access: Access,
+    /// The line the instruction came from
+    pub location: Location,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Access {
+    /// `LD`, `AND`, `IEN` and the rest of the instructions that take their operand as an input
+    Read,
+    /// `STO` and `STOC`
+    Write,
+}
+
+impl Program {
+    /// Every instruction that reads or writes each address, indexed by address and in program
+    /// order. Jumps aren't included, since their operand is a position in the program rather
+    /// than an address.
+    pub fn xref(&self) -> Result<[Vec<Reference>; 16], AssemblerError> {
+        let source_map = self.source_map()?;
+        let mut references: [Vec<Reference>; 16] = Default::default();
+
+        for (address, instruction) in self.iter()? {
+            let access = match instruction {
+                Instruction::Store(_) | Instruction::StoreComplement(_) => Access::Write,
+                Instruction::Jump(_) => continue,
+                _ => Access::Read,
+            };
+            let Some(operand) = instruction.operand() else {
+                continue;
+            };
+
+            references[usize::from(operand)].push(Reference {
+                address,
+                instruction,
+                access,
+                location: source_map[address].clone(),
+            });
+        }
+
+        Ok(references)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Access, Instruction, Program};
+
+    #[test]
+    fn handles_xref() {
+        let program = Program::from_assembly("OEN 0\nloop: LD 7\nSTOC 7\nJMP loop");
+        let xref = program.xref().unwrap();
+
+        let uses: Vec<_> = xref[7]
+            .iter()
+            .map(|reference| {
+                (
+                    reference.address,
+                    reference.instruction,
+                    reference.access,
+                    reference.location.line,
+                )
+            })
+            .collect();
+        assert_eq!(
+            uses,
+            [
+                (2, Instruction::Load(7), Access::Read, 2),
+                (4, Instruction::StoreComplement(7), Access::Write, 3),
+            ]
+        );
+
+        assert_eq!(xref[0].len(), 1);
+        assert!(xref[2].is_empty());
+    }
+}